@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+
+// Variables holding expressions are evaluated too, up to this depth
+const MAX_DEPTH: usize = 32;
+
+const OPERATORS: &[&str] = &[
+    "**", "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "<", ">", "!", "(", ")",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+/// Evaluates an integer expression such as `2 + x * (y - 1)` the way `$(( ))` does.
+/// Names refer to variables; unset or empty ones count as 0.
+pub fn evaluate(expression: &str, variables: &HashMap<String, String>) -> Result<i64, String> {
+    evaluate_at_depth(expression, variables, 0)
+}
+
+fn evaluate_at_depth(
+    expression: &str,
+    variables: &HashMap<String, String>,
+    depth: usize,
+) -> Result<i64, String> {
+    if depth > MAX_DEPTH {
+        return Err("expression recursion level exceeded".to_string());
+    }
+
+    let mut evaluator = Evaluator {
+        tokens: tokenize(expression)?,
+        pos: 0,
+        variables,
+        depth,
+    };
+
+    // An empty expression is 0, as in bash
+    if evaluator.tokens.is_empty() {
+        return Ok(0);
+    }
+
+    let value = evaluator.or()?;
+    match evaluator.tokens.get(evaluator.pos) {
+        None => Ok(value),
+        Some(token) => Err(format!("syntax error in expression (error token is \"{}\")", token)),
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = expression.trim_start();
+
+    while let Some(ch) = rest.chars().next() {
+        let len = if ch.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '#'))
+                .unwrap_or(rest.len());
+            let token = &rest[..len];
+            let number = parse_number(token)
+                .ok_or_else(|| format!("value too great for base (error token is \"{}\")", token))?;
+            tokens.push(Token::Number(number));
+            len
+        } else if ch.is_ascii_alphabetic() || ch == '_' || ch == '$' {
+            // `$x` is usually expanded already, but accept it like a bare name
+            let start = if ch == '$' { 1 } else { 0 };
+            let len = rest[start..]
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .map_or(rest.len(), |end| start + end);
+            if len == start {
+                return Err(format!("syntax error: operand expected (error token is \"{}\")", rest));
+            }
+            tokens.push(Token::Name(rest[start..len].to_string()));
+            len
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            op.len()
+        } else {
+            return Err(format!("syntax error: invalid arithmetic operator (error token is \"{}\")", rest));
+        };
+
+        rest = rest[len..].trim_start();
+    }
+
+    Ok(tokens)
+}
+
+// An integer constant: decimal, octal after a leading 0, hexadecimal after 0x, or
+// `base#digits` for bases 2 to 36
+fn parse_number(text: &str) -> Option<i64> {
+    if let Some((base, digits)) = text.split_once('#') {
+        let base = base.parse().ok().filter(|base| (2..=36).contains(base))?;
+        return i64::from_str_radix(digits, base).ok();
+    }
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        return i64::from_str_radix(hex, 16).ok();
+    }
+    match text.strip_prefix('0') {
+        Some(octal) if !octal.is_empty() => i64::from_str_radix(octal, 8).ok(),
+        _ => text.parse().ok(),
+    }
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Number(number) => write!(f, "{}", number),
+            Token::Name(name) => write!(f, "{}", name),
+            Token::Op(op) => write!(f, "{}", op),
+        }
+    }
+}
+
+// Recursive descent over the tokens, one method per precedence level
+struct Evaluator<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    variables: &'a HashMap<String, String>,
+    depth: usize,
+}
+
+impl Evaluator<'_> {
+    fn next_op(&mut self, ops: &[&'static str]) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) if ops.contains(op) => {
+                self.pos += 1;
+                Some(op)
+            }
+            _ => None,
+        }
+    }
+
+    fn or(&mut self) -> Result<i64, String> {
+        let mut value = self.and()?;
+        while self.next_op(&["||"]).is_some() {
+            let right = self.and()?;
+            value = (value != 0 || right != 0) as i64;
+        }
+        Ok(value)
+    }
+
+    fn and(&mut self) -> Result<i64, String> {
+        let mut value = self.equality()?;
+        while self.next_op(&["&&"]).is_some() {
+            let right = self.equality()?;
+            value = (value != 0 && right != 0) as i64;
+        }
+        Ok(value)
+    }
+
+    fn equality(&mut self) -> Result<i64, String> {
+        let mut value = self.comparison()?;
+        while let Some(op) = self.next_op(&["==", "!="]) {
+            let right = self.comparison()?;
+            value = match op {
+                "==" => (value == right) as i64,
+                _ => (value != right) as i64,
+            };
+        }
+        Ok(value)
+    }
+
+    fn comparison(&mut self) -> Result<i64, String> {
+        let mut value = self.additive()?;
+        while let Some(op) = self.next_op(&["<", "<=", ">", ">="]) {
+            let right = self.additive()?;
+            value = match op {
+                "<" => (value < right) as i64,
+                "<=" => (value <= right) as i64,
+                ">" => (value > right) as i64,
+                _ => (value >= right) as i64,
+            };
+        }
+        Ok(value)
+    }
+
+    fn additive(&mut self) -> Result<i64, String> {
+        let mut value = self.multiplicative()?;
+        while let Some(op) = self.next_op(&["+", "-"]) {
+            let right = self.multiplicative()?;
+            value = match op {
+                "+" => value.wrapping_add(right),
+                _ => value.wrapping_sub(right),
+            };
+        }
+        Ok(value)
+    }
+
+    fn multiplicative(&mut self) -> Result<i64, String> {
+        let mut value = self.power()?;
+        while let Some(op) = self.next_op(&["*", "/", "%"]) {
+            let right = self.power()?;
+            if op != "*" && right == 0 {
+                return Err("division by 0".to_string());
+            }
+            value = match op {
+                "*" => value.wrapping_mul(right),
+                "/" => value.wrapping_div(right),
+                _ => value.wrapping_rem(right),
+            };
+        }
+        Ok(value)
+    }
+
+    // ** is right-associative
+    fn power(&mut self) -> Result<i64, String> {
+        let base = self.unary()?;
+        if self.next_op(&["**"]).is_none() {
+            return Ok(base);
+        }
+
+        let exponent = self.power()?;
+        if exponent < 0 {
+            return Err("exponent less than 0".to_string());
+        }
+        Ok(base.wrapping_pow(exponent.min(u32::MAX as i64) as u32))
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        match self.next_op(&["-", "+", "!"]) {
+            Some("-") => Ok(self.unary()?.wrapping_neg()),
+            Some("!") => Ok((self.unary()? == 0) as i64),
+            Some(_) => self.unary(),
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<i64, String> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+
+        match token {
+            Some(Token::Number(number)) => Ok(number),
+            Some(Token::Name(name)) => self.variable(&name),
+            Some(Token::Op("(")) => {
+                let value = self.or()?;
+                match self.next_op(&[")"]) {
+                    Some(_) => Ok(value),
+                    None => Err("missing `)'".to_string()),
+                }
+            }
+            Some(token) => Err(format!("syntax error: operand expected (error token is \"{}\")", token)),
+            None => Err("syntax error: operand expected".to_string()),
+        }
+    }
+
+    fn variable(&self, name: &str) -> Result<i64, String> {
+        let value = self.variables.get(name).map(|value| value.trim()).unwrap_or_default();
+
+        match value.parse() {
+            Ok(number) => Ok(number),
+            Err(_) => evaluate_at_depth(value, self.variables, self.depth + 1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod arith_tests {
+    use super::*;
+
+    fn eval(expression: &str) -> Result<i64, String> {
+        let variables = HashMap::from([
+            ("x".to_string(), "6".to_string()),
+            ("sum".to_string(), "x + 1".to_string()),
+            ("empty".to_string(), String::new()),
+        ]);
+        evaluate(expression, &variables)
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(eval("2 + 3 * 4"), Ok(14));
+        assert_eq!(eval("(2 + 3) * 4"), Ok(20));
+        assert_eq!(eval("2 ** 3 ** 2"), Ok(512));
+        assert_eq!(eval("-2 ** 2"), Ok(4));
+        assert_eq!(eval("7 / 2 + 7 % 2"), Ok(4));
+        assert_eq!(eval("-7 / 2"), Ok(-3));
+    }
+
+    #[test]
+    fn test_comparisons_and_logic() {
+        assert_eq!(eval("1 < 2"), Ok(1));
+        assert_eq!(eval("2 <= 1"), Ok(0));
+        assert_eq!(eval("3 == 3 && 4 != 4"), Ok(0));
+        assert_eq!(eval("0 || !0"), Ok(1));
+    }
+
+    #[test]
+    fn test_variables() {
+        assert_eq!(eval("x * 2"), Ok(12));
+        assert_eq!(eval("$x - 1"), Ok(5));
+        assert_eq!(eval("sum * 2"), Ok(14));
+        assert_eq!(eval("unset + empty"), Ok(0));
+        assert_eq!(eval(""), Ok(0));
+    }
+
+    #[test]
+    fn test_number_bases() {
+        assert_eq!(eval("0x10 + 0XfF"), Ok(271));
+        assert_eq!(eval("010"), Ok(8));
+        assert_eq!(eval("2#101 + 36#z"), Ok(40));
+        assert_eq!(eval("0"), Ok(0));
+        assert!(eval("08").is_err());
+        assert!(eval("0x").is_err());
+        assert!(eval("37#1").is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(eval("1 / 0"), Err("division by 0".to_string()));
+        assert_eq!(eval("5 % (x - 6)"), Err("division by 0".to_string()));
+        assert!(eval("2 +").is_err());
+        assert!(eval("(1 + 2").is_err());
+        assert!(eval("1 2").is_err());
+        assert!(eval("2 ** -1").is_err());
+    }
+}
@@ -0,0 +1,208 @@
+/// Expands `{a,b}` alternatives and `{1..5}` sequences in a word, before any other
+/// expansion. Quoted or escaped braces and `${...}` are left alone, and so are braces
+/// that hold neither, so `{x}` stays as it is.
+pub fn expand(word: &str) -> Vec<String> {
+    let Some((open, close, items)) = find_expansion(word) else {
+        return vec![word.to_string()];
+    };
+
+    // Later braces, including ones nested in an item, are expanded for each item in turn
+    let (prefix, suffix) = (&word[..open], &word[close + 1..]);
+    items
+        .iter()
+        .flat_map(|item| expand(&format!("{}{}{}", prefix, item, suffix)))
+        .collect()
+}
+
+// Finds the first brace that expands, returning where it opens and closes and its items
+fn find_expansion(word: &str) -> Option<(usize, usize, Vec<String>)> {
+    let bytes = word.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'\'' | b'"' => i = closing_quote(word, i),
+            b'$' if bytes.get(i + 1) == Some(&b'{') => i = closing_brace(word, i + 1)?,
+            b'{' => {
+                if let Some(close) = closing_brace(word, i)
+                    && let Some(items) = brace_items(&word[i + 1..close])
+                {
+                    return Some((i, close, items));
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    None
+}
+
+// Index of the quote closing the one at `open`, or the end of the word
+fn closing_quote(word: &str, open: usize) -> usize {
+    let quote = word.as_bytes()[open];
+    let mut i = open + 1;
+
+    while let Some(&byte) = word.as_bytes().get(i) {
+        match byte {
+            b'\\' if quote == b'"' => i += 1,
+            _ if byte == quote => return i,
+            _ => {}
+        }
+        i += 1;
+    }
+
+    word.len()
+}
+
+// Index of the `}` matching the `{` at `open`, skipping quoted text
+fn closing_brace(word: &str, open: usize) -> Option<usize> {
+    let bytes = word.as_bytes();
+    let mut depth = 0;
+    let mut i = open;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'\'' | b'"' => i = closing_quote(word, i),
+            b'{' => depth += 1,
+            b'}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    None
+}
+
+// The items of `{a,b,c}` split at top-level commas, or the values of a `{1..5}` sequence
+fn brace_items(body: &str) -> Option<Vec<String>> {
+    let bytes = body.as_bytes();
+    let mut items = Vec::new();
+    let mut start = 0;
+    let mut depth = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'\'' | b'"' => i = closing_quote(body, i),
+            b'{' => depth += 1,
+            b'}' => depth -= 1,
+            b',' if depth == 0 => {
+                items.push(body[start..i].to_string());
+                start = i + 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    if items.is_empty() {
+        return sequence(body);
+    }
+
+    items.push(body[start..].to_string());
+    Some(items)
+}
+
+// `{first..last}` or `{first..last..step}` over integers or single letters. Integers
+// written with a leading zero are padded to the same width.
+fn sequence(body: &str) -> Option<Vec<String>> {
+    let parts: Vec<&str> = body.split("..").collect();
+    let (first, last, step) = match parts[..] {
+        [first, last] => (first, last, 1),
+        [first, last, step] => (first, last, step.parse::<i64>().ok()?.checked_abs()?.max(1)),
+        _ => return None,
+    };
+
+    if let (Ok(start), Ok(end)) = (first.parse::<i64>(), last.parse::<i64>()) {
+        let padded = |text: &str| {
+            let digits = text.trim_start_matches('-');
+            digits.len() > 1 && digits.starts_with('0')
+        };
+        let width = match padded(first) || padded(last) {
+            true => first.len().max(last.len()),
+            false => 0,
+        };
+
+        return Some(
+            range(start, end, step)
+                .into_iter()
+                .map(|n| format!("{:0width$}", n, width = width))
+                .collect(),
+        );
+    }
+
+    let letter = |text: &str| match text.as_bytes() {
+        [byte] if byte.is_ascii_alphabetic() => Some(*byte as i64),
+        _ => None,
+    };
+    let values = range(letter(first)?, letter(last)?, step);
+    Some(values.into_iter().map(|c| (c as u8 as char).to_string()).collect())
+}
+
+// Counts from `start` towards `end`, which may be below it, without passing it
+fn range(start: i64, end: i64, step: i64) -> Vec<i64> {
+    let mut values = vec![start];
+    let mut n = start;
+
+    loop {
+        let next = match start <= end {
+            true => n.checked_add(step).filter(|&next| next <= end),
+            false => n.checked_sub(step).filter(|&next| next >= end),
+        };
+        match next {
+            Some(next) => {
+                values.push(next);
+                n = next;
+            }
+            None => return values,
+        }
+    }
+}
+
+#[cfg(test)]
+mod brace_tests {
+    use super::*;
+
+    #[test]
+    fn test_alternatives() {
+        assert_eq!(expand("file{1,2,3}.txt"), ["file1.txt", "file2.txt", "file3.txt"]);
+        assert_eq!(expand("{a,b}{c,d}"), ["ac", "ad", "bc", "bd"]);
+        assert_eq!(expand("{a,{b,c}d}"), ["a", "bd", "cd"]);
+        assert_eq!(expand("x{,s}"), ["x", "xs"]);
+        assert_eq!(expand("{x{a,b}}"), ["{xa}", "{xb}"]);
+        assert_eq!(expand("{a,\"b c\"}"), ["a", "\"b c\""]);
+    }
+
+    #[test]
+    fn test_sequences() {
+        assert_eq!(expand("{1..5}"), ["1", "2", "3", "4", "5"]);
+        assert_eq!(expand("{3..1}"), ["3", "2", "1"]);
+        assert_eq!(expand("{0..10..4}"), ["0", "4", "8"]);
+        assert_eq!(expand("{10..0..-5}"), ["10", "5", "0"]);
+        assert_eq!(expand("{08..11}"), ["08", "09", "10", "11"]);
+        assert_eq!(expand("{-1..1}"), ["-1", "0", "1"]);
+        assert_eq!(expand("{a..e..2}"), ["a", "c", "e"]);
+        assert_eq!(expand("v{1..2}.{a,b}"), ["v1.a", "v1.b", "v2.a", "v2.b"]);
+    }
+
+    #[test]
+    fn test_literal_braces() {
+        assert_eq!(expand("{x}"), ["{x}"]);
+        assert_eq!(expand("{}"), ["{}"]);
+        assert_eq!(expand("{a,b"), ["{a,b"]);
+        assert_eq!(expand("\\{a,b}"), ["\\{a,b}"]);
+        assert_eq!(expand("'{a,b}'"), ["'{a,b}'"]);
+        assert_eq!(expand("${x:-a,b}"), ["${x:-a,b}"]);
+        assert_eq!(expand("${x}{1..2}"), ["${x}1", "${x}2"]);
+        assert_eq!(expand("{1..a}"), ["{1..a}"]);
+    }
+}
@@ -0,0 +1,409 @@
+use linefeed::complete::{Completer, Completion, Suffix};
+use linefeed::prompter::Prompter;
+use linefeed::terminal::Terminal;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Characters that end a word unless quoted
+const WORD_BREAKS: &str = " \t\n;|&<>()";
+// Word breaks after which another command starts
+const OPERATORS: &str = "\n;|&(";
+// Characters a completed name needs a backslash before when it is not in quotes
+const SPECIAL: &str = " \t\n'\"\\$`;|&<>()*?[]{}!#";
+
+/// What `complete` registered for the arguments of a command
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompletionSpec {
+    /// The word list of `-W`, split on whitespace when completing
+    pub words: Option<String>,
+    /// `-f`: file names
+    pub files: bool,
+    /// `-d`: directory names
+    pub directories: bool,
+}
+
+impl CompletionSpec {
+    /// The completions of `word` this spec offers, with files relative to `cwd`
+    pub fn complete(&self, word: &str, cwd: &Path, home: &Path) -> Vec<Completion> {
+        let mut completions = match &self.words {
+            Some(words) => {
+                let names: Vec<String> = words.split_whitespace().map(String::from).collect();
+                complete_names(word, &names)
+            }
+            None => Vec::new(),
+        };
+
+        if self.files || self.directories {
+            let paths = complete_path(word, cwd, home);
+            completions.extend(paths.into_iter().filter(|completion| {
+                self.files || completion.suffix == Suffix::Some('/')
+            }));
+        }
+        completions
+    }
+}
+
+/// Completes the word under the cursor at the interactive prompt: a command name where a
+/// command starts, and a file anywhere else unless `complete` said otherwise
+pub struct ShellCompleter {
+    home: PathBuf,
+    // The prompt runs on its own, so the shell hands over what it knows before each line
+    commands: Mutex<Vec<String>>,
+    specs: Mutex<HashMap<String, CompletionSpec>>,
+}
+
+impl ShellCompleter {
+    pub fn new(home: PathBuf) -> Self {
+        ShellCompleter {
+            home,
+            commands: Mutex::default(),
+            specs: Mutex::default(),
+        }
+    }
+
+    /// Sets the completions registered with `complete`, by command name
+    pub fn set_specs(&self, specs: HashMap<String, CompletionSpec>) {
+        if let Ok(mut current) = self.specs.lock() {
+            *current = specs;
+        }
+    }
+
+    /// Sets the names a command word completes to, sorted and without duplicates
+    pub fn set_commands(&self, names: Vec<String>) {
+        if let Ok(mut commands) = self.commands.lock() {
+            *commands = names;
+        }
+    }
+}
+
+impl<Term: Terminal> Completer<Term> for ShellCompleter {
+    fn complete(
+        &self,
+        word: &str,
+        prompter: &Prompter<Term>,
+        start: usize,
+        _end: usize,
+    ) -> Option<Vec<Completion>> {
+        let before = &prompter.buffer()[..start];
+        // A word with a directory part names a file to run, like `./build`
+        if is_command_position(before) && !word.contains('/') {
+            let commands = self.commands.lock().ok()?;
+            return Some(complete_names(word, &commands));
+        }
+
+        let cwd = std::env::current_dir().ok()?;
+        let specs = self.specs.lock().ok()?;
+        match current_command(before).and_then(|command| specs.get(&command)) {
+            Some(spec) => Some(spec.complete(word, &cwd, &self.home)),
+            None => Some(complete_path(word, &cwd, &self.home)),
+        }
+    }
+
+    fn word_start(&self, line: &str, end: usize, _prompter: &Prompter<Term>) -> usize {
+        word_start(&line[..end])
+    }
+}
+
+/// Where the word that ends `line` starts, skipping over quoted and escaped word breaks
+pub fn word_start(line: &str) -> usize {
+    let mut start = 0;
+    let mut quote = None;
+    let mut chars = line.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => {
+                chars.next();
+            }
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, _) if WORD_BREAKS.contains(c) => start = i + c.len_utf8(),
+            _ => {}
+        }
+    }
+    start
+}
+
+/// Whether a word after `before` is where a command name goes: first on the line, or after
+/// an operator that starts another command
+pub fn is_command_position(before: &str) -> bool {
+    before
+        .trim_end()
+        .chars()
+        .next_back()
+        .is_none_or(|c| matches!(c, ';' | '|' | '&' | '(' | '\n'))
+}
+
+/// The command whose arguments follow `before`: the first word after the last operator,
+/// without its quotes
+pub fn current_command(before: &str) -> Option<String> {
+    let mut first = None;
+    let mut word = String::new();
+    let mut quote = None;
+    let mut chars = before.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => {
+                word.push(c);
+                word.extend(chars.next());
+                continue;
+            }
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, _) if WORD_BREAKS.contains(c) => {
+                if first.is_none() && !word.is_empty() {
+                    first = Some(unquote(&word).0);
+                }
+                word.clear();
+                if OPERATORS.contains(c) {
+                    first = None;
+                }
+                continue;
+            }
+            _ => {}
+        }
+        word.push(c);
+    }
+    first
+}
+
+/// The names in `names` that start with `word`
+pub fn complete_names(word: &str, names: &[String]) -> Vec<Completion> {
+    let (text, quote) = unquote(word);
+    names
+        .iter()
+        .filter(|name| name.starts_with(&text))
+        .map(|name| Completion {
+            completion: format!("{}{}", word, quote_rest(&name[text.len()..], quote)),
+            display: Some(name.clone()),
+            suffix: Suffix::Default,
+        })
+        .collect()
+}
+
+/// The files `word` could name, as it was typed, relative to `cwd`. Each completion keeps
+/// the quoting of the word, so a name with spaces stays one word; a leading `~/` stands
+/// for `home`.
+pub fn complete_path(word: &str, cwd: &Path, home: &Path) -> Vec<Completion> {
+    let (text, quote) = unquote(word);
+
+    // A lone `~` is the home directory
+    if text == "~" && word == "~" {
+        return vec![Completion {
+            completion: word.to_string(),
+            display: None,
+            suffix: Suffix::Some('/'),
+        }];
+    }
+
+    let (dir, prefix) = match text.rfind('/') {
+        Some(i) => text.split_at(i + 1),
+        None => ("", text.as_str()),
+    };
+    let lookup = match dir.strip_prefix("~/") {
+        Some(rest) if word.starts_with('~') => home.join(rest),
+        _ if dir.is_empty() => cwd.to_path_buf(),
+        _ => cwd.join(dir),
+    };
+    let Ok(entries) = std::fs::read_dir(&lookup) else {
+        return Vec::new();
+    };
+
+    let mut found: Vec<(String, bool)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let is_dir = lookup.join(&name).is_dir();
+            name.starts_with(prefix).then_some((name, is_dir))
+        })
+        .collect();
+    found.sort();
+
+    found
+        .into_iter()
+        .map(|(name, is_dir)| {
+            let mut completion = format!("{}{}", word, quote_rest(&name[prefix.len()..], quote));
+            // A file ends the word, so an open quote is closed; a directory may go on
+            let suffix = match (is_dir, quote) {
+                (true, _) => Suffix::Some('/'),
+                (false, Some(quote)) => {
+                    completion.push(quote);
+                    Suffix::Default
+                }
+                (false, None) => Suffix::Default,
+            };
+            Completion {
+                completion,
+                display: Some(name),
+                suffix,
+            }
+        })
+        .collect()
+}
+
+// The text `word` stands for, and the quote still open at its end
+fn unquote(word: &str) -> (String, Option<char>) {
+    let mut text = String::new();
+    let mut quote = None;
+    let mut chars = word.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '\\') => text.extend(chars.next()),
+            (Some('"'), '\\') if matches!(chars.peek(), Some('"' | '\\' | '$' | '`')) => {
+                text.extend(chars.next())
+            }
+            _ => text.push(c),
+        }
+    }
+    (text, quote)
+}
+
+// `rest` written so that it means itself inside `quote`, or outside any quotes
+fn quote_rest(rest: &str, quote: Option<char>) -> String {
+    let mut quoted = String::new();
+    for c in rest.chars() {
+        match quote {
+            Some('\'') if c == '\'' => quoted.push_str("'\\''"),
+            Some('"') if matches!(c, '"' | '\\' | '$' | '`') => quoted.push('\\'),
+            None if SPECIAL.contains(c) => quoted.push('\\'),
+            _ => {}
+        }
+        if !(quote == Some('\'') && c == '\'') {
+            quoted.push(c);
+        }
+    }
+    quoted
+}
+
+#[cfg(test)]
+mod complete_tests {
+    use super::*;
+
+    #[test]
+    fn test_word_start() {
+        assert_eq!(word_start("ls sr"), 3);
+        assert_eq!(word_start("ls "), 3);
+        assert_eq!(word_start("cat a|grep x"), 11);
+        assert_eq!(word_start("ls my\\ di"), 3);
+        assert_eq!(word_start("ls \"my di"), 3);
+        assert_eq!(word_start("ls 'a b' 'my di"), 9);
+        assert_eq!(word_start("echo >out"), 6);
+    }
+
+    #[test]
+    fn test_complete_command() {
+        assert!(is_command_position(""));
+        assert!(is_command_position("  "));
+        assert!(is_command_position("ls | "));
+        assert!(is_command_position("true && "));
+        assert!(is_command_position("(cd /; "));
+        assert!(!is_command_position("ls "));
+        assert!(!is_command_position("echo a|b "));
+
+        let commands = ["echo", "exec", "exit", "ls"].map(String::from);
+        let names: Vec<String> = complete_names("ex", &commands)
+            .into_iter()
+            .map(|completion| completion.completion)
+            .collect();
+        assert_eq!(names, ["exec", "exit"]);
+        assert!(complete_names("nope", &commands).is_empty());
+    }
+
+    #[test]
+    fn test_completion_spec() {
+        assert_eq!(current_command("svc "), Some("svc".to_string()));
+        assert_eq!(current_command("sudo 'my svc' a "), Some("sudo".to_string()));
+        assert_eq!(current_command("ls | grep -i "), Some("grep".to_string()));
+        assert_eq!(current_command("a; \"b c\" >out "), Some("b c".to_string()));
+        assert_eq!(current_command("echo 'a;b' "), Some("echo".to_string()));
+        assert_eq!(current_command(""), None);
+
+        let base = std::env::temp_dir().join(format!("wpcsh-{}-spec", std::process::id()));
+        std::fs::create_dir_all(base.join("sub")).unwrap();
+        std::fs::write(base.join("start.txt"), "").unwrap();
+
+        let complete = |spec: &CompletionSpec, word: &str| -> Vec<String> {
+            spec.complete(word, &base, &base)
+                .into_iter()
+                .map(|completion| completion.completion)
+                .collect()
+        };
+        let words = CompletionSpec {
+            words: Some("start stop  restart".to_string()),
+            ..CompletionSpec::default()
+        };
+        assert_eq!(complete(&words, "st"), ["start", "stop"]);
+        assert_eq!(complete(&words, ""), ["start", "stop", "restart"]);
+
+        let files = CompletionSpec {
+            files: true,
+            ..words.clone()
+        };
+        assert_eq!(complete(&files, "st"), ["start", "stop", "start.txt"]);
+        let directories = CompletionSpec {
+            directories: true,
+            ..CompletionSpec::default()
+        };
+        assert_eq!(complete(&directories, ""), ["sub"]);
+
+        let _ = std::fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn test_complete_path() {
+        let base = std::env::temp_dir().join(format!("wpcsh-{}-complete", std::process::id()));
+        std::fs::create_dir_all(base.join("my dir")).unwrap();
+        std::fs::write(base.join("my file.txt"), "").unwrap();
+        std::fs::write(base.join("other"), "").unwrap();
+        std::fs::write(base.join("my dir").join("inner"), "").unwrap();
+
+        let complete = |word: &str| -> Vec<(String, Suffix)> {
+            complete_path(word, &base, &base)
+                .into_iter()
+                .map(|completion| (completion.completion, completion.suffix))
+                .collect()
+        };
+
+        assert_eq!(complete("o"), [("other".to_string(), Suffix::Default)]);
+        assert_eq!(
+            complete("my"),
+            [
+                ("my\\ dir".to_string(), Suffix::Some('/')),
+                ("my\\ file.txt".to_string(), Suffix::Default),
+            ]
+        );
+        // The word keeps the quoting it was typed with
+        assert_eq!(complete("my\\ d"), [("my\\ dir".to_string(), Suffix::Some('/'))]);
+        assert_eq!(complete("\"my d"), [("\"my dir".to_string(), Suffix::Some('/'))]);
+        assert_eq!(
+            complete("'my f"),
+            [("'my file.txt'".to_string(), Suffix::Default)]
+        );
+        assert_eq!(
+            complete("\"my dir/i"),
+            [("\"my dir/inner\"".to_string(), Suffix::Default)]
+        );
+        assert_eq!(complete("~/o"), [("~/other".to_string(), Suffix::Default)]);
+        assert_eq!(complete("~"), [("~".to_string(), Suffix::Some('/'))]);
+        assert!(complete("missing/").is_empty());
+
+        let _ = std::fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn test_quote_rest() {
+        assert_eq!(quote_rest("a b$c", None), "a\\ b\\$c");
+        assert_eq!(quote_rest("it's", Some('\'')), "it'\\''s");
+        assert_eq!(quote_rest("a \"b\" $c", Some('"')), "a \\\"b\\\" \\$c");
+        assert_eq!(unquote("\"a \\\"b\" c\\ d'e"), ("a \"b c de".to_string(), Some('\'')));
+    }
+}
@@ -0,0 +1,419 @@
+use std::fs::Metadata;
+
+use regex::Regex;
+
+use crate::Shell;
+use crate::matcher;
+
+const UNARY: &[&str] = &["-e", "-f", "-d", "-r", "-w", "-x", "-s", "-z", "-n"];
+const BINARY: &[&str] = &["=", "==", "!=", "-eq", "-ne", "-lt", "-le", "-gt", "-ge"];
+// `[[ ]]` also matches regexes and orders strings
+const EXTENDED_BINARY: &[&str] = &["=~", "<", ">"];
+
+/// Evaluates the arguments of `test` or `[`, such as `-f path` or `$a -lt 3 -a -n "$b"`.
+/// Returns an error for malformed expressions, which `test` reports with status 2.
+pub fn evaluate(args: &[String]) -> Result<bool, String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    evaluate_words(&args)
+}
+
+fn evaluate_words(args: &[&str]) -> Result<bool, String> {
+    // With few arguments the count decides what each one is, so `[ -n ]` and `[ = = = ]`
+    // test a string instead of failing
+    match *args {
+        [] => return Ok(false),
+        [word] => return Ok(!word.is_empty()),
+        ["!", word] => return Ok(word.is_empty()),
+        [op, operand] => return unary(op, operand),
+        [left, op, right] if BINARY.contains(&op) => return binary(left, op, right),
+        ["!", ..] if args.len() <= 4 => return evaluate_words(&args[1..]).map(|result| !result),
+        ["(", word, ")"] => return Ok(!word.is_empty()),
+        ["(", _, _, ")"] => return evaluate_words(&args[1..3]),
+        _ => {}
+    }
+
+    let mut parser = Parser { args, pos: 0 };
+    let result = parser.or()?;
+    match parser.peek(0) {
+        None => Ok(result),
+        Some(extra) => Err(format!("{}: unexpected argument", extra)),
+    }
+}
+
+// Precedence from loosest to tightest: `-o`, `-a`, `!`, then single tests and `( )`
+struct Parser<'a> {
+    args: &'a [&'a str],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self, offset: usize) -> Option<&'a str> {
+        self.args.get(self.pos + offset).copied()
+    }
+
+    fn or(&mut self) -> Result<bool, String> {
+        let mut result = self.and()?;
+        while self.peek(0) == Some("-o") {
+            self.pos += 1;
+            // Both sides are parsed so errors on the right are still reported
+            result |= self.and()?;
+        }
+        Ok(result)
+    }
+
+    fn and(&mut self) -> Result<bool, String> {
+        let mut result = self.not()?;
+        while self.peek(0) == Some("-a") {
+            self.pos += 1;
+            result &= self.not()?;
+        }
+        Ok(result)
+    }
+
+    fn not(&mut self) -> Result<bool, String> {
+        if self.peek(0) == Some("!") {
+            self.pos += 1;
+            return self.not().map(|result| !result);
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<bool, String> {
+        let Some(word) = self.peek(0) else {
+            return Err("argument expected".to_string());
+        };
+
+        if let Some(op) = self.peek(1)
+            && BINARY.contains(&op)
+            && let Some(right) = self.peek(2)
+        {
+            self.pos += 3;
+            return binary(word, op, right);
+        }
+
+        if word == "(" {
+            self.pos += 1;
+            let result = self.or()?;
+            if self.peek(0) != Some(")") {
+                return Err("`)' expected".to_string());
+            }
+            self.pos += 1;
+            return Ok(result);
+        }
+
+        if UNARY.contains(&word)
+            && let Some(operand) = self.peek(1)
+        {
+            self.pos += 2;
+            return unary(word, operand);
+        }
+
+        self.pos += 1;
+        Ok(!word.is_empty())
+    }
+}
+
+impl Shell {
+    /// Runs `[[ ... ]]` on its unexpanded words and returns its status: 0 for true, 1 for
+    /// false and 2 for a malformed expression or a bad regex. Operands are expanded only
+    /// when they are reached, without splitting them or matching files. The right side of
+    /// `==` and `!=` is a glob pattern and that of `=~` a regex.
+    pub(crate) fn extended_test(&mut self, words: &[String]) -> i32 {
+        let mut parser = Extended {
+            shell: self,
+            words,
+            pos: 0,
+            live: true,
+        };
+        let result = parser.or().and_then(|result| match parser.peek(0) {
+            None => Ok(result),
+            Some(extra) => Err(TestError::Malformed(format!("{}: unexpected argument", extra))),
+        });
+
+        match result {
+            Ok(true) => 0,
+            Ok(false) => 1,
+            Err(TestError::Malformed(message)) => {
+                eprintln!("{}[[: {}", self.error_prefix(), message);
+                2
+            }
+            Err(TestError::Status(status)) => status,
+        }
+    }
+
+    // The whole match of `=~` goes in `${BASH_REMATCH[0]}` and each group in the elements
+    // after it. A failed match clears them all.
+    fn set_rematch(&mut self, captures: Option<regex::Captures>) {
+        let groups = captures.iter().flat_map(|captures| captures.iter());
+        let texts: Vec<String> = groups
+            .map(|group| group.map_or("", |group| group.as_str()).to_string())
+            .collect();
+        self.set_array("BASH_REMATCH", texts);
+    }
+}
+
+enum TestError {
+    Malformed(String),
+    // An expansion failed and has been reported, or a regex did not compile
+    Status(i32),
+}
+
+// The same precedence as `test`, with `||` and `&&` in place of `-o` and `-a`
+struct Extended<'a> {
+    shell: &'a mut Shell,
+    words: &'a [String],
+    pos: usize,
+    // Cleared while parsing the side of `&&` or `||` that is not run, so nothing in it
+    // is expanded
+    live: bool,
+}
+
+impl<'a> Extended<'a> {
+    fn peek(&self, offset: usize) -> Option<&'a str> {
+        self.words.get(self.pos + offset).map(String::as_str)
+    }
+
+    fn or(&mut self) -> Result<bool, TestError> {
+        let mut result = self.and()?;
+        while self.peek(0) == Some("||") {
+            self.pos += 1;
+            result = self.skip_if(result, Self::and)? || result;
+        }
+        Ok(result)
+    }
+
+    fn and(&mut self) -> Result<bool, TestError> {
+        let mut result = self.not()?;
+        while self.peek(0) == Some("&&") {
+            self.pos += 1;
+            result = self.skip_if(!result, Self::not)? && result;
+        }
+        Ok(result)
+    }
+
+    // Parses the right side of `&&` or `||` without running it when `skip` is set
+    fn skip_if(
+        &mut self,
+        skip: bool,
+        parse: fn(&mut Self) -> Result<bool, TestError>,
+    ) -> Result<bool, TestError> {
+        let live = self.live;
+        self.live = live && !skip;
+        let result = parse(self);
+        self.live = live;
+        result
+    }
+
+    fn not(&mut self) -> Result<bool, TestError> {
+        if self.peek(0) == Some("!") {
+            self.pos += 1;
+            return self.not().map(|result| !result);
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<bool, TestError> {
+        let Some(word) = self.peek(0) else {
+            return Err(TestError::Malformed("argument expected".to_string()));
+        };
+
+        if let Some(op) = self.peek(1)
+            && (BINARY.contains(&op) || EXTENDED_BINARY.contains(&op))
+            && let Some(right) = self.peek(2)
+        {
+            self.pos += 3;
+            return self.binary(word, op, right);
+        }
+
+        if word == "(" {
+            self.pos += 1;
+            let result = self.or()?;
+            if self.peek(0) != Some(")") {
+                return Err(TestError::Malformed("`)' expected".to_string()));
+            }
+            self.pos += 1;
+            return Ok(result);
+        }
+
+        if UNARY.contains(&word)
+            && let Some(operand) = self.peek(1)
+        {
+            self.pos += 2;
+            if !self.live {
+                return Ok(false);
+            }
+            let operand = self.expand(operand)?;
+            return unary(word, &operand).map_err(TestError::Malformed);
+        }
+
+        self.pos += 1;
+        Ok(self.live && !self.expand(word)?.is_empty())
+    }
+
+    fn binary(&mut self, left: &str, op: &str, right: &str) -> Result<bool, TestError> {
+        if !self.live {
+            return Ok(false);
+        }
+        let left = self.expand(left)?;
+        let failed = |_| TestError::Status(1);
+
+        match op {
+            "=" | "==" | "!=" => {
+                let pattern = self.shell.expand_pattern(right).map_err(failed)?;
+                Ok(matcher::matches(&pattern, &left) == (op != "!="))
+            }
+            "=~" => {
+                let pattern = self.shell.expand_regex(right).map_err(failed)?;
+                let Ok(regex) = Regex::new(&pattern) else {
+                    return Err(TestError::Status(2));
+                };
+                let captures = regex.captures(&left);
+                let matched = captures.is_some();
+                self.shell.set_rematch(captures);
+                Ok(matched)
+            }
+            "<" => Ok(left < self.expand(right)?),
+            ">" => Ok(left > self.expand(right)?),
+            _ => binary(&left, op, &self.expand(right)?).map_err(TestError::Malformed),
+        }
+    }
+
+    fn expand(&mut self, word: &str) -> Result<String, TestError> {
+        self.shell.expand_parameter_word(word).map_err(|_| TestError::Status(1))
+    }
+}
+
+fn unary(op: &str, operand: &str) -> Result<bool, String> {
+    let metadata = || std::fs::metadata(operand);
+
+    match op {
+        "-z" => Ok(operand.is_empty()),
+        "-n" => Ok(!operand.is_empty()),
+        "-e" => Ok(metadata().is_ok()),
+        "-f" => Ok(metadata().is_ok_and(|meta| meta.is_file())),
+        "-d" => Ok(metadata().is_ok_and(|meta| meta.is_dir())),
+        "-s" => Ok(metadata().is_ok_and(|meta| meta.len() > 0)),
+        "-r" | "-w" | "-x" => Ok(metadata().is_ok_and(|meta| accessible(operand, &meta, op))),
+        _ => Err(format!("{}: unary operator expected", op)),
+    }
+}
+
+fn binary(left: &str, op: &str, right: &str) -> Result<bool, String> {
+    match op {
+        "=" | "==" => return Ok(left == right),
+        "!=" => return Ok(left != right),
+        _ => {}
+    }
+
+    let number = |text: &str| {
+        text.trim()
+            .parse::<i64>()
+            .map_err(|_| format!("{}: integer expression expected", text))
+    };
+    let (left, right) = (number(left)?, number(right)?);
+
+    Ok(match op {
+        "-eq" => left == right,
+        "-ne" => left != right,
+        "-lt" => left < right,
+        "-le" => left <= right,
+        "-gt" => left > right,
+        _ => left >= right,
+    })
+}
+
+// Whether the shell may read, write or execute `path`, as `-r`, `-w` and `-x` ask
+#[cfg(unix)]
+fn accessible(path: &str, _metadata: &Metadata, op: &str) -> bool {
+    let Ok(path) = std::ffi::CString::new(path) else {
+        return false;
+    };
+    let mode = match op {
+        "-r" => libc::R_OK,
+        "-w" => libc::W_OK,
+        _ => libc::X_OK,
+    };
+    // SAFETY: `path` is a valid C string that outlives the call
+    unsafe { libc::access(path.as_ptr(), mode) == 0 }
+}
+
+// Without access(2), readable means present and writable means not read-only
+#[cfg(not(unix))]
+fn accessible(path: &str, metadata: &Metadata, op: &str) -> bool {
+    match op {
+        "-r" => true,
+        "-w" => !metadata.permissions().readonly(),
+        _ => {
+            let extension = std::path::Path::new(path).extension();
+            metadata.is_dir() || extension.is_some_and(|ext| ext == "exe" || ext == "bat")
+        }
+    }
+}
+
+#[cfg(test)]
+mod condition_tests {
+    use super::*;
+
+    fn test(args: &[&str]) -> Result<bool, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        evaluate(&args)
+    }
+
+    #[test]
+    fn test_strings() {
+        assert_eq!(test(&[]), Ok(false));
+        assert_eq!(test(&[""]), Ok(false));
+        assert_eq!(test(&["-n"]), Ok(true));
+        assert_eq!(test(&["-z", ""]), Ok(true));
+        assert_eq!(test(&["-n", ""]), Ok(false));
+        assert_eq!(test(&["a", "=", "a"]), Ok(true));
+        assert_eq!(test(&["a", "!=", "a"]), Ok(false));
+        assert_eq!(test(&["=", "=", "="]), Ok(true));
+        assert_eq!(test(&["!", "-z", "x"]), Ok(true));
+    }
+
+    #[test]
+    fn test_integers() {
+        assert_eq!(test(&["2", "-lt", "10"]), Ok(true));
+        assert_eq!(test(&["-3", "-ge", "-3"]), Ok(true));
+        assert_eq!(test(&[" 7 ", "-eq", "7"]), Ok(true));
+        assert_eq!(test(&["2", "-gt", "10"]), Ok(false));
+        assert!(test(&["a", "-eq", "1"]).is_err());
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        assert_eq!(test(&["a", "-a", ""]), Ok(false));
+        assert_eq!(test(&["", "-o", "b"]), Ok(true));
+        // -a binds tighter than -o
+        assert_eq!(test(&["x", "-o", "", "-a", ""]), Ok(true));
+        assert_eq!(test(&["(", "x", "-o", "", ")", "-a", ""]), Ok(false));
+        assert_eq!(test(&["!", "(", "1", "-eq", "1", ")", "-o", "-n", ""]), Ok(false));
+        assert!(test(&["(", "x", "-a", "y"]).is_err());
+        assert!(test(&["-q", "x"]).is_err());
+        assert!(test(&["a", "b", "c", "d"]).is_err());
+    }
+
+    #[test]
+    fn test_files() {
+        let dir = std::env::temp_dir().join(format!("wpcsh-{}-condition", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let empty = dir.join("empty");
+        let full = dir.join("full");
+        std::fs::write(&empty, "").unwrap();
+        std::fs::write(&full, "data").unwrap();
+        let path = |path: &std::path::Path| path.to_string_lossy().into_owned();
+
+        assert_eq!(test(&["-e", &path(&empty)]), Ok(true));
+        assert_eq!(test(&["-f", &path(&empty)]), Ok(true));
+        assert_eq!(test(&["-d", &path(&empty)]), Ok(false));
+        assert_eq!(test(&["-d", &path(&dir)]), Ok(true));
+        assert_eq!(test(&["-s", &path(&empty)]), Ok(false));
+        assert_eq!(test(&["-s", &path(&full)]), Ok(true));
+        assert_eq!(test(&["-r", &path(&full)]), Ok(true));
+        assert_eq!(test(&["-e", &path(&dir.join("missing"))]), Ok(false));
+        assert_eq!(test(&["-w", &path(&dir.join("missing"))]), Ok(false));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
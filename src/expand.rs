@@ -0,0 +1,1130 @@
+use std::borrow::Cow;
+use std::io::{ErrorKind, Read};
+
+use crate::{Jump, Shell, shell_pid};
+use crate::arith;
+use crate::brace;
+use crate::foreground;
+use crate::matcher;
+use crate::flash::lexer::Lexer;
+use crate::flash::parser::{Node, ParameterExpansionType, Parser, ReplaceScope, parse_parameter};
+use crate::redirect;
+
+/// Fields produced while expanding a single word
+#[derive(Default)]
+struct Fields {
+    done: Vec<String>,
+    current: String,
+    // Set once the current field has text or quotes, so `""` still yields a field
+    started: bool,
+    // Command words are split; patterns and case subjects are not
+    split: bool,
+    // Patterns and command words escape quoted glob characters so they only match themselves
+    escape_globs: bool,
+    // The regex of `[[ a =~ b ]]` escapes every quoted character regexes give a meaning to
+    escape_regex: bool,
+}
+
+impl Fields {
+    fn push(&mut self, ch: char) {
+        self.current.push(ch);
+        self.started = true;
+    }
+
+    fn push_str(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+
+        // Backslashes from expansions are plain text, not escapes
+        if self.escape_globs {
+            self.current.push_str(&text.replace('\\', "\\\\"));
+        } else {
+            self.current.push_str(text);
+        }
+        self.started = true;
+    }
+
+    fn push_quoted(&mut self, ch: char) {
+        if self.escape_regex {
+            self.current.push_str(&regex::escape(ch.encode_utf8(&mut [0; 4])));
+            self.started = true;
+            return;
+        }
+        if self.escape_globs && matches!(ch, '*' | '?' | '[' | ']' | '\\') {
+            self.current.push('\\');
+        }
+        self.push(ch);
+    }
+
+    fn push_quoted_str(&mut self, text: &str) {
+        self.mark_quoted();
+        for ch in text.chars() {
+            self.push_quoted(ch);
+        }
+    }
+
+    // With no values, a field holding only the quotes around "$@" is dropped
+    fn push_each_quoted(&mut self, values: &[String]) {
+        if values.is_empty() && self.current.is_empty() {
+            self.started = false;
+        }
+
+        for (i, value) in values.iter().enumerate() {
+            if i > 0 {
+                self.end_field();
+            }
+            self.push_quoted_str(value);
+        }
+    }
+
+    fn mark_quoted(&mut self) {
+        self.started = true;
+    }
+
+    // Unquoted expansion results are split on whitespace into separate fields
+    fn push_split(&mut self, text: &str) {
+        if !self.split {
+            self.push_str(text);
+            return;
+        }
+
+        if text.starts_with(char::is_whitespace) {
+            self.end_field();
+        }
+
+        let mut words = text.split_whitespace().peekable();
+        while let Some(word) = words.next() {
+            self.push_str(word);
+            if words.peek().is_some() {
+                self.end_field();
+            }
+        }
+
+        if text.ends_with(char::is_whitespace) {
+            self.end_field();
+        }
+    }
+
+    fn end_field(&mut self) {
+        if self.started {
+            self.done.push(std::mem::take(&mut self.current));
+            self.started = false;
+        }
+    }
+
+    fn finish(mut self) -> Vec<String> {
+        self.end_field();
+        self.done
+    }
+}
+
+impl Shell {
+    /// Expands every word of a command, braces first, dropping words that expand to nothing.
+    /// Errors are reported when they happen and set `$?` to 1.
+    pub(crate) fn expand_words(
+        &mut self,
+        words: impl IntoIterator<Item = String>,
+    ) -> Result<Vec<String>, ErrorKind> {
+        let mut fields = Vec::new();
+        for word in words.into_iter().flat_map(|word| brace::expand(&word)) {
+            fields.extend(self.expand_word(&word)?);
+        }
+        Ok(fields)
+    }
+
+    /// Expands the words of `local`, where a `name=value` word is expanded like an
+    /// assignment's value, without splitting or globbing
+    pub(crate) fn expand_declaration(
+        &mut self,
+        words: impl IntoIterator<Item = String>,
+    ) -> Result<Vec<String>, ErrorKind> {
+        let mut fields = Vec::new();
+        for word in words {
+            match word.split_once('=').is_some_and(|(name, _)| crate::is_name(name)) {
+                true => fields.push(self.expand_target(&word)?),
+                false => fields.extend(self.expand_words([word])?),
+            }
+        }
+        Ok(fields)
+    }
+
+    /// Expands one command word: removes quotes, expands `~`, variables and command
+    /// substitutions, splits unquoted expansion results into several fields, and replaces
+    /// fields with unquoted glob characters by the files they match
+    pub(crate) fn expand_word(&mut self, word: &str) -> Result<Vec<String>, ErrorKind> {
+        let mut fields = Fields {
+            split: true,
+            escape_globs: true,
+            ..Fields::default()
+        };
+        self.expand_into(word, &mut fields)?;
+
+        let mut words = Vec::new();
+        for field in fields.finish() {
+            let matches = match matcher::has_glob(&field) {
+                true => matcher::glob(&field),
+                false => Vec::new(),
+            };
+
+            // A pattern matching nothing is kept as it is written
+            if matches.is_empty() {
+                words.push(matcher::unescape(&field));
+            } else {
+                words.extend(matches);
+            }
+        }
+        Ok(words)
+    }
+
+    /// Expands a redirection target or here-string: quotes are removed and a leading `~`,
+    /// parameters and substitutions expanded, but the result is never split or globbed
+    pub(crate) fn expand_target(&mut self, word: &str) -> Result<String, ErrorKind> {
+        let mut fields = Fields::default();
+        self.expand_into(word, &mut fields)?;
+        Ok(fields.finish().concat())
+    }
+
+    /// Expands a word into a glob pattern for `case`, where quoted characters
+    /// are escaped so they match literally
+    pub(crate) fn expand_pattern(&mut self, word: &str) -> Result<String, ErrorKind> {
+        let mut fields = Fields {
+            escape_globs: true,
+            ..Fields::default()
+        };
+        self.expand_into(word, &mut fields)?;
+        Ok(fields.finish().concat())
+    }
+
+    /// Expands the right side of `[[ a =~ b ]]` into a regex, where quoted characters
+    /// are escaped so they match literally
+    pub(crate) fn expand_regex(&mut self, word: &str) -> Result<String, ErrorKind> {
+        let mut fields = Fields {
+            escape_regex: true,
+            ..Fields::default()
+        };
+        self.expand_into(word, &mut fields)?;
+        Ok(fields.finish().concat())
+    }
+
+    fn expand_into(&mut self, word: &str, fields: &mut Fields) -> Result<(), ErrorKind> {
+        let mut in_double = false;
+        let mut i = 0;
+
+        if let Some(rest) = word.strip_prefix('~')
+            && (rest.is_empty() || rest.starts_with('/'))
+        {
+            fields.push_quoted_str(&self.home_dir.to_string_lossy());
+            i = 1;
+        }
+
+        while let Some(ch) = word[i..].chars().next() {
+            match ch {
+                '\'' if !in_double => {
+                    let end = word[i + 1..].find('\'').map_or(word.len(), |end| i + 1 + end);
+                    fields.push_quoted_str(&word[i + 1..end]);
+                    i = (end + 1).min(word.len());
+                    continue;
+                }
+                '"' => {
+                    // Only the opening quote makes a field, so an empty "$@" can remove it
+                    in_double = !in_double;
+                    if in_double {
+                        fields.mark_quoted();
+                    }
+                }
+                '\\' => match word[i + 1..].chars().next() {
+                    // Inside double quotes only a few characters can be escaped
+                    Some(next) if !in_double || matches!(next, '$' | '`' | '"' | '\\') => {
+                        fields.push_quoted(next);
+                        i += 1 + next.len_utf8();
+                        continue;
+                    }
+                    _ => fields.push_quoted('\\'),
+                },
+                // "$@" gives each positional parameter its own field
+                '$' if in_double && word[i..].starts_with("$@") => {
+                    fields.push_each_quoted(&self.positional);
+                    i += 2;
+                    continue;
+                }
+                // The word of an unquoted ${var:-word} keeps its own quoting
+                '$' if !in_double && word[i..].starts_with("${") => {
+                    let (parameter, end) = self.parameter_at(word, i)?;
+                    match parameter {
+                        Parameter::Value(value) => fields.push_split(&value),
+                        Parameter::Word(inner) => self.expand_into(&inner, fields)?,
+                    }
+                    i = end;
+                    continue;
+                }
+                '$' | '`' => {
+                    let (value, end) = self.expand_dollar(word, i)?;
+                    if in_double {
+                        fields.push_quoted_str(&value);
+                    } else {
+                        fields.push_split(&value);
+                    }
+                    i = end;
+                    continue;
+                }
+                _ if in_double => fields.push_quoted(ch),
+                _ => fields.push(ch),
+            }
+            i += ch.len_utf8();
+        }
+
+        Ok(())
+    }
+
+    /// Expands variables and command substitutions the way double quotes do,
+    /// without removing quotes or splitting the result
+    pub(crate) fn expand_string(&mut self, input: &str) -> Result<String, ErrorKind> {
+        let mut out = String::with_capacity(input.len());
+        let mut i = 0;
+
+        while let Some(ch) = input[i..].chars().next() {
+            match ch {
+                '\\' => match input[i + 1..].chars().next() {
+                    Some(next @ ('$' | '`' | '\\')) => {
+                        out.push(next);
+                        i += 1 + next.len_utf8();
+                        continue;
+                    }
+                    _ => out.push('\\'),
+                },
+                '$' | '`' => {
+                    let (value, end) = self.expand_dollar(input, i)?;
+                    out.push_str(&value);
+                    i = end;
+                    continue;
+                }
+                _ => out.push(ch),
+            }
+            i += ch.len_utf8();
+        }
+
+        Ok(out)
+    }
+
+    // Expands the `$...` or backtick substitution at `start`. Returns the text and the index
+    // just past the expansion.
+    fn expand_dollar(&mut self, input: &str, start: usize) -> Result<(String, usize), ErrorKind> {
+        let rest = &input[start..];
+
+        if rest.starts_with("${") {
+            let (parameter, end) = self.parameter_at(input, start)?;
+            let value = match parameter {
+                Parameter::Value(value) => value,
+                Parameter::Word(word) => self.expand_parameter_word(&word)?,
+            };
+            return Ok((value, end));
+        }
+
+        if rest.starts_with("$((")
+            && let Some(close) = closing_bracket(input, start + 1)
+            && input[..close].ends_with(')')
+        {
+            let value = self.evaluate_arithmetic(&input[start + 3..close - 1])?;
+            return Ok((value.to_string(), close + 1));
+        }
+
+        if rest.starts_with("$(") {
+            let (source, end) = match closing_bracket(input, start + 1) {
+                Some(close) => (&input[start + 2..close], close + 1),
+                None => (&input[start + 2..], input.len()),
+            };
+            return Ok((self.command_substitution(source), end));
+        }
+
+        if rest.starts_with('`') {
+            let (source, end) = match closing_backtick(input, start + 1) {
+                Some(close) => (&input[start + 1..close], close + 1),
+                None => (&input[start + 1..], input.len()),
+            };
+            return Ok((self.command_substitution(&source.replace("\\`", "`")), end));
+        }
+
+        Ok(match self.variable_reference(input, start) {
+            Some(reference) => reference,
+            None => ("$".to_string(), start + 1),
+        })
+    }
+
+    // Expands the `${...}` at `start` and returns the index just past it
+    fn parameter_at(&mut self, input: &str, start: usize) -> Result<(Parameter, usize), ErrorKind> {
+        let Some(close) = closing_bracket(input, start + 1) else {
+            return Err(self.bad_substitution(&input[start..]));
+        };
+        Ok((self.parameter_expansion(&input[start + 2..close])?, close + 1))
+    }
+
+    // Expands the inside of a `${...}`, such as `name` or `name:-word`
+    fn parameter_expansion(&mut self, inner: &str) -> Result<Parameter, ErrorKind> {
+        let Some((name, expansion_type)) = parse_parameter(inner) else {
+            return Err(self.bad_substitution(&format!("${{{}}}", inner)));
+        };
+
+        let value = self.parameter_value(&name);
+        let set = value.is_some();
+        // The forms with a colon treat an empty value like an unset one
+        let non_empty = value.as_deref().is_some_and(|value| !value.is_empty());
+
+        let value = match expansion_type {
+            ParameterExpansionType::Default(word) if !non_empty => return Ok(Parameter::Word(word)),
+            ParameterExpansionType::UnsetDefault(word) if !set => return Ok(Parameter::Word(word)),
+            ParameterExpansionType::Assign(word) if !non_empty => {
+                self.assign_parameter(&name, &word)?
+            }
+            ParameterExpansionType::UnsetAssign(word) if !set => {
+                self.assign_parameter(&name, &word)?
+            }
+            ParameterExpansionType::Error(word) if !non_empty => {
+                return Err(self.parameter_error(&name, &word, "parameter null or not set"));
+            }
+            ParameterExpansionType::UnsetError(word) if !set => {
+                return Err(self.parameter_error(&name, &word, "parameter not set"));
+            }
+            ParameterExpansionType::Alternative(word) if non_empty => {
+                return Ok(Parameter::Word(word));
+            }
+            ParameterExpansionType::UnsetAlternative(word) if set => {
+                return Ok(Parameter::Word(word));
+            }
+            ParameterExpansionType::Alternative(_)
+            | ParameterExpansionType::UnsetAlternative(_) => String::new(),
+            ParameterExpansionType::Length => match array_name(&name) {
+                Some((array, "@" | "*")) => self.array_elements(array).len().to_string(),
+                _ => value.map_or(0, |value| value.chars().count()).to_string(),
+            },
+            ParameterExpansionType::Substring(offset, length) => {
+                self.substring(&value.unwrap_or_default(), &offset, length.as_deref())?
+            }
+            ParameterExpansionType::RemoveSmallestPrefix(pattern) => {
+                self.remove_match(value, &pattern, Trim::Prefix, false)?
+            }
+            ParameterExpansionType::RemoveLargestPrefix(pattern) => {
+                self.remove_match(value, &pattern, Trim::Prefix, true)?
+            }
+            ParameterExpansionType::RemoveSmallestSuffix(pattern) => {
+                self.remove_match(value, &pattern, Trim::Suffix, false)?
+            }
+            ParameterExpansionType::RemoveLargestSuffix(pattern) => {
+                self.remove_match(value, &pattern, Trim::Suffix, true)?
+            }
+            ParameterExpansionType::Replace(scope, pattern, replacement) => {
+                self.replace_matches(value, scope, &pattern, &replacement)?
+            }
+            _ => value.unwrap_or_default(),
+        };
+
+        Ok(Parameter::Value(value))
+    }
+
+    // ${var:offset:length} counts characters; negative numbers count back from the end
+    fn substring(
+        &mut self,
+        value: &str,
+        offset: &str,
+        length: Option<&str>,
+    ) -> Result<String, ErrorKind> {
+        let chars: Vec<char> = value.chars().collect();
+        let len = chars.len() as i64;
+
+        let mut start = self.evaluate_arithmetic(offset)?;
+        if start < 0 {
+            start += len;
+        }
+        if start < 0 || start > len {
+            return Ok(String::new());
+        }
+
+        let end = match length {
+            None => len,
+            Some(length) => {
+                let length = self.evaluate_arithmetic(length)?;
+                if length >= 0 {
+                    start.saturating_add(length).min(len)
+                } else if len + length >= start {
+                    len + length
+                } else {
+                    eprintln!("{}{}: substring expression < 0", self.error_prefix(), length);
+                    self.exit_status = 1;
+                    return Err(ErrorKind::InvalidInput);
+                }
+            }
+        };
+
+        Ok(chars[start as usize..end as usize].iter().collect())
+    }
+
+    // ${var#pattern} and friends drop the shortest or longest matching prefix or suffix
+    fn remove_match(
+        &mut self,
+        value: Option<String>,
+        pattern: &str,
+        trim: Trim,
+        longest: bool,
+    ) -> Result<String, ErrorKind> {
+        let value = value.unwrap_or_default();
+        let pattern = self.expand_pattern(pattern)?;
+
+        let mut bounds: Vec<usize> = value.char_indices().map(|(i, _)| i).collect();
+        bounds.push(value.len());
+        // Try the shortest candidate first unless the longest is wanted
+        if longest == (trim == Trim::Prefix) {
+            bounds.reverse();
+        }
+
+        let kept = bounds.into_iter().find_map(|i| match trim {
+            Trim::Prefix => matcher::matches(&pattern, &value[..i]).then(|| &value[i..]),
+            Trim::Suffix => matcher::matches(&pattern, &value[i..]).then(|| &value[..i]),
+        });
+        Ok(kept.unwrap_or(&value).to_string())
+    }
+
+    // ${var/pattern/replacement} replaces the longest match at the first place, or at every
+    // place, where the pattern matches. The anchored forms may also match nothing.
+    fn replace_matches(
+        &mut self,
+        value: Option<String>,
+        scope: ReplaceScope,
+        pattern: &str,
+        replacement: &str,
+    ) -> Result<String, ErrorKind> {
+        let value = value.unwrap_or_default();
+        let pattern = self.expand_pattern(pattern)?;
+        let replacement = self.expand_parameter_word(replacement)?;
+
+        let mut bounds: Vec<usize> = value.char_indices().map(|(i, _)| i).collect();
+        bounds.push(value.len());
+
+        match scope {
+            ReplaceScope::Prefix => {
+                let end = bounds
+                    .iter()
+                    .rev()
+                    .find(|&&end| matcher::matches(&pattern, &value[..end]));
+                return Ok(match end {
+                    Some(&end) => replacement + &value[end..],
+                    None => value,
+                });
+            }
+            ReplaceScope::Suffix => {
+                let start = bounds
+                    .iter()
+                    .find(|&&start| matcher::matches(&pattern, &value[start..]));
+                return Ok(match start {
+                    Some(&start) => value[..start].to_string() + &replacement,
+                    None => value,
+                });
+            }
+            ReplaceScope::First | ReplaceScope::All => {}
+        }
+
+        let mut out = String::with_capacity(value.len());
+        let mut replaced = false;
+        let mut i = 0;
+
+        while i + 1 < bounds.len() {
+            let start = bounds[i];
+            let end = if replaced && scope == ReplaceScope::First {
+                None
+            } else {
+                (i + 1..bounds.len())
+                    .rev()
+                    .find(|&j| matcher::matches(&pattern, &value[start..bounds[j]]))
+            };
+
+            match end {
+                Some(j) => {
+                    out.push_str(&replacement);
+                    replaced = true;
+                    i = j;
+                }
+                None => {
+                    out.push_str(&value[start..bounds[i + 1]]);
+                    i += 1;
+                }
+            }
+        }
+
+        Ok(out)
+    }
+
+    // The word after `:-` and friends, like an operand of `[[ ]]`, is expanded and unquoted
+    // but never split
+    pub(crate) fn expand_parameter_word(&mut self, word: &str) -> Result<String, ErrorKind> {
+        let mut fields = Fields::default();
+        self.expand_into(word, &mut fields)?;
+        Ok(fields.finish().concat())
+    }
+
+    fn assign_parameter(&mut self, name: &str, word: &str) -> Result<String, ErrorKind> {
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') || name.contains('[')
+        {
+            eprintln!("{}${}: cannot assign in this way", self.error_prefix(), name);
+            self.exit_status = 1;
+            return Err(ErrorKind::InvalidInput);
+        }
+
+        let value = self.expand_parameter_word(word)?;
+        self.variables.insert(name.to_string(), value.clone());
+        Ok(value)
+    }
+
+    // `${name:?message}` fails the command with the message, but does not exit the shell
+    fn parameter_error(&mut self, name: &str, word: &str, default: &str) -> ErrorKind {
+        let message = match self.expand_parameter_word(word) {
+            Ok(message) if !message.is_empty() => message,
+            Ok(_) => default.to_string(),
+            Err(err) => return err,
+        };
+
+        eprintln!("{}{}: {}", self.error_prefix(), name, message);
+        self.exit_status = 1;
+        // Only an interactive shell carries on to the next command
+        if !self.interactive {
+            self.jump = Some(Jump::Exit);
+        }
+        ErrorKind::InvalidInput
+    }
+
+    fn bad_substitution(&mut self, text: &str) -> ErrorKind {
+        eprintln!("{}{}: bad substitution", self.error_prefix(), text);
+        self.exit_status = 1;
+        ErrorKind::InvalidInput
+    }
+
+    /// Expands and evaluates the inside of `$(( ))`, reporting errors like division by zero
+    pub(crate) fn evaluate_arithmetic(&mut self, expression: &str) -> Result<i64, ErrorKind> {
+        let expression = self.expand_string(expression)?;
+
+        arith::evaluate(&expression, &self.variables).map_err(|err| {
+            eprintln!("{}{}: {}", self.error_prefix(), expression.trim(), err);
+            self.exit_status = 1;
+            ErrorKind::InvalidInput
+        })
+    }
+
+    fn command_substitution(&mut self, source: &str) -> String {
+        let mut parser = Parser::new(Lexer::new(source));
+        let script = parser.parse_script();
+
+        self.capture_output(script)
+    }
+
+    /// Runs `node` with its standard output captured and returns that output
+    /// without trailing newlines, as `$(...)` does
+    pub(crate) fn capture_output(&mut self, node: Node) -> String {
+        let (mut reader, writer) = match redirect::pipe() {
+            Ok(ends) => ends,
+            Err(err) => {
+                eprintln!("{}{}", self.error_prefix(), err);
+                self.exit_status = 1;
+                return String::new();
+            }
+        };
+
+        // Read while the command runs so a full pipe cannot block it
+        let output = std::thread::spawn(move || {
+            let mut output = Vec::new();
+            let _ = reader.read_to_end(&mut output);
+            output
+        });
+
+        // The command runs in a copy of the shell, so what it changes, an `exit` or `set -e`
+        // included, ends with it. The copy stays in the shell's process group, which has the
+        // terminal.
+        let outer_capture = self.streams.stdout.replace(writer);
+        let processes = self.fork_job(foreground::shell_group(), |shell| {
+            // The substitution must not see the enclosing command's prefixes
+            shell.command_env.clear();
+            shell.execute_node(node)
+        });
+
+        // Dropping our write end lets the reader see end of file
+        self.streams.stdout = outer_capture;
+        let status = processes
+            .into_iter()
+            .next()
+            .map_or(self.exit_status, |mut process| process.wait());
+        self.exit_status = status;
+
+        let output = output.join().unwrap_or_default();
+        let mut output = String::from_utf8_lossy(&output).into_owned();
+        output.truncate(output.trim_end_matches('\n').len());
+        output
+    }
+
+    // Expands $NAME and $? references
+    pub(crate) fn expand_variables<'a>(&self, input: &'a str) -> Cow<'a, str> {
+        if !input.contains('$') {
+            return Cow::Borrowed(input);
+        }
+
+        let mut out = String::with_capacity(input.len());
+        let mut i = 0;
+
+        while let Some(ch) = input[i..].chars().next() {
+            if ch != '$' {
+                out.push(ch);
+                i += ch.len_utf8();
+                continue;
+            }
+
+            match self.variable_reference(input, i) {
+                Some((value, end)) => {
+                    out.push_str(&value);
+                    i = end;
+                }
+                None => {
+                    out.push('$');
+                    i += 1;
+                }
+            }
+        }
+
+        Cow::Owned(out)
+    }
+
+    // Value of the `$?`, `$$`, `$1`, `$NAME` or similar reference at `start` and the index
+    // just past it, or None when the `$` does not start a reference
+    fn variable_reference(&self, input: &str, start: usize) -> Option<(String, usize)> {
+        let next = start + 1;
+        let next_ch = input[next..].chars().next()?;
+
+        // Only one digit is read, so `$10` is `$1` followed by `0`
+        let special = matches!(next_ch, '?' | '#' | '$' | '!' | '@' | '*');
+        let end = if special || next_ch.is_ascii_digit() {
+            next + 1
+        } else {
+            input[next..]
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .map_or(input.len(), |end| next + end)
+        };
+        if end == next {
+            return None;
+        }
+
+        // Unset variables expand to nothing, as in other shells
+        let value = self.parameter_value(&input[next..end]).unwrap_or_default();
+        Some((value, end))
+    }
+
+    /// Stores `values` as the array `name`, replacing any variable of that name. `$name`
+    /// is element 0 and `${name[n]}` element n.
+    pub(crate) fn set_array(&mut self, name: &str, values: impl IntoIterator<Item = String>) {
+        self.variables.remove(name);
+        self.arrays.insert(name.to_string(), values.into_iter().collect());
+    }
+
+    // Every element of the array `name`, as `${name[@]}` lists them. A plain variable is an
+    // array of one.
+    fn array_elements(&self, name: &str) -> &[String] {
+        match self.variables.get(name) {
+            Some(value) => std::slice::from_ref(value),
+            None => self.arrays.get(name).map_or(&[], Vec::as_slice),
+        }
+    }
+
+    // Value of a parameter such as `?`, `#`, `1`, `NAME` or `NAME[1]`, or None when it is
+    // unset
+    fn parameter_value(&self, name: &str) -> Option<String> {
+        if let Some((array, subscript)) = array_name(name) {
+            return match subscript {
+                "@" | "*" => Some(self.array_elements(array).join(" ")),
+                _ => {
+                    let index: usize = subscript.trim().parse().ok()?;
+                    self.array_elements(array).get(index).cloned()
+                }
+            };
+        }
+
+        match name {
+            "?" => Some(self.exit_status.to_string()),
+            "#" => Some(self.positional.len().to_string()),
+            "$" => Some(shell_pid().to_string()),
+            "!" => self.last_background.map(|pid| pid.to_string()),
+            "0" => Some(self.shell_name.clone()),
+            "@" => Some(self.positional.join(" ")),
+            // "$*" joins with the first character of IFS, or nothing when IFS is empty
+            "*" => {
+                let separator = match self.variables.get("IFS") {
+                    Some(ifs) => ifs.chars().next().map(String::from).unwrap_or_default(),
+                    None => " ".to_string(),
+                };
+                Some(self.positional.join(&separator))
+            }
+            _ if name.starts_with(|c: char| c.is_ascii_digit()) => {
+                let index = name.parse::<usize>().ok()?.checked_sub(1)?;
+                self.positional.get(index).cloned()
+            }
+            _ => self.array_elements(name).first().cloned(),
+        }
+    }
+}
+
+// Splits `name[subscript]` into the array and the subscript
+fn array_name(name: &str) -> Option<(&str, &str)> {
+    name.strip_suffix(']')?.split_once('[')
+}
+
+// What a `${...}` stands for: a final value, or the word of a form like `${var:-word}`,
+// which is expanded in its place
+enum Parameter {
+    Value(String),
+    Word(String),
+}
+
+#[derive(PartialEq)]
+enum Trim {
+    Prefix,
+    Suffix,
+}
+
+// Index of the `)` or `}` matching the `(` or `{` at `open`, skipping quoted text
+fn closing_bracket(input: &str, open: usize) -> Option<usize> {
+    let (open_ch, close_ch) = match input[open..].chars().next() {
+        Some('{') => ('{', '}'),
+        _ => ('(', ')'),
+    };
+
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (i, ch) in input[open..].char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+
+        match (quote, ch) {
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => escaped = true,
+            (Some(q), _) if ch == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"' | '`') => quote = Some(ch),
+            (None, _) if ch == open_ch => depth += 1,
+            (None, _) if ch == close_ch => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(open + i);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+fn closing_backtick(input: &str, from: usize) -> Option<usize> {
+    let mut escaped = false;
+
+    for (i, ch) in input[from..].char_indices() {
+        match ch {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '`' => return Some(from + i),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod expand_tests {
+    use super::*;
+
+    fn shell() -> Shell {
+        let mut shell = Shell::default();
+        shell.variables.insert("NAME".to_string(), "wörld".to_string());
+        shell
+    }
+
+    #[test]
+    fn test_quote_removal() {
+        let mut shell = shell();
+
+        assert_eq!(shell.expand_word("'$NAME'").unwrap(), ["$NAME"]);
+        assert_eq!(shell.expand_word("\"$NAME and\\\"more\"").unwrap(), ["wörld and\"more"]);
+        assert_eq!(shell.expand_word("\\$NAME").unwrap(), ["$NAME"]);
+        assert_eq!(shell.expand_word("\"\"").unwrap(), [""]);
+        assert!(shell.expand_word("$UNSET").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_variable_splitting() {
+        let mut shell = shell();
+        shell.variables.insert("LIST".to_string(), " a  b ".to_string());
+        shell.variables.insert("EMPTY".to_string(), String::new());
+        shell.exit_status = 3;
+
+        assert_eq!(shell.expand_word("$LIST").unwrap(), ["a", "b"]);
+        assert_eq!(shell.expand_word("\"$LIST\"").unwrap(), [" a  b "]);
+        assert_eq!(shell.expand_word("x${LIST}y").unwrap(), ["x", "a", "b", "y"]);
+        assert_eq!(shell.expand_word("[$?]").unwrap(), ["[3]"]);
+        assert!(shell.expand_word("$EMPTY").unwrap().is_empty());
+        assert_eq!(shell.expand_word("\"$EMPTY\"").unwrap(), [""]);
+        let words = ["$LIST", "$UNSET", "c"].map(String::from);
+        assert_eq!(shell.expand_words(words).unwrap(), ["a", "b", "c"]);
+
+        // Assignments are never split
+        assert_eq!(shell.execute("COPY=$LIST"), Ok(0));
+        assert_eq!(shell.variables["COPY"], " a  b ");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_substitution_splitting() {
+        let mut shell = shell();
+
+        assert_eq!(shell.expand_word("$(printf 'a  b\n\n')").unwrap(), ["a", "b"]);
+        assert_eq!(shell.expand_word("\"$(printf 'a  b\n\n')\"").unwrap(), ["a  b"]);
+        assert_eq!(shell.expand_word("x$(echo 1 2)y").unwrap(), ["x1", "2y"]);
+        assert_eq!(shell.expand_word("`echo hi`").unwrap(), ["hi"]);
+        assert!(shell.expand_word("$(true)").unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_nested_command_substitution() {
+        let mut shell = shell();
+
+        assert_eq!(shell.expand_word("$(echo $(echo hi) there)").unwrap(), ["hi", "there"]);
+        assert_eq!(shell.expand_word("\"$(echo \"a ) b\")\"").unwrap(), ["a ) b"]);
+        assert_eq!(shell.expand_string("[$(echo $NAME)]").unwrap(), "[wörld]");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_substitution_sets_status() {
+        let mut shell = shell();
+
+        shell.expand_word("$(false)").unwrap();
+        assert_eq!(shell.exit_status, 1);
+
+        assert_eq!(shell.execute("X=$(echo 1   2 | tr 1 9)"), Ok(0));
+        assert_eq!(shell.variables["X"], "9 2");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_substitution_is_isolated() {
+        let mut shell = shell();
+        let start = shell.current_dir.clone();
+
+        // An `exit` ends only the substitution, whose status it sets
+        assert_eq!(shell.execute("X=$(echo out; exit 4)"), Ok(4));
+        assert_eq!(shell.variables["X"], "out");
+
+        let script = "X=$(cd /; y=5; set -e; alias a=echo; f() { :; }; pwd)";
+        assert_eq!(shell.execute(script), Ok(0));
+        assert_eq!(shell.variables["X"], "/");
+        assert_eq!(shell.current_dir, start);
+        assert!(!shell.variables.contains_key("y"));
+        assert!(!shell.options.errexit);
+        assert!(shell.aliases.is_empty());
+        assert!(!shell.functions.contains_key("f"));
+    }
+
+    #[test]
+    fn test_arithmetic_expansion() {
+        let mut shell = shell();
+        shell.variables.insert("x".to_string(), "5".to_string());
+
+        assert_eq!(shell.expand_word("$((2 + 3 * 4))").unwrap(), ["14"]);
+        assert_eq!(shell.expand_word("\"n=$((x * 2)) $(($x ** 2))\"").unwrap(), ["n=10 25"]);
+        assert_eq!(shell.expand_string("$(( (x + 1) > 5 ))").unwrap(), "1");
+
+        assert_eq!(shell.execute("y=$((x % 3))"), Ok(0));
+        assert_eq!(shell.variables["y"], "2");
+    }
+
+    #[test]
+    fn test_division_by_zero_fails_command() {
+        let mut shell = shell();
+
+        assert_eq!(shell.expand_word("$((1 / 0))"), Err(ErrorKind::InvalidInput));
+        assert_eq!(shell.exit_status, 1);
+
+        // The command is not run, so the assignment never happens
+        assert_eq!(shell.execute("z=$((1 / 0))"), Ok(1));
+        assert!(!shell.variables.contains_key("z"));
+    }
+
+    #[test]
+    fn test_parameter_defaults() {
+        let mut shell = shell();
+        shell.variables.insert("EMPTY".to_string(), String::new());
+
+        assert_eq!(shell.expand_word("${NAME}s").unwrap(), ["wörlds"]);
+        assert_eq!(shell.expand_word("${UNSET:-\"a b\"}").unwrap(), ["a b"]);
+        assert_eq!(shell.expand_word("${EMPTY:-$NAME}").unwrap(), ["wörld"]);
+        assert!(shell.expand_word("${EMPTY-$NAME}").unwrap().is_empty());
+        assert_eq!(shell.expand_word("${NAME:+set}").unwrap(), ["set"]);
+        assert!(shell.expand_word("${EMPTY:+set}").unwrap().is_empty());
+        assert_eq!(shell.expand_word("${EMPTY+set}").unwrap(), ["set"]);
+        assert_eq!(shell.expand_word("\"${UNSET:-}\"").unwrap(), [""]);
+    }
+
+    #[test]
+    fn test_parameter_assignment() {
+        let mut shell = shell();
+
+        assert_eq!(shell.expand_word("${NEW:=fresh}").unwrap(), ["fresh"]);
+        assert_eq!(shell.variables["NEW"], "fresh");
+        assert_eq!(shell.expand_word("${NEW:=other}").unwrap(), ["fresh"]);
+
+        assert_eq!(shell.execute("echo ${1:=x}"), Ok(1));
+    }
+
+    #[test]
+    fn test_parameter_error() {
+        let mut shell = shell();
+
+        assert_eq!(shell.expand_word("${NAME:?missing}").unwrap(), ["wörld"]);
+        assert_eq!(shell.expand_word("${UNSET:?missing}"), Err(ErrorKind::InvalidInput));
+        assert_eq!(shell.exit_status, 1);
+
+        // A script stops there
+        let output = shell.execute_capture("X=${UNSET?}; echo after").unwrap();
+        assert_eq!(output.stdout, b"");
+        assert_eq!(output.status, 1);
+
+        // An interactive shell fails the command but keeps going
+        shell.interactive = true;
+        assert_eq!(shell.execute("X=${UNSET?}; Y=after"), Ok(0));
+        assert!(!shell.variables.contains_key("X"));
+        assert_eq!(shell.variables["Y"], "after");
+        shell.interactive = false;
+
+        assert_eq!(shell.expand_word("${NAME;}"), Err(ErrorKind::InvalidInput));
+    }
+
+    #[test]
+    fn test_length_and_substring() {
+        let mut shell = shell();
+        shell.variables.insert("n".to_string(), "2".to_string());
+
+        assert_eq!(shell.expand_word("${#NAME}").unwrap(), ["5"]);
+        assert_eq!(shell.expand_word("${#UNSET}").unwrap(), ["0"]);
+        assert_eq!(shell.expand_word("${NAME:1}").unwrap(), ["örld"]);
+        assert_eq!(shell.expand_word("${NAME:1:n}").unwrap(), ["ör"]);
+        assert_eq!(shell.expand_word("${NAME: -3}").unwrap(), ["rld"]);
+        assert_eq!(shell.expand_word("${NAME:(-3):1}").unwrap(), ["r"]);
+        assert_eq!(shell.expand_word("${NAME:1:-1}").unwrap(), ["örl"]);
+        assert_eq!(shell.expand_word("${NAME:0:99}").unwrap(), ["wörld"]);
+        assert!(shell.expand_word("${NAME:9}").unwrap().is_empty());
+        assert!(shell.expand_word("${NAME: -9}").unwrap().is_empty());
+
+        assert_eq!(shell.expand_word("${NAME:3:-3}"), Err(ErrorKind::InvalidInput));
+        assert_eq!(shell.exit_status, 1);
+    }
+
+    #[test]
+    fn test_pattern_removal() {
+        let mut shell = shell();
+        shell.variables.insert("path".to_string(), "/usr/lib/file.tar.gz".to_string());
+
+        assert_eq!(shell.expand_word("${path#*/}").unwrap(), ["usr/lib/file.tar.gz"]);
+        assert_eq!(shell.expand_word("${path##*/}").unwrap(), ["file.tar.gz"]);
+        assert_eq!(shell.expand_word("${path%.*}").unwrap(), ["/usr/lib/file.tar"]);
+        assert_eq!(shell.expand_word("${path%%.*}").unwrap(), ["/usr/lib/file"]);
+        assert_eq!(shell.expand_word("${path%/*}").unwrap(), ["/usr/lib"]);
+        assert_eq!(shell.expand_word("${path#/nope}").unwrap(), ["/usr/lib/file.tar.gz"]);
+        assert_eq!(shell.expand_word("${NAME#w?}").unwrap(), ["rld"]);
+
+        // Quoted glob characters only match themselves
+        shell.variables.insert("star".to_string(), "a*b".to_string());
+        assert_eq!(shell.expand_word("${star#\"a*\"}").unwrap(), ["b"]);
+        assert_eq!(shell.expand_word("${path#\"*/\"}").unwrap(), ["/usr/lib/file.tar.gz"]);
+    }
+
+    #[test]
+    fn test_pattern_replacement() {
+        let mut shell = shell();
+        shell.variables.insert("path".to_string(), "/home/me/src/me".to_string());
+
+        assert_eq!(shell.expand_word("${path/me/you}").unwrap(), ["/hoyou/me/src/me"]);
+        assert_eq!(shell.expand_word("${path//me/you}").unwrap(), ["/hoyou/you/src/you"]);
+        assert_eq!(shell.expand_word("${path//\\//:}").unwrap(), [":home:me:src:me"]);
+        assert_eq!(shell.expand_word("${path/#\\/home/H}").unwrap(), ["H/me/src/me"]);
+        assert_eq!(shell.expand_word("${path/%me/it}").unwrap(), ["/home/me/src/it"]);
+        assert_eq!(shell.expand_word("${path/#x/y}").unwrap(), ["/home/me/src/me"]);
+        assert_eq!(shell.expand_word("${path/m*/X}").unwrap(), ["/hoX"]);
+        assert_eq!(shell.expand_word("${path//[eo]}").unwrap(), ["/hm/m/src/m"]);
+        assert_eq!(shell.expand_word("\"${NAME/#/hello }\"").unwrap(), ["hello wörld"]);
+        assert_eq!(shell.expand_word("${NAME//}").unwrap(), ["wörld"]);
+    }
+
+    #[test]
+    fn test_special_parameters() {
+        let mut shell = shell();
+        let pid = std::process::id().to_string();
+
+        assert_eq!(shell.expand_word("$$").unwrap(), [pid.as_str()]);
+        assert_eq!(shell.expand_word("${$}x").unwrap(), [format!("{}x", pid)]);
+        assert_eq!(shell.expand_word("$( (echo $$) )").unwrap(), [pid.as_str()]);
+        assert_eq!(shell.expand_word("\"$#\"").unwrap(), ["0"]);
+        assert!(shell.expand_word("$!").unwrap().is_empty());
+        assert_eq!(shell.expand_word("${!-none}").unwrap(), ["none"]);
+
+        shell.last_background = Some(42);
+        shell.positional = vec!["a".to_string(), "b".to_string()];
+        assert_eq!(shell.expand_word("$!:$#").unwrap(), ["42:2"]);
+    }
+
+    #[test]
+    fn test_all_positional_parameters() {
+        let mut shell = Shell {
+            shell_name: "wpcsh".to_string(),
+            positional: vec!["a b".to_string(), "c".to_string()],
+            ..shell()
+        };
+
+        assert_eq!(shell.expand_word("$0").unwrap(), ["wpcsh"]);
+        assert_eq!(shell.expand_word("\"$@\"").unwrap(), ["a b", "c"]);
+        assert_eq!(shell.expand_word("x\"$@\"y").unwrap(), ["xa b", "cy"]);
+        assert_eq!(shell.expand_word("\"$*\"").unwrap(), ["a b c"]);
+        assert_eq!(shell.expand_word("$@").unwrap(), ["a", "b", "c"]);
+
+        shell.variables.insert("IFS".to_string(), ":".to_string());
+        assert_eq!(shell.expand_word("\"$*\"").unwrap(), ["a b:c"]);
+
+        // With no parameters "$@" is no word at all, but "$*" is an empty one
+        shell.positional.clear();
+        assert!(shell.expand_word("\"$@\"").unwrap().is_empty());
+        assert_eq!(shell.expand_word("\"$*\"").unwrap(), [""]);
+        assert_eq!(shell.expand_word("\"\"").unwrap(), [""]);
+    }
+
+    #[test]
+    fn test_filename_globbing() {
+        let mut shell = shell();
+        let dir = std::env::temp_dir().join(format!("wpcsh-{}-expand-glob", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["one.rs", "two.rs", "*.rs"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        let base = dir.to_string_lossy().into_owned();
+        shell.variables.insert("DIR".to_string(), base.clone());
+        shell.variables.insert("PAT".to_string(), "t*".to_string());
+
+        let path = |name: &str| format!("{}/{}", base, name);
+        assert_eq!(shell.expand_word("$DIR/[ot]*.rs").unwrap(), [path("one.rs"), path("two.rs")]);
+        assert_eq!(shell.expand_word("$DIR/$PAT").unwrap(), [path("two.rs")]);
+
+        // Quoted or escaped glob characters are literal, and so is a pattern with no match
+        assert_eq!(shell.expand_word("\"$DIR/*.rs\"").unwrap(), [path("*.rs")]);
+        assert_eq!(shell.expand_word("$DIR/\\*.rs").unwrap(), [path("*.rs")]);
+        assert_eq!(shell.expand_word("$DIR/*.none").unwrap(), [path("*.none")]);
+        assert_eq!(shell.expand_word("'a\\b'").unwrap(), ["a\\b"]);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
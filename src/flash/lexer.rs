@@ -5,12 +5,15 @@
  * under GNU General Public License v3.0.
  */
 
+use std::rc::Rc;
+
 /// Token types that can be produced by the lexer
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenKind {
     Word(String),
     Assignment,               // =
     Pipe,                     // |
+    PipeBoth,                 // |&
     Semicolon,                // ;
     DoubleSemicolon,          // ;;
     Newline,                  // \n
@@ -24,6 +27,11 @@ pub enum TokenKind {
     Less,                     // <
     Great,                    // >
     DGreat,                   // >>
+    AndGreat,                 // &>
+    GreatAnd,                 // >&
+    LessAnd,                  // <&
+    Clobber,                  // >|
+    AndDGreat,                // &>>
     Dollar,                   // $
     Quote,                    // "
     SingleQuote,              // '
@@ -40,6 +48,7 @@ pub enum TokenKind {
     HereDocDash,              // <<- followed by delimiter
     HereDocContent(String),   // Content of here-document
     HereString,               // <<<
+    IoNumber(String),         // file descriptor directly before a redirection, as in 2>
     ExtGlob(char),            // For ?(, *(, +(, @(, !(
     // Shell control flow keywords
     If,   // if keyword
@@ -117,28 +126,42 @@ fn is_special_char(ch: char) -> bool {
 fn is_word_terminator(ch: char) -> bool {
     matches!(
         ch,
-        '=' | '|' | ';' | '\n' | '&' | '(' | ')' | '<' | '>' | '$' | '"' | '\'' | '`' | '#'
+        '=' | '|' | ';' | '\n' | '&' | '(' | ')' | '<' | '>' | '$' | '"' | '\'' | '`'
     )
 }
 
+// A `#` only starts a comment at the beginning of a word, so `a#b` and `"a"#b` are words
+fn starts_comment(previous: char) -> bool {
+    previous.is_ascii_whitespace() || matches!(previous, ';' | '|' | '&' | '(' | '<' | '>')
+}
+
 /// Lexer that converts input text into tokens
 #[derive(Clone)]
 #[derive(Debug)]
 pub struct Lexer {
-    input: Vec<char>,
+    // Shared so cloning the lexer to peek ahead does not copy the input
+    input: Rc<str>,
+    // Byte offsets of the current character and of the one after it
     pub position: usize,
     read_position: usize,
     ch: char,
     line: usize,
     column: usize,
     in_quotes: Option<char>,
-    quote_after_cmdsubst: Option<char>,
+    // Quote to resume and the index of the `)` that closes a `$(` opened inside it
+    quote_after_cmdsubst: Option<(char, usize)>,
+    // Set after << or <<- so the next token reads the delimiter and body; true strips tabs
+    pending_heredoc: Option<bool>,
+    // A here-document body ran to the end of the input without its delimiter line
+    open_heredoc: bool,
+    // Inside `$'...'`, whose backslash escapes are decoded while lexing
+    ansi_c_quote: bool,
 }
 
 impl Lexer {
     pub fn new(input: &str) -> Self {
         let mut lexer = Self {
-            input: input.chars().collect(),
+            input: Rc::from(input),
             position: 0,
             read_position: 0,
             ch: '\0',
@@ -146,58 +169,81 @@ impl Lexer {
             column: 0,
             in_quotes: None,
             quote_after_cmdsubst: None,
+            pending_heredoc: None,
+            open_heredoc: false,
+            ansi_c_quote: false,
         };
         lexer.read_char();
         lexer
     }
 
+    /// Whether the input read so far left a quote open
+    pub fn in_quotes(&self) -> bool {
+        self.in_quotes.is_some()
+    }
+
+    /// Whether the input read so far ends inside a here-document
+    pub fn in_heredoc(&self) -> bool {
+        self.open_heredoc
+    }
+
     fn read_char(&mut self) {
-        if self.read_position >= self.input.len() {
-            self.ch = '\0';
-        } else {
-            self.ch = self.input[self.read_position];
-        }
+        self.ch = self.peek_char();
         self.position = self.read_position;
-        self.read_position += 1;
+        self.read_position += self.ch.len_utf8();
         self.column += 1;
     }
 
     pub fn peek_char(&self) -> char {
-        if self.read_position >= self.input.len() {
-            '\0'
-        } else {
-            self.input[self.read_position]
-        }
+        self.rest_after().chars().next().unwrap_or('\0')
+    }
+
+    // The input after the current character
+    fn rest_after(&self) -> &str {
+        self.input.get(self.read_position..).unwrap_or("")
+    }
+
+    // Whether `text` comes right after the current character
+    fn followed_by(&self, text: &str) -> bool {
+        self.rest_after().starts_with(text)
+    }
+
+    fn previous_char(&self) -> Option<char> {
+        self.input.get(..self.position)?.chars().next_back()
+    }
+
+    // Steps back so the character before the current one is read again next
+    fn unread_char(&mut self) {
+        let width = self.previous_char().map_or(1, char::len_utf8);
+        self.read_position = self.position;
+        self.position -= width;
+        self.column -= 1;
     }
 
     // check if the current position is followed by whitespace or a special character
     fn is_word_boundary(&self) -> bool {
         let peek = self.peek_char();
-        peek.is_whitespace() || is_special_char(peek) || peek == '\0'
+        peek.is_ascii_whitespace() || is_special_char(peek) || peek == '\0'
     }
 
     pub fn peek_next_token(&mut self) -> Token {
-        // Save the current state
-        let saved_position = self.position;
-        let saved_read_position = self.read_position;
-        let saved_ch = self.ch;
-        let saved_line = self.line;
-        let saved_column = self.column;
+        // Here-documents consume input, so save the whole lexer state
+        let saved = self.clone();
 
         // Get the next token
         let token = self.next_token();
 
         // Restore the saved state
-        self.position = saved_position;
-        self.read_position = saved_read_position;
-        self.ch = saved_ch;
-        self.line = saved_line;
-        self.column = saved_column;
+        *self = saved;
 
         token
     }
 
     pub fn next_token(&mut self) -> Token {
+        if let Some(strip_tabs) = self.pending_heredoc.take() {
+            return self.read_heredoc(strip_tabs);
+        }
+
         if self.in_quotes.is_none() {
             self.skip_whitespace();
         }
@@ -235,20 +281,25 @@ impl Lexer {
             };
 
             self.in_quotes = None; // Clear the in_quotes state
+            self.ansi_c_quote = false;
             self.read_char();
             return token;
-        } else if self.in_quotes.is_some() {
+        } else if self.in_quotes.is_some() && self.ch != '\0' {
             // We're inside quotes, but check for command substitution first
-            if self.ch == '$' && self.peek_char() == '(' {
+            if self.ansi_c_quote {
+                return self.read_ansi_c_content();
+            } else if self.ch == '$' && self.peek_char() == '(' {
                 // Handle command substitution even inside quotes
                 // Save the quote state and temporarily exit quote mode
-                self.quote_after_cmdsubst = self.in_quotes;
+                let end = self.expansion_end();
+                let value = self.source_until(end);
+                self.quote_after_cmdsubst = self.in_quotes.map(|quote| (quote, end));
                 self.in_quotes = None;
                 self.read_char(); // Consume the '('
                 self.read_char(); // Advance to the next character (like the end of method does)
                 return Token {
                     kind: TokenKind::CmdSubst,
-                    value: "$(".to_string(),
+                    value,
                     position: current_position,
                 };
             } else {
@@ -271,6 +322,13 @@ impl Lexer {
                         value: "||".to_string(),
                         position: current_position,
                     }
+                } else if self.peek_char() == '&' {
+                    self.read_char();
+                    Token {
+                        kind: TokenKind::PipeBoth,
+                        value: "|&".to_string(),
+                        position: current_position,
+                    }
                 } else {
                     Token {
                         kind: TokenKind::Pipe,
@@ -303,6 +361,22 @@ impl Lexer {
                         value: "&&".to_string(),
                         position: current_position,
                     }
+                } else if self.peek_char() == '>' {
+                    self.read_char(); // Consume '>'
+                    if self.peek_char() == '>' {
+                        self.read_char(); // Consume second '>'
+                        Token {
+                            kind: TokenKind::AndDGreat,
+                            value: "&>>".to_string(),
+                            position: current_position,
+                        }
+                    } else {
+                        Token {
+                            kind: TokenKind::AndGreat,
+                            value: "&>".to_string(),
+                            position: current_position,
+                        }
+                    }
                 } else {
                     Token {
                         kind: TokenKind::Background,
@@ -339,7 +413,9 @@ impl Lexer {
             }
             ')' => {
                 // Check if we need to restore quote state after command substitution
-                if let Some(quote_char) = self.quote_after_cmdsubst {
+                if let Some((quote_char, end)) = self.quote_after_cmdsubst
+                    && end == self.position
+                {
                     self.in_quotes = Some(quote_char);
                     self.quote_after_cmdsubst = None;
                 }
@@ -389,6 +465,7 @@ impl Lexer {
                     } else if self.peek_char() == '-' {
                         // Here document with dash <<-
                         self.read_char(); // Consume '-'
+                        self.pending_heredoc = Some(true);
                         Token {
                             kind: TokenKind::HereDocDash,
                             value: "<<-".to_string(),
@@ -396,12 +473,20 @@ impl Lexer {
                         }
                     } else {
                         // Regular here document <<
+                        self.pending_heredoc = Some(false);
                         Token {
                             kind: TokenKind::HereDoc,
                             value: "<<".to_string(),
                             position: current_position,
                         }
                     }
+                } else if self.peek_char() == '&' {
+                    self.read_char(); // Consume '&'
+                    Token {
+                        kind: TokenKind::LessAnd,
+                        value: "<&".to_string(),
+                        position: current_position,
+                    }
                 } else {
                     Token {
                         kind: TokenKind::Less,
@@ -411,7 +496,21 @@ impl Lexer {
                 }
             }
             '>' => {
-                if self.peek_char() == '>' {
+                if self.peek_char() == '|' {
+                    self.read_char(); // Consume '|'
+                    Token {
+                        kind: TokenKind::Clobber,
+                        value: ">|".to_string(),
+                        position: current_position,
+                    }
+                } else if self.peek_char() == '&' {
+                    self.read_char(); // Consume '&'
+                    Token {
+                        kind: TokenKind::GreatAnd,
+                        value: ">&".to_string(),
+                        position: current_position,
+                    }
+                } else if self.peek_char() == '>' {
                     self.read_char();
                     Token {
                         kind: TokenKind::DGreat,
@@ -434,6 +533,12 @@ impl Lexer {
                     }
                 }
             }
+            // `$!` is the last background process, not history expansion
+            '!' if self.previous_char() == Some('$') => Token {
+                kind: TokenKind::Word("!".to_string()),
+                value: "!".to_string(),
+                position: current_position,
+            },
             '!' => {
                 // Check for != operator
                 if self.peek_char() == '=' {
@@ -502,30 +607,50 @@ impl Lexer {
                 // Check for arithmetic expansion $(( syntax
                 if self.peek_char() == '(' {
                     // Look ahead to see if it's $(( for arithmetic expansion
-                    if self.position + 2 < self.input.len() && self.input[self.position + 2] == '('
-                    {
+                    if self.followed_by("((") {
+                        // Like $(, the value is the whole source text
+                        let value = self.source_until(self.expansion_end());
                         self.read_char(); // Consume first '('
                         self.read_char(); // Consume second '('
                         Token {
                             kind: TokenKind::ArithSubst,
-                            value: "$((".to_string(),
+                            value,
                             position: current_position,
                         }
                     } else {
-                        // Regular command substitution $(
+                        // Regular command substitution $(, whose value is the whole source text
+                        let value = self.source_until(self.expansion_end());
                         self.read_char(); // Consume the '('
                         Token {
                             kind: TokenKind::CmdSubst,
-                            value: "$(".to_string(),
+                            value,
                             position: current_position,
                         }
                     }
                 } else if self.peek_char() == '{' {
-                    // Parameter expansion ${
-                    self.read_char(); // Consume the '{'
+                    // Parameter expansion ${...}, read whole with its source text as the value
+                    let end = self.expansion_end();
+                    let value = self.source_until(end);
+                    while self.position < end && self.peek_char() != '\0' {
+                        if self.ch == '\n' {
+                            self.line += 1;
+                            self.column = 0;
+                        }
+                        self.read_char();
+                    }
                     Token {
                         kind: TokenKind::ParamExpansion,
-                        value: "${".to_string(),
+                        value,
+                        position: current_position,
+                    }
+                } else if self.peek_char() == '\'' {
+                    // $'...' lexes like a single-quoted string with its escapes decoded
+                    self.read_char(); // Move onto the quote
+                    self.in_quotes = Some('\'');
+                    self.ansi_c_quote = true;
+                    Token {
+                        kind: TokenKind::SingleQuote,
+                        value: "$'".to_string(),
                         position: current_position,
                     }
                 } else {
@@ -546,11 +671,16 @@ impl Lexer {
                 value: "'".to_string(),
                 position: current_position,
             },
-            '`' => Token {
-                kind: TokenKind::Backtick,
-                value: "`".to_string(),
+            '`' => self.read_backticks(),
+            // `$#` is the argument count, not a comment
+            '#' if self.previous_char() == Some('$') => Token {
+                kind: TokenKind::Word("#".to_string()),
+                value: "#".to_string(),
                 position: current_position,
             },
+            '#' if self.previous_char().is_some_and(|previous| !starts_comment(previous)) => {
+                self.read_word()
+            }
             '#' => self.read_comment(),
             '\0' => Token {
                 kind: TokenKind::EOF,
@@ -559,12 +689,7 @@ impl Lexer {
             },
             't' => {
                 // Check for "then" keyword
-                if self.peek_char() == 'h'
-                    && self.position + 3 < self.input.len()
-                    && self.input[self.position + 1] == 'h'
-                    && self.input[self.position + 2] == 'e'
-                    && self.input[self.position + 3] == 'n'
-                {
+                if self.followed_by("hen") {
                     self.read_char(); // 'h'
                     self.read_char(); // 'e'
                     self.read_char(); // 'n'
@@ -589,7 +714,7 @@ impl Lexer {
             }
             'e' => {
                 // Check for "else", "elif", or "export" keywords
-                if self.peek_char() == 'l' && self.position + 3 < self.input.len() {
+                if self.peek_char() == 'l' {
                     self.read_char(); // 'l'
 
                     if self.peek_char() == 's' {
@@ -652,14 +777,7 @@ impl Lexer {
                         self.ch = 'e';
                         self.read_word()
                     }
-                } else if self.position + 5 < self.input.len()
-                    && self.peek_char() == 'x'
-                    && self.input[self.position + 1] == 'x'
-                    && self.input[self.position + 2] == 'p'
-                    && self.input[self.position + 3] == 'o'
-                    && self.input[self.position + 4] == 'r'
-                    && self.input[self.position + 5] == 't'
-                {
+                } else if self.followed_by("xport") {
                     self.read_char(); // 'x'
                     self.read_char(); // 'p'
                     self.read_char(); // 'o'
@@ -686,7 +804,7 @@ impl Lexer {
             }
             'f' => {
                 // Check for "fi" keyword
-                if self.peek_char() == 'i' && self.position + 1 < self.input.len() {
+                if self.peek_char() == 'i' {
                     self.read_char(); // Consume 'i'
                     if self.is_word_boundary() {
                         Token {
@@ -702,16 +820,7 @@ impl Lexer {
                         self.ch = 'f';
                         self.read_word()
                     }
-                } else if self.position + 7 < self.input.len()
-                    && self.peek_char() == 'u'
-                    && self.input[self.position + 1] == 'u'
-                    && self.input[self.position + 2] == 'n'
-                    && self.input[self.position + 3] == 'c'
-                    && self.input[self.position + 4] == 't'
-                    && self.input[self.position + 5] == 'i'
-                    && self.input[self.position + 6] == 'o'
-                    && self.input[self.position + 7] == 'n'
-                {
+                } else if self.followed_by("unction") {
                     // Check for "function" keyword
                     self.read_char(); // 'u'
                     self.read_char(); // 'n'
@@ -735,11 +844,7 @@ impl Lexer {
                         self.ch = 'f';
                         self.read_word()
                     }
-                } else if self.position + 2 < self.input.len()
-                    && self.peek_char() == 'o'
-                    && self.input[self.position + 1] == 'o'
-                    && self.input[self.position + 2] == 'r'
-                {
+                } else if self.followed_by("or") {
                     // Check for "for" keyword
                     self.read_char(); // 'o'
                     self.read_char(); // 'r'
@@ -764,13 +869,7 @@ impl Lexer {
             }
             'u' => {
                 // Check for "until" keyword
-                if self.position + 4 < self.input.len()
-                    && self.peek_char() == 'n'
-                    && self.input[self.position + 1] == 'n'
-                    && self.input[self.position + 2] == 't'
-                    && self.input[self.position + 3] == 'i'
-                    && self.input[self.position + 4] == 'l'
-                {
+                if self.followed_by("ntil") {
                     self.read_char(); // 'n'
                     self.read_char(); // 't'
                     self.read_char(); // 'i'
@@ -796,14 +895,7 @@ impl Lexer {
             }
             'r' => {
                 // Check for "return" keyword
-                if self.position + 5 < self.input.len()
-                    && self.peek_char() == 'e'
-                    && self.input[self.position + 1] == 'e'
-                    && self.input[self.position + 2] == 't'
-                    && self.input[self.position + 3] == 'u'
-                    && self.input[self.position + 4] == 'r'
-                    && self.input[self.position + 5] == 'n'
-                {
+                if self.followed_by("eturn") {
                     self.read_char(); // 'e'
                     self.read_char(); // 't'
                     self.read_char(); // 'u'
@@ -830,13 +922,7 @@ impl Lexer {
             }
             'w' => {
                 // Check for "while" keyword
-                if self.position + 4 < self.input.len()
-                    && self.peek_char() == 'h'
-                    && self.input[self.position + 1] == 'h'
-                    && self.input[self.position + 2] == 'i'
-                    && self.input[self.position + 3] == 'l'
-                    && self.input[self.position + 4] == 'e'
-                {
+                if self.followed_by("hile") {
                     self.read_char(); // 'h'
                     self.read_char(); // 'i'
                     self.read_char(); // 'l'
@@ -862,14 +948,10 @@ impl Lexer {
             }
             'd' => {
                 // Check for "do" or "done" keywords
-                if self.peek_char() == 'o' && self.position + 1 < self.input.len() {
+                if self.peek_char() == 'o' {
                     self.read_char(); // 'o'
 
-                    if self.peek_char() == 'n'
-                        && self.position + 2 < self.input.len()
-                        && self.input[self.position + 1] == 'n'
-                        && self.input[self.position + 2] == 'e'
-                    {
+                    if self.followed_by("ne") {
                         self.read_char(); // 'n'
                         self.read_char(); // 'e'
 
@@ -907,13 +989,7 @@ impl Lexer {
             }
             'b' => {
                 // Check for "break" keyword
-                if self.position + 4 < self.input.len()
-                    && self.peek_char() == 'r'
-                    && self.input[self.position + 1] == 'r'
-                    && self.input[self.position + 2] == 'e'
-                    && self.input[self.position + 3] == 'a'
-                    && self.input[self.position + 4] == 'k'
-                {
+                if self.followed_by("reak") {
                     self.read_char(); // 'r'
                     self.read_char(); // 'e'
                     self.read_char(); // 'a'
@@ -939,16 +1015,7 @@ impl Lexer {
             }
             'c' => {
                 // Check for "continue" keyword
-                if self.position + 7 < self.input.len()
-                    && self.peek_char() == 'o'
-                    && self.input[self.position + 1] == 'o'
-                    && self.input[self.position + 2] == 'n'
-                    && self.input[self.position + 3] == 't'
-                    && self.input[self.position + 4] == 'i'
-                    && self.input[self.position + 5] == 'n'
-                    && self.input[self.position + 6] == 'u'
-                    && self.input[self.position + 7] == 'e'
-                {
+                if self.followed_by("ontinue") {
                     self.read_char(); // 'o'
                     self.read_char(); // 'n'
                     self.read_char(); // 't'
@@ -977,7 +1044,7 @@ impl Lexer {
             }
             'i' => {
                 // Check for "if" keyword
-                if self.peek_char() == 'f' && self.position + 1 < self.input.len() {
+                if self.peek_char() == 'f' {
                     self.read_char(); // Consume 'f'
                     if self.is_word_boundary() {
                         Token {
@@ -993,10 +1060,7 @@ impl Lexer {
                         self.ch = 'i';
                         self.read_word()
                     }
-                } else if self.position + 1 < self.input.len() &&
-            // check in
-           self.peek_char() == 'n'
-                {
+                } else if self.peek_char() == 'n' {
                     self.read_char(); // 'n'
 
                     if self.is_word_boundary() {
@@ -1017,6 +1081,18 @@ impl Lexer {
                     self.read_word()
                 }
             }
+            '0'..='9' if self.is_io_number() => {
+                let mut digits = self.ch.to_string();
+                while self.peek_char().is_ascii_digit() {
+                    self.read_char();
+                    digits.push(self.ch);
+                }
+                Token {
+                    kind: TokenKind::IoNumber(digits.clone()),
+                    value: digits,
+                    position: current_position,
+                }
+            }
             _ => self.read_word(),
         };
 
@@ -1027,6 +1103,185 @@ impl Lexer {
         token
     }
 
+    // Index of the `)` or `}` closing the `$(` or `${` at the current position, or the end
+    // of input. Quotes and backslashes are skipped so brackets inside them do not count.
+    fn expansion_end(&self) -> usize {
+        // Every delimiter is ASCII, so the bytes of other characters never match one
+        let (open, close) = match self.peek_char() {
+            '{' => (b'{', b'}'),
+            _ => (b'(', b')'),
+        };
+        let bytes = self.input.as_bytes();
+        let mut depth = 0;
+        let mut quote = None;
+        let mut i = self.position + 1;
+
+        while i < bytes.len() {
+            let ch = bytes[i];
+            match quote {
+                Some(q) if ch == q => quote = None,
+                Some(b'\'') => {}
+                Some(_) if ch == b'\\' => i += 1,
+                Some(_) => {}
+                None => match ch {
+                    b'\\' => i += 1,
+                    b'\'' | b'"' | b'`' => quote = Some(ch),
+                    _ if ch == open => depth += 1,
+                    _ if ch == close => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return i;
+                        }
+                    }
+                    _ => {}
+                },
+            }
+            i += 1;
+        }
+
+        self.input.len()
+    }
+
+    fn source_until(&self, end: usize) -> String {
+        let end = (end + 1).min(self.input.len());
+        self.input[self.position..end].to_string()
+    }
+
+    // Reads a `...` command substitution as one word, keeping the backticks for expansion
+    fn read_backticks(&mut self) -> Token {
+        let position = Position::new(self.line, self.column);
+        let mut raw = String::from('`');
+        self.read_char(); // Skip the opening backtick
+
+        while self.ch != '`' && self.ch != '\0' {
+            if self.ch == '\\' && self.peek_char() == '`' {
+                raw.push(self.ch);
+                self.read_char();
+            }
+            if self.ch == '\n' {
+                self.line += 1;
+                self.column = 0;
+            }
+            raw.push(self.ch);
+            self.read_char();
+        }
+
+        // The closing backtick is consumed with the rest of the token
+        if self.ch == '`' {
+            raw.push('`');
+        }
+
+        Token {
+            kind: TokenKind::Word(raw.clone()),
+            value: raw,
+            position,
+        }
+    }
+
+    // Reads the delimiter after << and removes the following body lines from the input.
+    // The token value keeps the delimiter as written so callers can tell if it was quoted.
+    fn read_heredoc(&mut self, strip_tabs: bool) -> Token {
+        self.skip_whitespace();
+        let position = Position::new(self.line, self.column);
+
+        let mut delimiter = String::new();
+        let mut raw = String::new();
+
+        while !self.ch.is_ascii_whitespace()
+            && self.ch != '\0'
+            && !matches!(self.ch, ';' | '|' | '&' | '<' | '>' | '(' | ')')
+        {
+            match self.ch {
+                '\'' | '"' => {
+                    let quote = self.ch;
+                    raw.push(quote);
+                    self.read_char();
+                    while self.ch != quote && self.ch != '\0' {
+                        delimiter.push(self.ch);
+                        raw.push(self.ch);
+                        self.read_char();
+                    }
+                    if self.ch == quote {
+                        raw.push(quote);
+                        self.read_char();
+                    }
+                }
+                '\\' => {
+                    raw.push(self.ch);
+                    self.read_char();
+                    if self.ch != '\0' {
+                        delimiter.push(self.ch);
+                        raw.push(self.ch);
+                        self.read_char();
+                    }
+                }
+                c => {
+                    delimiter.push(c);
+                    raw.push(c);
+                    self.read_char();
+                }
+            }
+        }
+
+        let mut body = String::new();
+        let mut closed = false;
+
+        // The body starts on the line after the one holding the operator
+        let line_end = self
+            .input
+            .get(self.position..)
+            .and_then(|rest| rest.find('\n'))
+            .map(|offset| self.position + offset);
+
+        if let Some(line_end) = line_end {
+            let body_start = line_end + 1;
+            let mut cursor = body_start;
+            let mut end = self.input.len();
+
+            while cursor < self.input.len() {
+                let next_newline = self.input[cursor..].find('\n').map(|offset| cursor + offset);
+                let line_stop = next_newline.unwrap_or(self.input.len());
+                let after = next_newline.map_or(self.input.len(), |newline| newline + 1);
+
+                let mut line = &self.input[cursor..line_stop];
+                if strip_tabs {
+                    line = line.trim_start_matches('\t');
+                }
+
+                if line == delimiter {
+                    end = after;
+                    closed = true;
+                    break;
+                }
+
+                body.push_str(line);
+                body.push('\n');
+                cursor = after;
+            }
+
+            self.input = [&self.input[..body_start], &self.input[end..]].concat().into();
+        }
+        self.open_heredoc |= !closed;
+
+        Token {
+            kind: TokenKind::HereDocContent(body),
+            value: raw,
+            position,
+        }
+    }
+
+    // A run of digits is a file descriptor only when a redirection follows immediately
+    fn is_io_number(&self) -> bool {
+        let rest = self.input.get(self.position..).unwrap_or("").as_bytes();
+        let digits = rest.iter().take_while(|c| c.is_ascii_digit()).count();
+
+        match &rest[digits..] {
+            [b'>', b'(', ..] | [b'<', b'(' | b'<', ..] => false,
+            [b'<' | b'>', ..] => true,
+            _ => false,
+        }
+    }
+
     fn read_word(&mut self) -> Token {
         let position = Position::new(self.line, self.column);
         let mut word = String::new();
@@ -1060,7 +1315,7 @@ impl Lexer {
 
                 // After finding the closing parenthesis, continue reading
                 // any suffixes (like ".txt") that should be part of the pattern
-                while !self.ch.is_whitespace() && self.ch != '\0' && !is_word_terminator(self.ch) {
+                while !self.ch.is_ascii_whitespace() && self.ch != '\0' && !is_word_terminator(self.ch) {
                     word.push(self.ch);
                     self.read_char();
                 }
@@ -1074,7 +1329,7 @@ impl Lexer {
         }
 
         // Read word characters, including glob patterns but handling braces carefully
-        while !self.ch.is_whitespace() && self.ch != '\0' {
+        while !self.ch.is_ascii_whitespace() && self.ch != '\0' {
             // Handle special case for '=' in command line arguments first
             if self.ch == '=' && word.starts_with('-') {
                 // For command line arguments like --option=value, include the = as part of the word
@@ -1082,7 +1337,7 @@ impl Lexer {
                 self.read_char();
 
                 // Continue reading the value part
-                while !self.ch.is_whitespace() && self.ch != '\0' && !is_word_terminator(self.ch) {
+                while !self.ch.is_ascii_whitespace() && self.ch != '\0' && !is_word_terminator(self.ch) {
                     word.push(self.ch);
                     self.read_char();
                 }
@@ -1099,13 +1354,20 @@ impl Lexer {
                 word.push(self.ch);
                 self.read_char();
 
-                // Read until matching closing brace
+                // Read until matching closing brace; quoted alternatives may contain spaces
                 let mut depth = 1;
-                while depth > 0 && self.ch != '\0' && !self.ch.is_whitespace() {
-                    if self.ch == '{' {
-                        depth += 1;
-                    } else if self.ch == '}' {
-                        depth -= 1;
+                let mut quote = None;
+                while depth > 0
+                    && self.ch != '\0'
+                    && (quote.is_some() || !self.ch.is_ascii_whitespace())
+                {
+                    match (quote, self.ch) {
+                        (Some(q), ch) if ch == q => quote = None,
+                        (Some(_), _) => {}
+                        (None, '\'' | '"') => quote = Some(self.ch),
+                        (None, '{') => depth += 1,
+                        (None, '}') => depth -= 1,
+                        _ => {}
                     }
                     word.push(self.ch);
                     self.read_char();
@@ -1127,7 +1389,16 @@ impl Lexer {
                 }
 
                 // Read until closing bracket
-                while self.ch != ']' && self.ch != '\0' && !self.ch.is_whitespace() {
+                while self.ch != ']' && self.ch != '\0' && !self.ch.is_ascii_whitespace() {
+                    // A `${name[i]}` is read whole, so its `]` does not close the class
+                    if self.ch == '$' && self.peek_char() == '{' {
+                        let end = self.expansion_end();
+                        while self.position <= end && self.ch != '\0' {
+                            word.push(self.ch);
+                            self.read_char();
+                        }
+                        continue;
+                    }
                     word.push(self.ch);
                     self.read_char();
                 }
@@ -1142,8 +1413,14 @@ impl Lexer {
             else if self.ch == '\\' {
                 // Look at the next character
                 let next_ch = self.peek_char();
-                if next_ch != '\0' {
-                    // Skip the backslash and add the escaped character
+                if next_ch == '\n' {
+                    self.skip_line_continuation();
+                } else if next_ch != '\0' {
+                    // Characters that expansion treats specially keep their backslash,
+                    // and quote removal drops it later
+                    if matches!(next_ch, '$' | '`' | '"' | '\'' | '\\') {
+                        word.push(self.ch);
+                    }
                     self.read_char(); // Skip the backslash
                     word.push(self.ch); // Add the escaped character
                     self.read_char(); // Move past the escaped character
@@ -1162,9 +1439,7 @@ impl Lexer {
 
         // We moved ahead one character, so step back
         if self.position > 0 {
-            self.position -= 1;
-            self.read_position -= 1;
-            self.column -= 1;
+            self.unread_char();
         }
 
         // Check for keywords after reading the full word
@@ -1205,35 +1480,37 @@ impl Lexer {
             return false;
         }
 
-        // Look ahead to see if this matches a brace expansion pattern
+        // Look ahead to see if this matches a brace expansion pattern. Only ASCII bytes
+        // matter, and those never occur inside other characters.
+        let bytes = self.input.as_bytes();
         let mut pos = self.position + 1;
         let mut found_dots = false;
         let mut found_comma = false;
         let mut brace_count = 1;
         let mut in_quotes = false;
-        let mut quote_char = '\0';
+        let mut quote_char = 0;
 
-        while pos < self.input.len() && brace_count > 0 {
-            let ch = self.input[pos];
+        while pos < bytes.len() && brace_count > 0 {
+            let ch = bytes[pos];
 
             // Handle quotes - don't count commas inside quotes
-            if !in_quotes && (ch == '"' || ch == '\'') {
+            if !in_quotes && (ch == b'"' || ch == b'\'') {
                 in_quotes = true;
                 quote_char = ch;
             } else if in_quotes && ch == quote_char {
                 in_quotes = false;
             } else if !in_quotes {
                 match ch {
-                    '{' => brace_count += 1,
-                    '}' => brace_count -= 1,
-                    '.' if pos + 1 < self.input.len() && self.input[pos + 1] == '.' => {
+                    b'{' => brace_count += 1,
+                    b'}' => brace_count -= 1,
+                    b'.' if bytes.get(pos + 1) == Some(&b'.') => {
                         found_dots = true;
                         pos += 1; // Skip the second dot
                     }
-                    ',' => found_comma = true,
+                    b',' => found_comma = true,
                     // If we find certain characters that indicate this is likely a command block,
                     // not a brace expansion, return false early
-                    ';' | '\n' | '|' | '&' => return false,
+                    b';' | b'\n' | b'|' | b'&' => return false,
                     _ => {}
                 }
             }
@@ -1258,9 +1535,7 @@ impl Lexer {
 
         // We moved ahead one character, so step back
         if self.position > 0 {
-            self.position -= 1;
-            self.read_position -= 1;
-            self.column -= 1;
+            self.unread_char();
         }
 
         Token {
@@ -1277,6 +1552,24 @@ impl Lexer {
 
         // Keep reading until we hit the closing quote or EOF
         while self.ch != quote_char && self.ch != '\0' {
+            // A $(...) or ${...} may itself contain quotes, so copy it whole
+            if quote_char == '"' && self.ch == '$' && matches!(self.peek_char(), '(' | '{') {
+                let end = self.expansion_end();
+                while self.position <= end && self.ch != '\0' {
+                    if self.ch == '\n' {
+                        self.line += 1;
+                        self.column = 0;
+                    }
+                    content.push(self.ch);
+                    self.read_char();
+                }
+                continue;
+            }
+
+            if quote_char == '"' && self.skip_line_continuation() {
+                continue;
+            }
+
             // Handle escaped quotes
             if self.ch == '\\' && self.peek_char() == quote_char {
                 self.read_char(); // Skip the backslash
@@ -1298,12 +1591,111 @@ impl Lexer {
         }
     }
 
-    fn skip_whitespace(&mut self) {
-        while self.ch.is_whitespace() && self.ch != '\n' {
+    // The body of $'...' up to its closing quote, with C-style escapes such as \n, \t,
+    // \xHH and \0NNN turned into the characters they stand for
+    fn read_ansi_c_content(&mut self) -> Token {
+        let position = Position::new(self.line, self.column);
+        let mut content = String::new();
+
+        while self.ch != '\'' && self.ch != '\0' {
+            if self.ch == '\n' {
+                self.line += 1;
+                self.column = 0;
+            }
+
+            if self.ch != '\\' {
+                content.push(self.ch);
+                self.read_char();
+                continue;
+            }
+
+            self.read_char(); // Skip the backslash
+            let decoded = match self.ch {
+                'n' => Some('\n'),
+                't' => Some('\t'),
+                'r' => Some('\r'),
+                'a' => Some('\x07'),
+                'b' => Some('\x08'),
+                'e' | 'E' => Some('\x1b'),
+                'f' => Some('\x0c'),
+                'v' => Some('\x0b'),
+                '\\' | '\'' | '"' | '?' => Some(self.ch),
+                'x' => self.read_escape_number(0, 16, 2),
+                '0'..='7' => {
+                    let first = self.ch.to_digit(8).unwrap_or(0);
+                    self.read_escape_number(first, 8, if first == 0 { 3 } else { 2 })
+                }
+                '\0' => {
+                    content.push('\\');
+                    break;
+                }
+                _ => None,
+            };
+
+            match decoded {
+                // A NUL cannot be passed in an argument, so it is dropped
+                Some('\0') => {}
+                Some(ch) => content.push(ch),
+                // Unknown escapes, and \x without digits, are kept as written
+                None => {
+                    content.push('\\');
+                    content.push(self.ch);
+                }
+            }
+            self.read_char();
+        }
+
+        Token {
+            kind: TokenKind::Word(content.clone()),
+            value: content,
+            position,
+        }
+    }
+
+    // Reads up to `max` more digits after the current character, leaving the last one
+    // current. None when there are none to read and `value` has no digits of its own.
+    fn read_escape_number(&mut self, mut value: u32, radix: u32, max: usize) -> Option<char> {
+        let has_digit = self.ch.is_digit(radix);
+        let mut read = 0;
+
+        while read < max
+            && let Some(digit) = self.peek_char().to_digit(radix)
+        {
             self.read_char();
+            value = value * radix + digit;
+            read += 1;
+        }
+
+        if read == 0 && !has_digit {
+            return None;
+        }
+        char::from_u32(value)
+    }
+
+    fn skip_whitespace(&mut self) {
+        loop {
+            while self.ch.is_ascii_whitespace() && self.ch != '\n' {
+                self.read_char();
+            }
+            if !self.skip_line_continuation() {
+                break;
+            }
         }
     }
 
+    // A backslash right before a newline joins the two lines, so both are dropped
+    fn skip_line_continuation(&mut self) -> bool {
+        if self.ch != '\\' || self.peek_char() != '\n' {
+            return false;
+        }
+
+        self.read_char(); // Move onto the newline
+        self.line += 1;
+        self.column = 0;
+        self.read_char();
+        true
+    }
+
     // Parse parameter expansion content after ${
     pub fn read_parameter_expansion(&mut self) -> Vec<Token> {
         let mut tokens = Vec::new();
@@ -1336,9 +1728,7 @@ impl Lexer {
                 });
             } else {
                 // This might be prefix removal, put # back for later processing
-                self.position -= 1;
-                self.read_position -= 1;
-                self.column -= 1;
+                self.unread_char();
                 self.ch = '#';
             }
         }
@@ -1415,7 +1805,7 @@ impl Lexer {
 
         // Read the rest of the content until }
         while self.ch != '}' && self.ch != '\0' {
-            if self.ch.is_whitespace() {
+            if self.ch.is_ascii_whitespace() {
                 self.skip_whitespace();
                 continue;
             }
@@ -1748,14 +2138,98 @@ mod lexer_tests {
             TokenKind::Word("ls".to_string()),
             TokenKind::Great,
             TokenKind::Word("output.txt".to_string()),
-            TokenKind::Word("2".to_string()),
-            TokenKind::Great,
-            TokenKind::Background,
+            TokenKind::IoNumber("2".to_string()),
+            TokenKind::GreatAnd,
             TokenKind::Word("1".to_string()),
         ];
         test_tokens(input, expected);
     }
 
+    #[test]
+    fn test_stderr_redirection() {
+        test_tokens(
+            "echo hi 2>/dev/null",
+            vec![
+                TokenKind::Word("echo".to_string()),
+                TokenKind::Word("hi".to_string()),
+                TokenKind::IoNumber("2".to_string()),
+                TokenKind::Great,
+                TokenKind::Word("/dev/null".to_string()),
+            ],
+        );
+
+        // Digits only name a descriptor when the operator follows immediately
+        test_tokens(
+            "echo 2 > out",
+            vec![
+                TokenKind::Word("echo".to_string()),
+                TokenKind::Word("2".to_string()),
+                TokenKind::Great,
+                TokenKind::Word("out".to_string()),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_clobber_redirection() {
+        test_tokens(
+            "echo hi >| out | cat",
+            vec![
+                TokenKind::Word("echo".to_string()),
+                TokenKind::Word("hi".to_string()),
+                TokenKind::Clobber,
+                TokenKind::Word("out".to_string()),
+                TokenKind::Pipe,
+                TokenKind::Word("cat".to_string()),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_combined_output_redirection() {
+        test_tokens(
+            "cmd &> out.txt &>>log &",
+            vec![
+                TokenKind::Word("cmd".to_string()),
+                TokenKind::AndGreat,
+                TokenKind::Word("out.txt".to_string()),
+                TokenKind::AndDGreat,
+                TokenKind::Word("log".to_string()),
+                TokenKind::Background,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_heredoc_body() {
+        test_tokens(
+            "cat <<EOF > out\nhello $USER\n  indented\nEOF\necho ok",
+            vec![
+                TokenKind::Word("cat".to_string()),
+                TokenKind::HereDoc,
+                TokenKind::HereDocContent("hello $USER\n  indented\n".to_string()),
+                TokenKind::Great,
+                TokenKind::Word("out".to_string()),
+                TokenKind::Newline,
+                TokenKind::Word("echo".to_string()),
+                TokenKind::Word("ok".to_string()),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_heredoc_dash_and_quoted_delimiter() {
+        let mut lexer = Lexer::new("cat <<-'END'\n\tkeep $x\n\tEND\n");
+        assert_eq!(lexer.next_token().kind, TokenKind::Word("cat".to_string()));
+        assert_eq!(lexer.next_token().kind, TokenKind::HereDocDash);
+
+        let content = lexer.next_token();
+        assert_eq!(content.kind, TokenKind::HereDocContent("keep $x\n".to_string()));
+        assert_eq!(content.value, "'END'");
+        assert_eq!(lexer.next_token().kind, TokenKind::Newline);
+        assert_eq!(lexer.next_token().kind, TokenKind::EOF);
+    }
+
     #[test]
     fn test_quoted_strings() {
         let input = r#"echo "hello world" 'rio de janeiro'"#;
@@ -1798,6 +2272,58 @@ mod lexer_tests {
         test_tokens(input, expected);
     }
 
+    #[test]
+    fn test_command_substitution_keeps_source() {
+        let mut lexer = Lexer::new("echo $(a \"b)\" $(c)) d");
+        lexer.next_token();
+
+        let subst = lexer.next_token();
+        assert_eq!(subst.kind, TokenKind::CmdSubst);
+        assert_eq!(subst.value, "$(a \"b)\" $(c))");
+    }
+
+    #[test]
+    fn test_parameter_expansion_keeps_source() {
+        let mut lexer = Lexer::new("echo ${a:-${b} \"}\"}x ${c#*/}");
+        lexer.next_token();
+
+        let first = lexer.next_token();
+        assert_eq!(first.kind, TokenKind::ParamExpansion);
+        assert_eq!(first.value, "${a:-${b} \"}\"}");
+        assert_eq!(lexer.next_token().kind, TokenKind::Word("x".to_string()));
+        assert_eq!(lexer.next_token().value, "${c#*/}");
+        assert_eq!(lexer.next_token().kind, TokenKind::EOF);
+    }
+
+    #[test]
+    fn test_argument_count_is_not_a_comment() {
+        let input = "echo $# # done";
+        let expected = vec![
+            TokenKind::Word("echo".to_string()),
+            TokenKind::Dollar,
+            TokenKind::Word("#".to_string()),
+            TokenKind::Comment,
+        ];
+        test_tokens(input, expected);
+    }
+
+    #[test]
+    fn test_nested_command_substitution_in_quotes() {
+        let input = "\"$(echo $(date)) now\"";
+        let expected = vec![
+            TokenKind::Quote,
+            TokenKind::CmdSubst,
+            TokenKind::Word("echo".to_string()),
+            TokenKind::CmdSubst,
+            TokenKind::Word("date".to_string()),
+            TokenKind::RParen,
+            TokenKind::RParen,
+            TokenKind::Word(" now".to_string()),
+            TokenKind::Quote,
+        ];
+        test_tokens(input, expected);
+    }
+
     #[test]
     fn test_command_substitution_on_variable_with_quotes() {
         let input = "NUMBER=\"$(echo 85)\"";
@@ -2635,9 +3161,8 @@ mod lexer_tests {
             TokenKind::Word("input.txt".to_string()),
             TokenKind::Great,
             TokenKind::Word("output.txt".to_string()),
-            TokenKind::Word("2".to_string()),
-            TokenKind::Great,
-            TokenKind::Background,
+            TokenKind::IoNumber("2".to_string()),
+            TokenKind::GreatAnd,
             TokenKind::Word("1".to_string()),
             TokenKind::DGreat,
             TokenKind::Word("append.log".to_string()),
@@ -2650,10 +3175,7 @@ mod lexer_tests {
         let input = "echo `date +%Y`";
         let expected = vec![
             TokenKind::Word("echo".to_string()),
-            TokenKind::Backtick,
-            TokenKind::Word("date".to_string()),
-            TokenKind::Word("+%Y".to_string()),
-            TokenKind::Backtick,
+            TokenKind::Word("`date +%Y`".to_string()),
         ];
         test_tokens(input, expected);
     }
@@ -2720,8 +3242,6 @@ mod lexer_tests {
             TokenKind::Dollar,
             TokenKind::Word("HOME".to_string()),
             TokenKind::ParamExpansion,
-            TokenKind::Word("USER".to_string()),
-            TokenKind::RBrace,
             TokenKind::ArithSubst,
             TokenKind::Word("2+3".to_string()),
             TokenKind::RParen,
@@ -2737,14 +3257,10 @@ mod lexer_tests {
         let input = "echo ${array[0]} ${array[@]} ${#array[@]}";
         let expected = vec![
             TokenKind::Word("echo".to_string()),
+            // ${...} is read whole, so the # in ${#array[@]} is not a comment
             TokenKind::ParamExpansion,
-            TokenKind::Word("array[0]".to_string()),
-            TokenKind::RBrace,
             TokenKind::ParamExpansion,
-            TokenKind::Word("array[@]".to_string()),
-            TokenKind::RBrace,
             TokenKind::ParamExpansion,
-            TokenKind::Comment,
         ];
         test_tokens(input, expected);
     }
@@ -2780,6 +3296,20 @@ mod lexer_tests {
         test_tokens(input, expected);
     }
 
+    #[test]
+    fn test_pipe_both() {
+        let input = "make |& tee log | wc";
+        let expected = vec![
+            TokenKind::Word("make".to_string()),
+            TokenKind::PipeBoth,
+            TokenKind::Word("tee".to_string()),
+            TokenKind::Word("log".to_string()),
+            TokenKind::Pipe,
+            TokenKind::Word("wc".to_string()),
+        ];
+        test_tokens(input, expected);
+    }
+
     #[test]
     fn test_pipeline_with_multiple_commands() {
         let input = "cat file.txt | grep pattern | sort | uniq -c | head -10";
@@ -2874,6 +3404,21 @@ mod lexer_tests {
         test_tokens(input, expected);
     }
 
+    #[test]
+    fn test_hash_inside_words() {
+        let input = "echo a#b \"q\"#x # rest; of line";
+        let expected = vec![
+            TokenKind::Word("echo".to_string()),
+            TokenKind::Word("a#b".to_string()),
+            TokenKind::Quote,
+            TokenKind::Word("q".to_string()),
+            TokenKind::Quote,
+            TokenKind::Word("#x".to_string()),
+            TokenKind::Comment,
+        ];
+        test_tokens(input, expected);
+    }
+
     #[test]
     fn test_only_whitespace() {
         let input = "   \t  \t   ";
@@ -3239,4 +3784,113 @@ mod lexer_tests {
         ];
         test_tokens(input, expected);
     }
+
+    #[test]
+    fn test_multibyte_words() {
+        let input = "echo héllo wörld 日本語 🎉";
+        let expected = vec![
+            TokenKind::Word("echo".to_string()),
+            TokenKind::Word("héllo".to_string()),
+            TokenKind::Word("wörld".to_string()),
+            TokenKind::Word("日本語".to_string()),
+            TokenKind::Word("🎉".to_string()),
+        ];
+        test_tokens(input, expected);
+    }
+
+    #[test]
+    fn test_multibyte_quoted_strings() {
+        let input = "echo \"ça va 😀\" 'naïve'";
+        let expected = vec![
+            TokenKind::Word("echo".to_string()),
+            TokenKind::Quote,
+            TokenKind::Word("ça va 😀".to_string()),
+            TokenKind::Quote,
+            TokenKind::SingleQuote,
+            TokenKind::Word("naïve".to_string()),
+            TokenKind::SingleQuote,
+        ];
+        test_tokens(input, expected);
+    }
+
+    #[test]
+    fn test_unicode_spaces_do_not_split_words() {
+        // Only ASCII blanks separate words, like in POSIX shells
+        let input = "echo 日本\u{3000}語 a\u{a0}b";
+        let expected = vec![
+            TokenKind::Word("echo".to_string()),
+            TokenKind::Word("日本\u{3000}語".to_string()),
+            TokenKind::Word("a\u{a0}b".to_string()),
+        ];
+        test_tokens(input, expected);
+    }
+
+    #[test]
+    fn test_keywords_followed_by_multibyte() {
+        let input = "ifé doné fi";
+        let expected = vec![
+            TokenKind::Word("ifé".to_string()),
+            TokenKind::Word("doné".to_string()),
+            TokenKind::Fi,
+        ];
+        test_tokens(input, expected);
+    }
+
+    #[test]
+    fn test_multibyte_position_tracking() {
+        let tokens = collect_tokens("日本 語");
+
+        assert_eq!(tokens[0].position.column, 1);
+        assert_eq!(tokens[1].position.column, 4);
+    }
+
+    #[test]
+    fn test_multibyte_lookahead() {
+        // Substitutions, here-documents and comments find their end past multibyte text
+        let tokens = collect_tokens("é${ü:-ö} $(echo 日本) é#x ü #ñ\ncat <<É\nñ\nÉ\n");
+        let values: Vec<&str> = tokens.iter().map(|token| token.value.as_str()).collect();
+        assert_eq!(
+            values,
+            [
+                "é", "${ü:-ö}", "$(echo 日本)", "echo", "日本", ")", "é#x", "ü", "#ñ", "\n",
+                "cat", "<<", "É", "\n", ""
+            ]
+        );
+        assert_eq!(tokens[12].kind, TokenKind::HereDocContent("ñ\n".to_string()));
+    }
+
+    #[test]
+    fn test_line_continuation() {
+        let input = "echo a \\\n  b\\\nc \"d\\\ne\"";
+        let expected = vec![
+            TokenKind::Word("echo".to_string()),
+            TokenKind::Word("a".to_string()),
+            TokenKind::Word("bc".to_string()),
+            TokenKind::Quote,
+            TokenKind::Word("de".to_string()),
+            TokenKind::Quote,
+        ];
+        test_tokens(input, expected);
+
+        let tokens = collect_tokens("a \\\nb");
+        assert_eq!(tokens[1].position.line, 2);
+    }
+
+    #[test]
+    fn test_ansi_c_quoting() {
+        let input = r"echo $'a\tb\n' $'it\'s \x41\101\0102 \q' '$\t'";
+        let expected = vec![
+            TokenKind::Word("echo".to_string()),
+            TokenKind::SingleQuote,
+            TokenKind::Word("a\tb\n".to_string()),
+            TokenKind::SingleQuote,
+            TokenKind::SingleQuote,
+            TokenKind::Word("it's AAB \\q".to_string()),
+            TokenKind::SingleQuote,
+            TokenKind::SingleQuote,
+            TokenKind::Word("$\\t".to_string()),
+            TokenKind::SingleQuote,
+        ];
+        test_tokens(input, expected);
+    }
 }
@@ -1,2 +1,4 @@
-﻿pub mod lexer;
+﻿#[allow(dead_code, clippy::upper_case_acronyms)]
+pub mod lexer;
+#[allow(dead_code)]
 pub mod parser;
\ No newline at end of file
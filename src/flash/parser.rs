@@ -40,6 +40,7 @@ pub enum Node {
     },
     Subshell {
         list: Box<Node>,
+        redirects: Vec<Redirect>,
     },
     Comment(String),
     StringLiteral(String),
@@ -118,6 +119,7 @@ pub enum Node {
     },
     Group {
         list: Box<Node>,
+        redirects: Vec<Redirect>,
     },
     ParameterExpansion {
         parameter: String,
@@ -142,12 +144,17 @@ pub enum ParameterExpansionType {
     Assign(String),                      // ${var:=default}
     Error(String),                       // ${var:?error}
     Alternative(String),                 // ${var:+alternative}
+    UnsetDefault(String),                // ${var-default}
+    UnsetAssign(String),                 // ${var=default}
+    UnsetError(String),                  // ${var?error}
+    UnsetAlternative(String),            // ${var+alternative}
     Length,                              // ${#var}
     RemoveSmallestPrefix(String),        // ${var#pattern}
     RemoveLargestPrefix(String),         // ${var##pattern}
     RemoveSmallestSuffix(String),        // ${var%pattern}
     RemoveLargestSuffix(String),         // ${var%%pattern}
-    Substring(Option<i32>, Option<i32>), // ${var:offset:length}
+    Substring(String, Option<String>),   // ${var:offset:length}
+    Replace(ReplaceScope, String, String), // ${var/pattern/replacement}
     Indirect,                            // ${!var}
     ArrayAll,                            // ${array[@]}
     ArrayStar,                           // ${array[*]}
@@ -155,6 +162,15 @@ pub enum ParameterExpansionType {
     ArrayIndex(String),                  // ${array[index]}
 }
 
+/// Which matches `${var/pattern/replacement}` replaces
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplaceScope {
+    First,  // ${var/pattern/replacement}
+    All,    // ${var//pattern/replacement}
+    Prefix, // ${var/#pattern/replacement}
+    Suffix, // ${var/%pattern/replacement}
+}
+
 /// Case pattern for case statements
 #[derive(Debug, Clone, PartialEq)]
 pub struct CasePattern {
@@ -166,6 +182,7 @@ pub struct CasePattern {
 #[derive(Debug, Clone, PartialEq)]
 pub struct Redirect {
     pub kind: RedirectKind,
+    pub fd: Option<u32>, // explicit descriptor before the operator, as in 2>
     pub file: String,
 }
 
@@ -173,19 +190,315 @@ pub struct Redirect {
 pub enum RedirectKind {
     Input,       // <
     Output,      // >
+    OutputForce, // >|
     Append,      // >>
+    ErrorOutput, // 2>
+    ErrorAppend, // 2>>
+    OutputBoth,  // &>
+    AppendBoth,  // &>>
     HereDoc,     // <<
     HereDocDash, // <<-
+    HereDocLiteral, // <<'EOF', body is not expanded
     HereString,  // <<<
     InputDup,    // <&
     OutputDup,   // >&
 }
 
+// Adds `statement` to a list. One that follows the last without an operator, like the
+// command after a prefix assignment, is joined to it by "". A comment ends the line
+// instead, so it never takes a prefix.
+fn push_statement(statements: &mut Vec<Node>, operators: &mut Vec<String>, statement: Node) {
+    let joiner = match statement {
+        Node::Comment(_) => ";",
+        _ => "",
+    };
+    operators.resize(operators.len().max(statements.len()), joiner.to_string());
+    statements.push(statement);
+}
+
+// Appends `piece` to the last argument when it was written right after it
+fn push_piece(args: &mut Vec<String>, piece: &str, glued: bool) {
+    match args.last_mut() {
+        Some(last) if glued => last.push_str(piece),
+        _ => args.push(piece.to_string()),
+    }
+}
+
+// The text of an assignment value in the form `Shell::expand_string` reads, for joining
+// it with the pieces written after it. Substitutions are kept as their `source` text.
+fn assignment_text(value: &Node, source: &str) -> String {
+    match value {
+        Node::StringLiteral(text) => text.clone(),
+        Node::SingleQuotedString(text) => text
+            .replace('\\', "\\\\")
+            .replace('$', "\\$")
+            .replace('`', "\\`"),
+        _ => source.to_string(),
+    }
+}
+
+// Quoted arguments keep their quotes when expansion has to know about them: to skip
+// expanding, to avoid splitting the result into words, or to match glob characters literally
+fn quote_word(content: String, quote: char) -> String {
+    // An empty quoted string is still an argument, or part of one
+    if content.is_empty() {
+        return format!("{quote}{quote}");
+    }
+
+    if !content.contains(['$', '`', '"', '\'', '\\', '~', '*', '?', '[']) {
+        return content;
+    }
+
+    always_quote(content, quote)
+}
+
+// Writes quoted text back with its quotes, as the operands of `[[ ]]` need them to tell a
+// quoted regex character from one that is not
+fn always_quote(content: String, quote: char) -> String {
+    // A quote decoded from $'\'' has to be written outside the single quotes
+    if quote == '\'' {
+        return format!("'{}'", content.replace('\'', r"'\''"));
+    }
+
+    // Quotes inside $(...) and ${...} belong to the inner word and are not escaped
+    let mut quoted = String::from('"');
+    let mut depth = 0;
+    let mut chars = content.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '$' if matches!(chars.peek(), Some('(' | '{')) => {
+                quoted.push(ch);
+                quoted.extend(chars.next());
+                depth += 1;
+                continue;
+            }
+            '(' | '{' if depth > 0 => depth += 1,
+            ')' | '}' if depth > 0 => depth -= 1,
+            '"' if depth == 0 => quoted.push('\\'),
+            _ => {}
+        }
+        quoted.push(ch);
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+/// Splits the text inside `${...}` into the parameter name and what to do with it.
+/// Returns None when the text is malformed, which the shell reports as a bad substitution.
+pub fn parse_parameter(inner: &str) -> Option<(String, ParameterExpansionType)> {
+    // ${#name} is the length, while ${#} on its own is the argument count
+    if let Some(name) = inner.strip_prefix('#')
+        && !name.is_empty()
+    {
+        return (parameter_name_len(name)? == name.len())
+            .then(|| (name.to_string(), ParameterExpansionType::Length));
+    }
+
+    let (name, rest) = inner.split_at(parameter_name_len(inner)?);
+
+    let (colon, rest) = match rest.strip_prefix(':') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    let Some(op) = rest.chars().next() else {
+        return (!colon).then(|| (name.to_string(), ParameterExpansionType::Simple));
+    };
+
+    // With the colon an empty value counts as unset, without it only an unset one does
+    let word = rest[op.len_utf8()..].to_string();
+    let expansion_type = match (op, colon) {
+        ('#' | '%', false) => {
+            let longest = word.starts_with(op);
+            let pattern = if longest { word[1..].to_string() } else { word };
+            match (op, longest) {
+                ('#', false) => ParameterExpansionType::RemoveSmallestPrefix(pattern),
+                ('#', true) => ParameterExpansionType::RemoveLargestPrefix(pattern),
+                (_, false) => ParameterExpansionType::RemoveSmallestSuffix(pattern),
+                (_, true) => ParameterExpansionType::RemoveLargestSuffix(pattern),
+            }
+        }
+        ('-', true) => ParameterExpansionType::Default(word),
+        ('=', true) => ParameterExpansionType::Assign(word),
+        ('?', true) => ParameterExpansionType::Error(word),
+        ('+', true) => ParameterExpansionType::Alternative(word),
+        ('-', false) => ParameterExpansionType::UnsetDefault(word),
+        ('=', false) => ParameterExpansionType::UnsetAssign(word),
+        ('?', false) => ParameterExpansionType::UnsetError(word),
+        ('+', false) => ParameterExpansionType::UnsetAlternative(word),
+        ('/', false) => {
+            let (scope, rest) = match word.chars().next() {
+                Some('/') => (ReplaceScope::All, &word[1..]),
+                Some('#') => (ReplaceScope::Prefix, &word[1..]),
+                Some('%') => (ReplaceScope::Suffix, &word[1..]),
+                _ => (ReplaceScope::First, word.as_str()),
+            };
+            let (pattern, replacement) = match pattern_end(rest) {
+                Some(end) => (&rest[..end], &rest[end + 1..]),
+                None => (rest, ""),
+            };
+            ParameterExpansionType::Replace(scope, pattern.to_string(), replacement.to_string())
+        }
+        // The offset and length are arithmetic, so `${var: -1}` needs its space
+        (_, true) => match rest.split_once(':') {
+            Some((offset, length)) => {
+                ParameterExpansionType::Substring(offset.to_string(), Some(length.to_string()))
+            }
+            None => ParameterExpansionType::Substring(rest.to_string(), None),
+        },
+        _ => return None,
+    };
+
+    Some((name.to_string(), expansion_type))
+}
+
+// Index of the `/` ending the pattern of `${var/pattern/replacement}`, skipping
+// escaped and quoted slashes
+fn pattern_end(text: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (i, ch) in text.char_indices() {
+        match (quote, ch) {
+            _ if escaped => escaped = false,
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => escaped = true,
+            (Some(q), _) if ch == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(ch),
+            (None, '/') => return Some(i),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+// Length of the parameter name at the start of `text`: a variable name with an optional
+// `[subscript]`, a positional number, or one of the special parameters
+fn parameter_name_len(text: &str) -> Option<usize> {
+    let first = text.chars().next()?;
+
+    let len = if first.is_ascii_digit() {
+        text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len())
+    } else if first.is_ascii_alphabetic() || first == '_' {
+        let len =
+            text.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(text.len());
+        match text[len..].starts_with('[') {
+            true => len + text[len..].find(']')? + 1,
+            false => len,
+        }
+    } else if "?#$!@*".contains(first) {
+        1
+    } else {
+        return None;
+    };
+
+    Some(len)
+}
+
+/// Whether `input` stops partway through a command and more lines should be read before
+/// running it: an open quote, an `if`, loop, `case`, group or subshell that is not closed,
+/// a trailing `|`, `&&` or `||`, a here-document still waiting for its delimiter line, or
+/// a backslash at the very end.
+pub fn is_incomplete(input: &str) -> bool {
+    let mut lexer = Lexer::new(input);
+    // Closing tokens still owed, innermost last
+    let mut expected: Vec<TokenKind> = Vec::new();
+    // Keywords and braces only count where a command can start
+    let mut command_start = true;
+    let mut last = TokenKind::EOF;
+
+    loop {
+        let kind = lexer.next_token().kind;
+        if kind == TokenKind::EOF {
+            break;
+        }
+
+        let at_start = std::mem::replace(&mut command_start, false);
+        match kind {
+            TokenKind::If if at_start => {
+                expected.push(TokenKind::Fi);
+                command_start = true;
+            }
+            TokenKind::While | TokenKind::Until if at_start => {
+                expected.push(TokenKind::Done);
+                command_start = true;
+            }
+            TokenKind::For | TokenKind::Select if at_start => expected.push(TokenKind::Done),
+            TokenKind::Case if at_start => expected.push(TokenKind::Esac),
+            TokenKind::LBrace if at_start => {
+                expected.push(TokenKind::RBrace);
+                command_start = true;
+            }
+            TokenKind::Fi | TokenKind::Done | TokenKind::Esac | TokenKind::RBrace if at_start => {
+                expected.pop_if(|owed| *owed == kind);
+            }
+            TokenKind::Then | TokenKind::Else | TokenKind::Elif | TokenKind::Do if at_start => {
+                command_start = true;
+            }
+            // Patterns follow `case ... in`
+            TokenKind::In => command_start = expected.last() == Some(&TokenKind::Esac),
+            TokenKind::LParen
+            | TokenKind::CmdSubst
+            | TokenKind::ProcessSubstIn
+            | TokenKind::ProcessSubstOut
+            | TokenKind::ExtGlob(_) => {
+                expected.push(TokenKind::RParen);
+                command_start = true;
+            }
+            TokenKind::ArithSubst | TokenKind::ArithCommand => {
+                expected.extend([TokenKind::RParen, TokenKind::RParen]);
+            }
+            TokenKind::DoubleLBracket => expected.push(TokenKind::DoubleRBracket),
+            TokenKind::DoubleRBracket => {
+                expected.pop_if(|owed| *owed == TokenKind::DoubleRBracket);
+            }
+            // Also ends a `case` pattern, which has no opening parenthesis
+            TokenKind::RParen => {
+                expected.pop_if(|owed| *owed == TokenKind::RParen);
+                command_start = true;
+            }
+            TokenKind::Semicolon
+            | TokenKind::DoubleSemicolon
+            | TokenKind::Newline
+            | TokenKind::And
+            | TokenKind::Or
+            | TokenKind::Pipe
+            | TokenKind::PipeBoth
+            | TokenKind::Background
+            | TokenKind::History => command_start = true,
+            _ => {}
+        }
+
+        if !matches!(kind, TokenKind::Newline | TokenKind::Comment) {
+            last = kind;
+        }
+    }
+
+    // A final newline, as on a line read from a file, still follows the backslash
+    let body = input.strip_suffix('\n').unwrap_or(input);
+    let trailing_backslashes = body.chars().rev().take_while(|&c| c == '\\').count();
+
+    lexer.in_quotes()
+        || lexer.in_heredoc()
+        || !expected.is_empty()
+        || matches!(
+            last,
+            TokenKind::Pipe | TokenKind::PipeBoth | TokenKind::And | TokenKind::Or
+        )
+        || trailing_backslashes % 2 == 1
+}
+
 /// Parser converts tokens into an AST
 pub struct Parser {
     pub lexer: Lexer,
     pub current_token: Token,
     pub peek_token: Token,
+    // The token consumed last, to tell whether the current one is written right after it
+    previous_token: Token,
 }
 
 impl Parser {
@@ -202,6 +515,11 @@ impl Parser {
                 value: String::new(),
                 position: Position::new(0, 0),
             },
+            previous_token: Token {
+                kind: TokenKind::EOF,
+                value: String::new(),
+                position: Position::new(0, 0),
+            },
         };
 
         parser.next_token();
@@ -210,8 +528,8 @@ impl Parser {
     }
 
     pub fn next_token(&mut self) {
-        self.current_token = self.peek_token.clone();
-        self.peek_token = self.lexer.next_token();
+        let next = std::mem::replace(&mut self.peek_token, self.lexer.next_token());
+        self.previous_token = std::mem::replace(&mut self.current_token, next);
     }
 
     // Function definition: name() { ... }
@@ -245,7 +563,20 @@ impl Parser {
 
         Node::Function {
             name,
-            body: Box::new(body),
+            body: self.function_body(body),
+        }
+    }
+
+    // Redirections after a function's `}` belong to the body, which applies them on every
+    // call, so such a body becomes a group
+    fn function_body(&mut self, body: Node) -> Box<Node> {
+        let redirects = self.parse_trailing_redirects();
+        match redirects.is_empty() {
+            true => Box::new(body),
+            false => Box::new(Node::Group {
+                list: Box::new(body),
+                redirects,
+            }),
         }
     }
 
@@ -288,12 +619,15 @@ impl Parser {
 
         Node::Function {
             name,
-            body: Box::new(body),
+            body: self.function_body(body),
         }
     }
 
     pub fn parse_statement(&mut self) -> Option<Node> {
-        dbg!(&self.current_token);
+        if self.at_compound_command() {
+            return Some(self.parse_compound_stage());
+        }
+
         match self.current_token.kind {
             TokenKind::Function => {
                 // Handle function keyword: function func_name { ... }
@@ -305,7 +639,6 @@ impl Parser {
                 }
             }
             TokenKind::Word(ref word) => {
-                dbg!(&word);
                 // Check for function definition: func_name() { ... }
                 if self.peek_token.kind == TokenKind::LParen {
                     // Use peek_next_token to look two tokens ahead for the ')'
@@ -333,15 +666,8 @@ impl Parser {
                 let command_node = self.parse_command();
                 Some(command_node)
             }
-            TokenKind::If => Some(self.parse_if_statement()),
-            TokenKind::Case => Some(self.parse_case_statement()),
-            TokenKind::For => Some(self.parse_for_loop()),
-            TokenKind::While => Some(self.parse_while_loop()),
-            TokenKind::Until => Some(self.parse_until_loop()),
-            TokenKind::Select => Some(self.parse_select_statement()),
             TokenKind::Elif => Some(self.parse_elif_branch()),
             TokenKind::Else => Some(self.parse_else_branch()),
-            TokenKind::LParen => Some(self.parse_subshell()),
             TokenKind::ArithCommand => Some(self.parse_arithmetic_command()),
             TokenKind::Comment => {
                 let comment = self.current_token.value.clone();
@@ -353,7 +679,13 @@ impl Parser {
             TokenKind::Return => Some(self.parse_return()),
             TokenKind::DoubleLBracket => Some(self.parse_extended_test()),
             TokenKind::History => Some(self.parse_history_expansion()),
-            TokenKind::ParamExpansion => Some(self.parse_parameter_expansion()),
+            TokenKind::ParamExpansion => Some(self.parse_command()),
+            // A command name that is quoted or expanded, as in "echo" hi or $EDITOR file
+            TokenKind::Quote
+            | TokenKind::SingleQuote
+            | TokenKind::Dollar
+            | TokenKind::CmdSubst
+            | TokenKind::ArithSubst => Some(self.parse_command()),
             TokenKind::ProcessSubstIn => {
                 Some(self.parse_process_substitution(ProcessSubstDirection::Input))
             }
@@ -361,26 +693,107 @@ impl Parser {
                 Some(self.parse_process_substitution(ProcessSubstDirection::Output))
             }
             TokenKind::Complete => Some(self.parse_complete()),
+            // break and continue run as builtins
+            TokenKind::Break | TokenKind::Continue => Some(self.parse_command()),
             _ => None,
         }
     }
 
-    // Parse export statement: export VAR=value or export VAR
+    // Whether the current token starts a compound command, which may also be a pipeline stage
+    fn at_compound_command(&self) -> bool {
+        matches!(
+            self.current_token.kind,
+            TokenKind::If
+                | TokenKind::Case
+                | TokenKind::For
+                | TokenKind::While
+                | TokenKind::Until
+                | TokenKind::Select
+                | TokenKind::LParen
+                | TokenKind::LBrace
+        )
+    }
+
+    // Parse a compound command, along with the rest of the pipeline when a `|` follows it
+    fn parse_compound_stage(&mut self) -> Node {
+        let node = match self.current_token.kind {
+            TokenKind::If => self.parse_if_statement(),
+            TokenKind::Case => self.parse_case_statement(),
+            TokenKind::For => self.parse_for_loop(),
+            TokenKind::While => self.parse_while_loop(),
+            TokenKind::Until => self.parse_until_loop(),
+            TokenKind::Select => self.parse_select_statement(),
+            TokenKind::LParen => self.parse_subshell(),
+            _ => self.parse_group(),
+        };
+
+        if !matches!(self.current_token.kind, TokenKind::Pipe | TokenKind::PipeBoth) {
+            return node;
+        }
+
+        // `|&` merges stderr of the whole compound command, so it gets a group to carry the dup
+        let node = match self.current_token.kind {
+            TokenKind::PipeBoth => Node::Group {
+                list: Box::new(Node::List {
+                    statements: vec![node],
+                    operators: Vec::new(),
+                }),
+                redirects: vec![Redirect {
+                    kind: RedirectKind::OutputDup,
+                    fd: Some(2),
+                    file: "1".to_string(),
+                }],
+            },
+            _ => node,
+        };
+        self.next_token(); // Skip the '|'
+
+        let mut commands = vec![node];
+        match self.parse_pipeline_stage() {
+            Node::Pipeline {
+                commands: more_commands,
+            } => commands.extend(more_commands),
+            next_command => commands.push(next_command),
+        }
+        Node::Pipeline { commands }
+    }
+
+    // Parse the command after a `|`, which runs to the end of the pipeline
+    fn parse_pipeline_stage(&mut self) -> Node {
+        match self.at_compound_command() {
+            true => self.parse_compound_stage(),
+            false => self.parse_command(),
+        }
+    }
+
+    // Parse export statement: export VAR=value or export VAR, for one or more variables
     fn parse_export(&mut self) -> Node {
+        // A bare `export` and options such as `export -p` are handled by the builtin
+        match &self.peek_token.kind {
+            TokenKind::Word(word) if !word.starts_with('-') => {}
+            _ => return self.parse_command(),
+        }
+
         self.next_token(); // Skip 'export' keyword
 
-        // Get variable name
-        let name = match &self.current_token.kind {
-            TokenKind::Word(word) => word.clone(),
-            _ => {
-                // Handle error case - return empty export
-                return Node::Export {
-                    name: String::new(),
-                    value: None,
-                };
-            }
-        };
+        // `export A B=1` exports each variable in turn
+        let mut statements = Vec::new();
+        while let TokenKind::Word(name) = &self.current_token.kind {
+            let name = name.clone();
+            statements.push(self.parse_export_item(name));
+        }
+
+        match statements.len() {
+            1 => statements.remove(0),
+            count => Node::List {
+                statements,
+                operators: vec![";".to_string(); count - 1],
+            },
+        }
+    }
 
+    // Parse one VAR=value or VAR of an export statement, starting at its name
+    fn parse_export_item(&mut self, name: String) -> Node {
         self.next_token(); // Skip variable name
 
         // Check if there's an assignment
@@ -396,11 +809,10 @@ impl Parser {
                 };
             }
 
-            // Parse the value (similar to regular assignment)
-            let value = self.parse_assignment_value();
+            // Parse the value as a regular assignment does
             Node::Export {
                 name,
-                value: Some(Box::new(value)),
+                value: Some(self.parse_scalar_value()),
             }
         } else {
             // Export without assignment (export VAR)
@@ -449,6 +861,7 @@ impl Parser {
                 | TokenKind::Newline
                 | TokenKind::EOF
                 | TokenKind::Pipe
+                | TokenKind::PipeBoth
                 | TokenKind::And
                 | TokenKind::Or
         ) {
@@ -468,18 +881,17 @@ impl Parser {
                 }
                 TokenKind::CmdSubst => {
                     // For command substitution in concatenated context, preserve the syntax
-                    let cmd_node = self.parse_command_substitution();
-                    if let Node::CommandSubstitution { .. } = cmd_node {
-                        // We'll need to handle this during evaluation
-                        result.push_str("$(...)"); // Placeholder for now
-                    }
+                    result.push_str(&self.current_token.value.clone());
+                    self.parse_command_substitution();
+                }
+                TokenKind::ParamExpansion => {
+                    result.push_str(&self.current_token.value.clone());
+                    self.next_token();
                 }
                 TokenKind::ArithSubst => {
                     // For arithmetic expansion in concatenated context, preserve the syntax
-                    let arith_node = self.parse_arithmetic_expansion();
-                    if let Node::ArithmeticExpansion { expression } = arith_node {
-                        result.push_str(&format!("$(({expression}))"));
-                    }
+                    result.push_str(&self.current_token.value.clone());
+                    self.parse_arithmetic_expansion();
                 }
                 TokenKind::Dollar => {
                     // Handle variable expansion
@@ -623,10 +1035,18 @@ impl Parser {
 
         let mut quoted_value = String::new();
         while self.current_token.kind != quote_type && self.current_token.kind != TokenKind::EOF {
-            if let TokenKind::Word(word) = &self.current_token.kind {
-                quoted_value.push_str(word);
+            match &self.current_token.kind {
+                TokenKind::Word(word) => {
+                    quoted_value.push_str(word);
+                    self.next_token();
+                }
+                TokenKind::CmdSubst => {
+                    // Keep the source text so it can be expanded later
+                    quoted_value.push_str(&self.current_token.value.clone());
+                    self.parse_command_substitution();
+                }
+                _ => self.next_token(),
             }
-            self.next_token();
         }
 
         if self.current_token.kind == quote_type {
@@ -768,6 +1188,13 @@ impl Parser {
                 // Handle quoted strings
                 self.parse_quoted_string(TokenKind::Quote)
             }
+            TokenKind::SingleQuote => self.parse_quoted_string(TokenKind::SingleQuote),
+            TokenKind::Dollar => Node::StringLiteral(self.parse_variable_reference()),
+            TokenKind::ParamExpansion => {
+                let expr = Node::StringLiteral(self.current_token.value.clone());
+                self.next_token();
+                expr
+            }
             TokenKind::CmdSubst => {
                 // Handle command substitution
                 self.parse_command_substitution()
@@ -798,20 +1225,22 @@ impl Parser {
                 break;
             }
 
+            // A pattern list may start with an optional '('
+            if self.current_token.kind == TokenKind::LParen {
+                self.next_token();
+            }
+
             // Parse pattern(s) - can be multiple patterns separated by |
             let mut pattern_list = Vec::new();
 
-            // Parse the first pattern
-            if let TokenKind::Word(pattern) = &self.current_token.kind {
-                pattern_list.push(pattern.clone());
-                self.next_token();
+            if let Some(pattern) = self.parse_case_pattern() {
+                pattern_list.push(pattern);
 
                 // Check for additional patterns separated by |
                 while self.current_token.kind == TokenKind::Pipe {
                     self.next_token(); // Skip |
-                    if let TokenKind::Word(pattern) = &self.current_token.kind {
-                        pattern_list.push(pattern.clone());
-                        self.next_token();
+                    if let Some(pattern) = self.parse_case_pattern() {
+                        pattern_list.push(pattern);
                     }
                 }
             }
@@ -819,6 +1248,10 @@ impl Parser {
             // Expect )
             if self.current_token.kind == TokenKind::RParen {
                 self.next_token(); // Skip )
+            } else if pattern_list.is_empty() {
+                // Not a pattern at all; skip the token so we cannot loop forever
+                self.next_token();
+                continue;
             }
 
             // Skip any newlines
@@ -827,36 +1260,8 @@ impl Parser {
             }
 
             // Parse the body until we hit ;; or esac
-            // Use a simpler approach - parse until we hit ;; or esac
-            let mut body_statements = Vec::new();
-            let mut body_operators = Vec::new();
-
-            while self.current_token.kind != TokenKind::DoubleSemicolon
-                && self.current_token.kind != TokenKind::Esac
-                && self.current_token.kind != TokenKind::EOF
-            {
-                if let Some(statement) = self.parse_statement() {
-                    body_statements.push(statement);
-
-                    // Handle operators between statements
-                    if self.current_token.kind == TokenKind::Semicolon {
-                        body_operators.push(";".to_string());
-                        self.next_token();
-                    } else if self.current_token.kind == TokenKind::Newline {
-                        body_operators.push("\n".to_string());
-                        self.next_token();
-                    }
-                }
-            }
-
-            let body = if body_statements.len() == 1 && body_operators.is_empty() {
-                body_statements.into_iter().next().unwrap()
-            } else {
-                Node::List {
-                    statements: body_statements,
-                    operators: body_operators,
-                }
-            };
+            let body =
+                self.parse_until_token_kinds(&[TokenKind::DoubleSemicolon, TokenKind::Esac]);
 
             // Skip ;; if present
             if self.current_token.kind == TokenKind::DoubleSemicolon {
@@ -887,6 +1292,32 @@ impl Parser {
         }
     }
 
+    // Reads one case pattern, keeping quotes so quoted glob characters match literally
+    fn parse_case_pattern(&mut self) -> Option<String> {
+        match &self.current_token.kind {
+            TokenKind::Word(word) => {
+                let word = word.clone();
+                self.next_token();
+                Some(word)
+            }
+            TokenKind::Quote => {
+                let content = self.parse_quoted_string_value(TokenKind::Quote);
+                Some(quote_word(content, '"'))
+            }
+            TokenKind::SingleQuote => {
+                let content = self.parse_quoted_string_value(TokenKind::SingleQuote);
+                Some(quote_word(content, '\''))
+            }
+            TokenKind::Dollar => Some(self.parse_variable_reference()),
+            TokenKind::ParamExpansion => {
+                let reference = self.current_token.value.clone();
+                self.next_token();
+                Some(reference)
+            }
+            _ => None,
+        }
+    }
+
     // Parse for loop: for var in list; do ... done
     fn parse_for_loop(&mut self) -> Node {
         self.next_token(); // Skip "for"
@@ -945,7 +1376,7 @@ impl Parser {
         }
     }
 
-    // Parse the iterable part of a for loop (handles brace expansion)
+    // Parse the iterable part of a for loop
     fn parse_for_iterable(&mut self) -> Node {
         let mut elements = Vec::new();
 
@@ -954,102 +1385,42 @@ impl Parser {
             && self.current_token.kind != TokenKind::Do
             && self.current_token.kind != TokenKind::EOF
         {
-            if let TokenKind::Word(word) = &self.current_token.kind {
-                // Check for brace expansion like {1..10} or {a,b,c}
-                if word.starts_with('{')
-                    && word.ends_with('}')
-                    && (word.contains("..") || word.contains(','))
-                {
-                    if let Some(expanded) = self.expand_brace_pattern(word) {
-                        elements.extend(expanded);
-                    } else {
-                        elements.push(word.clone());
-                    }
-                } else {
+            match &self.current_token.kind {
+                // Braces such as {1..10} are expanded by the executor, like other words
+                TokenKind::Word(word) => {
                     elements.push(word.clone());
+                    self.next_token();
                 }
-            }
-            self.next_token();
-        }
-
-        Node::Array { elements }
-    }
-
-    // Expand brace patterns like {1..10}, {a..z}, or {a,b,c}
-    fn expand_brace_pattern(&self, word: &str) -> Option<Vec<String>> {
-        if !word.starts_with('{') || !word.ends_with('}') {
-            return None;
-        }
-
-        let inner = &word[1..word.len() - 1]; // Remove { and }
-
-        // Check for range expansion first
-        if inner.contains("..") {
-            return self.expand_brace_range(word);
-        }
-
-        // Check for comma-separated expansion
-        if inner.contains(',') {
-            let items: Vec<&str> = inner.split(',').collect();
-            let mut result = Vec::new();
-            for item in items {
-                result.push(item.trim().to_string());
-            }
-            return Some(result);
-        }
-
-        None
-    }
-
-    // Expand brace ranges like {1..10} or {a..z}
-    fn expand_brace_range(&self, word: &str) -> Option<Vec<String>> {
-        if !word.starts_with('{') || !word.ends_with('}') || !word.contains("..") {
-            return None;
-        }
-
-        let inner = &word[1..word.len() - 1]; // Remove { and }
-        let parts: Vec<&str> = inner.split("..").collect();
-        if parts.len() != 2 {
-            return None;
-        }
-
-        let start = parts[0];
-        let end = parts[1];
-
-        // Try numeric expansion
-        if let (Ok(start_num), Ok(end_num)) = (start.parse::<i32>(), end.parse::<i32>()) {
-            let mut result = Vec::new();
-            if start_num <= end_num {
-                for i in start_num..=end_num {
-                    result.push(i.to_string());
+                // Quotes and substitutions are kept for the executor to expand, as for arguments
+                TokenKind::Quote => {
+                    let content = self.parse_quoted_string_value(TokenKind::Quote);
+                    elements.push(quote_word(content, '"'));
                 }
-            } else {
-                for i in (end_num..=start_num).rev() {
-                    result.push(i.to_string());
+                TokenKind::SingleQuote => {
+                    let content = self.parse_quoted_string_value(TokenKind::SingleQuote);
+                    elements.push(quote_word(content, '\''));
                 }
-            }
-            return Some(result);
-        }
-
-        // Try character expansion (single characters only)
-        if start.len() == 1 && end.len() == 1 {
-            let start_char = start.chars().next().unwrap();
-            let end_char = end.chars().next().unwrap();
-            let mut result = Vec::new();
-
-            if start_char <= end_char {
-                for c in start_char..=end_char {
-                    result.push(c.to_string());
+                TokenKind::CmdSubst => {
+                    elements.push(self.current_token.value.clone());
+                    self.parse_command_substitution();
                 }
-            } else {
-                for c in (end_char..=start_char).rev() {
-                    result.push(c.to_string());
+                TokenKind::ArithSubst => {
+                    elements.push(self.current_token.value.clone());
+                    self.parse_arithmetic_expansion();
+                }
+                TokenKind::ParamExpansion => {
+                    elements.push(self.current_token.value.clone());
+                    self.next_token();
+                }
+                TokenKind::Dollar => {
+                    let var_ref = self.parse_variable_reference();
+                    elements.push(var_ref);
                 }
+                _ => self.next_token(),
             }
-            return Some(result);
         }
 
-        None
+        Node::Array { elements }
     }
 
     // Parse while loop: while condition; do body; done
@@ -1192,26 +1563,25 @@ impl Parser {
         }
     }
 
-    // method to parse a single command condition until a specific token kind is encountered
+    // method to parse a condition until a specific token kind is encountered
     fn parse_condition_until_token_kind(&mut self, stop_at: TokenKind) -> Node {
-        // Parse a single command as the condition
-        if let Some(statement) = self.parse_statement() {
-            // Skip any semicolons or newlines before the stop token
-            while (self.current_token.kind == TokenKind::Semicolon
-                || self.current_token.kind == TokenKind::Newline)
-                && self.current_token.kind != stop_at
-                && self.current_token.kind != TokenKind::EOF
-            {
-                self.next_token();
-            }
-            statement
-        } else {
-            // Return empty command if no valid statement found
-            Node::Command {
-                name: String::new(),
-                args: Vec::new(),
-                redirects: Vec::new(),
+        // A condition may be a whole list such as `a && b`, but a single
+        // command is kept as is
+        match self.parse_until_token_kind(stop_at) {
+            Node::List {
+                mut statements,
+                operators,
+            } => {
+                if statements.len() == 1 {
+                    statements.remove(0)
+                } else {
+                    Node::List {
+                        statements,
+                        operators,
+                    }
+                }
             }
+            condition => condition,
         }
     }
 
@@ -1222,7 +1592,7 @@ impl Parser {
 
         while self.current_token.kind != stop_at && self.current_token.kind != TokenKind::EOF {
             if let Some(statement) = self.parse_statement() {
-                statements.push(statement);
+                push_statement(&mut statements, &mut operators, statement);
 
                 // Check for operators between statements
                 match self.current_token.kind {
@@ -1246,11 +1616,7 @@ impl Parser {
                         operators.push("||".to_string());
                         self.next_token();
                     }
-                    _ => {
-                        if statements.len() > 1 && operators.len() < statements.len() - 1 {
-                            operators.push("".to_string());
-                        }
-                    }
+                    _ => {}
                 }
             } else {
                 // Skip tokens that don't form valid statements
@@ -1259,7 +1625,7 @@ impl Parser {
         }
 
         // Ensure we have the right number of operators
-        while operators.len() < statements.len() - 1 {
+        while operators.len() < statements.len().saturating_sub(1) {
             operators.push("".to_string());
         }
 
@@ -1287,7 +1653,7 @@ impl Parser {
             && self.current_token.kind != TokenKind::EOF
         {
             if let Some(statement) = self.parse_statement() {
-                statements.push(statement);
+                push_statement(&mut statements, &mut operators, statement);
 
                 // Check for operators between statements
                 match self.current_token.kind {
@@ -1311,11 +1677,7 @@ impl Parser {
                         operators.push("||".to_string());
                         self.next_token();
                     }
-                    _ => {
-                        if statements.len() > 1 && operators.len() < statements.len() - 1 {
-                            operators.push("".to_string());
-                        }
-                    }
+                    _ => {}
                 }
             } else {
                 // Skip tokens that don't form valid statements
@@ -1403,6 +1765,32 @@ impl Parser {
         }
     }
 
+    // Joins words and `$` signs that touch each other, since the lexer splits `a:$B` apart
+    fn parse_unquoted_value(&mut self) -> String {
+        let mut value = String::new();
+
+        loop {
+            match &self.current_token.kind {
+                TokenKind::Word(word) => value.push_str(word),
+                TokenKind::Dollar => value.push('$'),
+                TokenKind::ParamExpansion => value.push_str(&self.current_token.value),
+                _ => break,
+            }
+
+            let current = &self.current_token.position;
+            let adjacent = self.peek_token.position.line == current.line
+                && self.peek_token.position.column
+                    == current.column + self.current_token.value.chars().count();
+
+            self.next_token();
+            if !adjacent {
+                break;
+            }
+        }
+
+        value
+    }
+
     pub fn parse_assignment(&mut self) -> Node {
         let name = match &self.current_token.kind {
             TokenKind::Word(word) => word.clone(),
@@ -1417,48 +1805,19 @@ impl Parser {
             return self.parse_array_assignment(name);
         }
 
-        // Check for quotes, command substitution, or plain word
-        let value = match self.current_token.kind {
-            TokenKind::Quote => {
-                // Handle double quoted string
-                self.next_token(); // Skip opening quote
-
-                let mut quoted_value = String::new();
-                while self.current_token.kind != TokenKind::Quote
-                    && self.current_token.kind != TokenKind::EOF
-                {
-                    if let TokenKind::Word(word) = &self.current_token.kind {
-                        quoted_value.push_str(word);
-                    }
-                    self.next_token();
-                }
-
-                if self.current_token.kind == TokenKind::Quote {
-                    self.next_token(); // Skip closing quote
-                }
-
-                Box::new(Node::StringLiteral(quoted_value))
-            }
-            TokenKind::SingleQuote => {
-                // Handle single quoted string
-                self.next_token(); // Skip opening quote
-
-                let mut quoted_value = String::new();
-                while self.current_token.kind != TokenKind::SingleQuote
-                    && self.current_token.kind != TokenKind::EOF
-                {
-                    if let TokenKind::Word(word) = &self.current_token.kind {
-                        quoted_value.push_str(word);
-                    }
-                    self.next_token();
-                }
+        let value = self.parse_scalar_value();
+        Node::Assignment { name, value }
+    }
 
-                if self.current_token.kind == TokenKind::SingleQuote {
-                    self.next_token(); // Skip closing quote
-                }
+    // The value after `NAME=`, joining pieces written back to back into one string
+    fn parse_scalar_value(&mut self) -> Box<Node> {
+        // Substitutions are parsed into nodes, so keep their text in case more follows
+        let source = self.current_token.value.clone();
 
-                Box::new(Node::StringLiteral(quoted_value))
-            }
+        // Check for quotes, command substitution, or plain word
+        let value = match self.current_token.kind {
+            TokenKind::Quote => Box::new(self.parse_quoted_string(TokenKind::Quote)),
+            TokenKind::SingleQuote => Box::new(self.parse_quoted_string(TokenKind::SingleQuote)),
             TokenKind::CmdSubst => {
                 // Handle command substitution like $(...)
                 let cmd_subst = self.parse_command_substitution();
@@ -1469,9 +1828,9 @@ impl Parser {
                 let arith_subst = self.parse_arithmetic_expansion();
                 Box::new(arith_subst)
             }
-            TokenKind::Word(ref word) => {
-                let value = word.clone();
-                self.next_token(); // Skip value
+            TokenKind::Word(_) | TokenKind::Dollar | TokenKind::ParamExpansion => {
+                // Handle values with variable references like ~/bin:$PATH
+                let value = self.parse_unquoted_value();
                 Box::new(Node::StringLiteral(value))
             }
             // Handle keywords as assignment values
@@ -1537,11 +1896,40 @@ impl Parser {
             }
         };
 
-        Node::Assignment { name, value }
+        // Pieces written right after the first, as in A=x"$y"'z', continue the value
+        let mut joined = None;
+        while self.follows(&self.previous_token)
+            && let Some(piece) = self.assignment_piece()
+        {
+            joined
+                .get_or_insert_with(|| assignment_text(&value, &source))
+                .push_str(&piece);
+        }
+
+        match joined {
+            Some(text) => Box::new(Node::StringLiteral(text)),
+            None => value,
+        }
     }
 
-    fn parse_array_assignment(&mut self, name: String) -> Node {
-        self.next_token(); // Skip '('
+    // One piece of an assignment value, in the form `assignment_text` gives
+    fn assignment_piece(&mut self) -> Option<String> {
+        let source = self.current_token.value.clone();
+        let value = match self.current_token.kind {
+            TokenKind::Word(_) | TokenKind::Dollar | TokenKind::ParamExpansion => {
+                return Some(self.parse_unquoted_value());
+            }
+            TokenKind::Quote => self.parse_quoted_string(TokenKind::Quote),
+            TokenKind::SingleQuote => self.parse_quoted_string(TokenKind::SingleQuote),
+            TokenKind::CmdSubst => self.parse_command_substitution(),
+            TokenKind::ArithSubst => self.parse_arithmetic_expansion(),
+            _ => return None,
+        };
+        Some(assignment_text(&value, &source))
+    }
+
+    fn parse_array_assignment(&mut self, name: String) -> Node {
+        self.next_token(); // Skip '('
 
         let mut array_elements = Vec::new();
 
@@ -1611,79 +1999,129 @@ impl Parser {
         }
     }
 
+    // Reads a variable reference such as $VAR, joining ones written back to back.
+    // ${VAR} is lexed as a single ParamExpansion token instead.
+    fn parse_variable_reference(&mut self) -> String {
+        let mut var_ref = String::new();
+
+        loop {
+            var_ref.push('$');
+            let mut previous = self.current_token.clone();
+            self.next_token(); // Skip $
+
+            if let TokenKind::Word(word) = &self.current_token.kind
+                && self.follows(&previous)
+            {
+                // Handle $VAR syntax
+                var_ref.push_str(word);
+                previous = self.current_token.clone();
+                self.next_token(); // Skip variable name
+            }
+
+            // This handles cases like $i$j where consecutive variables should be one argument,
+            // while `$i $j` stays two
+            if self.current_token.kind != TokenKind::Dollar || !self.follows(&previous) {
+                break;
+            }
+        }
+
+        var_ref
+    }
+
+    // Whether the current token starts right where `previous` ends
+    fn follows(&self, previous: &Token) -> bool {
+        self.current_token.position.line == previous.position.line
+            && self.current_token.position.column
+                == previous.position.column + previous.value.chars().count()
+    }
+
     pub fn parse_command(&mut self) -> Node {
         let name = match &self.current_token.kind {
             TokenKind::Word(word) => word.clone(),
+            TokenKind::ParamExpansion => self.current_token.value.clone(),
             TokenKind::Export => "export".to_string(),
+            TokenKind::Complete => "complete".to_string(),
+            TokenKind::Break => "break".to_string(),
+            TokenKind::Continue => "continue".to_string(),
             _ => String::new(),
         };
 
-        self.next_token();
+        let bracket_test = name == "[";
+        // Whether the last argument may go on, like after a word but not a redirect
+        let mut joinable = matches!(
+            self.current_token.kind,
+            TokenKind::Word(_) | TokenKind::ParamExpansion
+        );
+        // A quoted or expanded name is read like any argument
+        let piece_name = matches!(
+            self.current_token.kind,
+            TokenKind::Quote
+                | TokenKind::SingleQuote
+                | TokenKind::Dollar
+                | TokenKind::CmdSubst
+                | TokenKind::ArithSubst
+        );
+        if !piece_name {
+            self.next_token();
+        }
 
+        // The name is gathered with the arguments, so pieces written after it join it
+        let has_name = !name.is_empty() || piece_name;
         let mut args = Vec::new();
+        if !name.is_empty() {
+            args.push(name);
+        }
         let mut redirects = Vec::new();
 
         // Loop to collect arguments and handle quotes
         loop {
+            // Pieces written back to back, like x${Y}z or name='value', make up one argument
+            let glued = std::mem::take(&mut joinable) && self.follows(&self.previous_token);
+
             match &self.current_token.kind {
                 TokenKind::Word(word) => {
                     // Special case: if command name is "[" and we encounter "]", include it and stop
-                    if name == "[" && word == "]" {
+                    if bracket_test && word == "]" {
                         args.push(word.clone());
                         self.next_token(); // Skip the "]"
                         break;
                     }
-                    // Check if this word is a variable reference (starts with $)
-                    // and keep it as a single token
-                    args.push(word.clone());
+                    push_piece(&mut args, word, glued);
+                    joinable = true;
                     self.next_token();
                 }
                 TokenKind::ArithSubst => {
-                    // Handle arithmetic expansion like $((expr))
-                    let arith_expansion = self.parse_arithmetic_expansion();
-                    if let Node::ArithmeticExpansion { expression } = arith_expansion {
-                        args.push(format!("$(({expression}))"));
-                    }
+                    // Handle arithmetic expansion like $((expr)), keeping its source text
+                    push_piece(&mut args, &self.current_token.value.clone(), glued);
+                    joinable = true;
+                    self.parse_arithmetic_expansion();
                 }
                 TokenKind::CmdSubst => {
-                    // Handle command substitution like $(...)
-                    let cmd_subst = self.parse_command_substitution();
-                    if let Node::CommandSubstitution { command } = &cmd_subst {
-                        if let Node::Command {
-                            name,
-                            args: cmd_args,
-                            redirects,
-                        } = command.as_ref()
-                        {
-                            if redirects.is_empty() && cmd_args.is_empty() {
-                                args.push(format!("$({name})"));
-                            } else if redirects.is_empty() {
-                                let mut cmd_str = name.clone();
-                                for arg in cmd_args {
-                                    cmd_str.push(' ');
-                                    cmd_str.push_str(arg);
-                                }
-                                args.push(format!("$({cmd_str})"));
-                            } else {
-                                args.push("$(...)".to_string());
-                            }
-                        } else {
-                            args.push("$(...)".to_string());
-                        }
-                    }
+                    // Handle command substitution like $(...), keeping its source text
+                    push_piece(&mut args, &self.current_token.value.clone(), glued);
+                    joinable = true;
+                    self.parse_command_substitution();
+                }
+                TokenKind::ParamExpansion => {
+                    // ${...} is a single token holding its source text
+                    push_piece(&mut args, &self.current_token.value, glued);
+                    joinable = true;
+                    self.next_token();
                 }
                 TokenKind::Quote => {
                     // Handle double quoted strings
                     let quoted = self.parse_quoted_string(TokenKind::Quote);
                     if let Node::StringLiteral(s) = quoted {
-                        args.push(s);
+                        push_piece(&mut args, &quote_word(s, '"'), glued);
+                        joinable = true;
                     }
                 }
                 TokenKind::SingleQuote => {
                     // Handle single quoted strings
                     let quoted = self.parse_quoted_string(TokenKind::SingleQuote);
                     if let Node::SingleQuotedString(s) = quoted {
-                        args.push(s);
+                        push_piece(&mut args, &quote_word(s, '\''), glued);
+                        joinable = true;
                     }
                 }
                 // Handle keywords as regular arguments when they appear in command arguments
@@ -1743,6 +2181,10 @@ impl Parser {
                     args.push("export".to_string());
                     self.next_token();
                 }
+                TokenKind::Complete => {
+                    args.push("complete".to_string());
+                    self.next_token();
+                }
                 TokenKind::ExtGlob(_) => {
                     // Handle extended glob pattern in command arguments
                     let extglob = self.parse_extglob();
@@ -1762,76 +2204,19 @@ impl Parser {
 
                     args.push(pattern_str);
                 }
-                TokenKind::Less | TokenKind::Great | TokenKind::DGreat => {
+                _ if self.at_redirect() => {
                     let redirect = self.parse_redirect();
                     redirects.push(redirect);
                 }
                 TokenKind::Dollar => {
-                    // Handle variable references explicitly (like $VAR or ${VAR})
-                    let mut var_ref = "$".to_string();
-                    self.next_token(); // Skip $
-
-                    if let TokenKind::LBrace = &self.current_token.kind {
-                        // Handle ${VAR} syntax
-                        var_ref.push('{');
-                        self.next_token(); // Skip {
-
-                        if let TokenKind::Word(word) = &self.current_token.kind {
-                            var_ref.push_str(word);
-                            self.next_token(); // Skip variable name/expression
-                        }
-
-                        if let TokenKind::RBrace = &self.current_token.kind {
-                            var_ref.push('}');
-                            self.next_token(); // Skip }
-                        }
-                    } else if let TokenKind::Word(word) = &self.current_token.kind {
-                        // Handle $VAR syntax
-                        var_ref.push_str(word);
-                        self.next_token(); // Skip variable name
-                    }
-
-                    // Check if the next token is also a Dollar or Word that should be concatenated
-                    // This handles cases like $i$j where consecutive variables should be one argument
-                    while let TokenKind::Dollar = &self.current_token.kind {
-                        match &self.current_token.kind {
-                            TokenKind::Dollar => {
-                                // Another variable reference - concatenate it
-                                var_ref.push('$');
-                                self.next_token(); // Skip $
-
-                                if let TokenKind::LBrace = &self.current_token.kind {
-                                    // Handle ${VAR} syntax
-                                    var_ref.push('{');
-                                    self.next_token(); // Skip {
-
-                                    if let TokenKind::Word(word) = &self.current_token.kind {
-                                        var_ref.push_str(word);
-                                        self.next_token(); // Skip variable name/expression
-                                    }
-
-                                    if let TokenKind::RBrace = &self.current_token.kind {
-                                        var_ref.push('}');
-                                        self.next_token(); // Skip }
-                                    }
-                                } else if let TokenKind::Word(word) = &self.current_token.kind {
-                                    // Handle $VAR syntax
-                                    var_ref.push_str(word);
-                                    self.next_token(); // Skip variable name
-                                }
-                            }
-                            _ => {
-                                // Stop concatenating when we hit something else
-                                break;
-                            }
-                        }
-                    }
-
-                    args.push(var_ref);
+                    let var_ref = self.parse_variable_reference();
+                    push_piece(&mut args, &var_ref, glued);
+                    joinable = true;
                 }
                 TokenKind::Assignment => {
                     // In command context, treat = as a regular argument
-                    args.push("=".to_string());
+                    push_piece(&mut args, "=", glued);
+                    joinable = true;
                     self.next_token();
                 }
                 TokenKind::LBrace => {
@@ -1867,12 +2252,25 @@ impl Parser {
             }
         }
 
+        let name = match has_name && !args.is_empty() {
+            true => args.remove(0),
+            false => String::new(),
+        };
+
         // Check for pipeline
-        if self.current_token.kind == TokenKind::Pipe {
+        if matches!(self.current_token.kind, TokenKind::Pipe | TokenKind::PipeBoth) {
+            // `|&` is short for `2>&1 |`, applied after the command's own redirects
+            if self.current_token.kind == TokenKind::PipeBoth {
+                redirects.push(Redirect {
+                    kind: RedirectKind::OutputDup,
+                    fd: Some(2),
+                    file: "1".to_string(),
+                });
+            }
             self.next_token(); // Skip the '|'
 
             // Parse the next command in the pipeline
-            let next_command = self.parse_command();
+            let next_command = self.parse_pipeline_stage();
 
             let mut commands = vec![Node::Command {
                 name,
@@ -1913,7 +2311,7 @@ impl Parser {
             && self.current_token.kind != TokenKind::EOF
         {
             if let Some(statement) = self.parse_statement() {
-                statements.push(statement);
+                push_statement(&mut statements, &mut operators, statement);
 
                 match self.current_token.kind {
                     TokenKind::Semicolon => {
@@ -1932,11 +2330,7 @@ impl Parser {
                         operators.push("||".to_string());
                         self.next_token();
                     }
-                    _ => {
-                        if statements.len() > 1 && operators.len() < statements.len() - 1 {
-                            operators.push("".to_string());
-                        }
-                    }
+                    _ => {}
                 }
             } else {
                 self.next_token();
@@ -1988,7 +2382,11 @@ impl Parser {
                     self.next_token();
                 }
                 TokenKind::Word(word) => {
-                    if !expression.is_empty() && !expression.ends_with(' ') {
+                    // A name directly after `$` stays attached to it
+                    if !expression.is_empty()
+                        && !expression.ends_with(' ')
+                        && !expression.ends_with('$')
+                    {
                         expression.push(' ');
                     }
                     expression.push_str(word);
@@ -2286,7 +2684,7 @@ impl Parser {
 
         while self.current_token.kind != TokenKind::EOF {
             if let Some(statement) = self.parse_statement() {
-                statements.push(statement);
+                push_statement(&mut statements, &mut operators, statement);
 
                 match self.current_token.kind {
                     TokenKind::Semicolon => {
@@ -2309,14 +2707,7 @@ impl Parser {
                         operators.push("||".to_string());
                         self.next_token();
                     }
-                    _ => {
-                        // Don't add an empty operator if we've reached the end
-                        if self.current_token.kind != TokenKind::EOF
-                            && statements.len() > operators.len() + 1
-                        {
-                            operators.push("".to_string());
-                        }
-                    }
+                    _ => {}
                 }
             } else {
                 // Skip tokens that don't form valid statements
@@ -2325,7 +2716,7 @@ impl Parser {
         }
 
         // make sure we have the right number of operators
-        while operators.len() < statements.len() - 1 {
+        while operators.len() < statements.len().saturating_sub(1) {
             operators.push("".to_string());
         }
 
@@ -2335,25 +2726,132 @@ impl Parser {
         }
     }
 
+    fn at_redirect(&self) -> bool {
+        matches!(
+            self.current_token.kind,
+            TokenKind::Less
+                | TokenKind::Great
+                | TokenKind::DGreat
+                | TokenKind::AndGreat
+                | TokenKind::AndDGreat
+                | TokenKind::HereDoc
+                | TokenKind::HereDocDash
+                | TokenKind::HereString
+                | TokenKind::GreatAnd
+                | TokenKind::LessAnd
+                | TokenKind::Clobber
+                | TokenKind::IoNumber(_)
+        )
+    }
+
+    // Redirections after a compound command, as in `( ... ) > out`
+    fn parse_trailing_redirects(&mut self) -> Vec<Redirect> {
+        let mut redirects = Vec::new();
+        while self.at_redirect() {
+            redirects.push(self.parse_redirect());
+        }
+        redirects
+    }
+
     // Fix for redirection handling
     fn parse_redirect(&mut self) -> Redirect {
-        let kind = match self.current_token.kind {
-            TokenKind::Less => RedirectKind::Input,
-            TokenKind::Great => RedirectKind::Output,
-            TokenKind::DGreat => RedirectKind::Append,
+        let fd = match &self.current_token.kind {
+            TokenKind::IoNumber(fd) => {
+                let fd = fd.parse::<u32>().ok();
+                self.next_token(); // Skip the file descriptor
+                fd
+            }
+            _ => None,
+        };
+
+        // Any other descriptor is kept in `fd` for the redirection to replace
+        let kind = match (fd, &self.current_token.kind) {
+            (Some(2), TokenKind::Great) => RedirectKind::ErrorOutput,
+            (Some(2), TokenKind::DGreat) => RedirectKind::ErrorAppend,
+            (_, TokenKind::GreatAnd) => RedirectKind::OutputDup,
+            (_, TokenKind::LessAnd) => RedirectKind::InputDup,
+            (_, TokenKind::Less) => RedirectKind::Input,
+            (_, TokenKind::Great) => RedirectKind::Output,
+            (_, TokenKind::DGreat) => RedirectKind::Append,
+            (_, TokenKind::Clobber) => RedirectKind::OutputForce,
+            (None, TokenKind::AndGreat) => RedirectKind::OutputBoth,
+            (None, TokenKind::AndDGreat) => RedirectKind::AppendBoth,
+            (_, TokenKind::HereDoc) => RedirectKind::HereDoc,
+            (_, TokenKind::HereDocDash) => RedirectKind::HereDocDash,
+            (_, TokenKind::HereString) => RedirectKind::HereString,
             _ => panic!("Expected a redirection token"),
         };
 
         self.next_token(); // Skip the redirection operator
 
-        let file = match &self.current_token.kind {
-            TokenKind::Word(word) => word.clone(),
-            _ => String::new(),
+        let (kind, file) = match &self.current_token.kind {
+            TokenKind::HereDocContent(body) => {
+                let body = body.clone();
+                // A quoted delimiter turns off expansion in the body
+                let kind = if self.current_token.value.contains(['\'', '"', '\\']) {
+                    RedirectKind::HereDocLiteral
+                } else {
+                    kind
+                };
+                self.next_token(); // Skip the body
+                (kind, body)
+            }
+            _ => (kind, self.parse_word_value()),
         };
 
-        self.next_token(); // Skip the filename
+        Redirect { kind, fd, file }
+    }
+
+    // Reads one word, which may be written as pieces back to back like out$((n))'.txt', such
+    // as a redirection target. Quotes are kept the way command arguments keep them.
+    fn parse_word_value(&mut self) -> String {
+        let mut word = String::new();
+        let mut first = true;
 
-        Redirect { kind, file }
+        while first || self.follows(&self.previous_token) {
+            let piece = match self.current_token.kind.clone() {
+                TokenKind::Word(text) => {
+                    self.next_token();
+                    text
+                }
+                TokenKind::Assignment => {
+                    self.next_token();
+                    "=".to_string()
+                }
+                TokenKind::ParamExpansion => {
+                    let text = self.current_token.value.clone();
+                    self.next_token();
+                    text
+                }
+                quote @ (TokenKind::Quote | TokenKind::SingleQuote) => {
+                    match self.parse_quoted_string(quote) {
+                        Node::StringLiteral(text) => quote_word(text, '"'),
+                        Node::SingleQuotedString(text) => quote_word(text, '\''),
+                        _ => String::new(),
+                    }
+                }
+                TokenKind::Dollar => self.parse_variable_reference(),
+                TokenKind::CmdSubst => {
+                    let text = self.current_token.value.clone();
+                    self.parse_command_substitution();
+                    text
+                }
+                TokenKind::ArithSubst => {
+                    let text = self.current_token.value.clone();
+                    self.parse_arithmetic_expansion();
+                    text
+                }
+                _ if first => {
+                    self.next_token(); // Skip whatever stands where the word should be
+                    String::new()
+                }
+                _ => break,
+            };
+            word.push_str(&piece);
+            first = false;
+        }
+
+        word
     }
 
     fn parse_subshell(&mut self) -> Node {
@@ -2368,7 +2866,7 @@ impl Parser {
         {
             // Try to parse a statement
             if let Some(statement) = self.parse_statement() {
-                statements.push(statement);
+                push_statement(&mut statements, &mut operators, statement);
 
                 // Handle operators between statements
                 match self.current_token.kind {
@@ -2396,13 +2894,7 @@ impl Parser {
                         // We're at the end parenthesis, don't add an operator
                         break;
                     }
-                    _ => {
-                        // If we have multiple statements but missing operators between them
-                        // Only add blank operator if we're not at the end
-                        if statements.len() > 1 && operators.len() < statements.len() - 1 {
-                            operators.push("".to_string());
-                        }
-                    }
+                    _ => {}
                 }
             } else {
                 // If we couldn't parse a statement, skip the token to avoid infinite loops
@@ -2431,29 +2923,86 @@ impl Parser {
 
         Node::Subshell {
             list: Box::new(list_node),
+            redirects: self.parse_trailing_redirects(),
+        }
+    }
+
+    // Parse command group: { list; }
+    fn parse_group(&mut self) -> Node {
+        self.next_token(); // Skip '{'
+
+        let list = self.parse_until_token_kind(TokenKind::RBrace);
+
+        if self.current_token.kind == TokenKind::RBrace {
+            self.next_token(); // Skip '}'
+        }
+
+        Node::Group {
+            list: Box::new(list),
+            redirects: self.parse_trailing_redirects(),
         }
     }
 
     // Parse extended test command: [[ condition ]]
+    // Reads the words of `[[ ... ]]`. Pieces written back to back make one word, as in a
+    // command, while `&&`, `||` and parentheses stand alone. The pattern after `=~` takes
+    // every piece up to the next blank, so `^(a|b)$` stays one word.
     fn parse_extended_test(&mut self) -> Node {
         self.next_token(); // Skip '[['
 
-        // Parse the condition inside [[ ]]
-        let mut condition_parts = Vec::new();
+        let mut words: Vec<String> = Vec::new();
+        let mut joinable = false;
+        let mut in_regex = false;
 
         while self.current_token.kind != TokenKind::DoubleRBracket
             && self.current_token.kind != TokenKind::EOF
         {
+            let glued = std::mem::take(&mut joinable) && self.follows(&self.previous_token);
+            if !glued {
+                in_regex = words.last().is_some_and(|word| word == "=~");
+            }
+
             match &self.current_token.kind {
+                TokenKind::Quote => {
+                    if let Node::StringLiteral(s) = self.parse_quoted_string(TokenKind::Quote) {
+                        push_piece(&mut words, &always_quote(s, '"'), glued);
+                    }
+                }
+                TokenKind::SingleQuote => {
+                    let quoted = self.parse_quoted_string(TokenKind::SingleQuote);
+                    if let Node::SingleQuotedString(s) = quoted {
+                        push_piece(&mut words, &always_quote(s, '\''), glued);
+                    }
+                }
+                TokenKind::Dollar => {
+                    let var_ref = self.parse_variable_reference();
+                    push_piece(&mut words, &var_ref, glued);
+                }
+                TokenKind::ArithSubst => {
+                    push_piece(&mut words, &self.current_token.value.clone(), glued);
+                    self.parse_arithmetic_expansion();
+                }
+                TokenKind::CmdSubst => {
+                    push_piece(&mut words, &self.current_token.value.clone(), glued);
+                    self.parse_command_substitution();
+                }
+                TokenKind::And | TokenKind::Or | TokenKind::LParen | TokenKind::RParen
+                    if !in_regex =>
+                {
+                    words.push(self.current_token.value.clone());
+                    self.next_token();
+                    continue;
+                }
                 TokenKind::Word(word) => {
-                    condition_parts.push(word.clone());
+                    push_piece(&mut words, &word.clone(), glued);
                     self.next_token();
                 }
                 _ => {
-                    condition_parts.push(self.current_token.value.clone());
+                    push_piece(&mut words, &self.current_token.value.clone(), glued);
                     self.next_token();
                 }
             }
+            joinable = true;
         }
 
         if self.current_token.kind == TokenKind::DoubleRBracket {
@@ -2463,7 +3012,7 @@ impl Parser {
         // Create a command node that represents the extended test
         let condition = Node::Command {
             name: "[[".to_string(),
-            args: condition_parts,
+            args: words,
             redirects: Vec::new(),
         };
 
@@ -2495,154 +3044,6 @@ impl Parser {
         Node::HistoryExpansion { pattern }
     }
 
-    // Parse parameter expansion: ${var}, ${var:-default}, etc.
-    fn parse_parameter_expansion(&mut self) -> Node {
-        self.next_token(); // Skip ${
-
-        let mut parameter = String::new();
-        let mut expansion_type = ParameterExpansionType::Simple;
-
-        // Handle indirect expansion ${!var}
-        if let TokenKind::Word(word) = &self.current_token.kind {
-            if word == "!" {
-                self.next_token(); // Skip !
-                if let TokenKind::Word(var_name) = &self.current_token.kind {
-                    parameter = var_name.clone();
-                    expansion_type = ParameterExpansionType::Indirect;
-                    self.next_token(); // Skip variable name
-                }
-            } else if word == "#" {
-                // Length expansion ${#var}
-                self.next_token(); // Skip #
-                if let TokenKind::Word(var_name) = &self.current_token.kind {
-                    parameter = var_name.clone();
-                    expansion_type = ParameterExpansionType::Length;
-                    self.next_token(); // Skip variable name
-                }
-            } else {
-                // Regular variable name
-                parameter = word.clone();
-                self.next_token(); // Skip variable name
-
-                // Check for expansion operators
-                if let TokenKind::Word(op) = &self.current_token.kind {
-                    match op.as_str() {
-                        ":-" => {
-                            // Default value ${var:-default}
-                            self.next_token(); // Skip :-
-                            let default_val = self.parse_parameter_expansion_value();
-                            expansion_type = ParameterExpansionType::Default(default_val);
-                        }
-                        ":=" => {
-                            // Assign default ${var:=default}
-                            self.next_token(); // Skip :=
-                            let default_val = self.parse_parameter_expansion_value();
-                            expansion_type = ParameterExpansionType::Assign(default_val);
-                        }
-                        ":?" => {
-                            // Error if unset ${var:?error}
-                            self.next_token(); // Skip :?
-                            let error_msg = self.parse_parameter_expansion_value();
-                            expansion_type = ParameterExpansionType::Error(error_msg);
-                        }
-                        ":+" => {
-                            // Alternative value ${var:+alt}
-                            self.next_token(); // Skip :+
-                            let alt_val = self.parse_parameter_expansion_value();
-                            expansion_type = ParameterExpansionType::Alternative(alt_val);
-                        }
-                        "#" => {
-                            // Remove smallest prefix ${var#pattern}
-                            self.next_token(); // Skip #
-                            let pattern = self.parse_parameter_expansion_value();
-                            expansion_type = ParameterExpansionType::RemoveSmallestPrefix(pattern);
-                        }
-                        "##" => {
-                            // Remove largest prefix ${var##pattern}
-                            self.next_token(); // Skip ##
-                            let pattern = self.parse_parameter_expansion_value();
-                            expansion_type = ParameterExpansionType::RemoveLargestPrefix(pattern);
-                        }
-                        "%" => {
-                            // Remove smallest suffix ${var%pattern}
-                            self.next_token(); // Skip %
-                            let pattern = self.parse_parameter_expansion_value();
-                            expansion_type = ParameterExpansionType::RemoveSmallestSuffix(pattern);
-                        }
-                        "%%" => {
-                            // Remove largest suffix ${var%%pattern}
-                            self.next_token(); // Skip %%
-                            let pattern = self.parse_parameter_expansion_value();
-                            expansion_type = ParameterExpansionType::RemoveLargestSuffix(pattern);
-                        }
-                        _ if op.starts_with(':') => {
-                            // Substring expansion ${var:offset:length}
-                            let offset_str = &op[1..];
-                            if let Ok(offset) = offset_str.parse::<i32>() {
-                                self.next_token(); // Skip offset
-
-                                // Check for length
-                                let length =
-                                    if let TokenKind::Word(len_str) = &self.current_token.kind {
-                                        if let Ok(len) = len_str.parse::<i32>() {
-                                            self.next_token(); // Skip length
-                                            Some(len)
-                                        } else {
-                                            None
-                                        }
-                                    } else {
-                                        None
-                                    };
-
-                                expansion_type =
-                                    ParameterExpansionType::Substring(Some(offset), length);
-                            }
-                        }
-                        _ => {
-                            // Unknown operator, treat as simple expansion
-                        }
-                    }
-                }
-            }
-        }
-
-        // Skip closing brace
-        if self.current_token.kind == TokenKind::RBrace {
-            self.next_token();
-        }
-
-        Node::ParameterExpansion {
-            parameter,
-            expansion_type,
-        }
-    }
-
-    // Parse the value part of parameter expansion (after operators like :-, :=, etc.)
-    fn parse_parameter_expansion_value(&mut self) -> String {
-        let mut value = String::new();
-
-        // Collect tokens until we hit the closing brace
-        while self.current_token.kind != TokenKind::RBrace
-            && self.current_token.kind != TokenKind::EOF
-        {
-            match &self.current_token.kind {
-                TokenKind::Word(word) => {
-                    value.push_str(word);
-                }
-                TokenKind::Dollar => {
-                    value.push('$');
-                }
-                _ => {
-                    // For other tokens, add their string representation
-                    value.push_str(&self.current_token.value);
-                }
-            }
-            self.next_token();
-        }
-
-        value
-    }
-
     // Parse process substitution: <(cmd) or >(cmd)
     fn parse_process_substitution(&mut self, direction: ProcessSubstDirection) -> Node {
         self.next_token(); // Skip <( or >(
@@ -2658,43 +3059,24 @@ impl Parser {
         }
     }
 
-    // Parse complete command: complete [options] command
+    // Parse complete command: complete [options] command. Its words are read as any
+    // command's are, so quoted word lists and expansions keep their meaning.
     fn parse_complete(&mut self) -> Node {
-        self.next_token(); // Skip 'complete'
-
-        let mut options = Vec::new();
-        let mut command = String::new();
-
-        // Parse options and command
-        while self.current_token.kind != TokenKind::Newline
-            && self.current_token.kind != TokenKind::Semicolon
-            && self.current_token.kind != TokenKind::EOF
-        {
-            match &self.current_token.kind {
-                TokenKind::Word(word) => {
-                    if word.starts_with('-') {
-                        options.push(word.clone());
-                    } else {
-                        // If we haven't found the command yet, this could be an option argument or the command
-                        // For simplicity, add non-dash words as options until we find the last word
-                        if command.is_empty() {
-                            options.push(word.clone());
-                        }
-                    }
-                    self.next_token();
-                }
-                _ => {
-                    self.next_token();
+        match self.parse_command() {
+            Node::Command {
+                mut args,
+                redirects,
+                ..
+            } if redirects.is_empty() => {
+                let command = args.pop().unwrap_or_default();
+                Node::Complete {
+                    options: args,
+                    command,
                 }
             }
+            // Redirected or in a pipeline, it stays a command that runs the builtin
+            node => node,
         }
-
-        // The last option should be the command
-        if !options.is_empty() {
-            command = options.pop().unwrap();
-        }
-
-        Node::Complete { options, command }
     }
 
     // Parse logical negation: ! command
@@ -2732,6 +3114,53 @@ mod parser_tests {
         Parser::new(lexer)
     }
 
+    #[test]
+    fn test_is_incomplete() {
+        for input in [
+            "if true; then",
+            "if true; then\n  echo hi",
+            "for x in a b; do echo $x",
+            "while true\ndo",
+            "case $x in\n  a) echo a;;",
+            "f() {",
+            "{ echo a",
+            "(echo a",
+            "echo \"open",
+            "echo 'open",
+            "echo a |",
+            "echo a |&",
+            "cat <<EOF",
+            "cat <<EOF\nline",
+            "cat <<-EOF\n\tline\n  EOF",
+            "cat <<'END' | wc\nEOF",
+            "true &&",
+            "false ||",
+            "echo a \\",
+            "echo a \\\n",
+            "if true; then if false; then echo; fi",
+        ] {
+            assert!(is_incomplete(input), "{input:?} should need more input");
+        }
+
+        for input in [
+            "",
+            "echo if then do done fi",
+            "if true; then echo yes; fi",
+            "for x in a b; do echo $x; done",
+            "case $x in a) echo a;; *) echo b;; esac",
+            "f() { echo; }",
+            "(echo a) | cat",
+            "echo \"closed\" 'too'",
+            "echo a \\\\",
+            "echo }",
+            "echo $(echo a)",
+            "cat <<EOF\nline\nEOF",
+            "cat <<-EOF\n\tline\n\tEOF\necho after",
+        ] {
+            assert!(!is_incomplete(input), "{input:?} should be complete");
+        }
+    }
+
     #[test]
     fn test_simple_command() {
         let input = "echo hello world";
@@ -2762,6 +3191,22 @@ mod parser_tests {
         }
     }
 
+    #[test]
+    fn test_prefix_assignment_operators() {
+        // The prefix is joined to its command by "", whatever follows the command
+        for (input, expected) in [
+            ("G=h printenv G; echo x", ["", ";"]),
+            ("G=h printenv G && echo x", ["", "&&"]),
+            ("G=h printenv G\necho x", ["", "\n"]),
+            ("echo x; G=h printenv G", [";", ""]),
+        ] {
+            match parse_test(input) {
+                Node::List { operators, .. } => assert_eq!(operators, expected, "{input:?}"),
+                other => panic!("Expected List node, got: {other:?}"),
+            }
+        }
+    }
+
     #[test]
     fn test_simple_assignment() {
         let input = "value=123";
@@ -2790,35 +3235,143 @@ mod parser_tests {
             }
             _ => panic!("Expected List node"),
         }
-    }
+    }
+
+    #[test]
+    fn test_command_with_redirects() {
+        let input = "cat file.txt > output.txt";
+        let result = parse_test(input);
+
+        match result {
+            Node::List {
+                statements,
+                operators: _,
+            } => {
+                assert_eq!(statements.len(), 1);
+
+                match &statements[0] {
+                    Node::Command {
+                        name,
+                        args,
+                        redirects,
+                    } => {
+                        assert_eq!(name, "cat");
+                        assert_eq!(args, &["file.txt"]);
+                        assert_eq!(redirects.len(), 1);
+                        assert!(matches!(redirects[0].kind, RedirectKind::Output));
+                        assert_eq!(redirects[0].file, "output.txt");
+                    }
+                    _ => panic!("Expected Command node"),
+                }
+            }
+            _ => panic!("Expected List node"),
+        }
+    }
+
+    #[test]
+    fn test_stderr_redirect() {
+        let input = "cmd arg 2> /tmp/err > out.txt";
+        let result = parse_test(input);
+
+        match result {
+            Node::List { statements, .. } => match &statements[0] {
+                Node::Command {
+                    name,
+                    args,
+                    redirects,
+                } => {
+                    assert_eq!(name, "cmd");
+                    assert_eq!(args, &["arg"]);
+                    assert_eq!(redirects.len(), 2);
+                    assert_eq!(redirects[0].kind, RedirectKind::ErrorOutput);
+                    assert_eq!(redirects[0].file, "/tmp/err");
+                    assert_eq!(redirects[1].kind, RedirectKind::Output);
+                    assert_eq!(redirects[1].file, "out.txt");
+                }
+                _ => panic!("Expected Command node"),
+            },
+            _ => panic!("Expected List node"),
+        }
+
+        match parse_test("cmd &> all.log | wc -l") {
+            Node::List { statements, .. } => match &statements[0] {
+                Node::Pipeline { commands } => match &commands[0] {
+                    Node::Command { redirects, .. } => {
+                        assert_eq!(redirects[0].kind, RedirectKind::OutputBoth);
+                        assert_eq!(redirects[0].file, "all.log");
+                    }
+                    _ => panic!("Expected Command node"),
+                },
+                _ => panic!("Expected Pipeline node"),
+            },
+            _ => panic!("Expected List node"),
+        }
+
+        match parse_test("cat <<EOF | wc -l\none\ntwo\nEOF\n") {
+            Node::List { statements, .. } => match &statements[0] {
+                Node::Pipeline { commands } => match &commands[0] {
+                    Node::Command { redirects, .. } => {
+                        assert_eq!(redirects[0].kind, RedirectKind::HereDoc);
+                        assert_eq!(redirects[0].file, "one\ntwo\n");
+                    }
+                    _ => panic!("Expected Command node"),
+                },
+                _ => panic!("Expected Pipeline node"),
+            },
+            _ => panic!("Expected List node"),
+        }
 
-    #[test]
-    fn test_command_with_redirects() {
-        let input = "cat file.txt > output.txt";
-        let result = parse_test(input);
+        match parse_test("cat <<'EOF'\n$HOME\nEOF") {
+            Node::List { statements, .. } => match &statements[0] {
+                Node::Command { redirects, .. } => {
+                    assert_eq!(redirects[0].kind, RedirectKind::HereDocLiteral);
+                    assert_eq!(redirects[0].file, "$HOME\n");
+                }
+                _ => panic!("Expected Command node"),
+            },
+            _ => panic!("Expected List node"),
+        }
 
-        match result {
-            Node::List {
-                statements,
-                operators: _,
-            } => {
-                assert_eq!(statements.len(), 1);
+        match parse_test("grep foo <<< \"$var bar\" > out") {
+            Node::List { statements, .. } => match &statements[0] {
+                Node::Command { args, redirects, .. } => {
+                    assert_eq!(args, &["foo"]);
+                    assert_eq!(redirects[0].kind, RedirectKind::HereString);
+                    // The quotes stay for the shell to expand the word like an argument
+                    assert_eq!(redirects[0].file, "\"$var bar\"");
+                    assert_eq!(redirects[1].kind, RedirectKind::Output);
+                }
+                _ => panic!("Expected Command node"),
+            },
+            _ => panic!("Expected List node"),
+        }
 
-                match &statements[0] {
-                    Node::Command {
-                        name,
-                        args,
-                        redirects,
-                    } => {
-                        assert_eq!(name, "cat");
-                        assert_eq!(args, &["file.txt"]);
-                        assert_eq!(redirects.len(), 1);
-                        assert!(matches!(redirects[0].kind, RedirectKind::Output));
-                        assert_eq!(redirects[0].file, "output.txt");
+        match parse_test("cmd 2>&1 >&2 | grep error") {
+            Node::List { statements, .. } => match &statements[0] {
+                Node::Pipeline { commands } => match &commands[0] {
+                    Node::Command { redirects, .. } => {
+                        assert_eq!(redirects[0].kind, RedirectKind::OutputDup);
+                        assert_eq!(redirects[0].fd, Some(2));
+                        assert_eq!(redirects[0].file, "1");
+                        assert_eq!(redirects[1].kind, RedirectKind::OutputDup);
+                        assert_eq!(redirects[1].fd, None);
+                        assert_eq!(redirects[1].file, "2");
                     }
                     _ => panic!("Expected Command node"),
+                },
+                _ => panic!("Expected Pipeline node"),
+            },
+            _ => panic!("Expected List node"),
+        }
+
+        match parse_test("cmd 2>>log") {
+            Node::List { statements, .. } => match &statements[0] {
+                Node::Command { redirects, .. } => {
+                    assert_eq!(redirects[0].kind, RedirectKind::ErrorAppend);
+                    assert_eq!(redirects[0].file, "log");
                 }
-            }
+                _ => panic!("Expected Command node"),
+            },
             _ => panic!("Expected List node"),
         }
     }
@@ -2911,6 +3464,94 @@ mod parser_tests {
         }
     }
 
+    #[test]
+    fn test_pipeline_with_stderr() {
+        let input = "make 2> /dev/null |& grep error | wc -l";
+        let result = parse_test(input);
+
+        match result {
+            Node::List { statements, .. } => match &statements[0] {
+                Node::Pipeline { commands } => {
+                    assert_eq!(commands.len(), 3);
+
+                    // The joined stderr comes after the command's own redirects
+                    match &commands[0] {
+                        Node::Command { redirects, .. } => {
+                            assert_eq!(redirects.len(), 2);
+                            assert_eq!(redirects[0].kind, RedirectKind::ErrorOutput);
+                            assert_eq!(redirects[1].kind, RedirectKind::OutputDup);
+                            assert_eq!(redirects[1].fd, Some(2));
+                            assert_eq!(redirects[1].file, "1");
+                        }
+                        _ => panic!("Expected Command node"),
+                    }
+
+                    match &commands[1] {
+                        Node::Command { redirects, .. } => assert!(redirects.is_empty()),
+                        _ => panic!("Expected Command node"),
+                    }
+                }
+                _ => panic!("Expected Pipeline node"),
+            },
+            _ => panic!("Expected List node"),
+        }
+    }
+
+    #[test]
+    fn test_pipeline_with_compound_commands() {
+        let result = parse_test("echo a | while read l; do echo $l; done; echo b");
+        match result {
+            Node::List { statements, .. } => {
+                assert_eq!(statements.len(), 2);
+                match &statements[0] {
+                    Node::Pipeline { commands } => {
+                        assert_eq!(commands.len(), 2);
+                        assert!(matches!(commands[1], Node::WhileLoop { .. }));
+                    }
+                    _ => panic!("Expected Pipeline node"),
+                }
+            }
+            _ => panic!("Expected List node"),
+        }
+
+        // A compound command can start or sit in the middle of a pipeline too
+        let result = parse_test("{ echo a; } | (cat) | if true; then cat; fi");
+        match result {
+            Node::List { statements, .. } => match &statements[0] {
+                Node::Pipeline { commands } => {
+                    assert_eq!(commands.len(), 3);
+                    assert!(matches!(commands[0], Node::Group { .. }));
+                    assert!(matches!(commands[1], Node::Subshell { .. }));
+                    assert!(matches!(commands[2], Node::IfStatement { .. }));
+                }
+                _ => panic!("Expected Pipeline node"),
+            },
+            _ => panic!("Expected List node"),
+        }
+
+        // `|&` after a compound command merges the stderr of all of it
+        let result = parse_test("for i in 1; do echo $i; done |& cat");
+        match result {
+            Node::List { statements, .. } => match &statements[0] {
+                Node::Pipeline { commands } => match &commands[0] {
+                    Node::Group { list, redirects } => {
+                        assert_eq!(redirects[0].kind, RedirectKind::OutputDup);
+                        assert_eq!(redirects[0].fd, Some(2));
+                        match list.as_ref() {
+                            Node::List { statements, .. } => {
+                                assert!(matches!(statements[0], Node::ForLoop { .. }))
+                            }
+                            _ => panic!("Expected List node"),
+                        }
+                    }
+                    _ => panic!("Expected Group node"),
+                },
+                _ => panic!("Expected Pipeline node"),
+            },
+            _ => panic!("Expected List node"),
+        }
+    }
+
     #[test]
     fn test_command_list_semicolon() {
         let input = "echo hello; echo world";
@@ -2963,6 +3604,129 @@ mod parser_tests {
         }
     }
 
+    #[test]
+    fn test_separate_variable_arguments() {
+        match parse_test("echo $a $b $c$d $$ x") {
+            Node::List { statements, .. } => match &statements[0] {
+                Node::Command { args, .. } => assert_eq!(args, &["$a", "$b", "$c$d", "$$", "x"]),
+                _ => panic!("Expected Command node"),
+            },
+            _ => panic!("Expected List node"),
+        }
+    }
+
+    #[test]
+    fn test_adjacent_expansions_join() {
+        match parse_test("echo x${Y}z a$b ${c}$d ${e} f") {
+            Node::List { statements, .. } => match &statements[0] {
+                Node::Command { args, .. } => {
+                    assert_eq!(args, &["x${Y}z", "a$b", "${c}$d", "${e}", "f"])
+                }
+                _ => panic!("Expected Command node"),
+            },
+            _ => panic!("Expected List node"),
+        }
+
+        match parse_test("alias ll='ls -la' k=v a= b") {
+            Node::List { statements, .. } => match &statements[0] {
+                Node::Command { args, .. } => assert_eq!(args, &["ll=ls -la", "k=v", "a=", "b"]),
+                _ => panic!("Expected Command node"),
+            },
+            _ => panic!("Expected List node"),
+        }
+
+        match parse_test("ec\"ho\" a''b '' p$(q)r n$((1))m") {
+            Node::List { statements, .. } => match &statements[0] {
+                Node::Command { name, args, .. } => {
+                    assert_eq!(name, "echo");
+                    assert_eq!(args, &["a''b", "''", "p$(q)r", "n$((1))m"]);
+                }
+                _ => panic!("Expected Command node"),
+            },
+            _ => panic!("Expected List node"),
+        }
+
+        // A quoted name joins only what touches it, not the arguments after a space
+        let command_of = |input: &str| match parse_test(input) {
+            Node::List { statements, .. } => match &statements[0] {
+                Node::Command { name, args, .. } => (name.clone(), args.clone()),
+                _ => panic!("Expected Command node"),
+            },
+            _ => panic!("Expected List node"),
+        };
+        assert_eq!(command_of("\"echo\" hi"), ("echo".to_string(), vec!["hi".to_string()]));
+        assert_eq!(command_of("'f' x"), ("f".to_string(), vec!["x".to_string()]));
+        assert_eq!(command_of("\"ec\"ho hi").0, "echo");
+        let (name, args) = command_of("\"printf\" \"%s\\n\" a");
+        assert_eq!((name.as_str(), args.len()), ("printf", 2));
+        assert_eq!(command_of("\"$E\" hi").1, ["hi"]);
+
+        // An expanded name keeps its `$` for the shell to expand
+        assert_eq!(command_of("$E hi"), ("$E".to_string(), vec!["hi".to_string()]));
+        assert_eq!(command_of("$E$x y").0, "$E$x");
+        let (name, args) = command_of("$(which ls) -l");
+        assert_eq!((name.as_str(), args), ("$(which ls)", vec!["-l".to_string()]));
+    }
+
+    #[test]
+    fn test_adjacent_assignment_pieces_join() {
+        let value_of = |input: &str| match parse_test(input) {
+            Node::List { statements, .. } => match &statements[0] {
+                Node::Assignment { value, .. } => (**value).clone(),
+                _ => panic!("Expected Assignment node"),
+            },
+            _ => panic!("Expected List node"),
+        };
+
+        let literal = |text: &str| Node::StringLiteral(text.to_string());
+        assert_eq!(value_of("A=x'$a b'\"$c\"d"), literal("x\\$a b$cd"));
+        assert_eq!(value_of("A=$(b)c"), literal("$(b)c"));
+        assert_eq!(value_of("A='x';"), Node::SingleQuotedString("x".to_string()));
+    }
+
+    #[test]
+    fn test_subshell_redirects() {
+        let input = "(echo a; echo b) > out 2>&1\necho c";
+        let result = parse_test(input);
+
+        match result {
+            Node::List { statements, .. } => {
+                assert_eq!(statements.len(), 2);
+                match &statements[0] {
+                    Node::Subshell { redirects, .. } => {
+                        assert_eq!(redirects.len(), 2);
+                        assert_eq!(redirects[0].kind, RedirectKind::Output);
+                        assert_eq!(redirects[0].file, "out");
+                        assert_eq!(redirects[1].kind, RedirectKind::OutputDup);
+                    }
+                    _ => panic!("Expected Subshell node"),
+                }
+            }
+            _ => panic!("Expected List node"),
+        }
+    }
+
+    #[test]
+    fn test_group_redirects() {
+        match parse_test("{ echo a; echo b; } >> out; echo c") {
+            Node::List { statements, .. } => {
+                assert_eq!(statements.len(), 2);
+                match &statements[0] {
+                    Node::Group { list, redirects } => {
+                        let Node::List { statements, .. } = &**list else {
+                            panic!("Expected List node inside group");
+                        };
+                        assert_eq!(statements.len(), 2);
+                        assert_eq!(redirects.len(), 1);
+                        assert_eq!(redirects[0].kind, RedirectKind::Append);
+                    }
+                    _ => panic!("Expected Group node"),
+                }
+            }
+            _ => panic!("Expected List node"),
+        }
+    }
+
     #[test]
     fn test_basic_subshell() {
         let input = "(echo hello)";
@@ -2970,7 +3734,7 @@ mod parser_tests {
 
         match result {
             Node::List { statements, .. } => match &statements[0] {
-                Node::Subshell { list } => match &**list {
+                Node::Subshell { list, .. } => match &**list {
                     Node::List { statements, .. } => {
                         assert_eq!(statements.len(), 1);
                         match &statements[0] {
@@ -2996,7 +3760,7 @@ mod parser_tests {
 
         match result {
             Node::List { statements, .. } => match &statements[0] {
-                Node::Subshell { list } => match &**list {
+                Node::Subshell { list, .. } => match &**list {
                     Node::List {
                         statements,
                         operators,
@@ -3036,7 +3800,7 @@ mod parser_tests {
 
         match result {
             Node::List { statements, .. } => match &statements[0] {
-                Node::Subshell { list } => match &**list {
+                Node::Subshell { list, .. } => match &**list {
                     Node::List {
                         statements,
                         operators,
@@ -3060,7 +3824,7 @@ mod parser_tests {
 
         match result {
             Node::List { statements, .. } => match &statements[0] {
-                Node::Subshell { list } => match &**list {
+                Node::Subshell { list, .. } => match &**list {
                     Node::List {
                         statements,
                         operators,
@@ -3084,7 +3848,7 @@ mod parser_tests {
 
         match result {
             Node::List { statements, .. } => match &statements[0] {
-                Node::Subshell { list } => match &**list {
+                Node::Subshell { list, .. } => match &**list {
                     Node::List {
                         statements,
                         operators,
@@ -3108,7 +3872,7 @@ mod parser_tests {
 
         match result {
             Node::List { statements, .. } => match &statements[0] {
-                Node::Subshell { list } => match &**list {
+                Node::Subshell { list, .. } => match &**list {
                     Node::List {
                         statements,
                         operators,
@@ -3132,7 +3896,7 @@ mod parser_tests {
 
         match result {
             Node::List { statements, .. } => match &statements[0] {
-                Node::Subshell { list } => match &**list {
+                Node::Subshell { list, .. } => match &**list {
                     Node::List { statements, .. } => {
                         assert_eq!(statements.len(), 0);
                     }
@@ -3179,7 +3943,7 @@ mod parser_tests {
 
                 // Check first subshell
                 match &statements[0] {
-                    Node::Subshell { list } => match &**list {
+                    Node::Subshell { list, .. } => match &**list {
                         Node::List { statements, .. } => {
                             assert_eq!(statements.len(), 1);
                             match &statements[0] {
@@ -3197,7 +3961,7 @@ mod parser_tests {
 
                 // Check second subshell
                 match &statements[1] {
-                    Node::Subshell { list } => match &**list {
+                    Node::Subshell { list, .. } => match &**list {
                         Node::List { statements, .. } => {
                             assert_eq!(statements.len(), 1);
                             match &statements[0] {
@@ -3224,7 +3988,7 @@ mod parser_tests {
 
         match result {
             Node::List { statements, .. } => match &statements[0] {
-                Node::Subshell { list } => match &**list {
+                Node::Subshell { list, .. } => match &**list {
                     Node::List {
                         statements,
                         operators,
@@ -3317,6 +4081,25 @@ mod parser_tests {
             _ => panic!("Expected List node"),
         }
     }
+    #[test]
+    fn test_assignment_values() {
+        let value_of = |input: &str| match parse_test(input) {
+            Node::List { statements, .. } => match &statements[0] {
+                Node::Assignment { value, .. } => *value.clone(),
+                other => panic!("Expected Assignment node, got {other:?}"),
+            },
+            _ => panic!("Expected List node"),
+        };
+
+        assert_eq!(value_of("A=$HOME/bin"), Node::StringLiteral("$HOME/bin".to_string()));
+        assert_eq!(
+            value_of("A=~/bin:$PATH x"),
+            Node::StringLiteral("~/bin:$PATH".to_string())
+        );
+        assert_eq!(value_of("A='$HOME'"), Node::SingleQuotedString("$HOME".to_string()));
+        assert_eq!(value_of("A="), Node::StringLiteral(String::new()));
+    }
+
     #[test]
     fn test_subshell_with_variable_assignment() {
         let input = "(VAR=value; echo $VAR)";
@@ -3324,7 +4107,7 @@ mod parser_tests {
 
         match result {
             Node::List { statements, .. } => match &statements[0] {
-                Node::Subshell { list } => match &**list {
+                Node::Subshell { list, .. } => match &**list {
                     Node::List {
                         statements,
                         operators,
@@ -3371,7 +4154,7 @@ mod parser_tests {
 
         match result {
             Node::List { statements, .. } => match &statements[0] {
-                Node::Subshell { list } => match &**list {
+                Node::Subshell { list, .. } => match &**list {
                     Node::List {
                         statements,
                         operators,
@@ -3413,7 +4196,7 @@ mod parser_tests {
 
         match result {
             Node::List { statements, .. } => match &statements[0] {
-                Node::Subshell { list } => match &**list {
+                Node::Subshell { list, .. } => match &**list {
                     Node::List { statements, .. } => {
                         // We should have 2 echo commands and possible comment nodes
                         let mut echo_count = 0;
@@ -3450,7 +4233,7 @@ mod parser_tests {
 
         match result {
             Node::List { statements, .. } => match &statements[0] {
-                Node::Subshell { list } => match &**list {
+                Node::Subshell { list, .. } => match &**list {
                     Node::List { statements, .. } => {
                         assert_eq!(statements.len(), 1);
 
@@ -3490,7 +4273,7 @@ mod parser_tests {
 
         match result {
             Node::List { statements, .. } => match &statements[0] {
-                Node::Subshell { list } => match &**list {
+                Node::Subshell { list, .. } => match &**list {
                     Node::List { statements, .. } => {
                         assert_eq!(statements.len(), 1);
 
@@ -3561,7 +4344,8 @@ mod parser_tests {
                                         args: vec![
                                             "$LOG_DIR".to_string(),
                                             "-name".to_string(),
-                                            "*.log".to_string()
+                                            // Quoted glob characters keep their quotes
+                                            "\"*.log\"".to_string()
                                         ],
                                         redirects: vec![],
                                     },
@@ -4184,11 +4968,17 @@ EOF"#;
 
     #[test]
     fn test_brace_expansion() {
-        let input = "echo file{1,2,3}.txt";
+        let input = "echo file{1,2,3}.txt x{a,\"b c\"}y";
+        let result = parse_test(input);
 
-        // This would require additional lexing/parsing logic
-        // Just verify it doesn't panic
-        let _result = parse_test(input);
+        // Brace words reach the executor whole, which expands them
+        let Node::List { statements, .. } = result else {
+            panic!("Expected List node");
+        };
+        let Node::Command { args, .. } = &statements[0] else {
+            panic!("Expected Command node");
+        };
+        assert_eq!(args, &["file{1,2,3}.txt", "x{a,\"b c\"}y"]);
     }
 
     #[test]
@@ -4219,11 +5009,100 @@ EOF"#;
 
     #[test]
     fn test_parameter_expansion() {
-        let input = r#"echo ${VAR:-default} ${VAR##*/}"#;
+        let input = r#"echo ${VAR:-"a b"} "${VAR:+x}""#;
+        let result = parse_test(input);
 
-        // This would require additional parsing logic not present in the current code
-        // Just verify it doesn't panic
-        let _result = parse_test(input);
+        let Node::List { statements, .. } = result else {
+            panic!("Expected List node");
+        };
+        let Node::Command { args, .. } = &statements[0] else {
+            panic!("Expected Command node");
+        };
+        assert_eq!(args, &["${VAR:-\"a b\"}", "\"${VAR:+x}\""]);
+    }
+
+    #[test]
+    fn test_parse_parameter() {
+        assert_eq!(
+            parse_parameter("HOME"),
+            Some(("HOME".to_string(), ParameterExpansionType::Simple))
+        );
+        assert_eq!(
+            parse_parameter("x:-a b"),
+            Some(("x".to_string(), ParameterExpansionType::Default("a b".to_string())))
+        );
+        assert_eq!(
+            parse_parameter("x=$y"),
+            Some(("x".to_string(), ParameterExpansionType::UnsetAssign("$y".to_string())))
+        );
+        assert_eq!(
+            parse_parameter("1?"),
+            Some(("1".to_string(), ParameterExpansionType::UnsetError(String::new())))
+        );
+        assert_eq!(
+            parse_parameter("#"),
+            Some(("#".to_string(), ParameterExpansionType::Simple))
+        );
+        assert_eq!(
+            parse_parameter("#x"),
+            Some(("x".to_string(), ParameterExpansionType::Length))
+        );
+        assert_eq!(
+            parse_parameter("#a[@]"),
+            Some(("a[@]".to_string(), ParameterExpansionType::Length))
+        );
+        assert_eq!(
+            parse_parameter("a[1]:-x"),
+            Some(("a[1]".to_string(), ParameterExpansionType::Default("x".to_string())))
+        );
+        assert_eq!(parse_parameter("a[1"), None);
+        assert_eq!(
+            parse_parameter("x: -3:n+1"),
+            Some((
+                "x".to_string(),
+                ParameterExpansionType::Substring(" -3".to_string(), Some("n+1".to_string()))
+            ))
+        );
+        assert_eq!(
+            parse_parameter("path##*/"),
+            Some((
+                "path".to_string(),
+                ParameterExpansionType::RemoveLargestPrefix("*/".to_string())
+            ))
+        );
+        assert_eq!(
+            parse_parameter("file%.*"),
+            Some((
+                "file".to_string(),
+                ParameterExpansionType::RemoveSmallestSuffix(".*".to_string())
+            ))
+        );
+        assert_eq!(
+            parse_parameter("p//\\/x/\"/\""),
+            Some((
+                "p".to_string(),
+                ParameterExpansionType::Replace(
+                    ReplaceScope::All,
+                    "\\/x".to_string(),
+                    "\"/\"".to_string()
+                )
+            ))
+        );
+        assert_eq!(
+            parse_parameter("p/#a"),
+            Some((
+                "p".to_string(),
+                ParameterExpansionType::Replace(
+                    ReplaceScope::Prefix,
+                    "a".to_string(),
+                    String::new()
+                )
+            ))
+        );
+        assert_eq!(parse_parameter(""), None);
+        assert_eq!(parse_parameter("#x-"), None);
+        assert_eq!(parse_parameter("x:"), None);
+        assert_eq!(parse_parameter("a b"), None);
     }
 
     #[test]
@@ -4726,7 +5605,8 @@ fi
                 if let Node::Command { name, args, .. } = &**condition {
                     assert_eq!(name, "[");
                     assert_eq!(args.len(), 4);
-                    assert_eq!(args[0], "$1");
+                    // Quotes around expansions are kept for the expansion step
+                    assert_eq!(args[0], "\"$1\"");
                     assert_eq!(args[1], "=");
                     assert_eq!(args[2], "test");
                     assert_eq!(args[3], "]");
@@ -4809,7 +5689,8 @@ fi
                 if let Node::Command { name, args, .. } = &**condition {
                     assert_eq!(name, "[");
                     assert_eq!(args.len(), 4);
-                    assert_eq!(args[0], "$1");
+                    // Quotes around expansions are kept for the expansion step
+                    assert_eq!(args[0], "\"$1\"");
                     assert_eq!(args[1], "=");
                     assert_eq!(args[2], "test1");
                     assert_eq!(args[3], "]");
@@ -4843,7 +5724,7 @@ fi
                             if let Node::Command { name, args, .. } = &**elif_condition {
                                 assert_eq!(name, "[");
                                 assert_eq!(args.len(), 4);
-                                assert_eq!(args[0], "$1");
+                                assert_eq!(args[0], "\"$1\"");
                                 assert_eq!(args[1], "=");
                                 assert_eq!(args[2], "test2");
                                 assert_eq!(args[3], "]");
@@ -4875,7 +5756,7 @@ fi
                             if let Node::Command { name, args, .. } = &**elif_condition {
                                 assert_eq!(name, "[");
                                 assert_eq!(args.len(), 4);
-                                assert_eq!(args[0], "$1");
+                                assert_eq!(args[0], "\"$1\"");
                                 assert_eq!(args[1], "=");
                                 assert_eq!(args[2], "test2");
                                 assert_eq!(args[3], "]");
@@ -5493,20 +6374,18 @@ fi
                                 let mut found_array = false;
 
                                 for statement in statements {
-                                    if let Node::Assignment { name, value } = statement {
-                                        if name == "tools" {
-                                            found_array = true;
-                                            match &**value {
-                                                Node::Array { elements } => {
-                                                    assert_eq!(elements.len(), 3);
-                                                    assert_eq!(elements[0], "grep");
-                                                    assert_eq!(elements[1], "awk");
-                                                    assert_eq!(elements[2], "sed");
-                                                }
-                                                _ => panic!(
-                                                    "Expected Node::Array, got something else"
-                                                ),
+                                    if let Node::Assignment { name, value } = statement
+                                        && name == "tools"
+                                    {
+                                        found_array = true;
+                                        match &**value {
+                                            Node::Array { elements } => {
+                                                assert_eq!(elements.len(), 3);
+                                                assert_eq!(elements[0], "grep");
+                                                assert_eq!(elements[1], "awk");
+                                                assert_eq!(elements[2], "sed");
                                             }
+                                            _ => panic!("Expected Node::Array, got something else"),
                                         }
                                     }
                                 }
@@ -5892,6 +6771,45 @@ fi
         }
     }
 
+    #[test]
+    fn test_export_several_names() {
+        let mut parser = Parser::new(Lexer::new("export A B=1 C=x'y z'"));
+        let result = parser.parse_statement().unwrap();
+
+        let Node::List { statements, operators } = result else {
+            panic!("Expected List node, got {result:?}");
+        };
+        assert_eq!(operators, [";", ";"]);
+
+        let exports: Vec<(String, Option<Node>)> = statements
+            .into_iter()
+            .map(|statement| match statement {
+                Node::Export { name, value } => (name, value.map(|value| *value)),
+                _ => panic!("Expected Export node, got {statement:?}"),
+            })
+            .collect();
+        assert_eq!(
+            exports,
+            [
+                ("A".to_string(), None),
+                ("B".to_string(), Some(Node::StringLiteral("1".to_string()))),
+                ("C".to_string(), Some(Node::StringLiteral("xy z".to_string()))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_export_options_parse_as_command() {
+        for input in ["export", "export -p", "export -n A"] {
+            let mut parser = Parser::new(Lexer::new(input));
+            let result = parser.parse_statement().unwrap();
+            assert!(
+                matches!(&result, Node::Command { name, .. } if name == "export"),
+                "{input}: {result:?}"
+            );
+        }
+    }
+
     #[test]
     fn test_glob_patterns_in_commands() {
         // Test various glob patterns in command arguments
@@ -6169,7 +7087,7 @@ fi
                         assert_eq!(name, "[");
                         assert_eq!(args.len(), 3); // "-n", "$HOME", "]"
                         assert_eq!(args[0], "-n");
-                        assert_eq!(args[1], "$HOME");
+                        assert_eq!(args[1], "\"$HOME\"");
                         assert_eq!(args[2], "]");
                     }
                     _ => panic!("Expected Command node for variable test"),
@@ -6399,6 +7317,18 @@ fi
             }
             _ => panic!("Expected List node, got: {result:?}"),
         }
+
+        // A quoted word list stays one word
+        match parse_test("complete -W \"start stop\" service") {
+            Node::List { statements, .. } => match &statements[0] {
+                Node::Complete { options, command } => {
+                    assert_eq!(options, &["-W", "start stop"]);
+                    assert_eq!(command, "service");
+                }
+                other => panic!("Expected Complete node, got: {other:?}"),
+            },
+            other => panic!("Expected List node, got: {other:?}"),
+        }
     }
 
     #[test]
@@ -6653,4 +7583,25 @@ fi
             _ => panic!("Expected List node, got: {result:?}"),
         }
     }
+
+    #[test]
+    fn test_extended_test_words() {
+        let input = r#"[[ $x == a* && -n "$y b" || ( $z =~ ^(a|b)+$ ) ]]"#;
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+
+        match parser.parse_statement().unwrap() {
+            Node::ExtendedTest { condition } => match *condition {
+                Node::Command { args, .. } => assert_eq!(
+                    args,
+                    [
+                        "$x", "==", "a*", "&&", "-n", "\"$y b\"", "||", "(", "$z", "=~",
+                        "^(a|b)+$", ")"
+                    ]
+                ),
+                other => panic!("Expected Command, got {other:?}"),
+            },
+            other => panic!("Expected ExtendedTest node, got {other:?}"),
+        }
+    }
 }
@@ -0,0 +1,134 @@
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+
+// Process group of the command the shell is waiting for, or 0 while it waits for none
+#[cfg(unix)]
+static GROUP: AtomicI32 = AtomicI32::new(0);
+
+// Set when the shell received an interrupt it has not acted on yet
+static CAUGHT: AtomicBool = AtomicBool::new(false);
+
+// SIGTERM or SIGHUP when the shell received one it has not acted on yet, otherwise 0
+static TERMINATING: AtomicI32 = AtomicI32::new(0);
+
+/// Keeps an interrupt from ending the shell. One the shell receives is passed on to the
+/// command running in the foreground, and remembered until [`take_interrupt`].
+#[cfg(unix)]
+pub fn catch_interrupts() {
+    use signal_hook::consts::SIGINT;
+    use std::sync::Once;
+
+    static INSTALLED: Once = Once::new();
+    INSTALLED.call_once(|| {
+        // The handler runs as the signal arrives, so the shell sees a `kill -INT $$`
+        // before it goes on to the next command
+        // SAFETY: the handler only uses atomics and kill, which are async-signal-safe
+        let _ = unsafe {
+            signal_hook::low_level::register(SIGINT, || {
+                CAUGHT.store(true, Ordering::SeqCst);
+                let group = GROUP.load(Ordering::SeqCst);
+                if group > 0 {
+                    libc::kill(-group, libc::SIGINT);
+                }
+            })
+        };
+    });
+}
+
+#[cfg(not(unix))]
+pub fn catch_interrupts() {}
+
+/// Whether an interrupt was caught since the last call
+pub fn take_interrupt() -> bool {
+    CAUGHT.swap(false, Ordering::SeqCst)
+}
+
+/// Keeps SIGTERM and SIGHUP from ending the shell on the spot. It ends between commands
+/// instead, see [`take_termination`], so that its EXIT trap still runs.
+#[cfg(unix)]
+pub fn catch_terminations() {
+    use signal_hook::consts::{SIGHUP, SIGTERM};
+
+    for signal in [SIGTERM, SIGHUP] {
+        // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+        let _ = unsafe {
+            signal_hook::low_level::register(signal, move || {
+                TERMINATING.store(signal, Ordering::SeqCst);
+            })
+        };
+    }
+}
+
+#[cfg(not(unix))]
+pub fn catch_terminations() {}
+
+/// The terminating signal caught since the last call, if any
+pub fn take_termination() -> Option<i32> {
+    match TERMINATING.swap(0, Ordering::SeqCst) {
+        0 => None,
+        signal => Some(signal),
+    }
+}
+
+/// The shell's own process group, for a copy of the shell that should stay in it
+#[cfg(unix)]
+pub fn shell_group() -> i32 {
+    // SAFETY: getpgrp has no memory effects
+    unsafe { libc::getpgrp() }
+}
+
+#[cfg(not(unix))]
+pub fn shell_group() -> i32 {
+    0
+}
+
+/// Gives the terminal to a process group for as long as it lives, so that Ctrl-C goes to
+/// the command instead of the shell
+pub struct Foreground {
+    #[cfg(unix)]
+    group: i32,
+}
+
+impl Foreground {
+    #[cfg(unix)]
+    pub fn new(group: u32) -> Self {
+        let group = group as libc::pid_t;
+        GROUP.store(group, Ordering::SeqCst);
+
+        // SAFETY: these calls only change the terminal's process group and send a signal
+        unsafe {
+            if libc::isatty(libc::STDIN_FILENO) == 1 {
+                libc::tcsetpgrp(libc::STDIN_FILENO, group);
+            }
+            // A command that read the terminal before it was handed over was stopped
+            libc::kill(-group, libc::SIGCONT);
+        }
+        Foreground { group }
+    }
+
+    #[cfg(not(unix))]
+    pub fn new(_group: u32) -> Self {
+        Foreground {}
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Foreground {
+    fn drop(&mut self) {
+        let _ = GROUP.compare_exchange(self.group, 0, Ordering::SeqCst, Ordering::SeqCst);
+
+        // Taking the terminal back from the background would stop the shell with SIGTTOU
+        // SAFETY: the signal sets are initialized before use and only change this thread
+        unsafe {
+            if libc::isatty(libc::STDIN_FILENO) != 1 {
+                return;
+            }
+            let mut block: libc::sigset_t = std::mem::zeroed();
+            let mut previous: libc::sigset_t = std::mem::zeroed();
+            libc::sigemptyset(&mut block);
+            libc::sigaddset(&mut block, libc::SIGTTOU);
+            libc::pthread_sigmask(libc::SIG_BLOCK, &block, &mut previous);
+            libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp());
+            libc::pthread_sigmask(libc::SIG_SETMASK, &previous, std::ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::ops::Range;
+use std::path::Path;
+
+/// Command history shared by the interactive loop and the `history` builtin
+#[derive(Debug, Default)]
+pub struct History {
+    entries: Vec<String>,
+    // Number of leading entries that are already stored in the history file
+    synced: usize,
+    // Entries past `synced` that were read from the file, so appending skips them
+    loaded: Vec<Range<usize>>,
+    // Set when entries were replaced rather than pushed, so line editors can reload
+    reloaded: bool,
+}
+
+impl History {
+    pub fn push(&mut self, line: String) {
+        if line.trim().is_empty() {
+            return;
+        }
+        self.entries.push(line);
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.synced = 0;
+        self.loaded.clear();
+        self.reloaded = true;
+    }
+
+    pub fn take_reloaded(&mut self) -> bool {
+        std::mem::take(&mut self.reloaded)
+    }
+
+    // history -a: append the entries added since the last sync
+    pub fn append_to_file(&mut self, path: &Path) -> std::io::Result<()> {
+        let mut file = OpenOptions::new().append(true).create(true).open(path)?;
+
+        for (i, entry) in self.entries.iter().enumerate().skip(self.synced) {
+            if !self.loaded.iter().any(|range| range.contains(&i)) {
+                writeln!(file, "{}", entry)?;
+            }
+        }
+
+        self.synced = self.entries.len();
+        self.loaded.clear();
+        Ok(())
+    }
+
+    // history -r: read the file and append its contents to the session. Entries typed
+    // before it that were not appended yet still are by the next `history -a`.
+    pub fn read_file(&mut self, path: &Path) -> std::io::Result<()> {
+        let reader = BufReader::new(File::open(path)?);
+        let start = self.entries.len();
+
+        for line in reader.lines() {
+            self.push(line?);
+        }
+
+        if self.synced == start {
+            self.synced = self.entries.len();
+        } else {
+            self.loaded.push(start..self.entries.len());
+        }
+        self.reloaded = true;
+        Ok(())
+    }
+
+    // history -w: overwrite the file with the whole session history
+    pub fn write_file(&mut self, path: &Path) -> std::io::Result<()> {
+        let mut file = File::create(path)?;
+
+        for entry in &self.entries {
+            writeln!(file, "{}", entry)?;
+        }
+
+        self.synced = self.entries.len();
+        self.loaded.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod history_tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("wpcsh-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn test_append_then_read_back() {
+        let path = temp_path("history-append");
+        let _ = std::fs::remove_file(&path);
+
+        let mut history = History::default();
+        history.push("echo one".to_string());
+        history.push("echo two".to_string());
+        history.append_to_file(&path).unwrap();
+
+        // A second append only writes the new entries
+        history.push("echo three".to_string());
+        history.append_to_file(&path).unwrap();
+
+        let mut fresh = History::default();
+        fresh.read_file(&path).unwrap();
+        assert_eq!(fresh.entries(), &["echo one", "echo two", "echo three"]);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_write_replaces_file() {
+        let path = temp_path("history-write");
+        std::fs::write(&path, "old entry\n").unwrap();
+
+        let mut history = History::default();
+        history.push("ls".to_string());
+        history.write_file(&path).unwrap();
+
+        let mut fresh = History::default();
+        fresh.read_file(&path).unwrap();
+        assert_eq!(fresh.entries(), &["ls"]);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_read_does_not_reappend() {
+        let path = temp_path("history-read");
+        std::fs::write(&path, "pwd\n").unwrap();
+
+        let mut history = History::default();
+        history.read_file(&path).unwrap();
+        history.push("cd /".to_string());
+        history.append_to_file(&path).unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "pwd\ncd /\n");
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_read_keeps_session_entries_pending() {
+        let path = temp_path("history-read-pending");
+        std::fs::write(&path, "pwd\n").unwrap();
+
+        // Typed before `history -r`, the entry is still appended by the next `history -a`
+        let mut history = History::default();
+        history.push("echo session".to_string());
+        history.read_file(&path).unwrap();
+        history.push("cd /".to_string());
+        history.append_to_file(&path).unwrap();
+
+        assert_eq!(history.entries(), &["echo session", "pwd", "cd /"]);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "pwd\necho session\ncd /\n");
+
+        // Nothing is pending afterwards
+        history.append_to_file(&path).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "pwd\necho session\ncd /\n");
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_blank_lines_are_skipped() {
+        let mut history = History::default();
+        history.push("   ".to_string());
+        assert!(history.entries().is_empty());
+    }
+
+    #[test]
+    fn test_clear_marks_reloaded() {
+        let mut history = History::default();
+        history.push("ls".to_string());
+        assert!(!history.take_reloaded());
+
+        history.clear();
+        assert!(history.take_reloaded());
+        assert!(!history.take_reloaded());
+    }
+}
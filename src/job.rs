@@ -0,0 +1,240 @@
+use std::process::Child;
+
+/// A command started with `&`, which the shell does not wait for
+#[derive(Debug)]
+pub struct Job {
+    /// The number `%n` refers to
+    pub number: usize,
+    /// The command as it was written, for listing the job
+    pub command: String,
+    pub processes: Vec<Process>,
+    pub state: JobState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    Running,
+    Stopped,
+    /// Finished, with the status of its last process
+    Done(i32),
+}
+
+/// One process of a job: a started command, or a copy of the shell running a builtin,
+/// function or compound command
+#[derive(Debug)]
+pub struct Process {
+    pid: u32,
+    // Where there is no waitpid, the handle is what the process is waited for with
+    #[cfg(not(unix))]
+    child: Child,
+    // Exit status once the process has finished
+    status: Option<i32>,
+    // The signal that ended it, if one did
+    signal: Option<i32>,
+}
+
+impl Process {
+    pub fn spawned(child: Child) -> Self {
+        Process {
+            pid: child.id(),
+            #[cfg(not(unix))]
+            child,
+            status: None,
+            signal: None,
+        }
+    }
+
+    #[cfg(unix)]
+    pub fn forked(pid: u32) -> Self {
+        Process {
+            pid,
+            status: None,
+            signal: None,
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.pid
+    }
+
+    /// Exit status, once the process has finished
+    pub fn status(&self) -> Option<i32> {
+        self.status
+    }
+
+    #[cfg(unix)]
+    fn finish(&mut self, status: i32) {
+        self.status = Some(exit_code(status));
+        self.signal = libc::WIFSIGNALED(status).then(|| libc::WTERMSIG(status));
+    }
+
+    /// Waits for the process to finish and returns its exit status
+    #[cfg(unix)]
+    pub fn wait(&mut self) -> i32 {
+        while self.status.is_none() {
+            let mut status = 0;
+            // SAFETY: waitpid only writes the status through a valid pointer
+            let pid = unsafe { libc::waitpid(self.pid as libc::pid_t, &mut status, 0) };
+
+            if pid > 0 {
+                self.finish(status);
+            } else if std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted {
+                self.status = Some(1);
+            }
+        }
+        self.status.unwrap_or(1)
+    }
+
+    #[cfg(not(unix))]
+    pub fn wait(&mut self) -> i32 {
+        let code = self.child.wait().map_or(1, |status| status.code().unwrap_or(1));
+        self.status = Some(code);
+        code
+    }
+}
+
+// The `$?` of a process that ended with wait status `status`
+#[cfg(unix)]
+fn exit_code(status: i32) -> i32 {
+    match libc::WIFSIGNALED(status) {
+        true => 128 + libc::WTERMSIG(status),
+        false => libc::WEXITSTATUS(status),
+    }
+}
+
+impl Job {
+    pub fn new(number: usize, command: String, processes: Vec<Process>) -> Self {
+        Job {
+            number,
+            command,
+            processes,
+            state: JobState::Running,
+        }
+    }
+
+    /// Process ID of the last process, which `$!` reports
+    pub fn pid(&self) -> u32 {
+        self.processes.last().map_or(0, Process::id)
+    }
+
+    /// Process group of an interactive shell's job, which is that of its first process
+    pub fn group(&self) -> u32 {
+        self.processes.first().map_or(0, Process::id)
+    }
+
+    /// Collects the processes that have finished or stopped, without blocking
+    pub fn poll(&mut self) {
+        self.update(false);
+    }
+
+    /// Waits until the job finishes or stops
+    pub fn wait(&mut self) {
+        self.update(true);
+    }
+
+    #[cfg(unix)]
+    fn update(&mut self, block: bool) {
+        let flags = libc::WUNTRACED | if block { 0 } else { libc::WNOHANG };
+        let mut stopped = false;
+
+        for process in self.processes.iter_mut().filter(|process| process.status.is_none()) {
+            let mut status = 0;
+            // SAFETY: waitpid only writes the status through a valid pointer
+            let pid = unsafe { libc::waitpid(process.pid as libc::pid_t, &mut status, flags) };
+
+            if pid == 0 {
+                continue;
+            }
+            // Someone else already collected it, so its status is lost
+            if pid < 0 {
+                process.status = Some(0);
+            } else if libc::WIFSTOPPED(status) {
+                stopped = true;
+                if block {
+                    break;
+                }
+            } else {
+                process.finish(status);
+            }
+        }
+
+        self.settle(stopped);
+    }
+
+    #[cfg(not(unix))]
+    fn update(&mut self, block: bool) {
+        for process in self.processes.iter_mut().filter(|process| process.status.is_none()) {
+            let status = match block {
+                true => process.child.wait().map(Some),
+                false => process.child.try_wait(),
+            };
+            process.status = match status {
+                Ok(Some(status)) => Some(status.code().unwrap_or(1)),
+                Ok(None) => None,
+                Err(_) => Some(1),
+            };
+        }
+
+        self.settle(false);
+    }
+
+    fn settle(&mut self, stopped: bool) {
+        let statuses: Option<Vec<i32>> = self.processes.iter().map(|p| p.status).collect();
+        self.state = match statuses {
+            Some(statuses) => JobState::Done(statuses.last().copied().unwrap_or(0)),
+            None if stopped => JobState::Stopped,
+            // A job that was stopped stays so until it is continued
+            None => self.state,
+        };
+    }
+
+    /// The state as `jobs` shows it, like `Running`, `Exit 2` or `Terminated`
+    pub fn state_text(&self) -> String {
+        let signal = self.processes.last().and_then(|process| process.signal);
+        match (self.state, signal) {
+            (JobState::Running, _) => "Running".to_string(),
+            (JobState::Stopped, _) => "Stopped".to_string(),
+            (JobState::Done(_), Some(signal)) => crate::signal::description(signal),
+            (JobState::Done(0), None) => "Done".to_string(),
+            (JobState::Done(status), None) => format!("Exit {}", status),
+        }
+    }
+
+    /// Sends SIGCONT to every process that has not finished
+    #[cfg(unix)]
+    pub fn resume(&mut self) {
+        for process in self.processes.iter().filter(|process| process.status.is_none()) {
+            // SAFETY: kill has no memory effects
+            unsafe { libc::kill(process.pid as libc::pid_t, libc::SIGCONT) };
+        }
+        self.state = JobState::Running;
+    }
+
+    /// Sends `signal` to every process that has not finished. A stopped job is continued
+    /// after being asked to end, so that it can.
+    #[cfg(unix)]
+    pub fn signal(&mut self, signal: i32) -> std::io::Result<()> {
+        for process in self.processes.iter().filter(|process| process.status.is_none()) {
+            crate::signal::send(process.pid as i32, signal)?;
+        }
+        if self.state == JobState::Stopped && matches!(signal, libc::SIGTERM | libc::SIGHUP) {
+            self.resume();
+        }
+        Ok(())
+    }
+
+    // Without signals, any of them ends the job
+    #[cfg(not(unix))]
+    pub fn signal(&mut self, _signal: i32) -> std::io::Result<()> {
+        for process in self.processes.iter_mut().filter(|process| process.status.is_none()) {
+            process.child.kill()?;
+        }
+        Ok(())
+    }
+
+    // Nothing can stop a job without job control signals
+    #[cfg(not(unix))]
+    pub fn resume(&mut self) {
+        self.state = JobState::Running;
+    }
+}
@@ -1,34 +1,169 @@
-﻿mod flash;
+﻿mod arith;
+mod brace;
+mod complete;
+mod condition;
+mod expand;
+mod flash;
+mod foreground;
+mod history;
+mod job;
+mod matcher;
+mod options;
+mod path;
+mod printf;
+mod prompt;
+mod redirect;
+mod signal;
+#[allow(dead_code)]
 mod token;
 
 use std::borrow::Cow;
-use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
-use std::io::{ErrorKind, Write};
-use std::ops::Deref;
-use std::path::PathBuf;
-use std::process::{Child, Command, ExitStatus, Stdio};
-
-#[cfg(windows)]
-use std::os::windows::process::ExitStatusExt;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{ErrorKind, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
 
+use crate::complete::CompletionSpec;
 use crate::flash::parser::{Node, Redirect, RedirectKind};
-#[cfg(unix)]
-use std::os::unix::process::ExitStatusExt;
+use crate::foreground::Foreground;
+use crate::history::History;
+use crate::job::{Job, JobState, Process};
+use crate::options::ShellOptions;
+use crate::redirect::StdStreams;
+use crate::signal::Signal;
+
+const BUILTINS: &[&str] = &[
+    "cd", "exit", "export", "alias", "source", "clear", "history", "set", "break", "continue",
+    "command", "shift", "echo", "pushd", "popd", "dirs",
+    "unset", "unalias", "read", "test", "[", "printf", "type", "hash", "which",
+    "jobs", "fg", "bg", "kill", "trap", "exec", "complete", "local",
+    "wait",
+];
+
+// How many aliases one command word may expand through
+const MAX_ALIAS_DEPTH: usize = 32;
 
-const BUILTINS: &[&str] = &["cd", "exit", "export", "alias", "source", "clear"];
+// `$?` of a command ended by Ctrl-C, which is 128 plus SIGINT
+const INTERRUPTED: i32 = 130;
+
+// The function that runs in place of a command that is not found, which distributions use
+// for "did you mean" hints
+const NOT_FOUND_HANDLER: &str = "command_not_found_handler";
 
 fn is_builtin(command: &str) -> bool {
     BUILTINS.contains(&command)
 }
 
+const KEYWORDS: &[&str] = &[
+    "if", "then", "elif", "else", "fi", "case", "esac", "for", "select", "while", "until", "do",
+    "done", "in", "function", "break", "continue", "return", "export", "complete",
+];
+
+// A line that is a single plain word can be spawned without going through the parser
+fn simple_command_word(buffer: &str) -> Option<&str> {
+    let word = buffer.trim_matches(|c: char| c.is_ascii_whitespace());
+
+    let is_plain = !word.is_empty()
+        && word.chars().all(|c| {
+            c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | '/' | '+' | ',' | ':' | '@' | '%')
+        });
+
+    if is_plain && !KEYWORDS.contains(&word) {
+        Some(word)
+    } else {
+        None
+    }
+}
+
+// A `break n`, `continue n` or `return` waiting for the enclosing loops,
+// function or sourced file to unwind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Jump {
+    Break(usize),
+    Continue(usize),
+    Return,
+    // `set -e` saw a command fail; everything up to the outermost `execute` stops
+    Exit,
+    // Ctrl-C ended a foreground command; the rest of the line is skipped, but the shell
+    // goes on
+    Interrupt,
+}
+
+// What a command name stands for, found in the order the shell looks when running it
+enum CommandKind {
+    Alias(String),
+    Function,
+    Builtin,
+    File(PathBuf),
+}
+
+// What one stage of a pipeline runs: a program, or words or a compound command that a
+// copy of the shell runs
+enum Stage {
+    External(String, Command),
+    Words(Vec<String>),
+    Compound(Node),
+}
+
+/// What a command run by [`Shell::execute_capture`] wrote, and its exit status
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    pub status: i32,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
 #[derive(Debug, Default)]
 pub struct Shell {
     home_dir: PathBuf,
     current_dir: PathBuf,
+    // Directories saved by `pushd`, the most recent last
+    dir_stack: Vec<PathBuf>,
     variables: HashMap<String, String>,
+    // Arrays such as PIPESTATUS, by name. A name is either a variable or an array.
+    arrays: HashMap<String, Vec<String>>,
+    // Variables passed on to the environment of started commands
+    exported: HashSet<String>,
     aliases: HashMap<String, String>,
-    exit_status: ExitStatus,
+    functions: HashMap<String, Node>,
+    // Where commands were found in $PATH, which `hash -r` forgets
+    path_cache: RefCell<path::Cache>,
+    // Name the shell was started as, as `$0`
+    shell_name: String,
+    // Arguments of the running function or sourced file, as `$1`, `$2`, ...
+    positional: Vec<String>,
+    // For each running function, innermost last, the values its `local` variables hid
+    scopes: Vec<HashMap<String, Option<String>>>,
+    // `FOO=bar cmd` assignments waiting for the command they prefix
+    command_env: HashMap<String, String>,
+    // Streams commands fall back to, set by an enclosing `$(...)` or redirected `( ... )`
+    streams: StdStreams,
+    // Number of loops the running command is nested in
+    loop_depth: usize,
+    // Number of functions and sourced files being run, which `return` can leave
+    call_depth: usize,
+    // Number of conditions, negations and `&&`/`||` left sides being run, where a
+    // failure does not trip `set -e`
+    condition_depth: usize,
+    jump: Option<Jump>,
+    exit_status: i32,
+    // File and line of the sourced command being run, which error messages mention
+    source_location: Option<(String, usize)>,
+    // Process ID of the most recent background command, as `$!`
+    last_background: Option<u32>,
+    // Commands started with `&`, oldest first
+    jobs: Vec<Job>,
+    // Commands `trap` set to run on a signal or when the shell exits
+    traps: HashMap<Signal, String>,
+    // What `complete` registered for the arguments of each command
+    completions: HashMap<String, CompletionSpec>,
+    history: History,
+    // The last rendered prompt and a hash of the state it was rendered from
+    prompt_cache: Option<(u64, String)>,
+    options: ShellOptions,
+    interactive: bool,
 }
 
 impl Shell {
@@ -37,12 +172,37 @@ impl Shell {
 
         use std::env;
 
+        let variables = env::vars().collect::<HashMap<String, String>>();
         let mut shell = Self {
             home_dir: home_dir.clone(),
             current_dir: home_dir,
-            variables: env::vars().collect::<HashMap<String, String>>(),
+            dir_stack: Vec::new(),
+            // Everything inherited from the environment stays exported
+            exported: variables.keys().cloned().collect(),
+            variables,
+            arrays: HashMap::new(),
             aliases: HashMap::new(),
-            exit_status: ExitStatus::default(),
+            functions: HashMap::new(),
+            path_cache: RefCell::default(),
+            shell_name: env::args().next().unwrap_or_else(|| "wpcsh".to_string()),
+            positional: Vec::new(),
+            scopes: Vec::new(),
+            command_env: HashMap::new(),
+            streams: StdStreams::default(),
+            loop_depth: 0,
+            call_depth: 0,
+            condition_depth: 0,
+            jump: None,
+            last_background: None,
+            jobs: Vec::new(),
+            traps: HashMap::new(),
+            completions: HashMap::new(),
+            exit_status: 0,
+            history: History::default(),
+            prompt_cache: None,
+            source_location: None,
+            options: ShellOptions::default(),
+            interactive: false,
         };
 
         shell.set_default_variables();
@@ -72,6 +232,7 @@ impl Shell {
                 Err(_) => "".to_string(),
             },
         );
+        self.exported.extend(["PWD", "HOME", "SHELL"].map(String::from));
     }
 
     fn set_coreutils_alias(&mut self) {
@@ -99,609 +260,5711 @@ impl CommandContainer {
     }
 }
 
-fn apply_redirect(command: &mut Command, kind: &RedirectKind, target: &str) -> std::io::Result<()> {
-    match kind {
-        RedirectKind::Input => {
-            let file = File::open(target)?;
-            command.stdin(Stdio::from(file));
+/// Makes SIGTERM and SIGHUP end the shell between commands, after its EXIT trap has run,
+/// instead of on the spot
+pub fn catch_terminations() {
+    foreground::catch_terminations();
+}
+
+/// Parses `input` as a script and pretty-prints the resulting AST without running it
+pub fn dump_ast(input: &str) -> String {
+    let lexer = flash::lexer::Lexer::new(input);
+    let mut parser = flash::parser::Parser::new(lexer);
+
+    format!("{:#?}", parser.parse_script())
+}
+
+impl Shell {
+    pub fn execute(&mut self, buffer: &str) -> Result<i32, ErrorKind> {
+        let code = if let Some(word) = simple_command_word(buffer)
+            && !is_builtin(word)
+            && !self.aliases.contains_key(word)
+            && !self.functions.contains_key(word)
+        {
+            self.trace(&[word.to_string()]);
+            let code = self.spawn_command(word.to_string(), Vec::new(), Vec::new())?;
+            self.set_array("PIPESTATUS", [code.to_string()]);
+            self.check_errexit(code);
+            code
+        } else {
+            let lexer = flash::lexer::Lexer::new(buffer);
+            let mut parser = flash::parser::Parser::new(lexer);
+            let statement = parser.parse_script();
+
+            self.execute_node(statement)?
+        };
+        self.exit_status = code;
+
+        if self.call_depth == 0 && self.jump.take_if(|jump| *jump == Jump::Interrupt).is_some() {
+            self.exit_status = INTERRUPTED;
+            return Ok(INTERRUPTED);
+        }
+        // A sourced file stops as a whole; the outermost call reports that the shell should
+        if self.call_depth == 0 && self.jump.take_if(|jump| *jump == Jump::Exit).is_some() {
+            return Err(ErrorKind::Interrupted);
+        }
+        Ok(code)
+    }
+
+    /// Runs `buffer` like `execute`, collecting what it writes to standard output and
+    /// error instead of printing it. Builtins write to the same pipes as the commands
+    /// they start; the shell's own error messages still go to its stderr.
+    pub fn execute_capture(&mut self, buffer: &str) -> std::io::Result<Output> {
+        let (stdout_reader, stdout_writer) = redirect::pipe()?;
+        let (stderr_reader, stderr_writer) = redirect::pipe()?;
+
+        // Read while the commands run so a full pipe cannot block them
+        let collect = |mut reader: File| {
+            std::thread::spawn(move || {
+                let mut bytes = Vec::new();
+                let _ = reader.read_to_end(&mut bytes);
+                bytes
+            })
+        };
+        let (stdout, stderr) = (collect(stdout_reader), collect(stderr_reader));
+
+        let outer_stdout = self.streams.stdout.replace(stdout_writer);
+        let outer_stderr = self.streams.stderr.replace(stderr_writer);
+        let status = self.execute(buffer).unwrap_or(self.exit_status);
+
+        // Dropping our write ends lets the readers see end of file
+        self.streams.stdout = outer_stdout;
+        self.streams.stderr = outer_stderr;
+
+        Ok(Output {
+            status,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        })
+    }
+
+    /// Status of the last command that ran, as `$?`
+    pub fn last_status(&self) -> i32 {
+        self.exit_status
+    }
+
+    /// Value of a shell variable, as `$name` gives it. For an array, that is element 0.
+    pub fn get_var(&self, name: &str) -> Option<&str> {
+        let first = || self.arrays.get(name)?.first();
+        self.variables.get(name).or_else(first).map(String::as_str)
+    }
+
+    /// Sets a shell variable. Only exported ones reach the environment of started commands.
+    pub fn set_var(&mut self, name: &str, value: &str) {
+        self.variables.insert(name.to_string(), value.to_string());
+    }
+
+    /// Sets a variable and exports it, as `export name=value` does
+    pub fn export_var(&mut self, name: &str, value: &str) {
+        self.set_var(name, value);
+        self.exported.insert(name.to_string());
+    }
+
+    /// Removes a shell variable and returns the value it had
+    pub fn unset_var(&mut self, name: &str) -> Option<String> {
+        self.forget_variable(name)
+    }
+
+    /// Every shell variable with its value, in no particular order
+    pub fn vars(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.variables.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// What an alias expands to
+    pub fn get_alias(&self, name: &str) -> Option<&str> {
+        self.aliases.get(name).map(String::as_str)
+    }
+
+    /// Defines an alias, as `alias name=value` does
+    pub fn set_alias(&mut self, name: &str, value: &str) {
+        self.aliases.insert(name.to_string(), value.to_string());
+    }
+
+    /// Removes an alias and returns what it expanded to
+    pub fn unset_alias(&mut self, name: &str) -> Option<String> {
+        self.aliases.remove(name)
+    }
+
+    /// Every alias with what it expands to, in no particular order
+    pub fn aliases(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.aliases.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    // In an interactive shell, a command ended by Ctrl-C stops the whole line, as loops
+    // would otherwise go straight on to the next command
+    fn check_interrupt(&mut self, code: i32) {
+        if code == INTERRUPTED && self.interactive {
+            self.interrupt();
+        }
+    }
+
+    // Acts on an interrupt the shell caught itself, as it does while running builtins or
+    // a script that traps INT, and on a terminating signal, which ends the shell with
+    // 128 plus the signal. True if the rest of the line is skipped.
+    fn check_caught_signals(&mut self) -> bool {
+        if let Some(signal) = foreground::take_termination() {
+            self.exit_status = 128 + signal;
+            self.jump = Some(Jump::Exit);
+            return true;
+        }
+        foreground::take_interrupt() && self.interrupt()
+    }
+
+    // Runs the INT trap, or without one stops what is running. A script ends as if the
+    // signal had killed it.
+    fn interrupt(&mut self) -> bool {
+        if self.traps.contains_key(&Signal::Number(signal::INT)) {
+            self.run_trap(Signal::Number(signal::INT));
+            return false;
         }
-        RedirectKind::Output => {
-            let file = File::create(target)?;
-            command.stdout(Stdio::from(file));
+
+        if self.jump.is_none() {
+            self.exit_status = INTERRUPTED;
+            self.jump = Some(if self.interactive { Jump::Interrupt } else { Jump::Exit });
         }
-        RedirectKind::Append => {
-            let file = OpenOptions::new().append(true).create(true).open(target)?;
-            command.stdout(Stdio::from(file));
+        true
+    }
+
+    // Runs the command trapped on `signal`, which leaves `$?` as it was
+    fn run_trap(&mut self, signal: Signal) {
+        if let Some(command) = self.traps.get(&signal).cloned() {
+            self.run_trap_command(&command);
         }
-        RedirectKind::HereDoc | RedirectKind::HereDocDash => {
-            unimplemented!();
-            // let (mut reader, mut writer) = os_pipe::pipe()?;
-            // writer.write_all(target.as_bytes())?;
-            // drop(writer);
-            // command.stdin(Stdio::from(reader));
+    }
+
+    fn run_trap_command(&mut self, command: &str) {
+        let status = self.exit_status;
+        let jump = self.jump.take();
+        let _ = self.execute(command);
+        self.exit_status = status;
+        self.jump = jump;
+    }
+
+    /// Runs the EXIT trap, if one is set, as the shell is about to exit. It runs only once,
+    /// with `$?` as the status the shell exits with.
+    pub fn run_exit_trap(&mut self) {
+        if let Some(command) = self.traps.remove(&Signal::Exit) {
+            self.run_trap_command(&command);
         }
-        RedirectKind::HereString => {
-            unimplemented!();
-            // let (mut reader, mut writer) = os_pipe::pipe()?;
-            // writer.write_all(target.as_bytes())?;
-            // drop(writer);
-            // command.stdin(Stdio::from(reader));
+    }
+
+    // Hands the terminal to a command the interactive shell waits for
+    fn foreground(&self, group: u32) -> Option<Foreground> {
+        self.interactive.then(|| Foreground::new(group))
+    }
+
+    // Waits for processes run in the foreground and returns their statuses. If they are
+    // stopped, as by Ctrl-Z, they become a job in the table and each status is 128 plus SIGTSTP.
+    fn wait_foreground(&mut self, command: String, processes: Vec<Process>) -> Vec<i32> {
+        if processes.is_empty() {
+            return Vec::new();
         }
-        RedirectKind::InputDup | RedirectKind::OutputDup => {
-            // tutaj trzeba użyć unsafe i dup2 na Unixie, na Windows użyj handli
-            unimplemented!()
+        let mut job = Job::new(0, command, processes);
+        let foreground = self.foreground(job.group());
+        job.wait();
+        drop(foreground);
+
+        if job.state != JobState::Stopped {
+            return job.processes.iter().map(|process| process.status().unwrap_or(0)).collect();
         }
+        let count = job.processes.len();
+        job.number = self.jobs.last().map_or(1, |job| job.number + 1);
+        self.jobs.push(job);
+        eprintln!("{}", self.job_line(self.jobs.len() - 1, false));
+        vec![128 + 20; count]
     }
-    Ok(())
-}
 
-impl Shell {
-    pub fn execute(&mut self, buffer: &str) -> Result<i32, ErrorKind> {
-        let lexer = flash::lexer::Lexer::new(buffer);
-        let mut parser = flash::parser::Parser::new(lexer);
-        let statement = parser.parse_command();
+    // Syntax that parses but that the shell cannot run, which fails like a usage error
+    fn unsupported(&mut self, what: &str) -> Result<i32, ErrorKind> {
+        eprintln!("{}{}: not supported", self.error_prefix(), what);
+        self.exit_status = 2;
+        Ok(2)
+    }
 
-        #[cfg(debug_assertions)]
-        dbg!(&statement);
+    // With `set -e`, a failure outside any condition stops the script
+    fn check_errexit(&mut self, code: i32) {
+        if code != 0 && self.options.errexit && self.condition_depth == 0 && self.jump.is_none() {
+            self.jump = Some(Jump::Exit);
+        }
+    }
 
-        match statement {
+    fn execute_node(&mut self, node: Node) -> Result<i32, ErrorKind> {
+        match node {
             Node::Command {
                 name,
                 args,
                 redirects,
             } => {
-                let (name, args) = self.resolve_alias(Cow::Owned(name), args);
-                let args = args.into_iter().map(|a| self.resolve_variable(Cow::Owned(a))).map(|a| a.to_string()).collect();
-
-                if is_builtin(&name) {
-                    self.execute_command(&mut CommandContainer::new(name, args))
-                } else {
-                    let mut command = Command::new(name);
-                    command.envs(self.variables.iter()).args(args);
-
-                    for redirect in redirects.into_iter() {
-                        apply_redirect(&mut command, &redirect.kind, &redirect.file)
-                            .expect("Failed to apply redirect");
-                    }
+                let (name, args) = self.resolve_alias(&name, args);
 
-                    let status = command
-                        .spawn()
-                        .and_then(|mut c| c.wait())
-                        .expect("Failed to spawn child process");
-                    Ok(status.code().expect("Failed to get exit code"))
-                }
+                let words = match name.as_str() {
+                    "local" => self.expand_declaration(std::iter::once(name).chain(args)),
+                    _ => self.expand_words(std::iter::once(name).chain(args)),
+                };
+                let code = match words {
+                    Ok(words) => self.execute_words(words, redirects)?,
+                    Err(_) => 1,
+                };
+                // A command on its own is a pipeline of one
+                self.set_array("PIPESTATUS", [code.to_string()]);
+                Ok(code)
             }
             Node::Pipeline { commands } => {
-                let mut previous_stdout: Option<Stdio> = None;
-                let mut childrens: Vec<Child> = Vec::new();
-                let length = commands.len();
+                let command = commands.iter().map(job_text).collect::<Vec<_>>().join(" | ");
+                let stages = self.start_pipeline(commands);
 
-                for (i, command) in commands.into_iter().enumerate() {
-                    if let Node::Command {
-                        name,
-                        args,
-                        redirects,
-                    } = command
-                    {
-                        let (name, args) = self.resolve_alias(Cow::Owned(name), args);
+                // Stages that could not start already have their status
+                let failed: Vec<Option<i32>> =
+                    stages.iter().map(|stage| stage.as_ref().err().copied()).collect();
+                let started = stages.into_iter().flatten().collect();
+                let mut codes = self.wait_foreground(command, started).into_iter();
+                let statuses: Vec<i32> = failed
+                    .into_iter()
+                    .map(|code| code.unwrap_or_else(|| codes.next().unwrap_or(0)))
+                    .collect();
+
+                let last_code = match self.options.pipefail {
+                    true => statuses.iter().rev().find(|&&code| code != 0).copied().unwrap_or(0),
+                    false => statuses.last().copied().unwrap_or(0),
+                };
+                self.set_array("PIPESTATUS", statuses.iter().map(i32::to_string));
+                self.exit_status = last_code;
+                self.check_interrupt(last_code);
+                Ok(last_code)
+            }
+            Node::List {
+                statements,
+                operators,
+            } => {
+                let mut code = self.exit_status;
+                let mut run_next = true;
 
-                        let mut command = Command::new(name);
-                        command.envs(self.variables.iter()).args(args);
+                // operators[i] joins statements[i] and statements[i + 1]
+                for (i, statement) in statements.into_iter().enumerate() {
+                    let operator = operators.get(i).map(String::as_str);
 
-                        if let Some(stdin) = previous_stdout.take() {
-                            command.stdin(stdin);
+                    // A signal that arrived since the last command is acted on before the next
+                    if run_next && self.check_caught_signals() {
+                        code = self.exit_status;
+                        break;
+                    }
+                    if run_next {
+                        // `FOO=bar cmd` parses as an assignment joined to cmd without an operator
+                        if let Node::Assignment { name, value } = &statement
+                            && operator == Some("")
+                        {
+                            match self.assignment_value(value) {
+                                Ok(value) => {
+                                    self.command_env.insert(name.clone(), value);
+                                }
+                                // A failed expansion also skips the command it prefixes
+                                Err(_) => {
+                                    code = 1;
+                                    run_next = false;
+                                    self.command_env.clear();
+                                }
+                            }
+                            continue;
                         }
 
-                        let is_last = i == length - 1;
+                        // Only the last command of an `&&`/`||` chain can trip `set -e`, and
+                        // never one whose status is inverted with `!`
+                        let guarded = matches!(operator, Some("&&" | "||"));
+                        let negated = matches!(statement, Node::Negation { .. });
+                        self.condition_depth += guarded as usize;
+                        let result = match operator {
+                            Some("&") => self.run_in_background(statement),
+                            _ => self.execute_node(statement),
+                        };
+                        self.condition_depth -= guarded as usize;
 
-                        if !is_last {
-                            command.stdout(Stdio::piped());
-                        } else {
-                            command.stdout(Stdio::inherit());
+                        code = result?;
+                        self.exit_status = code;
+                        self.command_env.clear();
+                        if !guarded && !negated {
+                            self.check_errexit(code);
                         }
-
-                        for redirect in redirects.into_iter() {
-                            apply_redirect(&mut command, &redirect.kind, &redirect.file)
-                                .expect("Failed to apply redirect");
+                        if self.check_caught_signals() {
+                            code = self.exit_status;
                         }
 
-                        let mut child = command.spawn().expect("Failed to spawn child process");
-
-                        if !is_last {
-                            previous_stdout = Some(child.stdout.take().unwrap().into())
+                        // The rest of the list is skipped until the loop handles the jump
+                        if self.jump.is_some() {
+                            break;
                         }
-
-                        childrens.push(child);
                     }
-                }
 
-                let mut last_code = 0;
-                for mut child in childrens {
-                    let status = child.wait().ok();
-                    if let Some(code) = status.and_then(|s| s.code()) {
-                        last_code = code;
-                    }
+                    run_next = match operator {
+                        Some("&&") => code == 0,
+                        Some("||") => code != 0,
+                        // A skipped prefix assignment also skips its command
+                        Some("") => run_next,
+                        _ => true,
+                    };
                 }
 
-                Ok(last_code)
-            }
-            Node::List {
-                statements,
-                operators,
-            } => {
-                println!("{:?}", statements);
-                println!("{:?}", operators);
-                unimplemented!()
-            }
-            Node::Assignment { .. } => {
-                unimplemented!()
-            }
-            Node::CommandSubstitution { .. } => {
-                unimplemented!()
-            }
-            Node::ArithmeticExpansion { .. } => {
-                unimplemented!()
-            }
-            Node::ArithmeticCommand { .. } => {
-                unimplemented!()
+                Ok(code)
             }
-            Node::Subshell { .. } => {
-                unimplemented!()
-            }
-            Node::Comment(_) => {
-                unimplemented!()
-            }
-            Node::StringLiteral(_) => {
-                unimplemented!()
-            }
-            Node::SingleQuotedString(_) => {
-                unimplemented!()
-            }
-            Node::ExtGlobPattern { .. } => {
-                unimplemented!()
-            }
-            Node::IfStatement { .. } => {
-                unimplemented!()
-            }
-            Node::ElifBranch { .. } => {
-                unimplemented!()
+            Node::Assignment { name, value } => {
+                // Prefixes without a command, as in `A=1 B=2`, are plain assignments
+                let prefixed = std::mem::take(&mut self.command_env);
+                self.variables.extend(prefixed);
+
+                let substituted = matches!(*value, Node::CommandSubstitution { .. });
+                let Ok(value) = self.assignment_value(&value) else {
+                    return Ok(1);
+                };
+                self.variables.insert(name, value);
+
+                // Like other shells, `x=$(cmd)` reports the status of cmd
+                Ok(if substituted { self.exit_status } else { 0 })
             }
-            Node::ElseBranch { .. } => {
-                unimplemented!()
+            Node::CommandSubstitution { command } => {
+                // A substitution in command position runs its output as a command
+                let output = self.capture_output(*command);
+                let words = output.split_whitespace().map(String::from).collect();
+                self.execute_words(words, Vec::new())
             }
-            Node::CaseStatement { .. } => {
-                unimplemented!()
+            Node::ArithmeticExpansion { expression } => match self.evaluate_arithmetic(&expression) {
+                Ok(value) => self.execute_words(vec![value.to_string()], Vec::new()),
+                Err(_) => Ok(1),
+            },
+            // `(( expression ))` succeeds when the expression is not 0
+            Node::ArithmeticCommand { expression } => {
+                let code = match self.evaluate_arithmetic(&expression) {
+                    Ok(value) => (value == 0) as i32,
+                    Err(_) => 1,
+                };
+                self.exit_status = code;
+                Ok(code)
             }
-            Node::Array { .. } => {
-                unimplemented!()
+            Node::Subshell { list, redirects } => {
+                // The body runs in a copy of the shell, so neither what it changes nor an
+                // `exit` in it reaches the shell
+                let command = format!("( {} )", job_text(&list));
+                let processes =
+                    self.fork_job(0, |shell| shell.execute_redirected(*list, &redirects));
+                let codes = self.wait_foreground(command, processes);
+                let code = codes.last().copied().unwrap_or(self.exit_status);
+
+                self.exit_status = code;
+                self.check_interrupt(code);
+                Ok(code)
             }
-            Node::Function { .. } => {
-                unimplemented!()
+            // A comment leaves `$?` alone
+            Node::Comment(_) => Ok(self.exit_status),
+            Node::StringLiteral(_) | Node::SingleQuotedString(_) => self.unsupported("string"),
+            Node::ExtGlobPattern { .. } => self.unsupported("extended glob"),
+            Node::IfStatement {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                if self.execute_condition(*condition)? {
+                    self.execute_node(*consequence)
+                } else if let Some(alternative) = alternative {
+                    self.execute_node(*alternative)
+                } else {
+                    // No branch was taken
+                    Ok(0)
+                }
             }
-            Node::FunctionCall { .. } => {
-                unimplemented!()
+            Node::ElifBranch {
+                condition,
+                consequence,
+            } => {
+                if self.execute_condition(*condition)? {
+                    self.execute_node(*consequence)
+                } else {
+                    Ok(0)
+                }
             }
-            Node::Export { name, value } => {
-                match value.unwrap().deref() {
-                    Node::StringLiteral(value) => self.add_variable(&format!("{}={}", name, value)),
-                    _ => {}
+            Node::ElseBranch { consequence } => self.execute_node(*consequence),
+            Node::CaseStatement {
+                expression,
+                patterns,
+            } => {
+                let Ok(subject) = self.assignment_value(&expression) else {
+                    return Ok(1);
                 };
+
+                // Only the first branch with a matching pattern runs
+                for branch in patterns {
+                    for pattern in &branch.patterns {
+                        let Ok(pattern) = self.expand_pattern(pattern) else {
+                            return Ok(1);
+                        };
+
+                        if matcher::matches(&pattern, &subject) {
+                            return self.execute_node(*branch.body);
+                        }
+                    }
+                }
+
                 Ok(0)
             }
-            Node::Return { .. } => {
-                unimplemented!()
-            }
-            Node::ExtendedTest { .. } => {
-                unimplemented!()
-            }
-            Node::HistoryExpansion { .. } => {
-                unimplemented!()
-            }
-            Node::Complete { .. } => {
-                unimplemented!()
-            }
-            Node::ForLoop { .. } => {
-                unimplemented!()
-            }
-            Node::WhileLoop { .. } => {
-                unimplemented!()
+            Node::Array { .. } => self.unsupported("array"),
+            Node::Function { name, body } => {
+                self.functions.insert(name, *body);
+                Ok(0)
             }
-            Node::UntilLoop { .. } => {
-                unimplemented!()
+            Node::FunctionCall {
+                name,
+                args,
+                redirects,
+            } => match self.expand_words(std::iter::once(name).chain(args)) {
+                Ok(words) => self.execute_words(words, redirects),
+                Err(_) => Ok(1),
+            },
+            Node::Export { name, value } => {
+                if let Some(value) = value {
+                    let Ok(value) = self.assignment_value(&value) else {
+                        return Ok(1);
+                    };
+                    self.variables.insert(name.clone(), value);
+                }
+                self.exported.insert(name);
+                Ok(0)
             }
-            Node::Negation { .. } => {
-                unimplemented!()
+            Node::Return { value } => {
+                let code = match value {
+                    None => 0,
+                    Some(value) => {
+                        let Ok(value) = self.assignment_value(&value) else {
+                            return Ok(1);
+                        };
+                        match value.trim().parse::<i64>() {
+                            Ok(code) => code.rem_euclid(256) as i32,
+                            Err(_) => {
+                                eprintln!(
+                                    "{}return: {}: numeric argument required",
+                                    self.error_prefix(),
+                                    value
+                                );
+                                255
+                            }
+                        }
+                    }
+                };
+
+                // Unlike exit, a stray return only complains
+                if self.call_depth == 0 {
+                    eprintln!(
+                        "{}return: can only `return' from a function or sourced script",
+                        self.error_prefix()
+                    );
+                    return Ok(1);
+                }
+
+                self.jump = Some(Jump::Return);
+                Ok(code)
             }
-            Node::SelectStatement { .. } => {
-                unimplemented!()
+            Node::ExtendedTest { condition } => {
+                let Node::Command { args, .. } = condition.as_ref() else {
+                    return Ok(0);
+                };
+                Ok(self.extended_test(args))
             }
-            Node::Group { .. } => {
-                unimplemented!()
+            Node::HistoryExpansion { .. } => self.unsupported("history expansion"),
+            Node::Complete { options, command } => {
+                let mut args = options;
+                if !command.is_empty() {
+                    args.push(command);
+                }
+                self.execute_node(Node::Command {
+                    name: "complete".to_string(),
+                    args,
+                    redirects: Vec::new(),
+                })
             }
-            Node::ParameterExpansion { .. } => {
-                unimplemented!()
+            Node::ForLoop {
+                variable,
+                iterable,
+                body,
+            } => {
+                let elements = match *iterable {
+                    Node::Array { elements } => elements,
+                    _ => Vec::new(),
+                };
+                let Ok(words) = self.expand_words(elements) else {
+                    return Ok(1);
+                };
+
+                // A loop that never runs its body succeeds
+                let mut code = 0;
+                for word in words {
+                    self.variables.insert(variable.clone(), word);
+
+                    let keep_going;
+                    (code, keep_going) = self.execute_loop_body((*body).clone())?;
+                    if !keep_going {
+                        break;
+                    }
+                }
+
+                Ok(code)
             }
-            Node::ProcessSubstitution { .. } => {
-                unimplemented!()
+            Node::WhileLoop { condition, body } => self.execute_loop(*condition, *body, true),
+            Node::UntilLoop { condition, body } => self.execute_loop(*condition, *body, false),
+            Node::Negation { command } => {
+                self.condition_depth += 1;
+                let result = self.execute_node(*command);
+                self.condition_depth -= 1;
+
+                Ok(if result? == 0 { 1 } else { 0 })
             }
+            Node::SelectStatement { .. } => self.unsupported("select"),
+            // Unlike a subshell, a group runs in the current shell state
+            Node::Group { list, redirects } => self.execute_redirected(*list, &redirects),
+            Node::ParameterExpansion { .. } => self.unsupported("parameter expansion"),
+            Node::ProcessSubstitution { .. } => self.unsupported("process substitution"),
         }
     }
 
-    fn execute_command(&mut self, command: &mut CommandContainer) -> Result<i32, ErrorKind> {
-        let _ = match command.program.as_str() {
-            "clear" => self.clear_terminal(),
-            "cd" => self.change_directory(&command.args),
-            "export" => {
-                self.add_variable(&command.args.join(" "));
-                Ok(())
-            }
-            "alias" => {
-                for arg in &command.args {
-                    self.add_alias(&arg);
-                }
-                Ok(())
-            }
-            "exit" => self.exit(command),
-            "source" => self.source_command(command),
-            _ => unreachable!()
-        };
+    // Runs the condition of an `if` or loop; a zero status counts as true
+    fn execute_condition(&mut self, condition: Node) -> Result<bool, ErrorKind> {
+        self.condition_depth += 1;
+        let result = self.execute_node(condition);
+        self.condition_depth -= 1;
 
-        Ok(0)
+        let code = result?;
+        self.exit_status = code;
+        Ok(code == 0)
     }
 
-    fn exit(&mut self, command: &CommandContainer) -> Result<(), ErrorKind> {
-        let code = command
-            .args
-            .get(0)
-            .and_then(|a| a.parse::<i32>().ok())
-            .unwrap_or(0);
+    // Runs `body` for as long as the condition's success matches `run_while`
+    fn execute_loop(
+        &mut self,
+        condition: Node,
+        body: Node,
+        run_while: bool,
+    ) -> Result<i32, ErrorKind> {
+        // A loop that never runs its body succeeds
+        let mut code = 0;
 
-        std::process::exit(code);
+        while self.execute_condition(condition.clone())? == run_while {
+            let keep_going;
+            (code, keep_going) = self.execute_loop_body(body.clone())?;
+            if !keep_going {
+                break;
+            }
+        }
+
+        Ok(code)
     }
 
-    fn source_command(&mut self, command: &mut CommandContainer) -> Result<(), ErrorKind> {
-        let path = match command.args.get(0) {
-            Some(path) => PathBuf::from(path),
-            None => return Err(ErrorKind::InvalidInput),
-        };
+    // Runs one iteration and returns its status and whether the loop goes on
+    fn execute_loop_body(&mut self, body: Node) -> Result<(i32, bool), ErrorKind> {
+        self.loop_depth += 1;
+        let result = self.execute_node(body);
+        self.loop_depth -= 1;
 
-        self.source(path)
-    }
+        let mut code = result?;
+        self.exit_status = code;
+        if self.check_caught_signals() {
+            code = self.exit_status;
+        }
 
-    fn source(&mut self, path: PathBuf) -> Result<(), ErrorKind> {
-        let file = match File::open(&path) {
-            Ok(f) => f,
-            Err(_) => return Err(ErrorKind::InvalidInput),
+        // A count above one is passed on to the enclosing loop
+        let keep_going = match self.jump.take() {
+            None | Some(Jump::Continue(1)) => true,
+            Some(jump @ (Jump::Return | Jump::Exit | Jump::Interrupt)) => {
+                self.jump = Some(jump);
+                false
+            }
+            Some(Jump::Break(1)) => false,
+            Some(Jump::Break(count)) => {
+                self.jump = Some(Jump::Break(count - 1));
+                false
+            }
+            Some(Jump::Continue(count)) => {
+                self.jump = Some(Jump::Continue(count - 1));
+                false
+            }
         };
 
-        let reader = std::io::BufReader::new(file);
+        Ok((code, keep_going))
+    }
 
-        use std::io::BufRead;
-        for line in reader.lines().flatten() {
-            let l = line.trim().to_string();
-            if l.is_empty() || l.starts_with('#') {
-                continue;
-            }
+    // Runs `node` with `redirects` applied to every command inside it
+    fn execute_redirected(&mut self, node: Node, redirects: &[Redirect]) -> Result<i32, ErrorKind> {
+        self.with_redirects(redirects, |shell| shell.execute_node(node))
+    }
 
-            self.execute(&l)?;
+    // Runs `run` with the redirected streams as the ones commands inherit
+    fn with_redirects(
+        &mut self,
+        redirects: &[Redirect],
+        run: impl FnOnce(&mut Self) -> Result<i32, ErrorKind>,
+    ) -> Result<i32, ErrorKind> {
+        let mut streams = StdStreams::default();
+        if let Err(err) = self.open_redirects(&mut streams, redirects) {
+            self.report_redirect_error(&err);
+            return Ok(1);
         }
 
-        Ok(())
+        let outer = std::mem::replace(&mut self.streams, streams);
+        let result = run(self);
+        self.streams = outer;
+
+        result
     }
 
-    pub fn load_login_config(&mut self) {
-        let path = self.home_dir.join(".wpcsh_profile");
-        let _ = self.source(path);
+    // With `set -x`, prints the expanded command to stderr after $PS4, `+ ` by default
+    fn trace(&self, words: &[String]) {
+        if !self.options.xtrace {
+            return;
+        }
+
+        let prefix = self.variables.get("PS4").map(String::as_str).unwrap_or("+ ");
+        let words: Vec<Cow<str>> = words.iter().map(|word| trace_word(word)).collect();
+        let line = format!("{}{}\n", prefix, words.join(" "));
+
+        let _ = self.stderr().write_all(line.as_bytes());
     }
 
-    pub fn load_interactive_config(&mut self) {
-        let path = self.home_dir.join(".wpcshrc");
-        let _ = self.source(path);
+    // Exported variables, which a started command gets in its environment on top of the
+    // one the shell inherited. Borrowed rather than copied.
+    fn child_env(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.variables.iter().filter(|(name, _)| self.exported.contains(*name))
     }
 
-    fn get_result_of_external_command(
-        &mut self,
-        name: String,
-        args: Vec<String>,
-        redirects: Vec<Redirect>,
-    ) -> Result<std::process::Output, ErrorKind> {
-        let (name, args) = self.resolve_alias(Cow::Owned(name), args);
-
-        let mut command = Command::new(name);
-        command.envs(self.variables.iter()).args(args);
-
-        for redirect in redirects.into_iter() {
-            apply_redirect(&mut command, &redirect.kind, &redirect.file)
-                .expect("Failed to apply redirect");
-        }
-
-        let status = command.output().expect("Failed to execute child process");
-        Ok(status)
-    }
-
-    fn resolve_alias(&self, cmd: Cow<String>, args: Vec<String>) -> (String, Vec<String>) {
-        let alias = self.aliases.get(cmd.as_ref()).unwrap_or(cmd.as_ref());
-        let mut split = alias.split_whitespace();
-        let name = split.next().unwrap_or(cmd.as_ref()).to_string();
-        let mut argv = split.map(String::from).collect::<Vec<String>>();
-        argv.extend(args);
-
-        (name, argv)
-    }
-
-    // fn resolve_alias(&self, command: &str) -> Vec<String> {
-    //     let mut tokens = vec![command.to_owned()];
-    //     let mut seen = std::collections::HashSet::new();
-    //
-    //     while let Some(alias) = self.aliases.get(&tokens[0]) {
-    //         if !seen.insert(tokens[0].clone()) || seen.len() > 32 {
-    //             break;
-    //         }
-    //
-    //         let mut alias_tokens = match tokenize(alias) {
-    //             Ok(tokens) => tokens,
-    //             Err(_) => continue,
-    //         };
-    //
-    //         alias_tokens.extend(tokens.drain(1..));
-    //         tokens = alias_tokens;
-    //     }
-    //
-    //     tokens
-    // }
-
-    fn resolve_variable<'a>(&'a self, arg: Cow<'a, String>) -> Cow<'a, String> {
-        let arg = if arg.starts_with('~') {
-            Cow::Owned(arg.replace("~", &self.home_dir.to_string_lossy()))
-        } else {
-            arg
-        };
+    // Every name a command word can complete to: builtins, aliases, functions and the
+    // commands in $PATH
+    fn command_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = BUILTINS.iter().map(|name| name.to_string()).collect();
+        names.extend(self.aliases.keys().cloned());
+        names.extend(self.functions.keys().cloned());
+        names.extend_from_slice(self.path_cache.borrow_mut().commands(&self.search_path()));
+        names.sort();
+        names.dedup();
+        names
+    }
 
-        let input = arg.as_ref();
-        if !input.contains('$') {
-            return arg;
+    // The file `name` runs when it is no builtin or function: itself when it holds a slash,
+    // or else the first executable of that name in $PATH
+    fn which(&self, name: &str) -> Option<PathBuf> {
+        if name.contains(['/', std::path::MAIN_SEPARATOR]) {
+            return path::find_file(name);
         }
+        self.path_cache.borrow_mut().find(name, &self.search_path())
+    }
 
-        let mut out = String::with_capacity(input.len());
-        let mut i = 0;
+    // $PATH, or the one the shell inherited when it has no variable of its own
+    fn search_path(&self) -> String {
+        match self.variables.get("PATH") {
+            Some(path) => path.clone(),
+            None => std::env::var("PATH").unwrap_or_default(),
+        }
+    }
 
-        while i < input.len() {
-            let ch = input[i..].chars().next().unwrap();
-            if ch != '$' {
-                out.push(ch);
-                i += ch.len_utf8();
-                continue;
-            }
+    // A command that starts the file `name` runs, or the status for a name that runs
+    // nothing after saying why: 127 when there is no such file and 126 when it cannot run
+    fn external_command(&self, name: &str) -> Result<Command, i32> {
+        let Some(program) = self.which(name) else {
+            return Err(self.report_unrunnable(name));
+        };
 
-            let next = i + ch.len_utf8();
-            if next >= input.len() {
-                out.push('$');
-                break;
-            }
+        let mut command = Command::new(program);
+        // The command still sees the name it was called by as its $0
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::arg0(&mut command, name);
+        command.envs(self.child_env());
+        Ok(command)
+    }
 
-            let next_ch = input[next..].chars().next().unwrap();
-            if next_ch == '?' {
-                out.push_str(&self.exit_status.code().unwrap_or(0).to_string());
-                i = next + next_ch.len_utf8();
-                continue;
+    fn report_unrunnable(&self, name: &str) -> i32 {
+        let (message, status) = if name.contains(['/', std::path::MAIN_SEPARATOR]) {
+            match std::fs::metadata(name) {
+                Ok(meta) if meta.is_dir() => ("is a directory", 126),
+                Ok(_) => ("permission denied", 126),
+                Err(_) => ("no such file or directory", 127),
             }
+        } else if path::candidates(name, &self.search_path()).any(|file| file.is_file()) {
+            ("permission denied", 126)
+        } else {
+            ("command not found", 127)
+        };
 
-            if !next_ch.is_ascii_alphanumeric() {
-                out.push('$');
-                i = next;
-                continue;
-            }
+        eprintln!("{}{}: {}", self.error_prefix(), name, message);
+        status
+    }
 
-            let mut end = next;
-            for (offset, c) in input[next..].char_indices() {
-                if !c.is_ascii_alphanumeric() {
-                    break;
+    fn command_kind(&self, name: &str) -> Option<CommandKind> {
+        if let Some(value) = self.aliases.get(name) {
+            return Some(CommandKind::Alias(value.clone()));
+        }
+        if self.functions.contains_key(name) {
+            return Some(CommandKind::Function);
+        }
+        if is_builtin(name) {
+            return Some(CommandKind::Builtin);
+        }
+        self.which(name).map(CommandKind::File)
+    }
+
+    // Where builtins write their output: a redirect or capture if there is one
+    fn stdout(&self) -> Box<dyn Write + '_> {
+        match &self.streams.stdout {
+            Some(file) => Box::new(file),
+            None => Box::new(std::io::stdout()),
+        }
+    }
+
+    fn stderr(&self) -> Box<dyn Write + '_> {
+        match &self.streams.stderr {
+            Some(file) => Box::new(file),
+            None => Box::new(std::io::stderr()),
+        }
+    }
+
+    fn execute_words(
+        &mut self,
+        mut words: Vec<String>,
+        redirects: Vec<Redirect>,
+    ) -> Result<i32, ErrorKind> {
+        // A command made only of empty expansions does nothing
+        if words.is_empty() {
+            return Ok(self.exit_status);
+        }
+        self.trace(&words);
+        let mut name = words.remove(0);
+
+        // `command name` skips functions, so a function can wrap the command it shadows,
+        // while `command -v name` is the builtin
+        let bypass_functions =
+            name == "command" && words.first().is_some_and(|word| !word.starts_with('-'));
+        if bypass_functions {
+            name = words.remove(0);
+        } else if let Some(body) = self.functions.get(&name) {
+            let body = body.clone();
+            return self.with_prefixes(|shell| shell.call_function(body, words, redirects));
+        }
+
+        // The redirections of `exec` outlast it, so they are not undone like other builtins'
+        if name == "exec" {
+            self.exit_status = 0;
+            let _ = self.exec_command(&words, &redirects);
+            return Ok(self.exit_status);
+        }
+
+        if is_builtin(&name) {
+            let mut command = CommandContainer::new(name, words);
+            self.with_prefixes(|shell| match redirects.is_empty() {
+                true => shell.execute_command(&mut command),
+                false => {
+                    shell.with_redirects(&redirects, |shell| shell.execute_command(&mut command))
+                }
+            })
+        } else {
+            self.spawn_command(name, words, redirects)
+        }
+    }
+
+    // Runs a builtin or function with the prefix assignments of its command, as in
+    // `IFS=: read a b`, set as variables for as long as it runs
+    fn with_prefixes(
+        &mut self,
+        run: impl FnOnce(&mut Self) -> Result<i32, ErrorKind>,
+    ) -> Result<i32, ErrorKind> {
+        let prefixed = std::mem::take(&mut self.command_env);
+        let outer: Vec<(String, Option<String>)> = prefixed
+            .into_iter()
+            .map(|(name, value)| (name.clone(), self.variables.insert(name, value)))
+            .collect();
+
+        let result = run(self);
+
+        for (name, value) in outer {
+            match value {
+                Some(value) => self.variables.insert(name, value),
+                None => self.variables.remove(&name),
+            };
+        }
+        result
+    }
+
+    // Starts every command of a pipeline, each reading what the one before writes. Returns
+    // each started process, or the status of a command that failed before it could run.
+    fn start_pipeline(&mut self, commands: Vec<Node>) -> Vec<Result<Process, i32>> {
+        let mut previous_stdout: Option<File> = None;
+        let mut stages: Vec<Result<Process, i32>> = Vec::new();
+        let length = commands.len();
+
+        for (i, command) in commands.into_iter().enumerate() {
+            let (stage, redirects) = match command {
+                Node::Command {
+                    name,
+                    args,
+                    redirects,
+                } => {
+                    let (name, args) = self.resolve_alias(&name, args);
+                    let mut words = match self.expand_words(std::iter::once(name).chain(args)) {
+                        Ok(words) if !words.is_empty() => words,
+                        Ok(_) => {
+                            stages.push(Err(0));
+                            continue;
+                        }
+                        Err(_) => {
+                            stages.push(Err(1));
+                            continue;
+                        }
+                    };
+
+                    // Builtins and functions run in a copy of the shell, which writes to the pipe,
+                    // as does the handler for a command that is not found
+                    let internal = is_builtin(&words[0])
+                        || self.functions.contains_key(&words[0])
+                        || (self.functions.contains_key(NOT_FOUND_HANDLER)
+                            && self.which(&words[0]).is_none());
+                    if internal {
+                        (Stage::Words(words), redirects)
+                    } else {
+                        self.trace(&words);
+                        let name = words.remove(0);
+                        let mut command = match self.external_command(&name) {
+                            Ok(command) => command,
+                            Err(code) => {
+                                stages.push(Err(code));
+                                continue;
+                            }
+                        };
+                        command.args(&words);
+                        if i == 0 {
+                            command.envs(&self.command_env);
+                        }
+                        (Stage::External(name, command), redirects)
+                    }
+                }
+                // Compound commands like `while` run whole in a copy of the shell
+                node => (Stage::Compound(node), Vec::new()),
+            };
+
+            let mut streams = StdStreams {
+                stdin: previous_stdout.take(),
+                ..StdStreams::default()
+            };
+
+            // The pipe is in place before redirects so `2>&1` can join it
+            if i < length - 1 {
+                match redirect::pipe() {
+                    Ok((reader, writer)) => {
+                        streams.stdout = Some(writer);
+                        previous_stdout = Some(reader);
+                    }
+                    // Commands already started are still waited for by the caller
+                    Err(err) => {
+                        eprintln!("{}pipe error: {}", self.error_prefix(), err);
+                        stages.push(Err(1));
+                        break;
+                    }
                 }
-                end = next + offset + c.len_utf8();
             }
 
-            let name = &input[next..end];
-            if let Some(val) = self.variables.get(name) {
-                out.push_str(val);
-            } else {
-                out.push('$');
-                out.push_str(name);
+            // The command is skipped, but the rest of the pipeline still runs
+            if let Err(err) = self.open_redirects(&mut streams, &redirects) {
+                self.report_redirect_error(&err);
+                stages.push(Err(1));
+                continue;
             }
+            // The whole pipeline shares the process group of its first command
+            let group = first_process(&stages).map_or(0, |first| first.id() as i32);
 
-            i = end;
+            let (name, mut command) = match stage {
+                Stage::External(name, command) => (name, command),
+                stage => {
+                    let next_stdin = &mut previous_stdout;
+                    let mut processes = self.fork_job(group, |shell| {
+                        // Holding the next command's end would keep the pipe from closing
+                        drop(next_stdin.take());
+                        if i > 0 {
+                            shell.command_env.clear();
+                        }
+                        let outer = std::mem::replace(&mut shell.streams, streams);
+                        let result = match stage {
+                            Stage::Words(words) => shell.execute_words(words, Vec::new()),
+                            Stage::Compound(node) => shell.execute_node(node),
+                            Stage::External(..) => unreachable!(),
+                        };
+                        shell.streams = outer;
+                        result
+                    });
+                    stages.push(processes.pop().ok_or(self.exit_status));
+                    continue;
+                }
+            };
+
+            streams.apply_to(&mut command);
+            #[cfg(unix)]
+            if self.interactive {
+                std::os::unix::process::CommandExt::process_group(&mut command, group);
+            }
+
+            stages.push(
+                command
+                    .spawn()
+                    .map(Process::spawned)
+                    .map_err(|err| report_spawn_error(&self.error_prefix(), &name, &err)),
+            );
         }
 
-        Cow::Owned(out)
+        stages
     }
 
-    pub fn change_directory(&mut self, args: &[String]) -> Result<(), ErrorKind> {
-        if args.len() > 1 {
-            self.exit_status = ExitStatus::from_raw(1);
-            return Err(ErrorKind::InvalidInput);
+    fn call_function(
+        &mut self,
+        body: Node,
+        args: Vec<String>,
+        redirects: Vec<Redirect>,
+    ) -> Result<i32, ErrorKind> {
+        let outer_args = std::mem::replace(&mut self.positional, args);
+        self.call_depth += 1;
+        self.scopes.push(HashMap::new());
+
+        let result = self.execute_redirected(body, &redirects);
+
+        for (name, value) in self.scopes.pop().unwrap_or_default() {
+            match value {
+                Some(value) => self.variables.insert(name, value),
+                None => self.variables.remove(&name),
+            };
         }
+        self.call_depth -= 1;
+        self.positional = outer_args;
+        self.jump.take_if(|jump| *jump == Jump::Return);
 
-        let new_dir = match args.get(0) {
-            Some(path) => {
-                let path = if path.starts_with('~') {
-                    let rest = &path[1..];
-                    self.home_dir.join(rest)
-                } else {
-                    self.current_dir.join(path)
-                };
+        result
+    }
+
+    fn spawn_command(
+        &mut self,
+        name: String,
+        args: Vec<String>,
+        redirects: Vec<Redirect>,
+    ) -> Result<i32, ErrorKind> {
+        if let Some(result) = self.handle_not_found(&name, &args, &redirects) {
+            self.exit_status = result?;
+            return Ok(self.exit_status);
+        }
 
-                path
+        // The words as a stopped job lists them
+        let command = std::iter::once(&name)
+            .chain(&args)
+            .map(|word| match word.contains(char::is_whitespace) {
+                true => single_quote(word),
+                false => word.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        let child = match self.start_command(&name, args, &redirects) {
+            Ok(child) => child,
+            Err(code) => {
+                self.exit_status = code;
+                return Ok(code);
             }
-            None => self.home_dir.clone(),
         };
 
-        let new_dir = normalize_path(new_dir);
+        let processes = vec![Process::spawned(child)];
+        self.exit_status = self.wait_foreground(command, processes).pop().unwrap_or(1);
 
-        if std::env::set_current_dir(new_dir.clone()).is_err() {
-            return Err(ErrorKind::InvalidInput);
-        }
+        self.check_interrupt(self.exit_status);
+        Ok(self.exit_status)
+    }
 
-        if new_dir.is_dir() {
-            self.current_dir = new_dir.clone();
-            self.variables
-                .insert("PWD".to_string(), new_dir.to_string_lossy().to_string());
-            self.exit_status = ExitStatus::from_raw(0);
-            Ok(())
-        } else {
-            self.exit_status = ExitStatus::from_raw(1);
-            Err(ErrorKind::InvalidInput)
+    // Calls the user's `command_not_found_handler` with the words of a command that is not in
+    // $PATH. Its status stands for the command's; `None` when there is no handler to call.
+    fn handle_not_found(
+        &mut self,
+        name: &str,
+        args: &[String],
+        redirects: &[Redirect],
+    ) -> Option<Result<i32, ErrorKind>> {
+        if !self.functions.contains_key(NOT_FOUND_HANDLER)
+            || name.contains(['/', std::path::MAIN_SEPARATOR])
+            || self.which(name).is_some()
+        {
+            return None;
         }
+
+        // Set aside while it runs, so a missing command inside the handler is reported
+        let body = self.functions.remove(NOT_FOUND_HANDLER)?;
+        let words = std::iter::once(name.to_string()).chain(args.iter().cloned()).collect();
+        let result = self.with_prefixes(|shell| {
+            shell.call_function(body.clone(), words, redirects.to_vec())
+        });
+        self.functions.entry(NOT_FOUND_HANDLER.to_string()).or_insert(body);
+        Some(result)
     }
 
-    fn add_variable(&mut self, text: &str) {
-        if let Some((key, val)) = text.split_once('=') {
-            let val = val.trim_matches('"');
-            self.variables
-                .insert(key.trim().to_string(), val.to_string());
-            self.exit_status = ExitStatus::from_raw(0);
-        } else {
-            self.exit_status = ExitStatus::from_raw(1);
+    // Starts the file `name` runs, or says why it cannot and returns the status for that
+    fn start_command(
+        &mut self,
+        name: &str,
+        args: Vec<String>,
+        redirects: &[Redirect],
+    ) -> Result<Child, i32> {
+        let mut command = self.external_command(name)?;
+        command.envs(&self.command_env).args(args);
+        // Ctrl-C reaches the command through its own process group, and never a job
+        // left running in the background
+        #[cfg(unix)]
+        if self.interactive {
+            std::os::unix::process::CommandExt::process_group(&mut command, 0);
         }
-    }
 
-    fn add_alias(&mut self, text: &str) {
-        if let Some((key, val)) = text.split_once('=') {
-            let val = val.trim_matches('"');
-            self.aliases.insert(key.trim().to_string(), val.to_string());
-            self.exit_status = ExitStatus::from_raw(0);
-        } else {
-            self.exit_status = ExitStatus::from_raw(1);
+        let mut streams = StdStreams::default();
+        if let Err(err) = self.open_redirects(&mut streams, redirects) {
+            self.report_redirect_error(&err);
+            return Err(1);
         }
+        streams.apply_to(&mut command);
+
+        command
+            .spawn()
+            .map_err(|err| report_spawn_error(&self.error_prefix(), name, &err))
     }
 
-    fn get_prompt(&mut self) -> String {
-        if let Some(cmd) = self.variables.get("PROMPT") {
-            let lexer = flash::lexer::Lexer::new(cmd);
-            let mut parser = flash::parser::Parser::new(lexer);
+    // Starts `node` as a job without waiting for it. The status is 0 even when it fails
+    // to start, as the failure belongs to the job.
+    fn run_in_background(&mut self, node: Node) -> Result<i32, ErrorKind> {
+        let command = job_text(&node);
 
-            let node = parser.parse_command();
+        // A job reads nothing from the terminal unless its input is redirected
+        let null_stdin = self.streams.stdin.is_none();
+        if null_stdin {
+            self.streams.stdin = redirect::null_input().ok();
+        }
+        let processes = self.start_job(node);
+        if null_stdin {
+            self.streams.stdin = None;
+        }
 
-            if let Node::Command {
+        let Some(last) = processes.last() else {
+            return Ok(0);
+        };
+        let number = self.jobs.last().map_or(1, |job| job.number + 1);
+        self.last_background = Some(last.id());
+        if self.interactive {
+            eprintln!("[{}] {}", number, last.id());
+        }
+
+        self.jobs.push(Job::new(number, command, processes));
+        Ok(0)
+    }
+
+    // External commands and pipelines are started directly. Builtins, functions and
+    // compound commands need a copy of the shell to run in.
+    fn start_job(&mut self, node: Node) -> Vec<Process> {
+        match node {
+            Node::Command {
                 name,
                 args,
                 redirects,
-            } = node
-            {
-                if let Ok(out) = self.get_result_of_external_command(name, args, redirects) {
-                    return String::from_utf8_lossy(&out.stdout).to_string();
+            } => {
+                let (name, args) = self.resolve_alias(&name, args);
+                let Ok(mut words) = self.expand_words(std::iter::once(name).chain(args)) else {
+                    return Vec::new();
+                };
+                let Some(name) = words.first() else {
+                    return Vec::new();
+                };
+                if is_builtin(name) || self.functions.contains_key(name) {
+                    return self.fork_job(0, |shell| shell.execute_words(words, redirects));
+                }
+
+                self.trace(&words);
+                let name = words.remove(0);
+                match self.start_command(&name, words, &redirects) {
+                    Ok(child) => vec![Process::spawned(child)],
+                    Err(_) => Vec::new(),
                 }
             }
-        } else {
-            dbg!("PROMPT not set");
+            Node::Pipeline { commands } => {
+                let stages = self.start_pipeline(commands);
+                stages.into_iter().flatten().collect()
+            }
+            node => self.fork_job(0, |shell| shell.execute_node(node)),
         }
-
-        format!("{} > ", self.current_dir.display())
     }
 
-    pub fn run_non_interactive(&mut self) {
-        use std::io::{self, BufRead};
+    // Runs `run` in a forked copy of the shell, which exits with its status. An interactive
+    // shell puts the copy in process group `group`, or a new one for 0.
+    #[cfg(unix)]
+    fn fork_job(
+        &mut self,
+        group: i32,
+        run: impl FnOnce(&mut Self) -> Result<i32, ErrorKind>,
+    ) -> Vec<Process> {
+        // Output still buffered would otherwise be written by both processes
+        let _ = std::io::stdout().flush();
+        // Settled before forking, so that the copy reports the shell's id
+        shell_pid();
 
-        let stdin = io::stdin();
-        for line in stdin.lock().lines().flatten() {
-            let line = line.trim();
-            if line.is_empty() {
-                continue;
+        // SAFETY: the child only goes on running the shell and then leaves with _exit
+        match unsafe { libc::fork() } {
+            -1 => {
+                let err = std::io::Error::last_os_error();
+                eprintln!("{}fork: {}", self.error_prefix(), err);
+                Vec::new()
             }
-
-            if let Err(_) = self.execute(line) {
-                break;
+            0 => {
+                // SAFETY: only this process changes its group and signal dispositions
+                unsafe {
+                    if self.interactive {
+                        libc::setpgid(0, group);
+                        libc::signal(libc::SIGINT, libc::SIG_DFL);
+                    }
+                    // The copy has no EXIT trap to hold these off for
+                    libc::signal(libc::SIGTERM, libc::SIG_DFL);
+                    libc::signal(libc::SIGHUP, libc::SIG_DFL);
+                }
+                self.interactive = false;
+                self.jobs.clear();
+                // Leaving the copy is not the shell exiting
+                self.traps.clear();
+                let code = run(self).unwrap_or(1);
+                let _ = std::io::stdout().flush();
+                // SAFETY: ends the copy without running the parent's exit handlers
+                unsafe { libc::_exit(code) }
+            }
+            pid => {
+                // Both processes set the group, so it exists before another command joins it
+                if self.interactive {
+                    let group = if group == 0 { pid } else { group };
+                    // SAFETY: setpgid has no memory effects
+                    unsafe { libc::setpgid(pid, group) };
+                }
+                vec![Process::forked(pid as u32)]
             }
         }
     }
 
-    pub fn run_interactive(&mut self) {
-        use linefeed::{Interface, ReadResult, Signal};
+    // Without fork there is no copy of the shell to run in, so the job runs to the end
+    // before the shell goes on
+    #[cfg(not(unix))]
+    fn fork_job(
+        &mut self,
+        _group: i32,
+        run: impl FnOnce(&mut Self) -> Result<i32, ErrorKind>,
+    ) -> Vec<Process> {
+        let _ = run(self);
+        Vec::new()
+    }
 
-        self.load_interactive_config();
+    fn execute_command(&mut self, command: &mut CommandContainer) -> Result<i32, ErrorKind> {
+        self.exit_status = 0;
 
-        let interface = Interface::new("wpcsh").expect("no tty");
+        let result = match command.program.as_str() {
+            "clear" => self.clear_terminal(),
+            "cd" => self.change_directory(&command.args),
+            "pushd" => self.pushd_command(&command.args),
+            "popd" => self.popd_command(&command.args),
+            "dirs" => self.print_dir_stack(),
+            "export" => self.export_command(&command.args),
+            "alias" => self.alias_command(&command.args),
+            "unalias" => self.unalias_command(&command.args),
+            "exit" => self.exit(command),
+            "source" => self.source_command(command),
+            "history" => self.history_command(&command.args),
+            "set" => self.set_command(&command.args),
+            "break" | "continue" => self.jump_command(&command.program, &command.args),
+            "shift" => self.shift_command(&command.args),
+            "unset" => self.unset_command(&command.args),
+            "echo" => self.echo_command(&command.args),
+            "printf" => self.printf_command(&command.args),
+            "read" => self.read_command(&command.args),
+            "test" | "[" => self.test_command(&command.program, &command.args),
+            "command" => self.command_command(&command.args),
+            "type" => self.type_command(&command.args),
+            "hash" => self.hash_command(&command.args),
+            "which" => self.which_command(&command.args),
+            "jobs" => self.jobs_command(&command.args),
+            "fg" => self.fg_command(&command.args),
+            "bg" => self.bg_command(&command.args),
+            "kill" => self.kill_command(&command.args),
+            "trap" => self.trap_command(&command.args),
+            "exec" => self.exec_command(&command.args, &[]),
+            "complete" => self.complete_command(&command.args),
+            "local" => self.local_command(&command.args),
+            "wait" => self.wait_command(&command.args),
+            _ => unreachable!()
+        };
 
-        let history_path = self.home_dir.join(".wpcsh_history");
-        let _ = interface.load_history(&history_path);
+        // Builtins that fail without choosing a status report a generic failure
+        if result.is_err() && self.exit_status == 0 {
+            self.exit_status = 1;
+        }
 
-        loop {
-            let prompt = self.get_prompt();
+        Ok(self.exit_status)
+    }
 
-            if interface.set_prompt(&prompt).is_err() {
-                interface.set_prompt(">").expect("Failed to set prompt");
-            };
+    fn exit(&mut self, command: &CommandContainer) -> Result<(), ErrorKind> {
+        let code = command
+            .args
+            .first()
+            .and_then(|a| a.parse::<i32>().ok())
+            .unwrap_or(0);
 
-            match interface.read_line() {
-                Ok(ReadResult::Input(line)) => {
-                    interface.add_history(line.clone());
+        self.exit_status = code;
+        self.run_exit_trap();
+        std::process::exit(code);
+    }
 
-                    if let Err(err) = self.execute(&line) {
-                        match err {
-                            ErrorKind::InvalidInput => {
-                                eprintln!("wpcsh: invalid input: {}", line);
-                            }
-                            ErrorKind::NotFound => {
-                                eprintln!("wpcsh: command not found: {}", line);
-                            }
-                            ErrorKind::Interrupted => {
-                                break;
-                            }
-                            _ => {}
-                        }
-                    }
-                    std::io::stdout().flush().unwrap();
-                    println!();
-                }
-                Ok(ReadResult::Signal(Signal::Quit)) => break,
-                Ok(ReadResult::Eof) => break,
-                _ => {}
-            }
+    fn source_command(&mut self, command: &mut CommandContainer) -> Result<(), ErrorKind> {
+        let path = match command.args.first() {
+            Some(path) => PathBuf::from(path),
+            None => return Err(ErrorKind::InvalidInput),
+        };
 
-            let _ = interface.save_history(&history_path);
+        // Extra arguments become the file's positional parameters; without any it sees ours
+        if command.args.len() == 1 {
+            return self.source(path);
         }
+
+        let outer_args = std::mem::replace(&mut self.positional, command.args.split_off(1));
+        let result = self.source(path);
+        self.positional = outer_args;
+        result
     }
 
-    fn clear_terminal(&mut self) -> Result<(), ErrorKind> {
-        print!("\x1B[2J\x1B[1;1H");
-        use std::io::Write;
-        match std::io::stdout().flush() {
-            Ok(_) => {
-                self.exit_status = ExitStatus::from_raw(0);
-                Ok(())
+    fn source(&mut self, path: PathBuf) -> Result<(), ErrorKind> {
+        let file = match File::open(&path) {
+            Ok(f) => f,
+            Err(_) => return Err(ErrorKind::InvalidInput),
+        };
+
+        let reader = std::io::BufReader::new(file);
+
+        use std::io::BufRead;
+        self.call_depth += 1;
+
+        let name = self.display_path(&path);
+        let outer_location = self.source_location.take();
+
+        let mut result = Ok(());
+        // Lines are gathered until they form a whole command, as with a wrapped one
+        let mut buffer = String::new();
+        for (number, line) in (1..).zip(reader.lines().map_while(Result::ok)) {
+            let l = line.trim();
+            if buffer.is_empty() && (l.is_empty() || l.starts_with('#')) {
+                continue;
             }
-            Err(_) => {
-                self.exit_status = ExitStatus::from_raw(1);
-                Err(ErrorKind::InvalidInput)
+
+            if buffer.is_empty() {
+                self.source_location = Some((name.clone(), number));
+            }
+            buffer.push_str(&line);
+            buffer.push('\n');
+            if flash::parser::is_incomplete(&buffer) {
+                continue;
+            }
+
+            let command = std::mem::take(&mut buffer);
+            if let Err(err) = self.execute(command.trim()) {
+                if err != ErrorKind::Interrupted {
+                    eprintln!("{}{}: {}", self.error_prefix(), error_reason(err), command.trim());
+                }
+
+                // A bad line only stops the rest of the file under `set -e`
+                self.exit_status = 1;
+                if err == ErrorKind::Interrupted || self.options.errexit {
+                    result = Err(err);
+                    break;
+                }
+            }
+
+            // `return` stops reading the file, and `set -e` everything that sourced it
+            if self.jump.take_if(|jump| *jump == Jump::Return).is_some()
+                || matches!(self.jump, Some(Jump::Exit | Jump::Interrupt))
+            {
+                break;
             }
         }
+
+        self.source_location = outer_location;
+        self.call_depth -= 1;
+        result
     }
-}
 
-fn normalize_path(path: PathBuf) -> PathBuf {
-    let mut result = PathBuf::new();
+    // How error messages start: with the file and line when running a sourced file
+    fn error_prefix(&self) -> String {
+        match &self.source_location {
+            Some((file, line)) => format!("wpcsh: {}: line {}: ", file, line),
+            None => "wpcsh: ".to_string(),
+        }
+    }
 
-    for component in path.components() {
-        use std::path::Component;
-        match component {
-            Component::ParentDir => {
-                result.pop();
+    // A path as messages show it, with the home directory written as `~`
+    fn display_path(&self, path: &Path) -> String {
+        match path.strip_prefix(&self.home_dir) {
+            Ok(rest) if !self.home_dir.as_os_str().is_empty() => {
+                format!("~/{}", rest.display())
             }
-            Component::CurDir => {}
-            other => result.push(other),
+            _ => path.display().to_string(),
         }
     }
 
-    result
-}
+    fn history_file(&self) -> PathBuf {
+        match self.variables.get("HISTFILE") {
+            Some(path) if !path.is_empty() => PathBuf::from(path),
+            _ => self.home_dir.join(".wpcsh_history"),
+        }
+    }
 
-#[cfg(windows)]
-fn get_coreutils_commands() -> std::io::Result<Vec<String>> {
-    let output = Command::new("coreutils").arg("--list").output()?;
+    fn history_command(&mut self, args: &[String]) -> Result<(), ErrorKind> {
+        let path = self.history_file();
 
-    let coreutils_commands = String::from_utf8_lossy(&output.stdout).trim().to_string();
-    let coreutils_commands = coreutils_commands
-        .split_whitespace()
-        .map(String::from)
-        .collect::<Vec<String>>();
+        let result = match args.first().map(String::as_str) {
+            None => {
+                let listing: String = (1..)
+                    .zip(self.history.entries())
+                    .map(|(number, entry)| format!("{:5}  {}\n", number, entry))
+                    .collect();
+                let _ = self.stdout().write_all(listing.as_bytes());
+                Ok(())
+            }
+            Some("-c") => {
+                self.history.clear();
+                Ok(())
+            }
+            Some("-a") => self.history.append_to_file(&path),
+            Some("-r") => self.history.read_file(&path),
+            Some("-w") => self.history.write_file(&path),
+            Some(_) => {
+                self.exit_status = 2;
+                return Err(ErrorKind::InvalidInput);
+            }
+        };
 
-    Ok(coreutils_commands)
+        match result {
+            Ok(()) => {
+                self.exit_status = 0;
+                Ok(())
+            }
+            Err(err) => {
+                eprintln!("{}history: {}: {}", self.error_prefix(), path.display(), err);
+                self.exit_status = 1;
+                Err(err.kind())
+            }
+        }
+    }
+
+    fn set_command(&mut self, args: &[String]) -> Result<(), ErrorKind> {
+        let mut args = args.iter().map(String::as_str);
+
+        while let Some(flag) = args.next() {
+            let enabled = match flag {
+                "-o" => true,
+                "+o" => false,
+                // Single letters such as -x or +xC
+                _ if flag.len() > 1 && (flag.starts_with('-') || flag.starts_with('+')) => {
+                    let enabled = flag.starts_with('-');
+                    for letter in flag[1..].chars() {
+                        let Some(name) = ShellOptions::long_name(letter) else {
+                            eprintln!(
+                                "{}set: {}{}: invalid option",
+                                self.error_prefix(),
+                                &flag[..1],
+                                letter
+                            );
+                            self.exit_status = 2;
+                            return Err(ErrorKind::InvalidInput);
+                        };
+                        self.options.set(name, enabled);
+                    }
+                    continue;
+                }
+                _ => {
+                    eprintln!("{}set: {}: invalid option", self.error_prefix(), flag);
+                    self.exit_status = 2;
+                    return Err(ErrorKind::InvalidInput);
+                }
+            };
+
+            let Some(name) = args.next() else {
+                let listing: String = ShellOptions::NAMES
+                    .iter()
+                    .map(|name| {
+                        let state = if self.options.get(name) == Some(true) { "on" } else { "off" };
+                        format!("{:15}\t{}\n", name, state)
+                    })
+                    .collect();
+                let _ = self.stdout().write_all(listing.as_bytes());
+                continue;
+            };
+
+            if !self.options.set(name, enabled) {
+                eprintln!("{}set: {}: invalid option name", self.error_prefix(), name);
+                self.exit_status = 1;
+                return Err(ErrorKind::InvalidInput);
+            }
+        }
+
+        self.exit_status = 0;
+        Ok(())
+    }
+
+    fn jump_command(&mut self, name: &str, args: &[String]) -> Result<(), ErrorKind> {
+        let count = match args.first() {
+            None => 1,
+            Some(arg) => match arg.parse::<usize>() {
+                Ok(count) if count > 0 => count,
+                Ok(_) => {
+                    eprintln!("{}{}: {}: loop count out of range", self.error_prefix(), name, arg);
+                    self.exit_status = 1;
+                    return Err(ErrorKind::InvalidInput);
+                }
+                Err(_) => {
+                    eprintln!(
+                        "{}{}: {}: numeric argument required",
+                        self.error_prefix(),
+                        name,
+                        arg
+                    );
+                    self.exit_status = 128;
+                    return Err(ErrorKind::InvalidInput);
+                }
+            },
+        };
+
+        // Outside a loop this only warns, like other shells
+        if self.loop_depth == 0 {
+            eprintln!(
+                "{}{}: only meaningful in a `for', `while', or `until' loop",
+                self.error_prefix(),
+                name
+            );
+            return Ok(());
+        }
+
+        // Counts larger than the nesting leave every loop
+        let count = count.min(self.loop_depth);
+        self.jump = Some(if name == "break" {
+            Jump::Break(count)
+        } else {
+            Jump::Continue(count)
+        });
+        Ok(())
+    }
+
+    // `echo [-neE] [--] args`: -n drops the newline, -e interprets backslash escapes
+    fn echo_command(&mut self, args: &[String]) -> Result<(), ErrorKind> {
+        let mut newline = true;
+        let mut escapes = false;
+        let mut args = args;
+
+        while let Some(flag) = args.first() {
+            if flag == "--" {
+                args = &args[1..];
+                break;
+            }
+
+            let is_flag = flag.len() > 1
+                && flag.starts_with('-')
+                && flag[1..].chars().all(|c| matches!(c, 'n' | 'e' | 'E'));
+            if !is_flag {
+                break;
+            }
+
+            for c in flag[1..].chars() {
+                match c {
+                    'n' => newline = false,
+                    'e' => escapes = true,
+                    _ => escapes = false,
+                }
+            }
+            args = &args[1..];
+        }
+
+        let mut text = args.join(" ");
+        if escapes {
+            let (expanded, stop) = echo_escapes(&text);
+            text = expanded;
+            // \c ends the output, newline included
+            newline &= !stop;
+        }
+        if newline {
+            text.push('\n');
+        }
+
+        let result = self.stdout().write_all(text.as_bytes());
+        if let Err(err) = result {
+            eprintln!("{}echo: write error: {}", self.error_prefix(), err);
+            self.exit_status = 1;
+            return Err(err.kind());
+        }
+        Ok(())
+    }
+
+    // `type [-t] names...` tells what each name runs: an alias, function, builtin or file.
+    // With -t only that word is printed. Names that run nothing make the status 1.
+    fn type_command(&mut self, args: &[String]) -> Result<(), ErrorKind> {
+        let (terse, names) = match args.split_first() {
+            Some((flag, names)) if flag == "-t" => (true, names),
+            Some((flag, names)) if flag == "--" => (false, names),
+            Some((flag, _)) if flag.starts_with('-') && flag.len() > 1 => {
+                eprintln!("{}type: {}: invalid option", self.error_prefix(), flag);
+                self.exit_status = 2;
+                return Err(ErrorKind::InvalidInput);
+            }
+            _ => (false, args),
+        };
+
+        let mut missing = false;
+        for name in names {
+            let line = match (self.command_kind(name), terse) {
+                (Some(CommandKind::Alias(_)), true) => "alias".to_string(),
+                (Some(CommandKind::Function), true) => "function".to_string(),
+                (Some(CommandKind::Builtin), true) => "builtin".to_string(),
+                (Some(CommandKind::File(_)), true) => "file".to_string(),
+                (Some(CommandKind::Alias(value)), false) => {
+                    format!("{} is aliased to `{}'", name, value)
+                }
+                (Some(CommandKind::Function), false) => format!("{} is a function", name),
+                (Some(CommandKind::Builtin), false) => format!("{} is a shell builtin", name),
+                (Some(CommandKind::File(path)), false) => {
+                    format!("{} is {}", name, path.display())
+                }
+                (None, terse) => {
+                    if !terse {
+                        eprintln!("{}type: {}: not found", self.error_prefix(), name);
+                    }
+                    missing = true;
+                    continue;
+                }
+            };
+            let _ = writeln!(self.stdout(), "{}", line);
+        }
+
+        if missing {
+            self.exit_status = 1;
+            return Err(ErrorKind::NotFound);
+        }
+        Ok(())
+    }
+
+    // `jobs [-l|-p]` lists the jobs and their states, with -l their process IDs too and with
+    // -p only those. Jobs listed as finished are then forgotten.
+    fn jobs_command(&mut self, args: &[String]) -> Result<(), ErrorKind> {
+        let (mut long, mut pids) = (false, false);
+        for arg in args {
+            match arg.as_str() {
+                "-l" => long = true,
+                "-p" => pids = true,
+                _ => {
+                    eprintln!("{}jobs: {}: invalid option", self.error_prefix(), arg);
+                    self.exit_status = 2;
+                    return Err(ErrorKind::InvalidInput);
+                }
+            }
+        }
+
+        self.jobs.iter_mut().for_each(Job::poll);
+        let listing: String = (0..self.jobs.len())
+            .map(|index| match pids {
+                true => format!("{}\n", self.jobs[index].pid()),
+                false => format!("{}\n", self.job_line(index, long)),
+            })
+            .collect();
+        let _ = self.stdout().write_all(listing.as_bytes());
+
+        self.jobs.retain(|job| !matches!(job.state, JobState::Done(_)));
+        Ok(())
+    }
+
+    // `fg [job]` waits for a job as if it had been run without `&`, continuing it first if
+    // it was stopped. The job's status becomes the status of `fg`.
+    fn fg_command(&mut self, args: &[String]) -> Result<(), ErrorKind> {
+        let index = self.find_job("fg", args.first())?;
+        let mut job = self.jobs.remove(index);
+        let _ = writeln!(self.stdout(), "{}", job.command);
+
+        let foreground = self.foreground(job.group());
+        if job.state == JobState::Stopped {
+            job.resume();
+        }
+        job.wait();
+        drop(foreground);
+
+        match job.state {
+            JobState::Done(status) => {
+                self.exit_status = status;
+                self.check_interrupt(status);
+            }
+            // Stopped again, so it goes back in the table
+            _ => {
+                self.jobs.insert(index, job);
+                eprintln!("{}", self.job_line(index, false));
+                self.exit_status = 128 + 20;
+            }
+        }
+        Ok(())
+    }
+
+    // `wait [job|pid]...` waits for the jobs named, or for every job. Its status is that of
+    // the last one named, or 127 when that is not a job of this shell.
+    fn wait_command(&mut self, args: &[String]) -> Result<(), ErrorKind> {
+        if args.is_empty() {
+            self.jobs.iter_mut().for_each(Job::wait);
+            self.jobs.retain(|job| !matches!(job.state, JobState::Done(_)));
+            self.exit_status = 0;
+            return Ok(());
+        }
+
+        for arg in args {
+            self.exit_status = self.wait_for(arg);
+        }
+        Ok(())
+    }
+
+    // Waits for the job `spec` names, or the one with process `spec`, and returns its status
+    fn wait_for(&mut self, spec: &String) -> i32 {
+        let pid = spec.parse::<u32>().ok();
+        let index = match pid {
+            Some(pid) => {
+                let has_pid = |job: &Job| job.processes.iter().any(|process| process.id() == pid);
+                match self.jobs.iter().position(has_pid) {
+                    Some(index) => index,
+                    None => {
+                        let prefix = self.error_prefix();
+                        eprintln!("{}wait: pid {} is not a child of this shell", prefix, pid);
+                        return 127;
+                    }
+                }
+            }
+            None => match self.find_job("wait", Some(spec)) {
+                Ok(index) => index,
+                Err(_) => return 127,
+            },
+        };
+
+        let job = &mut self.jobs[index];
+        job.wait();
+        let JobState::Done(status) = job.state else {
+            return 128 + 20;
+        };
+        // A process of a pipeline has a status of its own
+        let process = job.processes.iter().find(|process| Some(process.id()) == pid);
+        let status = process.and_then(Process::status).unwrap_or(status);
+        self.jobs.remove(index);
+        status
+    }
+
+    // `bg [job]` continues a stopped job in the background
+    fn bg_command(&mut self, args: &[String]) -> Result<(), ErrorKind> {
+        let index = self.find_job("bg", args.first())?;
+        let job = &mut self.jobs[index];
+
+        match job.state {
+            JobState::Stopped => {
+                job.resume();
+                let line = format!("[{}] {} &", job.number, job.command);
+                let _ = writeln!(self.stdout(), "{}", line);
+                Ok(())
+            }
+            JobState::Running => {
+                let number = job.number;
+                eprintln!("{}bg: job {} already in background", self.error_prefix(), number);
+                Ok(())
+            }
+            JobState::Done(_) => {
+                eprintln!("{}bg: job has terminated", self.error_prefix());
+                self.exit_status = 1;
+                Err(ErrorKind::NotFound)
+            }
+        }
+    }
+
+    // `kill [-s sig | -n num | -sig] pid|job...` sends a signal, TERM unless one is given.
+    // `kill -l` lists the signals and their numbers, and `kill -l n` names signal n or the signal
+    // behind status n.
+    fn kill_command(&mut self, args: &[String]) -> Result<(), ErrorKind> {
+        let usage = || {
+            eprintln!("kill: usage: kill [-s sigspec | -n signum | -sigspec] pid | jobspec ...");
+            eprintln!("       kill -l [sigspec]");
+        };
+        let invalid = |shell: &mut Self, spec: &str| {
+            eprintln!("{}kill: {}: invalid signal specification", shell.error_prefix(), spec);
+            shell.exit_status = 1;
+            Err(ErrorKind::InvalidInput)
+        };
+
+        let (signal, targets) = match args {
+            [] => {
+                usage();
+                self.exit_status = 2;
+                return Err(ErrorKind::InvalidInput);
+            }
+            [flag, specs @ ..] if flag == "-l" => return self.list_signals("kill", specs),
+            [flag, spec, targets @ ..] if flag == "-s" || flag == "-n" => {
+                match signal::number(spec) {
+                    Some(signal) => (signal, targets),
+                    None => return invalid(self, spec),
+                }
+            }
+            [flag, targets @ ..] if flag == "--" => (signal::TERM, targets),
+            [flag, targets @ ..] if flag.len() > 1 && flag.starts_with('-') => {
+                match signal::number(&flag[1..]) {
+                    Some(signal) => (signal, targets),
+                    None => return invalid(self, &flag[1..]),
+                }
+            }
+            targets => (signal::TERM, targets),
+        };
+        let targets = match targets.split_first() {
+            Some((flag, rest)) if flag == "--" => rest,
+            _ => targets,
+        };
+        if targets.is_empty() {
+            usage();
+            self.exit_status = 2;
+            return Err(ErrorKind::InvalidInput);
+        }
+
+        let mut failed = false;
+        for target in targets {
+            let result = if target.starts_with('%') {
+                let Ok(index) = self.find_job("kill", Some(target)) else {
+                    failed = true;
+                    continue;
+                };
+                self.jobs[index].signal(signal)
+            } else if let Ok(pid) = target.parse::<i32>() {
+                self.signal_process(pid, signal)
+            } else {
+                eprintln!(
+                    "{}kill: {}: arguments must be process or job IDs",
+                    self.error_prefix(),
+                    target
+                );
+                failed = true;
+                continue;
+            };
+
+            if let Err(err) = result {
+                let reason = match err.kind() {
+                    ErrorKind::PermissionDenied => "operation not permitted",
+                    _ => "no such process",
+                };
+                eprintln!("{}kill: ({}) - {}", self.error_prefix(), target, reason);
+                failed = true;
+            }
+        }
+
+        if failed {
+            self.exit_status = 1;
+            return Err(ErrorKind::InvalidInput);
+        }
+        Ok(())
+    }
+
+    // `exec command args...` replaces the shell with the command. Without a command, its
+    // redirections apply to the shell from then on.
+    fn exec_command(&mut self, args: &[String], redirects: &[Redirect]) -> Result<(), ErrorKind> {
+        let args = match args.split_first() {
+            Some((flag, rest)) if flag == "--" => rest,
+            _ => args,
+        };
+
+        let mut streams = StdStreams::default();
+        if let Err(err) = self.open_redirects(&mut streams, redirects) {
+            self.report_redirect_error(&err);
+            return Err(ErrorKind::InvalidInput);
+        }
+        let Some((name, args)) = args.split_first() else {
+            self.streams = streams;
+            return Ok(());
+        };
+
+        let mut command = match self.external_command(name) {
+            Ok(command) => command,
+            Err(code) => {
+                self.exit_status = code;
+                return self.exec_failed();
+            }
+        };
+        command.envs(&self.command_env).args(args);
+        streams.apply_to(&mut command);
+
+        let _ = std::io::stdout().flush();
+        self.replace_process(name, command)
+    }
+
+    #[cfg(unix)]
+    fn replace_process(&mut self, name: &str, mut command: Command) -> Result<(), ErrorKind> {
+        use std::os::unix::process::CommandExt;
+
+        // exec only returns if the command could not be started
+        let err = command.exec();
+        self.exit_status = report_spawn_error(&self.error_prefix(), name, &err);
+        self.exec_failed()
+    }
+
+    // Windows cannot put another program in a running process, so the command runs as a
+    // child and the shell exits with its status once it finishes
+    #[cfg(not(unix))]
+    fn replace_process(&mut self, name: &str, mut command: Command) -> Result<(), ErrorKind> {
+        match command.status() {
+            Ok(status) => std::process::exit(status_code(status)),
+            Err(err) => {
+                self.exit_status = report_spawn_error(&self.error_prefix(), name, &err);
+                self.exec_failed()
+            }
+        }
+    }
+
+    // A script that meant to hand over to another program does not go on without it
+    fn exec_failed(&mut self) -> Result<(), ErrorKind> {
+        if !self.interactive && self.jump.is_none() {
+            self.jump = Some(Jump::Exit);
+        }
+        Err(ErrorKind::NotFound)
+    }
+
+    // `trap command signals...` runs command when one of the signals arrives, or for EXIT
+    // when the shell exits. `trap - signals...` removes traps, `trap` or `trap -p` lists
+    // them, and `trap -l` lists the signals as `kill -l` does.
+    fn trap_command(&mut self, args: &[String]) -> Result<(), ErrorKind> {
+        let args = match args.split_first() {
+            Some((flag, rest)) if flag == "--" => rest,
+            _ => args,
+        };
+        let (action, specs) = match args {
+            [] => return self.print_traps(&[]),
+            [flag, specs @ ..] if flag == "-p" => return self.print_traps(specs),
+            [flag, specs @ ..] if flag == "-l" => return self.list_signals("trap", specs),
+            // A signal on its own is reset, as with `-`
+            [spec] => (None, std::slice::from_ref(spec)),
+            [action, specs @ ..] if action == "-" => (None, specs),
+            [action, specs @ ..] => (Some(action), specs),
+        };
+
+        let mut failed = false;
+        for spec in specs {
+            let trap = match Signal::parse(spec) {
+                Some(trap @ (Signal::Exit | Signal::Number(signal::INT))) => trap,
+                Some(_) => {
+                    let prefix = self.error_prefix();
+                    eprintln!("{}trap: {}: only EXIT and INT can be trapped", prefix, spec);
+                    failed = true;
+                    continue;
+                }
+                None => {
+                    let prefix = self.error_prefix();
+                    eprintln!("{}trap: {}: invalid signal specification", prefix, spec);
+                    failed = true;
+                    continue;
+                }
+            };
+
+            match action {
+                Some(action) => {
+                    // The shell has to catch a signal to run its trap
+                    if trap != Signal::Exit {
+                        foreground::catch_interrupts();
+                    }
+                    self.traps.insert(trap, action.clone());
+                }
+                None => {
+                    self.traps.remove(&trap);
+                }
+            }
+        }
+
+        if failed {
+            self.exit_status = 1;
+            return Err(ErrorKind::InvalidInput);
+        }
+        Ok(())
+    }
+
+    // Lists traps as the commands that set them: `trap -- 'rm -f $tmp' EXIT`
+    fn print_traps(&mut self, specs: &[String]) -> Result<(), ErrorKind> {
+        let mut traps: Vec<Signal> = self.traps.keys().copied().collect();
+        if !specs.is_empty() {
+            traps.clear();
+            for spec in specs {
+                let Some(trap) = Signal::parse(spec) else {
+                    let prefix = self.error_prefix();
+                    eprintln!("{}trap: {}: invalid signal specification", prefix, spec);
+                    self.exit_status = 1;
+                    return Err(ErrorKind::InvalidInput);
+                };
+                traps.push(trap);
+            }
+        }
+        traps.sort();
+
+        let listing: String = traps
+            .iter()
+            .filter_map(|trap| {
+                let command = self.traps.get(trap)?;
+                Some(format!("trap -- {} {}\n", single_quote(command), trap.name()))
+            })
+            .collect();
+        let _ = self.stdout().write_all(listing.as_bytes());
+        Ok(())
+    }
+
+    // complete [-fd] [-W words] name... registers how arguments of each name complete;
+    // -p lists and -r removes what was registered
+    fn complete_command(&mut self, args: &[String]) -> Result<(), ErrorKind> {
+        let mut spec = CompletionSpec::default();
+        let (mut print, mut remove) = (false, false);
+        let mut names = Vec::new();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                names.extend(args.by_ref().cloned());
+                break;
+            }
+            let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty()) else {
+                names.push(arg.clone());
+                continue;
+            };
+
+            for flag in flags.chars() {
+                match flag {
+                    'f' => spec.files = true,
+                    'd' => spec.directories = true,
+                    'p' => print = true,
+                    'r' => remove = true,
+                    'W' => match args.next() {
+                        Some(words) => spec.words = Some(words.clone()),
+                        None => {
+                            let prefix = self.error_prefix();
+                            eprintln!("{}complete: -W: option requires an argument", prefix);
+                            self.exit_status = 2;
+                            return Err(ErrorKind::InvalidInput);
+                        }
+                    },
+                    _ => {
+                        eprintln!("{}complete: -{}: invalid option", self.error_prefix(), flag);
+                        self.exit_status = 2;
+                        return Err(ErrorKind::InvalidInput);
+                    }
+                }
+            }
+        }
+
+        if remove && names.is_empty() {
+            self.completions.clear();
+            return Ok(());
+        }
+        if print || (names.is_empty() && !remove) {
+            return self.print_completions(&names);
+        }
+
+        let mut missing = false;
+        for name in names {
+            if !remove {
+                self.completions.insert(name, spec.clone());
+            } else if self.completions.remove(&name).is_none() {
+                let prefix = self.error_prefix();
+                eprintln!("{}complete: {}: no completion specification", prefix, name);
+                missing = true;
+            }
+        }
+
+        if missing {
+            self.exit_status = 1;
+            return Err(ErrorKind::NotFound);
+        }
+        Ok(())
+    }
+
+    // Lists completions as the commands that register them: `complete -W 'a b' name`
+    fn print_completions(&mut self, names: &[String]) -> Result<(), ErrorKind> {
+        let mut listed: Vec<&String> = match names.is_empty() {
+            true => self.completions.keys().collect(),
+            false => names.iter().collect(),
+        };
+        listed.sort();
+
+        let mut listing = String::new();
+        let mut missing = false;
+        for name in listed {
+            let Some(spec) = self.completions.get(name) else {
+                let prefix = self.error_prefix();
+                eprintln!("{}complete: {}: no completion specification", prefix, name);
+                missing = true;
+                continue;
+            };
+
+            listing.push_str("complete");
+            if spec.files {
+                listing.push_str(" -f");
+            }
+            if spec.directories {
+                listing.push_str(" -d");
+            }
+            if let Some(words) = &spec.words {
+                listing.push_str(&format!(" -W {}", single_quote(words)));
+            }
+            listing.push_str(&format!(" {}\n", name));
+        }
+        let _ = self.stdout().write_all(listing.as_bytes());
+
+        if missing {
+            self.exit_status = 1;
+            return Err(ErrorKind::NotFound);
+        }
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn signal_process(&mut self, pid: i32, signal: i32) -> std::io::Result<()> {
+        signal::send(pid, signal)
+    }
+
+    // Only the processes of jobs can be ended, through the handles they were started with
+    #[cfg(not(unix))]
+    fn signal_process(&mut self, pid: i32, signal: i32) -> std::io::Result<()> {
+        let job = self
+            .jobs
+            .iter_mut()
+            .find(|job| job.processes.iter().any(|process| process.id() as i32 == pid));
+        match job {
+            Some(job) => job.signal(signal),
+            None => Err(ErrorKind::NotFound.into()),
+        }
+    }
+
+    // `-l` of `kill` and `trap`: every signal as `2) SIGINT`, or the name or number of each
+    // spec given
+    fn list_signals(&mut self, builtin: &str, specs: &[String]) -> Result<(), ErrorKind> {
+        if specs.is_empty() {
+            let signals: String = signal::SIGNALS
+                .iter()
+                .map(|(name, number)| format!("{}) SIG{}\n", number, name))
+                .collect();
+            let _ = self.stdout().write_all(signals.as_bytes());
+            return Ok(());
+        }
+
+        let mut failed = false;
+        for spec in specs {
+            // A number is a signal, or a status of 128 plus the signal that ended a command
+            let line = match spec.parse::<i32>() {
+                Ok(number) => signal::name(number)
+                    .or_else(|| signal::name(number - 128))
+                    .map(str::to_string),
+                Err(_) => signal::number(spec).map(|number| number.to_string()),
+            };
+            match line {
+                Some(line) => {
+                    let _ = writeln!(self.stdout(), "{}", line);
+                }
+                None => {
+                    let prefix = self.error_prefix();
+                    eprintln!("{}{}: {}: invalid signal specification", prefix, builtin, spec);
+                    failed = true;
+                }
+            }
+        }
+
+        if failed {
+            self.exit_status = 1;
+            return Err(ErrorKind::InvalidInput);
+        }
+        Ok(())
+    }
+
+    // The index of the job `spec` names: `%n` by number, `%%`, `%+` or no spec for the
+    // newest job, `%-` for the one before it and `%text` for the newest whose command
+    // starts with text
+    fn find_job(&mut self, builtin: &str, spec: Option<&String>) -> Result<usize, ErrorKind> {
+        let spec = spec.map_or("%%", String::as_str);
+        let newest = self.jobs.len().checked_sub(1);
+
+        let index = match spec.strip_prefix('%') {
+            Some("" | "%" | "+") => newest,
+            Some("-") => self.jobs.len().checked_sub(2).or(newest),
+            Some(text) => match text.parse::<usize>() {
+                Ok(number) => self.jobs.iter().position(|job| job.number == number),
+                Err(_) => self.jobs.iter().rposition(|job| job.command.starts_with(text)),
+            },
+            None => None,
+        };
+
+        index.ok_or_else(|| {
+            let spec = if spec == "%%" { "current" } else { spec };
+            eprintln!("{}{}: {}: no such job", self.error_prefix(), builtin, spec);
+            self.exit_status = 1;
+            ErrorKind::NotFound
+        })
+    }
+
+    // A job as `jobs` lists it: `[1]+  Running    sleep 10 &`. The newest job is marked
+    // with `+` and the one before it with `-`.
+    fn job_line(&self, index: usize, long: bool) -> String {
+        let job = &self.jobs[index];
+        let mark = match self.jobs.len() - index {
+            1 => '+',
+            2 => '-',
+            _ => ' ',
+        };
+        let pid = match long {
+            true => format!("{} ", job.pid()),
+            false => " ".to_string(),
+        };
+        let background = if job.state == JobState::Running { " &" } else { "" };
+
+        format!(
+            "[{}]{} {}{:<24}{}{}",
+            job.number,
+            mark,
+            pid,
+            job.state_text(),
+            job.command,
+            background
+        )
+    }
+
+    // Before each prompt, tells which jobs finished since the last one and forgets them
+    fn report_finished_jobs(&mut self) {
+        self.jobs.iter_mut().for_each(Job::poll);
+        for index in 0..self.jobs.len() {
+            if matches!(self.jobs[index].state, JobState::Done(_)) {
+                eprintln!("{}", self.job_line(index, false));
+            }
+        }
+        self.jobs.retain(|job| !matches!(job.state, JobState::Done(_)));
+    }
+
+    // `which [-a] names...` prints the file in $PATH each name runs, or with -a every match
+    // in search order. Names that are not found print nothing and make the status 1.
+    fn which_command(&mut self, args: &[String]) -> Result<(), ErrorKind> {
+        let mut all = false;
+        let mut names = args;
+        while let Some((flag, rest)) = names.split_first()
+            && flag.starts_with('-')
+        {
+            names = rest;
+            match flag.as_str() {
+                "--" => break,
+                "-a" => all = true,
+                _ => {
+                    eprintln!("{}which: {}: invalid option", self.error_prefix(), flag);
+                    self.exit_status = 2;
+                    return Err(ErrorKind::InvalidInput);
+                }
+            }
+        }
+
+        let search = self.search_path();
+        let mut missing = false;
+        for name in names {
+            let found: Vec<PathBuf> = match name.contains(['/', std::path::MAIN_SEPARATOR]) {
+                true => path::find_file(name).into_iter().collect(),
+                false if all => path::find_all(name, &search).collect(),
+                false => self.which(name).into_iter().collect(),
+            };
+            missing |= found.is_empty();
+
+            let listing: String = found
+                .iter()
+                .map(|file| format!("{}\n", file.display()))
+                .collect();
+            let _ = self.stdout().write_all(listing.as_bytes());
+        }
+
+        if missing {
+            self.exit_status = 1;
+            return Err(ErrorKind::NotFound);
+        }
+        Ok(())
+    }
+
+    // `hash` lists the commands found in $PATH so far, `hash names...` looks them up ahead
+    // of time and `hash -r` forgets them all
+    fn hash_command(&mut self, args: &[String]) -> Result<(), ErrorKind> {
+        match args.first().map(String::as_str) {
+            Some("-r") => {
+                self.path_cache.borrow_mut().clear();
+                return Ok(());
+            }
+            Some(flag) if flag.starts_with('-') => {
+                eprintln!("{}hash: {}: invalid option", self.error_prefix(), flag);
+                self.exit_status = 2;
+                return Err(ErrorKind::InvalidInput);
+            }
+            Some(_) => {}
+            None => {
+                let cache = self.path_cache.borrow();
+                let listing: String = cache
+                    .entries()
+                    .into_iter()
+                    .map(|(name, file)| format!("{}\t{}\n", name, file.display()))
+                    .collect();
+                let _ = self.stdout().write_all(listing.as_bytes());
+                return Ok(());
+            }
+        }
+
+        let mut missing = false;
+        for name in args {
+            // Builtins and functions are never looked up, as they do not run a file
+            if is_builtin(name) || self.functions.contains_key(name) {
+                continue;
+            }
+            if self.which(name).is_none() {
+                eprintln!("{}hash: {}: not found", self.error_prefix(), name);
+                missing = true;
+            }
+        }
+
+        if missing {
+            self.exit_status = 1;
+            return Err(ErrorKind::NotFound);
+        }
+        Ok(())
+    }
+
+    // `command -v names...` prints what each name runs in a form scripts can use: the
+    // path of a file, the alias definition or just the name. `command -V` describes it
+    // like `type`. Without a name the command does nothing.
+    fn command_command(&mut self, args: &[String]) -> Result<(), ErrorKind> {
+        let Some((flag, names)) = args.split_first() else {
+            return Ok(());
+        };
+        match flag.as_str() {
+            "-v" => {}
+            "-V" => return self.type_command(names),
+            _ => {
+                eprintln!("{}command: {}: invalid option", self.error_prefix(), flag);
+                self.exit_status = 2;
+                return Err(ErrorKind::InvalidInput);
+            }
+        }
+
+        let mut missing = false;
+        for name in names {
+            let line = match self.command_kind(name) {
+                Some(CommandKind::Alias(value)) => {
+                    format!("alias {}={}", name, single_quote(&value))
+                }
+                Some(CommandKind::Function | CommandKind::Builtin) => name.clone(),
+                Some(CommandKind::File(path)) => path.display().to_string(),
+                None => {
+                    missing = true;
+                    continue;
+                }
+            };
+            let _ = writeln!(self.stdout(), "{}", line);
+        }
+
+        if missing {
+            self.exit_status = 1;
+            return Err(ErrorKind::NotFound);
+        }
+        Ok(())
+    }
+
+    // `printf format [arguments...]` formats its arguments like C's printf, using the
+    // format again while arguments are left
+    fn printf_command(&mut self, args: &[String]) -> Result<(), ErrorKind> {
+        let args = match args.first() {
+            Some(first) if first == "--" => &args[1..],
+            _ => args,
+        };
+        let Some((format, args)) = args.split_first() else {
+            eprintln!("{}printf: usage: printf format [arguments]", self.error_prefix());
+            self.exit_status = 2;
+            return Err(ErrorKind::InvalidInput);
+        };
+
+        let formatted = printf::format(format, args);
+        for error in &formatted.errors {
+            eprintln!("{}printf: {}", self.error_prefix(), error);
+        }
+
+        let result = self.stdout().write_all(formatted.text.as_bytes());
+        if let Err(err) = result {
+            eprintln!("{}printf: write error: {}", self.error_prefix(), err);
+            self.exit_status = 1;
+            return Err(err.kind());
+        }
+        if !formatted.errors.is_empty() {
+            self.exit_status = 1;
+            return Err(ErrorKind::InvalidData);
+        }
+        Ok(())
+    }
+
+    // `read [-r] [-p prompt] [-n count] [name...]` reads a line from standard input and
+    // splits it on $IFS into the named variables, the last one taking the rest of the line.
+    // Without names the whole line goes to $REPLY. At the end of input the status is 1.
+    fn read_command(&mut self, args: &[String]) -> Result<(), ErrorKind> {
+        let mut raw = false;
+        let mut prompt = None;
+        let mut limit = None;
+        let mut names = args;
+
+        while let Some((flag, rest)) = names.split_first()
+            && flag.starts_with('-')
+            && flag.len() > 1
+        {
+            names = rest;
+            if flag == "--" {
+                break;
+            }
+
+            for (i, option) in flag.char_indices().skip(1) {
+                if option == 'r' {
+                    raw = true;
+                    continue;
+                }
+                if !matches!(option, 'p' | 'n') {
+                    eprintln!("{}read: -{}: invalid option", self.error_prefix(), option);
+                    self.exit_status = 2;
+                    return Err(ErrorKind::InvalidInput);
+                }
+
+                // The value is the rest of the word, as in `-n1`, or the next argument
+                let value = match &flag[i + 1..] {
+                    "" => match names.split_first() {
+                        Some((value, rest)) => {
+                            names = rest;
+                            value.as_str()
+                        }
+                        None => {
+                            eprintln!(
+                                "{}read: -{}: option requires an argument",
+                                self.error_prefix(),
+                                option
+                            );
+                            self.exit_status = 2;
+                            return Err(ErrorKind::InvalidInput);
+                        }
+                    },
+                    attached => attached,
+                };
+
+                if option == 'p' {
+                    prompt = Some(value);
+                } else {
+                    match value.parse::<usize>() {
+                        Ok(count) => limit = Some(count),
+                        Err(_) => {
+                            eprintln!("{}read: {}: invalid number", self.error_prefix(), value);
+                            self.exit_status = 2;
+                            return Err(ErrorKind::InvalidInput);
+                        }
+                    }
+                }
+                break;
+            }
+        }
+
+        // Like other shells, only prompt someone typing at a terminal
+        let from_terminal = match &self.streams.stdin {
+            Some(file) => file.is_terminal(),
+            None => std::io::stdin().is_terminal(),
+        };
+        if let Some(prompt) = prompt
+            && from_terminal
+        {
+            let mut stderr = self.stderr();
+            let _ = stderr.write_all(prompt.as_bytes());
+            let _ = stderr.flush();
+        }
+
+        let (line, complete) = match &self.streams.stdin {
+            Some(file) => read_line(&mut &*file, limit, raw),
+            None => read_line(&mut std::io::stdin().lock(), limit, raw),
+        };
+
+        if names.is_empty() {
+            let line = line.iter().map(|&(ch, _)| ch).collect();
+            self.variables.insert("REPLY".to_string(), line);
+        } else {
+            let ifs = self.variables.get("IFS").map_or(" \t\n", String::as_str);
+            let fields = split_read_fields(&line, ifs, names.len());
+            for (name, field) in names.iter().zip(fields) {
+                self.variables.insert(name.clone(), field);
+            }
+        }
+
+        self.exit_status = if complete { 0 } else { 1 };
+        Ok(())
+    }
+
+    // `test expr` and `[ expr ]` succeed when the expression holds, fail when it does not
+    // and return 2 when it cannot be evaluated
+    fn test_command(&mut self, name: &str, args: &[String]) -> Result<(), ErrorKind> {
+        let args = match args.split_last() {
+            _ if name == "test" => args,
+            Some((last, args)) if last == "]" => args,
+            _ => {
+                eprintln!("{}[: missing `]'", self.error_prefix());
+                self.exit_status = 2;
+                return Err(ErrorKind::InvalidInput);
+            }
+        };
+
+        match condition::evaluate(args) {
+            Ok(result) => {
+                self.exit_status = if result { 0 } else { 1 };
+                Ok(())
+            }
+            Err(message) => {
+                eprintln!("{}{}: {}", self.error_prefix(), name, message);
+                self.exit_status = 2;
+                Err(ErrorKind::InvalidInput)
+            }
+        }
+    }
+
+    // `shift [n]` drops the first n positional parameters, or fails if there are fewer
+    fn shift_command(&mut self, args: &[String]) -> Result<(), ErrorKind> {
+        let count = match args.first().map(|arg| arg.parse::<usize>()) {
+            None => 1,
+            Some(Ok(count)) => count,
+            Some(Err(_)) => {
+                eprintln!("{}shift: {}: numeric argument required", self.error_prefix(), args[0]);
+                self.exit_status = 1;
+                return Err(ErrorKind::InvalidInput);
+            }
+        };
+
+        if count > self.positional.len() {
+            self.exit_status = 1;
+            return Err(ErrorKind::InvalidInput);
+        }
+
+        self.positional.drain(..count);
+        Ok(())
+    }
+
+    // `unset [-v|-f] names`: without a flag a name is a variable, or a function if no
+    // variable has it. Names that are not set are ignored.
+    fn unset_command(&mut self, args: &[String]) -> Result<(), ErrorKind> {
+        let (target, names) = match args.first().map(String::as_str) {
+            Some(flag @ ("-v" | "-f")) => (Some(flag), &args[1..]),
+            Some(flag) if flag.starts_with('-') && flag.len() > 1 => {
+                eprintln!("{}unset: {}: invalid option", self.error_prefix(), flag);
+                self.exit_status = 2;
+                return Err(ErrorKind::InvalidInput);
+            }
+            _ => (None, args),
+        };
+
+        for name in names {
+            let unset_variable = target != Some("-f");
+            if unset_variable && self.forget_variable(name).is_some() {
+                continue;
+            }
+            if target != Some("-v") {
+                self.functions.remove(name);
+            }
+        }
+
+        self.exit_status = 0;
+        Ok(())
+    }
+
+    // Removes a variable, and unexports it so started commands no longer see it. Only the
+    // innermost binding goes: a local of the running function stays local until it returns,
+    // and one of a calling function gives way to the value it hid.
+    fn forget_variable(&mut self, name: &str) -> Option<String> {
+        match self.scopes.iter().rposition(|scope| scope.contains_key(name)) {
+            Some(index) if index + 1 == self.scopes.len() => self.variables.remove(name),
+            Some(index) => match self.scopes[index].remove(name).flatten() {
+                Some(outer) => self.variables.insert(name.to_string(), outer),
+                None => self.variables.remove(name),
+            },
+            None => {
+                self.unexport(name);
+                let array = self.arrays.remove(name).and_then(|values| values.into_iter().next());
+                self.variables.remove(name).or(array)
+            }
+        }
+    }
+
+    // `local [name[=value]...]` gives the running function its own variables, which get the
+    // caller's values back when it returns. Without names, lists the function's locals.
+    fn local_command(&mut self, args: &[String]) -> Result<(), ErrorKind> {
+        if self.scopes.is_empty() {
+            eprintln!("{}local: can only be used in a function", self.error_prefix());
+            self.exit_status = 1;
+            return Err(ErrorKind::InvalidInput);
+        }
+        if let Some(flag) = args.first().filter(|arg| arg.starts_with('-') && arg.len() > 1) {
+            eprintln!("{}local: {}: invalid option", self.error_prefix(), flag);
+            self.exit_status = 2;
+            return Err(ErrorKind::InvalidInput);
+        }
+
+        if args.is_empty() {
+            let scope = self.scopes.last().map(HashMap::keys).into_iter().flatten();
+            let mut names: Vec<&String> = scope.collect();
+            names.sort();
+            let listing: String = names
+                .into_iter()
+                .filter_map(|name| Some((name, self.variables.get(name)?)))
+                .map(|(name, value)| format!("{}={}\n", name, value))
+                .collect();
+            let _ = self.stdout().write_all(listing.as_bytes());
+            return Ok(());
+        }
+
+        for arg in args {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg.as_str(), None),
+            };
+            if !is_name(name) {
+                eprintln!("{}local: `{}': not a valid identifier", self.error_prefix(), arg);
+                self.exit_status = 1;
+                continue;
+            }
+
+            let outer = self.variables.get(name).cloned();
+            let Some(scope) = self.scopes.last_mut() else {
+                break;
+            };
+            let declared = scope.contains_key(name);
+            scope.entry(name.to_string()).or_insert(outer);
+            match value {
+                Some(value) => _ = self.variables.insert(name.to_string(), value.to_string()),
+                // A new local starts out unset
+                None if !declared => _ = self.variables.remove(name),
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    // Keeps a variable from started commands, also taking it out of the environment the
+    // shell inherited
+    fn unexport(&mut self, name: &str) {
+        self.exported.remove(name);
+        // SAFETY: commands run on this thread; the only other threads feed
+        // here-documents into pipes and never read the environment
+        unsafe { std::env::remove_var(name) };
+    }
+
+    /// Sets the positional parameters, and `$0` when `name` is given, as for a script
+    /// started with arguments
+    pub fn set_arguments(&mut self, name: Option<String>, args: Vec<String>) {
+        if let Some(name) = name {
+            self.shell_name = name;
+        }
+        self.positional = args;
+    }
+
+    /// Runs the file at `path` as a script with `args` as its positional parameters, and
+    /// returns the status of the last command that ran. Fails only if the file cannot be
+    /// opened; `set -e` ends the script early with the failing command's status.
+    pub fn execute_script(&mut self, path: &Path, args: Vec<String>) -> Result<i32, ErrorKind> {
+        File::open(path).map_err(|err| err.kind())?;
+
+        self.set_arguments(Some(path.display().to_string()), args);
+        let _ = self.source(path.to_path_buf());
+
+        // Nothing outside the script is left to stop
+        self.jump = None;
+        Ok(self.exit_status)
+    }
+
+    pub fn load_login_config(&mut self) {
+        let path = self.home_dir.join(".wpcsh_profile");
+        let _ = self.source(path);
+    }
+
+    pub fn load_interactive_config(&mut self) {
+        let path = self.home_dir.join(".wpcshrc");
+        let _ = self.source(path);
+    }
+
+    fn get_result_of_external_command(
+        &mut self,
+        name: String,
+        args: Vec<String>,
+        redirects: Vec<Redirect>,
+    ) -> Result<std::process::Output, ErrorKind> {
+        let (name, args) = self.resolve_alias(&name, args);
+
+        let mut command = self.external_command(&name).map_err(|_| ErrorKind::NotFound)?;
+        command.args(args);
+
+        let mut streams = StdStreams::default();
+        if let Err(err) = self.open_redirects(&mut streams, &redirects) {
+            self.report_redirect_error(&err);
+            return Err(err.kind());
+        }
+        streams.apply_to(&mut command);
+
+        command.output().map_err(|err| {
+            report_spawn_error(&self.error_prefix(), &name, &err);
+            err.kind()
+        })
+    }
+
+    fn open_redirects(
+        &mut self,
+        streams: &mut StdStreams,
+        redirects: &[Redirect],
+    ) -> std::io::Result<()> {
+        streams.noclobber = self.options.noclobber;
+
+        // Streams that are not piped elsewhere go where the enclosing command sends them
+        streams.inherit(&self.streams)?;
+
+        for redirect in redirects {
+            streams.redirect(redirect, &self.redirect_target(redirect)?)?;
+        }
+        Ok(())
+    }
+
+    fn report_redirect_error(&mut self, err: &std::io::Error) {
+        // A target whose expansion failed was already reported by the expansion
+        if err.get_ref().is_some() || err.raw_os_error().is_some() {
+            eprintln!("{}{}", self.error_prefix(), err);
+        }
+        self.exit_status = 1;
+    }
+
+    fn redirect_target<'a>(&mut self, redirect: &'a Redirect) -> std::io::Result<Cow<'a, str>> {
+        match redirect.kind {
+            RedirectKind::HereDoc | RedirectKind::HereDocDash => {
+                Ok(self.expand_variables(&redirect.file))
+            }
+            RedirectKind::HereDocLiteral | RedirectKind::InputDup | RedirectKind::OutputDup => {
+                Ok(Cow::Borrowed(&redirect.file))
+            }
+            RedirectKind::HereString => Ok(Cow::Owned(self.expand_target(&redirect.file)?)),
+            _ => {
+                let target = self.expand_target(&redirect.file)?;
+
+                // Like bash, refuse to open a file whose name expanded to nothing
+                if target.is_empty() {
+                    return Err(std::io::Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("{}: ambiguous redirect", redirect.file),
+                    ));
+                }
+
+                Ok(Cow::Owned(target))
+            }
+        }
+    }
+
+    // Expands aliases until the command word is not one. A name already expanded, as in
+    // `alias ls='ls --color'` or a cycle of aliases, is left as it is.
+    fn resolve_alias(&self, cmd: &str, args: Vec<String>) -> (String, Vec<String>) {
+        let mut words = vec![cmd.to_string()];
+        let mut seen = HashSet::new();
+
+        while seen.len() < MAX_ALIAS_DEPTH
+            && let Some(alias) = self.aliases.get(&words[0])
+            && seen.insert(words[0].clone())
+        {
+            let mut expanded: Vec<String> = alias.split_whitespace().map(String::from).collect();
+            if expanded.is_empty() {
+                break;
+            }
+            expanded.extend(words.drain(1..));
+            words = expanded;
+        }
+
+        let name = words.remove(0);
+        words.extend(args);
+        (name, words)
+    }
+
+    fn assignment_value(&mut self, value: &Node) -> Result<String, ErrorKind> {
+        match value {
+            Node::StringLiteral(value) => match value.strip_prefix('~') {
+                Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+                    let home = self.home_dir.to_string_lossy().into_owned();
+                    Ok(home + &self.expand_string(rest)?)
+                }
+                _ => self.expand_string(value),
+            },
+            Node::SingleQuotedString(value) => Ok(value.clone()),
+            Node::CommandSubstitution { command } => Ok(self.capture_output(*command.clone())),
+            Node::ArithmeticExpansion { expression } => {
+                Ok(self.evaluate_arithmetic(expression)?.to_string())
+            }
+            _ => Ok(String::new()),
+        }
+    }
+
+    pub fn change_directory(&mut self, args: &[String]) -> Result<(), ErrorKind> {
+        if args.len() > 1 {
+            self.exit_status = 1;
+            return Err(ErrorKind::InvalidInput);
+        }
+
+        // `cd -` goes back to $OLDPWD and says where it went
+        let back = args.first().is_some_and(|arg| arg == "-");
+        let (new_dir, from_cdpath) = match args.first() {
+            Some(_) if back => match self.variables.get("OLDPWD") {
+                Some(old) => (PathBuf::from(old), false),
+                None => {
+                    eprintln!("{}cd: OLDPWD not set", self.error_prefix());
+                    self.exit_status = 1;
+                    return Err(ErrorKind::NotFound);
+                }
+            },
+            Some(path) => match path.strip_prefix('~') {
+                Some(rest) => (self.home_dir.join(rest), false),
+                None => self.resolve_cd_target(path),
+            },
+            None => (self.home_dir.clone(), false),
+        };
+
+        let new_dir = normalize_path(new_dir);
+
+        if std::env::set_current_dir(new_dir.clone()).is_err() {
+            return Err(ErrorKind::InvalidInput);
+        }
+
+        if new_dir.is_dir() {
+            let old_dir = std::mem::replace(&mut self.current_dir, new_dir.clone());
+            self.variables
+                .insert("OLDPWD".to_string(), old_dir.to_string_lossy().to_string());
+            self.variables
+                .insert("PWD".to_string(), new_dir.to_string_lossy().to_string());
+            // Like bash, only announce the directory when CDPATH picked it, or for `cd -`
+            if back || (from_cdpath && self.interactive) {
+                let _ = writeln!(self.stdout(), "{}", new_dir.display());
+            }
+            self.exit_status = 0;
+            Ok(())
+        } else {
+            self.exit_status = 1;
+            Err(ErrorKind::InvalidInput)
+        }
+    }
+
+    // `pushd dir` saves the current directory and changes to `dir`; without one it
+    // swaps with the most recently saved directory
+    fn pushd_command(&mut self, args: &[String]) -> Result<(), ErrorKind> {
+        let target = match args {
+            [] => match self.dir_stack.pop() {
+                Some(top) => top.to_string_lossy().to_string(),
+                None => {
+                    eprintln!("{}pushd: no other directory", self.error_prefix());
+                    self.exit_status = 1;
+                    return Err(ErrorKind::NotFound);
+                }
+            },
+            [dir] => dir.clone(),
+            _ => {
+                eprintln!("{}pushd: too many arguments", self.error_prefix());
+                self.exit_status = 1;
+                return Err(ErrorKind::InvalidInput);
+            }
+        };
+
+        let old_dir = self.current_dir.clone();
+        if let Err(err) = self.change_directory(std::slice::from_ref(&target)) {
+            // Keep the directory we meant to swap with
+            if args.is_empty() {
+                self.dir_stack.push(PathBuf::from(target));
+            }
+            return Err(err);
+        }
+
+        self.dir_stack.push(old_dir);
+        self.print_dir_stack()
+    }
+
+    // `popd` changes back to the most recently saved directory and drops it from the stack
+    fn popd_command(&mut self, args: &[String]) -> Result<(), ErrorKind> {
+        if !args.is_empty() {
+            eprintln!("{}popd: too many arguments", self.error_prefix());
+            self.exit_status = 1;
+            return Err(ErrorKind::InvalidInput);
+        }
+
+        let Some(top) = self.dir_stack.pop() else {
+            eprintln!("{}popd: directory stack empty", self.error_prefix());
+            self.exit_status = 1;
+            return Err(ErrorKind::NotFound);
+        };
+
+        if let Err(err) = self.change_directory(&[top.to_string_lossy().to_string()]) {
+            self.dir_stack.push(top);
+            return Err(err);
+        }
+
+        self.print_dir_stack()
+    }
+
+    // Prints the current directory followed by the saved ones, newest first, like `dirs`
+    fn print_dir_stack(&mut self) -> Result<(), ErrorKind> {
+        let entries: Vec<String> = std::iter::once(&self.current_dir)
+            .chain(self.dir_stack.iter().rev())
+            .map(|dir| match dir.strip_prefix(&self.home_dir) {
+                Ok(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+                Ok(rest) => format!("~/{}", rest.display()),
+                Err(_) => dir.display().to_string(),
+            })
+            .collect();
+
+        let _ = writeln!(self.stdout(), "{}", entries.join(" "));
+        self.exit_status = 0;
+        Ok(())
+    }
+
+    // Returns the directory to change into and whether it came from a CDPATH entry
+    fn resolve_cd_target(&self, path: &str) -> (PathBuf, bool) {
+        let direct = self.current_dir.join(path);
+
+        let bypasses_cdpath = Path::new(path).is_absolute()
+            || path == "."
+            || path == ".."
+            || path.starts_with("./")
+            || path.starts_with("../");
+
+        if bypasses_cdpath {
+            return (direct, false);
+        }
+
+        let cdpath = self.variables.get("CDPATH").map(String::as_str).unwrap_or("");
+
+        for entry in cdpath.split(':') {
+            // An empty entry or "." stands for the current directory, which is not announced
+            let is_current = entry.is_empty() || entry == ".";
+            let base = if is_current {
+                self.current_dir.clone()
+            } else {
+                self.current_dir.join(entry)
+            };
+
+            let candidate = base.join(path);
+            if candidate.is_dir() {
+                return (candidate, !is_current);
+            }
+        }
+
+        (direct, false)
+    }
+
+    // `export name[=value]...` marks variables for the environment of started commands and
+    // `export -n name...` unmarks them. Without names, or with `-p`, it lists those that are.
+    fn export_command(&mut self, args: &[String]) -> Result<(), ErrorKind> {
+        let mut unexport = false;
+        let mut names = args;
+        while let Some((flag, rest)) = names.split_first()
+            && flag.starts_with('-')
+            && flag.len() > 1
+        {
+            match flag.as_str() {
+                "-n" => unexport = true,
+                "-p" => {}
+                "--" => {
+                    names = rest;
+                    break;
+                }
+                _ => {
+                    eprintln!("{}export: {}: invalid option", self.error_prefix(), flag);
+                    self.exit_status = 2;
+                    return Err(ErrorKind::InvalidInput);
+                }
+            }
+            names = rest;
+        }
+
+        if names.is_empty() {
+            let mut names: Vec<&String> = self.exported.iter().collect();
+            names.sort();
+            let listing: String = names
+                .into_iter()
+                .filter_map(|name| Some((name, self.variables.get(name)?)))
+                .map(|(name, value)| format!("declare -x {}={}\n", name, double_quote(value)))
+                .collect();
+
+            let _ = self.stdout().write_all(listing.as_bytes());
+            self.exit_status = 0;
+            return Ok(());
+        }
+
+        for arg in names {
+            let name = match arg.split_once('=') {
+                Some((name, value)) => {
+                    self.variables.insert(name.to_string(), value.to_string());
+                    name
+                }
+                None => arg.as_str(),
+            };
+            match unexport {
+                true => self.unexport(name),
+                false => _ = self.exported.insert(name.to_string()),
+            }
+        }
+
+        self.exit_status = 0;
+        Ok(())
+    }
+
+    // `alias` lists every alias, `alias name` shows one and `alias name=value` defines one
+    fn alias_command(&mut self, args: &[String]) -> Result<(), ErrorKind> {
+        if args.is_empty() {
+            let mut names: Vec<&String> = self.aliases.keys().collect();
+            names.sort();
+            let listing: String = names
+                .into_iter()
+                .map(|name| format!("alias {}={}\n", name, single_quote(&self.aliases[name])))
+                .collect();
+
+            let _ = self.stdout().write_all(listing.as_bytes());
+            self.exit_status = 0;
+            return Ok(());
+        }
+
+        let mut missing = false;
+        for arg in args {
+            if arg.contains('=') {
+                self.add_alias(arg);
+                continue;
+            }
+
+            match self.aliases.get(arg) {
+                Some(value) => {
+                    let line = format!("alias {}={}", arg, single_quote(value));
+                    let _ = writeln!(self.stdout(), "{}", line);
+                }
+                None => {
+                    eprintln!("{}alias: {}: not found", self.error_prefix(), arg);
+                    missing = true;
+                }
+            }
+        }
+
+        if missing {
+            self.exit_status = 1;
+            return Err(ErrorKind::NotFound);
+        }
+        self.exit_status = 0;
+        Ok(())
+    }
+
+    // `unalias names` removes aliases, `unalias -a` all of them
+    fn unalias_command(&mut self, args: &[String]) -> Result<(), ErrorKind> {
+        if args.first().is_some_and(|arg| arg == "-a") {
+            self.aliases.clear();
+            self.exit_status = 0;
+            return Ok(());
+        }
+
+        if args.is_empty() {
+            eprintln!("unalias: usage: unalias [-a] name [name ...]");
+            self.exit_status = 2;
+            return Err(ErrorKind::InvalidInput);
+        }
+
+        let mut missing = false;
+        for name in args {
+            if self.aliases.remove(name).is_none() {
+                eprintln!("{}unalias: {}: not found", self.error_prefix(), name);
+                missing = true;
+            }
+        }
+
+        if missing {
+            self.exit_status = 1;
+            return Err(ErrorKind::NotFound);
+        }
+        self.exit_status = 0;
+        Ok(())
+    }
+
+    fn add_alias(&mut self, text: &str) {
+        if let Some((key, val)) = text.split_once('=') {
+            let val = val.trim_matches('"');
+            self.aliases.insert(key.trim().to_string(), val.to_string());
+            self.exit_status = 0;
+        } else {
+            self.exit_status = 1;
+        }
+    }
+
+    // PS0 is printed after a line is read and before it runs; empty means no output
+    fn render_ps0(&self) -> Option<String> {
+        let template = self.variables.get("PS0").filter(|ps0| !ps0.is_empty())?;
+        Some(prompt::expand_escapes(template, &self.prompt_context()))
+    }
+
+    fn render_ps2(&self) -> String {
+        match self.variables.get("PS2") {
+            Some(ps2) => prompt::expand_escapes(ps2, &self.prompt_context()),
+            None => "> ".to_string(),
+        }
+    }
+
+    fn prompt_context(&self) -> prompt::PromptContext<'_> {
+        prompt::PromptContext {
+            current_dir: &self.current_dir,
+            home_dir: &self.home_dir,
+            variables: &self.variables,
+        }
+    }
+
+    // Rendering may run a command, so the result is reused until the state it can depend
+    // on changes. WPCSH_PROMPT_NOCACHE renders every time, as for a prompt with a clock.
+    // PS1 is cheap to expand and may show the time, so it is never cached.
+    fn get_prompt(&mut self) -> String {
+        if let Some(ps1) = self.variables.get("PS1") {
+            return prompt::expand_escapes(ps1, &self.prompt_context());
+        }
+
+        let no_cache = self
+            .variables
+            .get("WPCSH_PROMPT_NOCACHE")
+            .is_some_and(|value| !value.is_empty());
+        let key = self.prompt_key();
+
+        if !no_cache
+            && let Some((cached_key, prompt)) = &self.prompt_cache
+            && *cached_key == key
+        {
+            return prompt.clone();
+        }
+
+        let prompt = self.render_prompt();
+        self.prompt_cache = Some((key, prompt.clone()));
+        prompt
+    }
+
+    // Hashes what a prompt usually shows: the directory and its git state, the last
+    // status and the prompt settings
+    fn prompt_key(&self) -> u64 {
+        use std::hash::{DefaultHasher, Hash, Hasher};
+
+        let mut hasher = DefaultHasher::new();
+        self.current_dir.hash(&mut hasher);
+        self.exit_status.hash(&mut hasher);
+        for name in ["PROMPT", "WPCSH_PROMPT"] {
+            self.variables.get(name).hash(&mut hasher);
+        }
+
+        // A commit, checkout or staged change touches one of these
+        let mut gits = self.current_dir.ancestors().map(|dir| dir.join(".git"));
+        if let Some(git) = gits.find(|git| git.exists()) {
+            for file in ["HEAD", "index"] {
+                let modified = std::fs::metadata(git.join(file)).and_then(|meta| meta.modified());
+                modified.ok().hash(&mut hasher);
+            }
+        }
+
+        hasher.finish()
+    }
+
+    fn render_prompt(&mut self) -> String {
+        if let Some(cmd) = self.variables.get("PROMPT") {
+            let lexer = flash::lexer::Lexer::new(cmd);
+            let mut parser = flash::parser::Parser::new(lexer);
+
+            let node = parser.parse_command();
+
+            if let Node::Command {
+                name,
+                args,
+                redirects,
+            } = node
+                && let Ok(out) = self.get_result_of_external_command(name, args, redirects)
+            {
+                return String::from_utf8_lossy(&out.stdout).to_string();
+            }
+        } else if let Some(prompt) = self.starship_prompt() {
+            return prompt;
+        }
+
+        format!("{} > ", self.current_dir.display())
+    }
+
+    // Renders the prompt with starship when WPCSH_PROMPT=starship, or when WPCSH_PROMPT is
+    // unset and starship is installed. WPCSH_PROMPT=plain turns it off.
+    fn starship_prompt(&self) -> Option<String> {
+        let explicit = match self.variables.get("WPCSH_PROMPT").map(String::as_str) {
+            Some("starship") => true,
+            None | Some("") => false,
+            Some(_) => return None,
+        };
+
+        let status = self.exit_status.to_string();
+        let output = Command::new("starship")
+            .args(["prompt", "--status", &status])
+            .envs(self.child_env())
+            .env("STARSHIP_SHELL", "wpcsh")
+            .env("STARSHIP_CMD_STATUS", &status)
+            .env("PWD", &self.current_dir)
+            .current_dir(&self.current_dir)
+            .stderr(std::process::Stdio::null())
+            .output();
+
+        match output {
+            Ok(output) if output.status.success() => {
+                Some(String::from_utf8_lossy(&output.stdout).into_owned())
+            }
+            // Only a prompt the user asked for is worth a complaint
+            Ok(_) | Err(_) if explicit => {
+                eprintln!("wpcsh: starship prompt failed; using the default prompt");
+                None
+            }
+            _ => None,
+        }
+    }
+
+    pub fn run_non_interactive(&mut self) {
+        use std::io::{self, BufRead};
+
+        let stdin = io::stdin();
+        let mut buffer = String::new();
+        for line in stdin.lock().lines().map_while(Result::ok) {
+            if buffer.is_empty() && line.trim().is_empty() {
+                continue;
+            }
+
+            join_line(&mut buffer, &line);
+            if flash::parser::is_incomplete(&buffer) {
+                continue;
+            }
+
+            let command = std::mem::take(&mut buffer);
+            if self.execute(command.trim()).is_err() {
+                return;
+            }
+            if let Some(signal) = foreground::take_termination() {
+                self.exit_status = 128 + signal;
+                return;
+            }
+        }
+
+        if !buffer.is_empty() {
+            eprintln!("wpcsh: syntax error: unexpected end of file");
+            self.exit_status = 2;
+        }
+    }
+
+    pub fn run_interactive(&mut self) {
+        use linefeed::{Interface, ReadResult, Signal};
+
+        self.interactive = true;
+        self.load_interactive_config();
+        foreground::catch_interrupts();
+
+        let interface = Interface::new("wpcsh").expect("no tty");
+        let completer = std::sync::Arc::new(complete::ShellCompleter::new(self.home_dir.clone()));
+        interface.set_completer(completer.clone());
+        // Ctrl-C at the prompt drops the line being typed instead of the shell
+        interface.set_report_signal(Signal::Interrupt, true);
+
+        let _ = self.history.read_file(&self.history_file());
+
+        let mut editing_mode = self.options.editing_mode;
+        editing_mode.apply(&interface);
+
+        loop {
+            // set -o vi/emacs may have switched modes during the last command
+            if self.options.editing_mode != editing_mode {
+                editing_mode = self.options.editing_mode;
+                editing_mode.apply(&interface);
+            }
+
+            if self.history.take_reloaded() {
+                interface.clear_history();
+                for entry in self.history.entries() {
+                    interface.add_history(entry.clone());
+                }
+            }
+
+            self.report_finished_jobs();
+            completer.set_commands(self.command_names());
+            completer.set_specs(self.completions.clone());
+            let prompt = self.get_prompt();
+
+            if interface.set_prompt(&prompt).is_err() {
+                interface.set_prompt(">").expect("Failed to set prompt");
+            };
+
+            match self.read_prompt_line(&interface) {
+                Ok(ReadResult::Input(line)) => {
+                    let Some(line) = self.read_continuation(&interface, line) else {
+                        continue;
+                    };
+                    interface.add_history(line.clone());
+                    self.history.push(line.clone());
+
+                    if let Some(ps0) = self.render_ps0() {
+                        print!("{}", ps0);
+                        std::io::stdout().flush().unwrap();
+                    }
+
+                    if let Err(err) = self.execute(&line) {
+                        match err {
+                            ErrorKind::InvalidInput => {
+                                eprintln!("wpcsh: invalid input: {}", line);
+                            }
+                            ErrorKind::NotFound => {
+                                eprintln!("wpcsh: command not found: {}", line);
+                            }
+                            ErrorKind::Interrupted => {
+                                break;
+                            }
+                            _ => {}
+                        }
+                    }
+                    std::io::stdout().flush().unwrap();
+                    println!();
+                }
+                Ok(ReadResult::Signal(Signal::Interrupt)) => {
+                    let _ = interface.cancel_read_line();
+                    self.run_trap(signal::Signal::Number(signal::INT));
+                    continue;
+                }
+                Ok(ReadResult::Signal(Signal::Quit)) => break,
+                Ok(ReadResult::Eof) => break,
+                _ => {}
+            }
+
+            let _ = self.history.append_to_file(&self.history_file());
+        }
+    }
+
+    // Reads a line at the prompt. A terminating signal that arrives meanwhile ends input,
+    // leaving the status the shell exits with.
+    fn read_prompt_line(
+        &mut self,
+        interface: &linefeed::Interface<linefeed::DefaultTerminal>,
+    ) -> std::io::Result<linefeed::ReadResult> {
+        loop {
+            if let Some(signal) = foreground::take_termination() {
+                let _ = interface.cancel_read_line();
+                self.exit_status = 128 + signal;
+                return Ok(linefeed::ReadResult::Eof);
+            }
+            let timeout = std::time::Duration::from_millis(100);
+            if let Some(result) = interface.read_line_step(Some(timeout))? {
+                return Ok(result);
+            }
+        }
+    }
+
+    // Keeps reading lines with the PS2 prompt while `line` leaves a quote or compound
+    // command open, or ends in a backslash. None if input ends or is cancelled first.
+    fn read_continuation(
+        &mut self,
+        interface: &linefeed::Interface<linefeed::DefaultTerminal>,
+        mut line: String,
+    ) -> Option<String> {
+        use linefeed::ReadResult;
+
+        while flash::parser::is_incomplete(&line) {
+            if interface.set_prompt(&self.render_ps2()).is_err() {
+                interface.set_prompt("> ").expect("Failed to set prompt");
+            }
+
+            match interface.read_line() {
+                Ok(ReadResult::Input(next)) => join_line(&mut line, &next),
+                Ok(ReadResult::Eof) => {
+                    eprintln!("wpcsh: syntax error: unexpected end of file");
+                    self.exit_status = 2;
+                    return None;
+                }
+                _ => return None,
+            }
+        }
+
+        Some(line)
+    }
+
+    fn clear_terminal(&mut self) -> Result<(), ErrorKind> {
+        print!("\x1B[2J\x1B[1;1H");
+        use std::io::Write;
+        match std::io::stdout().flush() {
+            Ok(_) => {
+                self.exit_status = 0;
+                Ok(())
+            }
+            Err(_) => {
+                self.exit_status = 1;
+                Err(ErrorKind::InvalidInput)
+            }
+        }
+    }
+}
+
+// Appends the next line of a multiline command. A backslash ending the buffer is a line
+// continuation, so it is dropped along with the newline.
+fn join_line(buffer: &mut String, line: &str) {
+    let trailing_backslashes = buffer.chars().rev().take_while(|&c| c == '\\').count();
+
+    if trailing_backslashes % 2 == 1 {
+        buffer.pop();
+    } else if !buffer.is_empty() {
+        buffer.push('\n');
+    }
+    buffer.push_str(line);
+}
+
+// The first stage of a pipeline that was started
+fn first_process(stages: &[Result<Process, i32>]) -> Option<&Process> {
+    stages.iter().find_map(|stage| stage.as_ref().ok())
+}
+
+// The `$?` of a finished command
+#[cfg(not(unix))]
+fn status_code(status: std::process::ExitStatus) -> i32 {
+    status.code().unwrap_or(1)
+}
+
+// Reports a command that could not be started and returns its status: 127 when there is
+// no such program and 126 when it cannot be run
+fn report_spawn_error(prefix: &str, name: &str, err: &std::io::Error) -> i32 {
+    match err.kind() {
+        ErrorKind::NotFound => {
+            eprintln!("{}{}: command not found", prefix, name);
+            127
+        }
+        ErrorKind::PermissionDenied => {
+            eprintln!("{}{}: permission denied", prefix, name);
+            126
+        }
+        _ => {
+            eprintln!("{}{}: {}", prefix, name, err);
+            126
+        }
+    }
+}
+
+// What went wrong when running a line fails as a whole
+fn error_reason(err: ErrorKind) -> String {
+    match err {
+        ErrorKind::NotFound => "command not found".to_string(),
+        ErrorKind::InvalidInput => "invalid input".to_string(),
+        _ => err.to_string(),
+    }
+}
+
+// A word as `set -x` shows it, quoted when it would not read back as one plain word
+fn trace_word(word: &str) -> Cow<'_, str> {
+    let plain = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_alphanumeric() || "_./=:,+-@%^".contains(c));
+    match plain {
+        true => Cow::Borrowed(word),
+        false => Cow::Owned(single_quote(word)),
+    }
+}
+
+// The words of a job's command, joined back together for listing it
+fn job_text(node: &Node) -> String {
+    let join = |nodes: &[Node], separator: &str| {
+        nodes.iter().map(job_text).collect::<Vec<_>>().join(separator)
+    };
+
+    match node {
+        // Words keep their quotes only where expansion needs them, so spaces are quoted
+        // again to show where each word ends
+        Node::Command { name, args, .. } => std::iter::once(name)
+            .chain(args)
+            .map(|word| match word.contains(char::is_whitespace) && !word.contains(['\'', '"']) {
+                true => single_quote(word),
+                false => word.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join(" "),
+        Node::Pipeline { commands } => join(commands, " | "),
+        Node::List { statements, .. } => join(statements, "; "),
+        Node::Subshell { list, .. } => format!("( {} )", job_text(list)),
+        _ => "...".to_string(),
+    }
+}
+
+// `$$`, the shell's own process id, which forked copies of the shell keep reporting
+fn shell_pid() -> u32 {
+    static PID: std::sync::OnceLock<u32> = std::sync::OnceLock::new();
+    *PID.get_or_init(std::process::id)
+}
+
+// Whether `word` can name a variable: a letter or `_`, then letters, digits and `_`
+fn is_name(word: &str) -> bool {
+    word.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && word.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Quotes `text` so the shell reads it back unchanged, like the values `alias` prints
+fn single_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+// Quotes text for reading back inside double quotes, as `export -p` shows values
+fn double_quote(text: &str) -> String {
+    let mut quoted = String::from('"');
+    for ch in text.chars() {
+        if matches!(ch, '"' | '\\' | '$' | '`') {
+            quoted.push('\\');
+        }
+        quoted.push(ch);
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Reads a line for `read`, stopping after `limit` characters if given. Unless `raw`, a
+// backslash joins the next line or marks the next character as escaped, so it is not split
+// on. Also returns whether a whole line was read before the end of input.
+fn read_line(input: &mut impl Read, limit: Option<usize>, raw: bool) -> (Vec<(char, bool)>, bool) {
+    let mut line = Vec::new();
+    let mut escaped = false;
+
+    while limit.is_none_or(|limit| line.len() < limit) {
+        let Some(ch) = read_input_char(input) else {
+            return (line, false);
+        };
+        match ch {
+            '\\' if !raw && !escaped => escaped = true,
+            '\n' if escaped => escaped = false,
+            '\n' => return (line, true),
+            _ => {
+                line.push((ch, escaped));
+                escaped = false;
+            }
+        }
+    }
+
+    (line, true)
+}
+
+// Reads one UTF-8 character a byte at a time, so nothing past it is taken from the input
+fn read_input_char(input: &mut impl Read) -> Option<char> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes[..1]).ok()?;
+
+    let width = match bytes[0] {
+        0xf0.. => 4,
+        0xe0.. => 3,
+        0xc0.. => 2,
+        _ => 1,
+    };
+    input.read_exact(&mut bytes[1..width]).ok()?;
+
+    let text = String::from_utf8_lossy(&bytes[..width]);
+    text.chars().next()
+}
+
+// Splits a line read by `read` into `count` fields on the characters of `ifs`, the last
+// field keeping the rest of the line. IFS whitespace around a separator is dropped.
+fn split_read_fields(line: &[(char, bool)], ifs: &str, count: usize) -> Vec<String> {
+    let separator = |&(ch, escaped): &(char, bool)| !escaped && ifs.contains(ch);
+    let blank = |item: &(char, bool)| separator(item) && item.0.is_ascii_whitespace();
+    let blanks = |chars: &[(char, bool)]| chars.iter().take_while(|item| blank(item)).count();
+    let text = |chars: &[(char, bool)]| chars.iter().map(|&(ch, _)| ch).collect::<String>();
+
+    let mut rest = &line[blanks(line)..];
+    let trailing = rest.iter().rev().take_while(|item| blank(item)).count();
+    rest = &rest[..rest.len() - trailing];
+
+    let mut fields = Vec::new();
+    while fields.len() + 1 < count && !rest.is_empty() {
+        let end = rest.iter().position(separator).unwrap_or(rest.len());
+        fields.push(text(&rest[..end]));
+        rest = &rest[end..];
+
+        // One separator is any IFS whitespace around at most one other IFS character
+        rest = &rest[blanks(rest)..];
+        if let [first, tail @ ..] = rest
+            && separator(first)
+        {
+            rest = &tail[blanks(tail)..];
+        }
+    }
+
+    fields.push(text(rest));
+    fields.resize(count, String::new());
+    fields
+}
+
+// Interprets the escapes of `echo -e`. Returns the text and whether a `\c` cut it short.
+fn echo_escapes(text: &str) -> (String, bool) {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            out.push(ch);
+            continue;
+        }
+
+        match chars.next() {
+            Some('a') => out.push('\x07'),
+            Some('b') => out.push('\x08'),
+            Some('c') => return (out, true),
+            Some('e' | 'E') => out.push('\x1b'),
+            Some('f') => out.push('\x0c'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('v') => out.push('\x0b'),
+            Some('\\') => out.push('\\'),
+            Some('0') => {
+                let (value, _) = escape_number(&mut chars, 8, 3);
+                out.extend(char::from_u32(value));
+            }
+            Some('x') => match escape_number(&mut chars, 16, 2) {
+                (_, 0) => out.push_str("\\x"),
+                (value, _) => out.extend(char::from_u32(value)),
+            },
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            }
+            None => out.push('\\'),
+        }
+    }
+
+    (out, false)
+}
+
+// Reads up to `max` digits in `radix`, returning their value and how many there were
+fn escape_number(
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+    radix: u32,
+    max: usize,
+) -> (u32, usize) {
+    let mut value = 0;
+    let mut count = 0;
+    while count < max
+        && let Some(digit) = chars.peek().and_then(|c| c.to_digit(radix))
+    {
+        value = value * radix + digit;
+        count += 1;
+        chars.next();
+    }
+    (value, count)
+}
+
+// Resolves `.` and `..` without touching the filesystem, like `cd` without -P, so symlinks
+// are kept. `..` never climbs above the root, and relative paths keep the leading `..`
+// they cannot resolve.
+fn normalize_path(path: PathBuf) -> PathBuf {
+    use std::path::Component;
+
+    let mut result = PathBuf::new();
+    // Names pushed since the root or the leading `..`s, which a later `..` may remove
+    let mut names = 0;
+
+    for component in path.components() {
+        match component {
+            // A prefix such as `C:` or a root starts the path over
+            Component::Prefix(_) | Component::RootDir => {
+                result.push(component);
+                names = 0;
+            }
+            Component::CurDir => {}
+            Component::ParentDir if names > 0 => {
+                result.pop();
+                names -= 1;
+            }
+            Component::ParentDir if result.has_root() => {}
+            Component::ParentDir => result.push(".."),
+            Component::Normal(name) => {
+                result.push(name);
+                names += 1;
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(windows)]
+fn get_coreutils_commands() -> std::io::Result<Vec<String>> {
+    let output = Command::new("coreutils").arg("--list").output()?;
+
+    let coreutils_commands = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let coreutils_commands = coreutils_commands
+        .split_whitespace()
+        .map(String::from)
+        .collect::<Vec<String>>();
+
+    Ok(coreutils_commands)
+}
+
+#[cfg(test)]
+mod shell_tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("wpcsh-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn test_expand_variables_multibyte() {
+        let mut shell = Shell::default();
+        shell
+            .variables
+            .insert("NAME".to_string(), "wörld 🌍".to_string());
+
+        assert_eq!(shell.expand_variables("héllo $NAME!"), "héllo wörld 🌍!");
+        assert_eq!(shell.expand_variables("日本$NAME語"), "日本wörld 🌍語");
+        assert_eq!(shell.expand_variables("$é"), "$é");
+        assert_eq!(shell.expand_variables("[$NAME_UNSET]"), "[]");
+        assert_eq!(shell.expand_variables("ünchanged"), "ünchanged");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_echo_multibyte() {
+        let path = temp_path("echo-multibyte");
+        let mut shell = Shell::default();
+
+        shell
+            .execute(&format!("echo héllo 日本語 🎉 > {}", path.display()))
+            .unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "héllo 日本語 🎉\n");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_unset_variables_and_functions() {
+        let mut shell = Shell::default();
+        shell
+            .execute("A=1; B=2; C=3; greet() { echo hi; }; ok() { true; }")
+            .unwrap();
+
+        assert_eq!(shell.execute("unset A B missing"), Ok(0));
+        assert!(!shell.variables.contains_key("A"));
+        assert!(!shell.variables.contains_key("B"));
+
+        // A name with no variable falls back to the function
+        assert_eq!(shell.execute("unset -v greet; unset greet"), Ok(0));
+        assert!(!shell.functions.contains_key("greet"));
+
+        assert_eq!(shell.execute("unset -f C; unset -f ok"), Ok(0));
+        assert_eq!(shell.variables["C"], "3");
+        assert!(!shell.functions.contains_key("ok"));
+
+        assert_eq!(shell.execute("unset -x C"), Ok(2));
+    }
+
+    #[test]
+    fn test_local_variables() {
+        let mut shell = Shell::default();
+        shell.execute("x=global; y='a  b'; show() { echo \"$x\"; }").unwrap();
+
+        // Callees see a local; the caller's value comes back on return
+        let script = "f() { local x=$y z; show; echo \"[$z]\"; x=changed; }; f; echo $x";
+        let output = shell.execute_capture(script).unwrap();
+        assert_eq!(output.stdout, b"a  b\n[]\nglobal\n");
+
+        // Unsetting a local leaves the global alone
+        let script = "f() { local x=mine; unset x; echo \"[$x]\"; }; f; echo $x";
+        let output = shell.execute_capture(script).unwrap();
+        assert_eq!(output.stdout, b"[]\nglobal\n");
+
+        // In a callee, unset removes only the innermost binding
+        let script = "g() { unset x; show; }; f() { local x=mine; g; show; }; f; show";
+        let output = shell.execute_capture(script).unwrap();
+        assert_eq!(output.stdout, b"global\nglobal\nglobal\n");
+
+        assert_eq!(shell.execute("local x=1"), Ok(1));
+        assert_eq!(shell.variables["x"], "global");
+        assert_eq!(shell.execute("f() { local 1x; }; f"), Ok(1));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unset_hides_variable_from_children() {
+        let name = format!("WPCSH_UNSET_{}", std::process::id());
+        // SAFETY: the name is unique to this test
+        unsafe { std::env::set_var(&name, "inherited") };
+
+        let out = temp_path("unset-env");
+        let mut shell = Shell::default();
+        shell.variables.insert(name.clone(), "inherited".to_string());
+
+        let script = format!("unset {name}; sh -c 'echo \"[${name}]\"' > {}", out.display());
+        assert_eq!(shell.execute(&script), Ok(0));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "[]\n");
+        assert!(std::env::var_os(&name).is_none());
+
+        let _ = std::fs::remove_file(&out);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_only_exported_variables_reach_children() {
+        let out = temp_path("exported");
+        let mut shell = Shell::default();
+
+        let script = format!(
+            "LOCAL=l; SHARED=s; export SHARED; export LATER; export NEW='a \"b\"'; LATER=x; \
+             sh -c 'echo \"[$LOCAL][$SHARED][$NEW][$LATER]\"' > {}",
+            out.display()
+        );
+        assert_eq!(shell.execute(&script), Ok(0));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "[][s][a \"b\"][x]\n");
+
+        shell.execute(&format!("export -p > {}", out.display())).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            "declare -x LATER=\"x\"\ndeclare -x NEW=\"a \\\"b\\\"\"\ndeclare -x SHARED=\"s\"\n"
+        );
+
+        let _ = std::fs::remove_file(&out);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_export_n_keeps_variable_local() {
+        let out = temp_path("unexport");
+        let mut shell = Shell::default();
+
+        let script = format!(
+            "export A=1 B C=3; B=2; export -n C; \
+             sh -c 'echo \"[$A][$B][$C]\"' > {0}; echo \"$C\" >> {0}; export >> {0}",
+            out.display()
+        );
+        assert_eq!(shell.execute(&script), Ok(0));
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            "[1][2][]\n3\ndeclare -x A=\"1\"\ndeclare -x B=\"2\"\n"
+        );
+
+        assert_eq!(shell.execute("export -x A"), Ok(2));
+
+        let _ = std::fs::remove_file(&out);
+    }
+
+    #[test]
+    fn test_read_splits_lines_into_variables() {
+        let input = temp_path("read");
+        let lines = "one two  three four\nx\\ y\n p\\ q\\\nr \nab:c\\:d:e\nlast";
+        std::fs::write(&input, lines).unwrap();
+        let mut shell = Shell::default();
+
+        let script = format!(
+            "{{ read a b c; read -r d e; read f; IFS=: read g h; read -n 2 i; read; }} < {}",
+            input.display()
+        );
+        assert_eq!(shell.execute(&script), Ok(1));
+
+        let var = |name: &str| shell.get_var(name).unwrap_or_default().to_string();
+        assert_eq!([var("a"), var("b"), var("c")], ["one", "two", "three four"]);
+        assert_eq!([var("d"), var("e")], ["x\\", "y"]);
+        assert_eq!(var("f"), "p qr");
+        assert_eq!([var("g"), var("h")], ["ab", "c:d:e"]);
+        assert_eq!([var("i"), var("REPLY")], ["la", "st"]);
+
+        assert_eq!(shell.execute("read -n x y"), Ok(2));
+        assert_eq!(shell.execute("read -q"), Ok(2));
+
+        let _ = std::fs::remove_file(&input);
+    }
+
+    #[test]
+    fn test_test_builtin_statuses() {
+        let mut shell = Shell::default();
+
+        assert_eq!(shell.execute("[ -d / -a ! -f /no/such/file ]"), Ok(0));
+        assert_eq!(shell.execute("x=5; test \"$x\" -ge 10"), Ok(1));
+        assert_eq!(shell.execute("[ -z \"\" ] && [ abc != abd ]"), Ok(0));
+        assert_eq!(shell.execute("[ 1 = 1"), Ok(2));
+        assert_eq!(shell.execute("test one -lt 2"), Ok(2));
+        assert_eq!(shell.execute("if [ -n \"$x\" ]; then y=set; fi"), Ok(0));
+        assert_eq!(shell.get_var("y"), Some("set"));
+    }
+
+    #[test]
+    fn test_printf_builtin() {
+        let mut shell = Shell::default();
+
+        assert_eq!(shell.execute("X=$(printf '%s-%03d,' a 7 b 12)"), Ok(0));
+        assert_eq!(shell.get_var("X"), Some("a-007,b-012,"));
+        assert_eq!(shell.execute("Y=$(printf '%d' one)"), Ok(1));
+        assert_eq!(shell.get_var("Y"), Some("0"));
+        assert_eq!(shell.execute("printf"), Ok(2));
+        assert_eq!(shell.execute("printf %999999999999d 1"), Ok(1));
+    }
+
+    #[test]
+    fn test_extended_test_matching() {
+        let mut shell = Shell::default();
+
+        shell.execute("x=abc; y='two words'").unwrap();
+        assert_eq!(shell.execute("[[ $x == a* ]]"), Ok(0));
+        assert_eq!(shell.execute("[[ $x == \"a*\" ]]"), Ok(1));
+        assert_eq!(shell.execute("[[ $x != b* && $y == two* ]]"), Ok(0));
+        assert_eq!(shell.execute("[[ -z $y || ( a < b && ! 2 -gt 3 ) ]]"), Ok(0));
+        assert_eq!(shell.execute("[[ $y > z ]]"), Ok(1));
+        // The side that is not run is not expanded
+        assert_eq!(shell.execute("[[ -n $x || $(z=1) ]]; [[ -z $x && ${z=1} ]]"), Ok(1));
+        assert_eq!(shell.get_var("z"), None);
+        assert_eq!(shell.execute("[[ $x == ]]"), Ok(2));
+    }
+
+    #[test]
+    fn test_extended_test_regex() {
+        let mut shell = Shell::default();
+
+        shell.execute("x=abc; n=2024").unwrap();
+        assert_eq!(shell.execute("[[ $n =~ ^[0-9]+$ ]]"), Ok(0));
+        assert_eq!(shell.execute("[[ $x =~ ^(a)(x)?(b.)$ ]]"), Ok(0));
+        assert_eq!(shell.get_var("BASH_REMATCH"), Some("abc"));
+        assert_eq!(shell.arrays["BASH_REMATCH"], ["abc", "a", "", "bc"]);
+
+        // Quoted characters match themselves
+        assert_eq!(shell.execute("[[ $x =~ \"a.c\" ]]"), Ok(1));
+        assert_eq!(shell.get_var("BASH_REMATCH"), None);
+        assert!(shell.arrays["BASH_REMATCH"].is_empty());
+        assert_eq!(shell.execute("re='a('; [[ $x =~ $re ]]"), Ok(2));
+
+        // Elements expand inside other text, brackets included
+        let script = "[[ $x =~ a(b) ]]; echo [${BASH_REMATCH[0]}] x${BASH_REMATCH[1]}y";
+        let output = shell.execute_capture(script).unwrap();
+        assert_eq!(output.stdout, b"[ab] xby\n");
+
+        shell.execute("unset BASH_REMATCH").unwrap();
+        assert!(!shell.arrays.contains_key("BASH_REMATCH"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_type_and_command_v() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let bin = temp_path("type-bin");
+        std::fs::create_dir_all(&bin).unwrap();
+        let tool = bin.join("tool");
+        std::fs::write(&tool, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut shell = Shell::default();
+        shell.export_var("PATH", &bin.to_string_lossy());
+        shell.execute("alias ll='ls -l'; f() { :; }").unwrap();
+
+        let output = shell.execute_capture("type ll f cd tool").unwrap();
+        let expected = format!(
+            "ll is aliased to `ls -l'\nf is a function\ncd is a shell builtin\ntool is {}\n",
+            tool.display()
+        );
+        assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+
+        let output = shell.execute_capture("command -v tool ll cd; type -t f").unwrap();
+        let expected = format!("{}\nalias ll='ls -l'\ncd\nfunction\n", tool.display());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+
+        // Functions come before builtins, as when running them
+        shell.execute("cd() { :; }").unwrap();
+        assert_eq!(shell.execute_capture("type -t cd").unwrap().stdout, b"function\n");
+
+        assert_eq!(shell.execute("command -v missing"), Ok(1));
+        assert_eq!(shell.execute("type -t missing"), Ok(1));
+        assert_eq!(shell.execute("command -x tool"), Ok(2));
+
+        let _ = std::fs::remove_dir_all(&bin);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_lookup_statuses() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let bin = temp_path("lookup-bin");
+        std::fs::create_dir_all(&bin).unwrap();
+        for (name, mode) in [("tool", 0o755), ("plain", 0o644)] {
+            std::fs::write(bin.join(name), "#!/bin/sh\nexit 3\n").unwrap();
+            std::fs::set_permissions(bin.join(name), std::fs::Permissions::from_mode(mode))
+                .unwrap();
+        }
+
+        let mut shell = Shell::default();
+        shell.export_var("PATH", &bin.to_string_lossy());
+
+        assert_eq!(shell.execute("tool"), Ok(3));
+        assert_eq!(shell.execute("plain"), Ok(126));
+        assert_eq!(shell.execute("missing"), Ok(127));
+        assert_eq!(shell.execute(&format!("{}/plain", bin.display())), Ok(126));
+        assert_eq!(shell.execute(&format!("{}", bin.display())), Ok(126));
+        assert_eq!(shell.execute("missing | tool"), Ok(3));
+        assert_eq!(shell.execute("tool | missing"), Ok(127));
+
+        let output = shell.execute_capture("hash").unwrap();
+        let expected = format!("tool\t{}\n", bin.join("tool").display());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+        assert_eq!(shell.execute("hash -r; hash missing"), Ok(1));
+        assert!(shell.execute_capture("hash").unwrap().stdout.is_empty());
+
+        let _ = std::fs::remove_dir_all(&bin);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_names_for_completion() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let bin = temp_path("names-bin");
+        std::fs::create_dir_all(&bin).unwrap();
+        let executable = |name: &str| {
+            std::fs::write(bin.join(name), "").unwrap();
+            std::fs::set_permissions(bin.join(name), std::fs::Permissions::from_mode(0o755))
+                .unwrap();
+        };
+        executable("tool");
+        executable("echo");
+
+        let mut shell = Shell::default();
+        shell.export_var("PATH", &bin.to_string_lossy());
+        shell.execute("alias ll='ls -l'; greet() { echo hi; }").unwrap();
+
+        let names = shell.command_names();
+        for name in ["tool", "echo", "ll", "greet", "cd"] {
+            assert!(names.contains(&name.to_string()), "{name} should be listed");
+        }
+        assert_eq!(names.iter().filter(|name| *name == "echo").count(), 1);
+
+        // The listing of $PATH is kept until `hash -r`
+        executable("later");
+        assert!(!shell.command_names().contains(&"later".to_string()));
+        shell.execute("hash -r").unwrap();
+        assert!(shell.command_names().contains(&"later".to_string()));
+
+        let _ = std::fs::remove_dir_all(&bin);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_which_builtin() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (first, second) = (temp_path("which-first"), temp_path("which-second"));
+        for dir in [&first, &second] {
+            std::fs::create_dir_all(dir).unwrap();
+            std::fs::write(dir.join("tool"), "").unwrap();
+            std::fs::set_permissions(dir.join("tool"), std::fs::Permissions::from_mode(0o755))
+                .unwrap();
+        }
+        std::fs::write(first.join("data"), "").unwrap();
+
+        let mut shell = Shell::default();
+        shell.export_var("PATH", &format!("{}:{}", first.display(), second.display()));
+
+        let output = shell.execute_capture("which tool; which -a tool").unwrap();
+        let (first_tool, second_tool) = (first.join("tool"), second.join("tool"));
+        let expected = format!(
+            "{}\n{}\n{}\n",
+            first_tool.display(),
+            first_tool.display(),
+            second_tool.display()
+        );
+        assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+
+        // Files that cannot run are not matches
+        let output = shell.execute_capture("which data missing").unwrap();
+        assert_eq!((output.status, output.stdout, output.stderr), (1, vec![], vec![]));
+        assert_eq!(shell.execute("which -q tool"), Ok(2));
+
+        let _ = std::fs::remove_dir_all(&first);
+        let _ = std::fs::remove_dir_all(&second);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_background_jobs() {
+        let mut shell = Shell::default();
+        let start = std::time::Instant::now();
+
+        assert_eq!(shell.execute("sleep 2 & x=$!"), Ok(0));
+        assert_eq!(shell.execute("false; sleep 2 | cat &"), Ok(0));
+        assert!(start.elapsed() < std::time::Duration::from_secs(2));
+
+        let pid = shell.jobs[0].processes[0].id();
+        assert_eq!(shell.get_var("x"), Some(pid.to_string().as_str()));
+        let numbers: Vec<usize> = shell.jobs.iter().map(|job| job.number).collect();
+        assert_eq!(numbers, [1, 2]);
+        assert_eq!(shell.jobs[0].command, "sleep 2");
+        assert_eq!(shell.jobs[1].command, "sleep 2 | cat");
+        assert_eq!(shell.jobs[1].processes.len(), 2);
+
+        for job in &mut shell.jobs {
+            for process in &job.processes {
+                // SAFETY: kill has no memory effects
+                unsafe { libc::kill(process.id() as libc::pid_t, libc::SIGKILL) };
+            }
+            job.wait();
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_jobs_fg_bg() {
+        let mut shell = Shell::default();
+
+        let script = "sh -c 'exit 3' & true & sleep 0.2; jobs";
+        let output = shell.execute_capture(script).unwrap();
+        let listing = "[1]-  Exit 3                  sh -c 'exit 3'\n\
+                       [2]+  Done                    true\n";
+        assert_eq!(String::from_utf8_lossy(&output.stdout), listing);
+        // Finished jobs are listed once and then forgotten
+        assert!(shell.jobs.is_empty());
+        assert_eq!(shell.execute_capture("jobs").unwrap().stdout, b"");
+
+        let output = shell.execute_capture("sh -c 'exit 4' & fg").unwrap();
+        assert_eq!(output.status, 4);
+        assert_eq!(output.stdout, b"sh -c 'exit 4'\n");
+        assert!(shell.jobs.is_empty());
+
+        assert_eq!(shell.execute("fg %9"), Ok(1));
+        assert_eq!(shell.execute("bg"), Ok(1));
+
+        assert_eq!(shell.execute("sh -c 'exit 5' & sleep 0.2; fg %sh"), Ok(5));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_stopped_foreground_commands() {
+        let mut shell = Shell::default();
+
+        // A command stopped while the shell waits for it becomes a job
+        assert_eq!(shell.execute("sh -c 'kill -STOP $$; exit 6'"), Ok(148));
+        assert_eq!(shell.jobs.len(), 1);
+        assert_eq!(shell.jobs[0].state, JobState::Stopped);
+        assert_eq!(shell.jobs[0].command, "sh -c 'kill -STOP $$; exit 6'");
+        assert_eq!(shell.execute("fg >/dev/null"), Ok(6));
+
+        assert_eq!(shell.execute("true | sh -c 'kill -STOP $$'"), Ok(148));
+        assert_eq!(shell.arrays["PIPESTATUS"][1], "148");
+        assert_eq!(shell.jobs[0].command, "true | sh -c 'kill -STOP $$'");
+        assert_eq!(shell.execute("kill -KILL %1; fg >/dev/null"), Ok(137));
+        assert!(shell.jobs.is_empty());
+
+        // A job ended by a signal is listed by the signal, not its status
+        let output = shell.execute_capture("sleep 5 & kill %1; sleep 0.2; jobs").unwrap();
+        let listing = "[1]+  Terminated              sleep 5\n";
+        assert_eq!(String::from_utf8_lossy(&output.stdout), listing);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_wait_builtin() {
+        let mut shell = Shell::default();
+
+        assert_eq!(shell.execute("sh -c 'sleep 0.2; exit 3' & wait %1"), Ok(3));
+        assert_eq!(shell.execute("sh -c 'exit 4' | sh -c 'exit 5' & wait $!"), Ok(5));
+        assert_eq!(shell.execute("sleep 5 & kill %1; wait %1"), Ok(143));
+        assert!(shell.jobs.is_empty());
+
+        // Without arguments, every job is waited for
+        let start = std::time::Instant::now();
+        assert_eq!(shell.execute("sleep 0.2 & sh -c 'sleep 0.3; exit 1' & wait"), Ok(0));
+        assert!(start.elapsed() >= std::time::Duration::from_millis(300));
+        assert!(shell.jobs.is_empty());
+
+        assert_eq!(shell.execute("wait %5"), Ok(127));
+        assert_eq!(shell.execute("wait 1"), Ok(127));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_kill_builtin() {
+        let mut shell = Shell::default();
+
+        assert_eq!(shell.execute("sleep 5 & kill %1; fg >/dev/null"), Ok(143));
+        assert_eq!(shell.execute("sleep 5 & kill -s KILL $!; fg >/dev/null"), Ok(137));
+        assert_eq!(shell.execute("sleep 5 & kill -INT -- %sleep; fg >/dev/null"), Ok(130));
+        assert!(shell.jobs.is_empty());
+        assert_eq!(shell.execute("kill -0 $$"), Ok(0));
+
+        let output = shell.execute_capture("kill -l 143 9 KILL sigterm").unwrap();
+        assert_eq!(output.stdout, b"TERM\nKILL\n9\n15\n");
+
+        assert_eq!(shell.execute("kill %9"), Ok(1));
+        assert_eq!(shell.execute("kill nope"), Ok(1));
+        assert_eq!(shell.execute("kill -BOGUS $$"), Ok(1));
+        assert_eq!(shell.execute("kill -l BOGUS"), Ok(1));
+        assert_eq!(shell.execute("kill"), Ok(2));
+        assert_eq!(shell.execute("kill -9"), Ok(2));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pipestatus() {
+        let mut shell = Shell::default();
+
+        let script = "false | true | sh -c 'exit 3'; echo ${PIPESTATUS[1]} ${PIPESTATUS[@]}";
+        let output = shell.execute_capture(script).unwrap();
+        assert_eq!(output.stdout, b"0 1 0 3\n");
+
+        // A stage that cannot start still has its place
+        shell.execute("true | wpcsh-no-such-command | true").unwrap();
+        assert_eq!(shell.arrays["PIPESTATUS"], ["0", "127", "0"]);
+
+        // Elements are not variables, so a variable of a similar name is left alone
+        shell.execute("PIPESTATUS_1=mine; true | false").unwrap();
+        assert_eq!(shell.get_var("PIPESTATUS_1"), Some("mine"));
+
+        // Fewer stages leave no elements behind
+        let output = shell.execute_capture("false; echo ${#PIPESTATUS[@]} $PIPESTATUS").unwrap();
+        assert_eq!(output.stdout, b"1 1\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_builtins_in_pipeline() {
+        let mut shell = Shell::default();
+
+        let output = shell.execute_capture("echo hi | tr a-z A-Z").unwrap();
+        assert_eq!(output.stdout, b"HI\n");
+        let output = shell.execute_capture("type echo | cat").unwrap();
+        assert_eq!(output.stdout, b"echo is a shell builtin\n");
+
+        // A function reads the pipe before it, and its output goes on to the next command
+        shell.execute("shout() { tr a-z A-Z; echo \"$1\"; }").unwrap();
+        let output = shell.execute_capture("printf '%s\\n' a b | shout c | sort -r").unwrap();
+        assert_eq!(output.stdout, b"c\nB\nA\n");
+
+        // The copy of the shell a builtin runs in leaves the shell's own state alone
+        let output = shell.execute_capture("x=1; echo 2 | read x; echo $x").unwrap();
+        assert_eq!(output.stdout, b"1\n");
+        let output = shell.execute_capture("echo | exit 4; echo ${PIPESTATUS[1]}").unwrap();
+        assert_eq!(output.stdout, b"4\n");
+
+        // More output than a pipe holds does not block the shell
+        let output = shell.execute_capture("printf '%s\\n' {1..50000} | wc -l").unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "50000");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_compound_commands_in_pipeline() {
+        let mut shell = Shell::default();
+
+        let script = "printf 'a\\nb\\n' | while read l; do echo \"<$l>\"; done";
+        let output = shell.execute_capture(script).unwrap();
+        assert_eq!(output.stdout, b"<a>\n<b>\n");
+        let output = shell.execute_capture("echo abc | { read y; echo got $y; }").unwrap();
+        assert_eq!(output.stdout, b"got abc\n");
+        let output = shell.execute_capture("echo hi | if true; then cat; fi").unwrap();
+        assert_eq!(output.stdout, b"hi\n");
+
+        // Compound commands write to the pipe as well as read from it
+        let output = shell.execute_capture("{ echo a; echo b; } | sort -r").unwrap();
+        assert_eq!(output.stdout, b"b\na\n");
+        let output = shell.execute_capture("echo x | (cat) | tr x y").unwrap();
+        assert_eq!(output.stdout, b"y\n");
+        let output = shell.execute_capture("{ echo e >&2; } |& tr e E").unwrap();
+        assert_eq!(output.stdout, b"E\n");
+
+        // Like a builtin, the loop runs in a copy of the shell
+        let output = shell.execute_capture("n=0; echo 1 | while read l; do n=$l; done; echo $n");
+        assert_eq!(output.unwrap().stdout, b"0\n");
+    }
+
+    #[test]
+    fn test_exec_redirects_the_shell() {
+        let mut shell = Shell::default();
+        let path = std::env::temp_dir().join(format!("wpcsh-{}-exec", std::process::id()));
+        let file = path.display().to_string();
+
+        assert_eq!(shell.execute(&format!("exec >{}; echo one", file)), Ok(0));
+        assert_eq!(shell.execute("echo two"), Ok(0));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one\ntwo\n");
+
+        // A command that cannot be run leaves the shell in place
+        shell.interactive = true;
+        assert_eq!(shell.execute("exec wpcsh-no-such-command"), Ok(127));
+        assert_eq!(shell.execute("exec <wpcsh-no-such-file"), Ok(1));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_exec_opens_numbered_descriptors() {
+        let mut shell = Shell::default();
+        let path = temp_path("exec-fd");
+        let file = path.display().to_string();
+
+        // Builtins and started commands both see descriptor 3 until it is closed
+        let script = format!("exec 3>{}; echo one >&3; sh -c 'echo two >&3'; exec 3>&-", file);
+        assert_eq!(shell.execute(&script), Ok(0));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one\ntwo\n");
+        assert_eq!(shell.execute("echo three >&3"), Ok(1));
+
+        let script = format!("exec 4<{}; read a <&4; read b <&4; exec 4<&-", file);
+        assert_eq!(shell.execute(&script), Ok(0));
+        assert_eq!(shell.get_var("a"), Some("one"));
+        assert_eq!(shell.get_var("b"), Some("two"));
+        assert!(shell.streams.extra.is_empty());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_trap_set_list_and_reset() {
+        let mut shell = Shell::default();
+
+        let script = "trap 'echo \"bye\"' EXIT; trap 'rm -f $tmp' 2; trap -p";
+        let output = shell.execute_capture(script).unwrap();
+        let listing = "trap -- 'echo \"bye\"' EXIT\ntrap -- 'rm -f $tmp' SIGINT\n";
+        assert_eq!(String::from_utf8_lossy(&output.stdout), listing);
+        let output = shell.execute_capture("trap -p INT").unwrap();
+        assert_eq!(output.stdout, b"trap -- 'rm -f $tmp' SIGINT\n");
+
+        let output = shell.execute_capture("trap - INT; trap").unwrap();
+        assert_eq!(output.stdout, b"trap -- 'echo \"bye\"' EXIT\n");
+        let output = shell.execute_capture("trap EXIT; trap -p").unwrap();
+        assert_eq!(output.stdout, b"");
+
+        // The EXIT trap runs once, and leaves `$?` alone
+        assert_eq!(shell.execute("trap 'x=$?; [ a = b ]' EXIT; [ -z x ]"), Ok(1));
+        shell.run_exit_trap();
+        assert_eq!(shell.get_var("x"), Some("1"));
+        assert_eq!(shell.last_status(), 1);
+        assert!(shell.traps.is_empty());
+
+        assert_eq!(shell.execute("trap x NOPE"), Ok(1));
+        assert_eq!(shell.execute("trap x TERM"), Ok(1));
+        assert!(shell.traps.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_signal_listing() {
+        let mut shell = Shell::default();
+
+        // Every signal is listed with its number
+        for builtin in ["trap", "kill"] {
+            let output = shell.execute_capture(&format!("{builtin} -l")).unwrap();
+            let listing = String::from_utf8_lossy(&output.stdout).into_owned();
+            assert!(listing.starts_with("1) SIGHUP\n2) SIGINT\n3) SIGQUIT\n"));
+            assert!(listing.contains("\n9) SIGKILL\n"));
+            assert!(listing.contains("\n15) SIGTERM\n"));
+            assert_eq!(listing.lines().count(), signal::SIGNALS.len());
+        }
+
+        let output = shell.execute_capture("trap -l 2 TERM").unwrap();
+        assert_eq!(output.stdout, b"INT\n15\n");
+        assert_eq!(shell.execute("trap -l BOGUS"), Ok(1));
+    }
+
+    #[test]
+    fn test_complete_builtin() {
+        let mut shell = Shell::default();
+
+        let script = "w='start stop'; complete -W \"$w restart\" svc; complete -f git; complete";
+        let output = shell.execute_capture(script).unwrap();
+        let listing = "complete -f git\ncomplete -W 'start stop restart' svc\n";
+        assert_eq!(String::from_utf8_lossy(&output.stdout), listing);
+        assert_eq!(shell.completions["svc"].words.as_deref(), Some("start stop restart"));
+
+        let output = shell.execute_capture("complete -d -- a; complete -p a git").unwrap();
+        assert_eq!(output.stdout, b"complete -d a\ncomplete -f git\n");
+
+        assert_eq!(shell.execute("complete -r git; complete -p git"), Ok(1));
+        assert_eq!(shell.execute("complete -r git"), Ok(1));
+        assert_eq!(shell.execute("complete -x svc"), Ok(2));
+        assert_eq!(shell.execute("complete -W"), Ok(2));
+        assert_eq!(shell.completions.len(), 2);
+        assert_eq!(shell.execute("complete -r"), Ok(0));
+        assert!(shell.completions.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_interrupted_command_stops_the_line() {
+        let mut shell = Shell {
+            interactive: true,
+            ..Shell::default()
+        };
+
+        let script = "for i in 1 2; do sh -c 'kill -INT $$'; echo $i; done; echo after";
+        let output = shell.execute_capture(script).unwrap();
+        assert_eq!(output.status, 130);
+        assert_eq!(output.stdout, b"");
+        assert_eq!(shell.last_status(), 130);
+
+        // The next line runs as usual
+        assert_eq!(shell.execute_capture("echo next").unwrap().stdout, b"next\n");
+
+        // Only an interactive shell gives up on the rest of the line
+        shell.interactive = false;
+        let output = shell.execute_capture("sh -c 'kill -INT $$'; echo $?").unwrap();
+        assert_eq!(output.stdout, b"130\n");
+    }
+
+    #[test]
+    fn test_alias_resolution_stops_at_cycles() {
+        let mut shell = Shell::default();
+        let alias = |shell: &mut Shell, name: &str, value: &str| {
+            shell.aliases.insert(name.to_string(), value.to_string());
+        };
+        let args = vec!["x".to_string()];
+
+        alias(&mut shell, "ls", "ls --color");
+        let resolved = shell.resolve_alias("ls", args.clone());
+        assert_eq!(resolved, ("ls".to_string(), vec!["--color".to_string(), "x".to_string()]));
+
+        alias(&mut shell, "a", "b -1");
+        alias(&mut shell, "b", "a -2");
+        let resolved = shell.resolve_alias("a", args.clone());
+        assert_eq!(resolved.0, "a");
+        assert_eq!(resolved.1, ["-2", "-1", "x"]);
+
+        alias(&mut shell, "ll", "ls -l");
+        let resolved = shell.resolve_alias("ll", args.clone());
+        assert_eq!(resolved.1, ["--color", "-l", "x"]);
+
+        // A long chain still ends at the depth cap
+        for i in 0..40 {
+            alias(&mut shell, &format!("c{i}"), &format!("c{}", i + 1));
+        }
+        assert_eq!(shell.resolve_alias("c0", Vec::new()).0, format!("c{MAX_ALIAS_DEPTH}"));
+    }
+
+    #[test]
+    fn test_alias_listing_and_unalias() {
+        let out = temp_path("alias-list");
+        let path = out.display();
+        let mut shell = Shell::default();
+
+        let script = format!(
+            "alias ll='ls -la' q=\"it's\"; alias > {path}; alias ll >> {path}; alias nope >> {path}"
+        );
+        assert_eq!(shell.execute(&script), Ok(1));
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            "alias ll='ls -la'\nalias q='it'\\''s'\nalias ll='ls -la'\n"
+        );
+
+        assert_eq!(shell.execute("unalias ll"), Ok(0));
+        assert!(!shell.aliases.contains_key("ll"));
+        assert_eq!(shell.execute("unalias ll"), Ok(1));
+
+        assert_eq!(shell.execute("unalias -a"), Ok(0));
+        assert!(shell.aliases.is_empty());
+
+        let _ = std::fs::remove_file(&out);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exit_status_reaches_question_mark() {
+        let out = temp_path("exit-status");
+        let path = out.display();
+        let mut shell = Shell::default();
+
+        let script = format!("false\necho $? > {path}\ntrue | false\necho $? >> {path}");
+        assert_eq!(shell.execute(&script), Ok(0));
+        shell.execute("sh -c 'kill -TERM $$'").unwrap();
+        shell.execute(&format!("echo $? >> {path}")).unwrap();
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "1\n1\n143\n");
+
+        let _ = std::fs::remove_file(&out);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_starship_prompt() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let bin = temp_path("starship-bin");
+        std::fs::create_dir_all(&bin).unwrap();
+        let starship = bin.join("starship");
+        let script = "#!/bin/sh\nprintf '%s' \"[$STARSHIP_SHELL $STARSHIP_CMD_STATUS $*]\"\n";
+        std::fs::write(&starship, script).unwrap();
+        std::fs::set_permissions(&starship, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let root = std::env::temp_dir();
+        let mut shell = Shell {
+            current_dir: root.clone(),
+            exit_status: 3,
+            ..Shell::default()
+        };
+        shell.export_var("PATH", &bin.to_string_lossy());
+
+        assert_eq!(shell.get_prompt(), "[wpcsh 3 prompt --status 3]");
+
+        shell.variables.insert("WPCSH_PROMPT".to_string(), "plain".to_string());
+        assert_eq!(shell.get_prompt(), format!("{} > ", root.display()));
+
+        // Without starship installed the plain prompt is used
+        std::fs::remove_file(&starship).unwrap();
+        shell.variables.insert("WPCSH_PROMPT".to_string(), "starship".to_string());
+        assert_eq!(shell.get_prompt(), format!("{} > ", root.display()));
+
+        let _ = std::fs::remove_dir_all(&bin);
+    }
+
+    #[test]
+    fn test_ps1_takes_precedence() {
+        let mut shell = Shell {
+            current_dir: PathBuf::from("/home/alice/src"),
+            home_dir: PathBuf::from("/home/alice"),
+            ..Shell::default()
+        };
+        shell.variables.insert("USER".to_string(), "alice".to_string());
+        shell.variables.insert("PROMPT".to_string(), "wpcsh-no-such-command".to_string());
+        shell.variables.insert("PS1".to_string(), "\\u \\W\\$ ".to_string());
+
+        assert_eq!(shell.get_prompt(), "alice src$ ");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_prompt_is_cached_until_state_changes() {
+        let log = temp_path("prompt-cache");
+        let root = std::env::temp_dir();
+        let mut shell = Shell {
+            current_dir: root.clone(),
+            ..Shell::default()
+        };
+        let command = format!("sh -c 'echo run >> {}; printf p'", log.display());
+        shell.variables.insert("PROMPT".to_string(), command);
+        let runs = || std::fs::read_to_string(&log).unwrap().lines().count();
+
+        assert_eq!(shell.get_prompt(), "p");
+        assert_eq!(shell.get_prompt(), "p");
+        assert_eq!(runs(), 1);
+
+        shell.exit_status = 1;
+        shell.get_prompt();
+        shell.current_dir = root.join("..");
+        shell.get_prompt();
+        assert_eq!(runs(), 3);
+
+        shell.variables.insert("WPCSH_PROMPT_NOCACHE".to_string(), "1".to_string());
+        shell.get_prompt();
+        shell.get_prompt();
+        assert_eq!(runs(), 5);
+
+        let _ = std::fs::remove_file(&log);
+    }
+
+    #[test]
+    fn test_errexit_stops_at_failure() {
+        let mut shell = Shell::default();
+
+        let script = "set -e; A=1; false; A=2";
+        assert_eq!(shell.execute(script), Err(ErrorKind::Interrupted));
+        assert_eq!(shell.variables["A"], "1");
+        assert_eq!(shell.last_status(), 1);
+        assert_eq!(shell.jump, None);
+
+        // The exceptions: conditions, the left of && and ||, and negations
+        let script = "if false; then true; fi; while false; do true; done; false || true; \
+                      false && true; ! true; B=reached";
+        assert_eq!(shell.execute(script), Ok(0));
+        assert_eq!(shell.variables["B"], "reached");
+
+        // The last command of a chain still counts, as does a failing function body
+        assert_eq!(shell.execute("true && false; C=no"), Err(ErrorKind::Interrupted));
+        assert!(!shell.variables.contains_key("C"));
+        let script = "f() { false; D=no; }; f; D=no";
+        assert_eq!(shell.execute(script), Err(ErrorKind::Interrupted));
+        assert!(!shell.variables.contains_key("D"));
+
+        // A function called as a condition is exempt as a whole
+        assert_eq!(shell.execute("if f; then true; fi; E=yes"), Ok(0));
+        assert_eq!(shell.variables["E"], "yes");
+
+        shell.execute("set +e").unwrap();
+        assert_eq!(shell.execute("false; F=yes"), Ok(0));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_errexit_stops_sourced_file() {
+        let script = temp_path("errexit-source");
+        std::fs::write(&script, "A=1\nfalse\nA=2\n").unwrap();
+
+        let mut shell = Shell::default();
+        let line = format!("set -e; source {}; B=2", script.display());
+        assert_eq!(shell.execute(&line), Err(ErrorKind::Interrupted));
+        assert_eq!(shell.variables["A"], "1");
+        assert!(!shell.variables.contains_key("B"));
+
+        // Outside the shell's own error handling, an unknown command fails the same way
+        assert_eq!(shell.execute("wpcsh-no-such-command"), Err(ErrorKind::Interrupted));
+        assert_eq!(shell.last_status(), 127);
+
+        let _ = std::fs::remove_file(&script);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_execute_capture_collects_output() {
+        let mut shell = Shell::default();
+
+        let output = shell
+            .execute_capture("echo one; sh -c 'echo two; echo oops >&2' | tr a-z A-Z; false")
+            .unwrap();
+        assert_eq!(output.status, 1);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "one\nTWO\n");
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "oops\n");
+
+        let output = shell.execute_capture("alias a=b; alias; echo $?").unwrap();
+        assert_eq!((output.status, output.stdout), (0, b"alias a='b'\n0\n".to_vec()));
+    }
+
+    #[test]
+    fn test_variable_and_alias_accessors() {
+        let mut shell = Shell::default();
+        shell.set_var("GREETING", "hi");
+        shell.set_alias("greet", "echo $GREETING");
+
+        assert_eq!(shell.execute("REPLY=\"$GREETING there\""), Ok(0));
+        assert_eq!(shell.get_var("REPLY"), Some("hi there"));
+        assert_eq!(shell.get_alias("greet"), Some("echo $GREETING"));
+        assert!(shell.vars().any(|pair| pair == ("GREETING", "hi")));
+        assert_eq!(shell.aliases().collect::<Vec<_>>(), [("greet", "echo $GREETING")]);
+
+        assert_eq!(shell.unset_var("REPLY").as_deref(), Some("hi there"));
+        assert_eq!(shell.unset_alias("greet").as_deref(), Some("echo $GREETING"));
+        assert_eq!((shell.get_var("REPLY"), shell.get_alias("greet")), (None, None));
+    }
+
+    #[test]
+    fn test_execute_script_returns_last_status() {
+        let script = temp_path("script");
+        std::fs::write(&script, "A=$1\nif true; then\n  B=$2\nfi\nfalse\n").unwrap();
+
+        let mut shell = Shell::default();
+        let args = vec!["one".to_string(), "two".to_string()];
+        assert_eq!(shell.execute_script(&script, args), Ok(1));
+        assert_eq!((shell.variables["A"].as_str(), shell.variables["B"].as_str()), ("one", "two"));
+
+        std::fs::write(&script, "set -e\nwpcsh-no-such-command\nC=reached\n").unwrap();
+        assert_eq!(shell.execute_script(&script, Vec::new()), Ok(127));
+        assert!(!shell.variables.contains_key("C"));
+        assert_eq!(shell.execute("true"), Ok(0));
+
+        let _ = std::fs::remove_file(&script);
+        assert_eq!(shell.execute_script(&script, Vec::new()), Err(ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn test_source_joins_wrapped_commands() {
+        let script = temp_path("wrapped-source");
+        std::fs::write(&script, "A=one\\\ntwo\nif true; then\n  B=yes\nfi\n").unwrap();
+
+        let mut shell = Shell::default();
+        shell.execute(&format!("source {}", script.display())).unwrap();
+        assert_eq!(shell.variables["A"], "onetwo");
+        assert_eq!(shell.variables["B"], "yes");
+
+        let _ = std::fs::remove_file(&script);
+    }
+
+    #[test]
+    fn test_join_line_continuation() {
+        let mut buffer = String::new();
+        join_line(&mut buffer, "if true; then");
+        join_line(&mut buffer, "echo a \\");
+        join_line(&mut buffer, "b");
+        assert_eq!(buffer, "if true; then\necho a b");
+
+        let mut buffer = "echo \\\\".to_string();
+        join_line(&mut buffer, "x");
+        assert_eq!(buffer, "echo \\\\\nx");
+    }
+
+    #[test]
+    fn test_trace_word_quoting() {
+        assert_eq!(trace_word("ls"), "ls");
+        assert_eq!(trace_word("--color=auto"), "--color=auto");
+        assert_eq!(trace_word("a b"), "'a b'");
+        assert_eq!(trace_word(""), "''");
+        assert_eq!(trace_word("it's"), r"'it'\''s'");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_xtrace_prints_expanded_commands() {
+        let out = temp_path("xtrace");
+        let path = out.display();
+        let mut shell = Shell::default();
+
+        let script = format!(
+            "(X='a b'; set -x; echo $X \"$X\" > /dev/null; PS4='>> '; true | true; set +x; \
+             echo hidden) 2> {path}"
+        );
+        assert_eq!(shell.execute(&script), Ok(0));
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            "+ echo a b 'a b'\n>> true\n>> true\n>> set +x\n"
+        );
+        assert!(!shell.options.xtrace);
+        assert_eq!(shell.execute("set -x; set +x -q"), Ok(2));
+
+        // A line that is just a command name is traced too
+        assert_eq!(shell.execute("set -x"), Ok(0));
+        let output = shell.execute_capture("true").unwrap();
+        assert_eq!(output.stderr, b"+ true\n");
+        assert_eq!(shell.execute("set +x"), Ok(0));
+
+        let _ = std::fs::remove_file(&out);
+    }
+
+    #[test]
+    fn test_missing_command_status() {
+        let mut shell = Shell::default();
+
+        assert_eq!(shell.execute("wpcsh-no-such-command"), Ok(127));
+        assert_eq!(shell.execute("wpcsh-no-such-command a b"), Ok(127));
+        assert_eq!(shell.execute("wpcsh-no-such-command || X=$?"), Ok(0));
+        assert_eq!(shell.variables["X"], "127");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_not_found_handler() {
+        let mut shell = Shell::default();
+        let handler = "command_not_found_handler() { echo \"missing $1: $2\"; return 3; }";
+        assert_eq!(shell.execute(handler), Ok(0));
+
+        // The handler gets the command and its arguments, and its status is the command's
+        let output = shell.execute_capture("wpcsh-no-such-command a b").unwrap();
+        assert_eq!(output.stdout, b"missing wpcsh-no-such-command: a\n");
+        assert_eq!(output.status, 3);
+        let output = shell.execute_capture("wpcsh-no-such-command x | tr a-z A-Z").unwrap();
+        assert_eq!(output.stdout, b"MISSING WPCSH-NO-SUCH-COMMAND: X\n");
+
+        // A command that is found, or a path, never reaches it
+        assert_eq!(shell.execute_capture("true").unwrap().stdout, b"");
+        assert_eq!(shell.execute("./wpcsh-no-such-command"), Ok(127));
+
+        // A missing command inside the handler is reported instead of calling it again
+        assert_eq!(shell.execute("command_not_found_handler() { wpcsh-other; }"), Ok(0));
+        assert_eq!(shell.execute("wpcsh-no-such-command"), Ok(127));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_missing_command_in_pipeline() {
+        let out = temp_path("missing-in-pipeline");
+        let mut shell = Shell::default();
+
+        // The other stages still run and are reaped
+        let script = format!("wpcsh-no-such-command | echo ran > {}", out.display());
+        assert_eq!(shell.execute(&script), Ok(0));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "ran\n");
+
+        assert_eq!(shell.execute("echo x | wpcsh-no-such-command"), Ok(127));
+        assert_eq!(shell.execute("true | wpcsh-no-such-command | true"), Ok(0));
+
+        let _ = std::fs::remove_file(&out);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unexecutable_command_status() {
+        let path = temp_path("not-executable");
+        std::fs::write(&path, "echo hi\n").unwrap();
+
+        let mut shell = Shell::default();
+        assert_eq!(shell.execute(&path.display().to_string()), Ok(126));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_echo_escapes() {
+        assert_eq!(echo_escapes(r"a\tb\\n\n"), ("a\tb\\n\n".to_string(), false));
+        assert_eq!(echo_escapes(r"\0101\x42\0"), ("AB\0".to_string(), false));
+        assert_eq!(echo_escapes(r"\q\x"), (r"\q\x".to_string(), false));
+        assert_eq!(echo_escapes(r"one\ctwo"), ("one".to_string(), true));
+    }
+
+    #[test]
+    fn test_echo_flags_and_redirects() {
+        let path = temp_path("echo");
+        let mut shell = Shell::default();
+        let target = path.display();
+
+        let script = format!(
+            "echo -n a > {target}; echo -e 'b\\tc' >> {target}; echo -nE 'd\\n' >> {target}; \
+             echo -- -n x >> {target}; echo -en 'e\\cf' >> {target}; echo -x >> {target}"
+        );
+        assert_eq!(shell.execute(&script), Ok(0));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "ab\tc\nd\\n-n x\ne-x\n");
+
+        // Output is captured by command substitution
+        assert_eq!(shell.execute("X=$(echo -e 'one\\ntwo')"), Ok(0));
+        assert_eq!(shell.variables["X"], "one\ntwo");
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_ansi_c_quotes_reach_commands() {
+        let path = temp_path("ansi-c");
+        let mut shell = Shell::default();
+
+        let script = format!("A=$'1\\t2'; echo \"$A\" $'it\\'s' > {}", path.display());
+        assert_eq!(shell.execute(&script), Ok(0));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "1\t2 it's\n");
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_adjacent_pieces_make_one_word() {
+        let path = temp_path("concat");
+        let mut shell = Shell::default();
+
+        let script = format!(
+            "V=v; B=x'a b'\"$V\"; printf '[%s]' foo\"bar\"baz --o=\"$V\" '' a''b $B > {}",
+            path.display()
+        );
+        assert_eq!(shell.execute(&script), Ok(0));
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "[foobarbaz][--o=v][][ab][xa][bv]"
+        );
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_inline_comments_are_ignored() {
+        let mut shell = Shell::default();
+        assert_eq!(shell.execute("A=a#b # A=c\nfalse # set status\n# alone"), Ok(1));
+        assert_eq!(shell.variables["A"], "a#b");
+        assert_eq!(shell.last_status(), 1);
+    }
+
+    #[test]
+    fn test_echo_expands_words() {
+        let path = temp_path("echo-expand");
+        let mut shell = Shell {
+            positional: vec!["one".to_string()],
+            ..Shell::default()
+        };
+        let target = path.display();
+
+        let script = format!(
+            "B=b; Y=y; echo \"a $B c\" > {target}; echo x${{Y}}z >> {target}; \
+             echo '$NOEXPAND' \"$1/bin\" >> {target}"
+        );
+        assert_eq!(shell.execute(&script), Ok(0));
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "a b c\nxyz\n$NOEXPAND one/bin\n"
+        );
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_normalize_path() {
+        let normalize = |path: &str| normalize_path(PathBuf::from(path));
+
+        assert_eq!(normalize("/a/b/../c"), PathBuf::from("/a/c"));
+        assert_eq!(normalize("/a/./b/"), PathBuf::from("/a/b"));
+        assert_eq!(normalize("/../.."), PathBuf::from("/"));
+        assert_eq!(normalize("/a/../../b"), PathBuf::from("/b"));
+        assert_eq!(normalize("../.."), PathBuf::from("../.."));
+        assert_eq!(normalize("a/../../b"), PathBuf::from("../b"));
+        assert_eq!(normalize(""), PathBuf::new());
+
+        // Joining an absolute path, as `cd /etc/../usr` does, replaces the base
+        let target = String::from("/etc/../usr");
+        assert_eq!(normalize_path(Path::new("/home/me").join(&target)), PathBuf::from("/usr"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_normalize_drive_path() {
+        let normalize = |path: &str| normalize_path(PathBuf::from(path));
+
+        assert_eq!(normalize(r"C:\Windows\..\Users"), PathBuf::from(r"C:\Users"));
+        assert_eq!(normalize(r"C:\..\.."), PathBuf::from(r"C:\"));
+        assert_eq!(normalize(r"C:\a\.\b"), PathBuf::from(r"C:\a\b"));
+        let target = r"C:\Windows";
+        assert_eq!(normalize_path(Path::new(r"D:\work").join(target)), PathBuf::from(target));
+    }
+
+    #[test]
+    fn test_cd_reports_cdpath_hit() {
+        let root = temp_path("cdpath-hit");
+        std::fs::create_dir_all(root.join("projects/app")).unwrap();
+
+        let mut shell = Shell {
+            current_dir: root.clone(),
+            ..Shell::default()
+        };
+        shell.variables.insert(
+            "CDPATH".to_string(),
+            root.join("projects").to_string_lossy().to_string(),
+        );
+
+        assert_eq!(
+            shell.resolve_cd_target("app"),
+            (root.join("projects/app"), true)
+        );
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_cd_direct_relative_is_not_reported() {
+        let root = temp_path("cdpath-direct");
+        std::fs::create_dir_all(root.join("app")).unwrap();
+
+        let mut shell = Shell {
+            current_dir: root.clone(),
+            ..Shell::default()
+        };
+
+        assert_eq!(shell.resolve_cd_target("app"), (root.join("app"), false));
+
+        // "." in CDPATH means the current directory, which bash does not print
+        shell.variables.insert("CDPATH".to_string(), ".".to_string());
+        assert_eq!(shell.resolve_cd_target("app"), (root.join("app"), false));
+
+        // Explicit relative paths never consult CDPATH
+        shell.variables.insert("CDPATH".to_string(), "/".to_string());
+        assert_eq!(shell.resolve_cd_target("./app"), (root.join("./app"), false));
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_set_o_switches_editing_mode() {
+        use crate::options::EditingMode;
+
+        let mut shell = Shell::default();
+        assert_eq!(shell.options.editing_mode, EditingMode::Emacs);
+
+        shell.execute("set -o vi").unwrap();
+        assert_eq!(shell.options.editing_mode, EditingMode::Vi);
+
+        shell.execute("set -o emacs").unwrap();
+        assert_eq!(shell.options.editing_mode, EditingMode::Emacs);
+    }
+
+    #[test]
+    fn test_ps0_renders_before_execution() {
+        let mut shell = Shell::default();
+        assert_eq!(shell.render_ps0(), None);
+
+        shell.variables.insert("PS0".to_string(), "[start]".to_string());
+        assert_eq!(shell.render_ps0().as_deref(), Some("[start]"));
+
+        shell.variables.insert("USER".to_string(), "alice".to_string());
+        shell.variables.insert("PS0".to_string(), "\\u ran\\n".to_string());
+        assert_eq!(shell.render_ps0().as_deref(), Some("alice ran\n"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_stderr_redirect() {
+        let err = temp_path("stderr-redirect-err");
+        let out = temp_path("stderr-redirect-out");
+        let mut shell = Shell::default();
+
+        shell
+            .execute(&format!("ls /nonexistent-wpcsh-dir 2> {}", err.display()))
+            .unwrap();
+        assert!(!std::fs::read_to_string(&err).unwrap().is_empty());
+
+        // stdout redirection is unaffected and stderr can be discarded
+        shell
+            .execute(&format!("echo hi 2>/dev/null > {}", out.display()))
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "hi\n");
+
+        let _ = std::fs::remove_file(&err);
+        let _ = std::fs::remove_file(&out);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_stderr_append() {
+        let log = temp_path("stderr-append");
+        let _ = std::fs::remove_file(&log);
+        let mut shell = Shell::default();
+
+        let line = format!("sh -c 'echo a 1>&2; echo b 1>&2' 2>> {}", log.display());
+        shell.execute(&line).unwrap();
+        shell.execute(&line).unwrap();
+
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "a\nb\na\nb\n");
+
+        let _ = std::fs::remove_file(&log);
+    }
+
+    fn parse_simple(buffer: &str) -> Node {
+        let mut parser = flash::parser::Parser::new(flash::lexer::Lexer::new(buffer));
+        parser.parse_command()
+    }
+
+    #[test]
+    fn test_fast_path_matches_parser() {
+        for line in ["ls", "  pwd  ", "/usr/bin/true", "git-status", "ñandú", "x.sh"] {
+            let word = simple_command_word(line).expect(line);
+            assert_eq!(
+                parse_simple(line),
+                Node::Command {
+                    name: word.to_string(),
+                    args: Vec::new(),
+                    redirects: Vec::new(),
+                },
+                "{line}"
+            );
+        }
+
+        for line in ["", "ls -l", "echo $HOME", "a|b", "a=b", "~", "*.rs", "fi", "x;y", "'q'"] {
+            assert_eq!(simple_command_word(line), None, "{line}");
+        }
+    }
+
+    #[test]
+    fn test_fast_path_skips_builtins_and_aliases() {
+        let mut shell = Shell::default();
+        shell.aliases.insert("ll".to_string(), "true".to_string());
+
+        // Both would fail to spawn as external programs
+        assert!(shell.execute("history").is_ok());
+        assert_eq!(shell.execute("ll"), Ok(0));
+    }
+
+    // Run with `cargo test --release -- --ignored --nocapture bench_` to compare timings
+    #[test]
+    #[ignore]
+    fn bench_fast_path_vs_parser() {
+        use std::time::Instant;
+
+        const ITERATIONS: u32 = 100_000;
+        let line = "ls";
+
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            std::hint::black_box(simple_command_word(std::hint::black_box(line)));
+        }
+        let fast = start.elapsed();
+
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            std::hint::black_box(parse_simple(std::hint::black_box(line)));
+        }
+        let full = start.elapsed();
+
+        println!("fast path: {:?}, full parse: {:?}", fast / ITERATIONS, full / ITERATIONS);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_redirect_both_streams() {
+        let log = temp_path("redirect-both");
+        let mut shell = Shell::default();
+
+        shell
+            .execute(&format!("sh -c 'echo out; echo err 1>&2' &> {}", log.display()))
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "out\nerr\n");
+
+        shell
+            .execute(&format!("sh -c 'echo again 1>&2' &>> {}", log.display()))
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "out\nerr\nagain\n");
+
+        // In a pipeline the redirect belongs to the command it is attached to
+        shell
+            .execute(&format!("sh -c 'echo piped' &> {} | cat", log.display()))
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "piped\n");
+
+        let _ = std::fs::remove_file(&log);
+    }
+
+    #[test]
+    fn test_dump_ast_shows_structure() {
+        let dump = dump_ast("a | b && c");
+
+        assert!(dump.contains("List"));
+        assert!(dump.contains("Pipeline"));
+        assert!(dump.contains("\"&&\""));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_heredoc_feeds_stdin() {
+        let out = temp_path("heredoc");
+        let mut shell = Shell::default();
+        shell.variables.insert("NAME".to_string(), "world".to_string());
+
+        shell
+            .execute(&format!("cat <<EOF > {}\nhello $NAME\nbye\nEOF", out.display()))
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "hello world\nbye\n");
+
+        // A quoted delimiter keeps the body literal
+        shell
+            .execute(&format!("cat <<'EOF' > {}\nhello $NAME\nEOF", out.display()))
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "hello $NAME\n");
+
+        let _ = std::fs::remove_file(&out);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_here_string() {
+        let out = temp_path("here-string");
+        let mut shell = Shell::default();
+        shell
+            .variables
+            .insert("LINE".to_string(), "needle in haystack".to_string());
+
+        shell
+            .execute(&format!("grep needle <<< \"$LINE\" > {}", out.display()))
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "needle in haystack\n");
+
+        let _ = std::fs::remove_file(&out);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_stderr_joins_pipe() {
+        let out = temp_path("dup-pipe");
+        let mut shell = Shell::default();
+
+        shell
+            .execute(&format!(
+                "sh -c 'echo ok; echo error 1>&2' 2>&1 | grep error > {}",
+                out.display()
+            ))
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "error\n");
+
+        // `|&` is the same as `2>&1 |`, while a plain pipe leaves stderr alone
+        shell
+            .execute(&format!("sh -c 'echo ok; echo error 1>&2' |& cat > {}", out.display()))
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "ok\nerror\n");
+        shell
+            .execute(&format!(
+                "sh -c 'echo ok; echo error 1>&2' 2> /dev/null | cat > {}",
+                out.display()
+            ))
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "ok\n");
+
+        // Order matters: stderr follows stdout into the file only when dup comes second
+        shell
+            .execute(&format!("sh -c 'echo out; echo err 1>&2' > {} 2>&1", out.display()))
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "out\nerr\n");
+
+        let _ = std::fs::remove_file(&out);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_redirect_target_expansion() {
+        let dir = temp_path("redirect-expansion dir");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut shell = Shell {
+            home_dir: dir.clone(),
+            ..Shell::default()
+        };
+        shell
+            .variables
+            .insert("DIR".to_string(), dir.to_string_lossy().to_string());
+
+        shell.execute("echo one > $DIR/var.txt").unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join("var.txt")).unwrap(), "one\n");
+
+        shell.execute("echo two > ~/tilde.txt").unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join("tilde.txt")).unwrap(), "two\n");
+
+        shell.execute("echo three > \"$DIR/quoted.txt\"").unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join("quoted.txt")).unwrap(), "three\n");
+
+        // An unset variable is an error rather than a file named after it
+        assert_eq!(shell.execute("echo four > $WPCSH_UNSET_VARIABLE"), Ok(1));
+
+        // Targets expand like arguments, except that they are never split
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+        shell.execute("echo five > \"$DIR/$(echo sub)\"").unwrap();
+        assert_eq!(read("sub"), "five\n");
+        shell.execute("echo six > $DIR/${NOPE:-default}").unwrap();
+        assert_eq!(read("default"), "six\n");
+        shell.execute("echo seven > \"$DIR\"/'$DIR'").unwrap();
+        assert_eq!(read("$DIR"), "seven\n");
+        shell.execute("echo eight > $DIR/out$((1+2)).txt").unwrap();
+        assert_eq!(read("out3.txt"), "eight\n");
+        shell.execute("echo nine > ~/t~x").unwrap();
+        assert_eq!(read("t~x"), "nine\n");
+        let output = shell.execute_capture("X='a  b'; cat <<< $X'!'").unwrap();
+        assert_eq!(output.stdout, b"a  b!\n");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_reader_leaving_early_ends_pipeline() {
+        let mut shell = Shell::default();
+        let start = std::time::Instant::now();
+
+        let output = shell.execute_capture("yes | head -n1").unwrap();
+        assert_eq!(output.status, 0);
+        assert_eq!(output.stdout, b"y\n");
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+
+        // The writer was ended by SIGPIPE, and is reaped like the rest
+        assert_eq!(shell.arrays["PIPESTATUS"], ["141", "0"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_set_pipefail() {
+        let mut shell = Shell::default();
+
+        assert_eq!(shell.execute("false | true"), Ok(0));
+        shell.execute("set -o pipefail").unwrap();
+        assert_eq!(shell.execute("false | true"), Ok(1));
+        assert_eq!(shell.execute("sh -c 'exit 2' | sh -c 'exit 3' | true"), Ok(3));
+        assert_eq!(shell.execute("true | true"), Ok(0));
+        assert_eq!(shell.execute("false | true || echo caught >/dev/null"), Ok(0));
+
+        shell.execute("set +o pipefail").unwrap();
+        assert_eq!(shell.execute("false | true"), Ok(0));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_set_noclobber() {
+        let out = temp_path("noclobber");
+        std::fs::write(&out, "original\n").unwrap();
+        let mut shell = Shell::default();
+
+        shell.execute("set -o noclobber").unwrap();
+        assert_eq!(shell.execute(&format!("echo new > {}", out.display())), Ok(1));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "original\n");
+
+        shell.execute(&format!("echo forced >| {}", out.display())).unwrap();
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "forced\n");
+
+        shell.execute("set +o noclobber").unwrap();
+        shell.execute(&format!("echo again > {}", out.display())).unwrap();
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "again\n");
+
+        let _ = std::fs::remove_file(&out);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_failed_redirect_sets_status() {
+        let mut shell = Shell::default();
+
+        assert_eq!(shell.execute("cat < /no/such/file"), Ok(1));
+        assert_eq!(shell.exit_status, 1);
+
+        assert_eq!(shell.execute("echo hi > /no/such/dir/file"), Ok(1));
+        assert_eq!(shell.execute("echo hi | cat > /no/such/dir/file"), Ok(1));
+
+        // A descriptor that is not open fails rather than moving stdout
+        let output = shell.execute_capture("echo hi >&7").unwrap();
+        assert_eq!((output.status, output.stdout), (1, Vec::new()));
+        let output = shell.execute_capture("echo hi 1>/dev/null; echo there 2>/dev/null").unwrap();
+        assert_eq!(output.stdout, b"there\n");
+
+        // The shell keeps working afterwards
+        assert_eq!(shell.execute("true"), Ok(0));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_list_operators_short_circuit() {
+        let out = temp_path("list-operators");
+        let path = out.display();
+        let mut shell = Shell::default();
+
+        assert_eq!(shell.execute(&format!("true && echo and > {path}")), Ok(0));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "and\n");
+
+        assert_eq!(shell.execute(&format!("false && echo skipped > {path}")), Ok(1));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "and\n");
+
+        assert_eq!(shell.execute(&format!("false || echo or > {path}")), Ok(0));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "or\n");
+
+        assert_eq!(shell.execute(&format!("true || echo skipped > {path}")), Ok(0));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "or\n");
+
+        // A skipped command keeps the previous status for the next operator
+        shell
+            .execute(&format!("false && true || echo fallback > {path}"))
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "fallback\n");
+
+        let _ = std::fs::remove_file(&out);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_list_updates_status_between_statements() {
+        let out = temp_path("list-status");
+        let mut shell = Shell::default();
+
+        assert_eq!(
+            shell.execute(&format!("false; echo $? > {}; true", out.display())),
+            Ok(0)
+        );
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "1\n");
+        assert_eq!(shell.exit_status, 0);
+
+        let _ = std::fs::remove_file(&out);
+    }
+
+    #[test]
+    fn test_assignment_sets_variable() {
+        let mut shell = Shell {
+            home_dir: PathBuf::from("/home/test"),
+            ..Shell::default()
+        };
+
+        assert_eq!(shell.execute("FOO=bar"), Ok(0));
+        assert_eq!(shell.variables.get("FOO").map(String::as_str), Some("bar"));
+
+        shell.execute("PATHS=~/bin:$FOO").unwrap();
+        assert_eq!(shell.variables["PATHS"], "/home/test/bin:bar");
+
+        shell.execute("LITERAL='$FOO'").unwrap();
+        assert_eq!(shell.variables["LITERAL"], "$FOO");
+
+        shell.execute("FOO=").unwrap();
+        assert_eq!(shell.variables["FOO"], "");
+
+        shell.execute("A=1 B=2").unwrap();
+        assert_eq!(shell.variables["A"], "1");
+        assert_eq!(shell.variables["B"], "2");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_prefix_assignment_only_affects_command() {
+        let out = temp_path("prefix-assignment");
+        let path = out.display();
+        let mut shell = Shell::default();
+
+        assert_eq!(shell.execute(&format!("GREETING=hello printenv GREETING > {path}")), Ok(0));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "hello\n");
+        assert!(!shell.variables.contains_key("GREETING"));
+
+        // The prefix is gone for the next command
+        assert_eq!(shell.execute(&format!("printenv GREETING > {path}")), Ok(1));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "");
+
+        // Skipping the command also skips its prefix
+        shell.execute(&format!("false && GREETING=hi printenv GREETING > {path}")).unwrap();
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "");
+        assert!(shell.command_env.is_empty());
+
+        // The command still gets its prefix when more of the line follows it
+        for script in [
+            "GREETING=hello printenv GREETING; echo [$GREETING]",
+            "GREETING=hello printenv GREETING && echo [$GREETING]",
+            "GREETING=hello printenv GREETING\necho [$GREETING]",
+        ] {
+            let output = shell.execute_capture(script).unwrap();
+            assert_eq!(output.stdout, b"hello\n[]\n", "{script:?}");
+        }
+        assert!(!shell.variables.contains_key("GREETING"));
+
+        // Builtins and functions see the prefix only while they run
+        let script = "x=1; show() { echo $x; }; x=2 show; echo $x; IFS=: read a b <<< 'p:q'";
+        let output = shell.execute_capture(script).unwrap();
+        assert_eq!(output.stdout, b"2\n1\n");
+        assert_eq!(shell.get_var("b"), Some("q"));
+
+        let _ = std::fs::remove_file(&out);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_subshell_does_not_leak_state() {
+        let mut shell = Shell::default();
+
+        // The working directory is checked in tests/cli.rs, as it belongs to the process
+        assert_eq!(shell.execute("(FOO=inner; alias ll=ls; false)"), Ok(1));
+        assert!(!shell.variables.contains_key("FOO"));
+        assert!(!shell.aliases.contains_key("ll"));
+
+        // Functions, export marks and positional parameters stay inside too
+        shell.set_arguments(None, vec!["a".to_string()]);
+        let script = "(f() { echo hi; }; export HOME; set -- x y); echo $# $1";
+        let output = shell.execute_capture(script).unwrap();
+        assert_eq!(output.stdout, b"1 a\n");
+        assert!(!shell.functions.contains_key("f"));
+        assert!(!shell.exported.contains("HOME"));
+        assert_eq!(shell.execute("f"), Ok(127));
+    }
+
+    #[test]
+    fn test_quoted_command_names() {
+        let mut shell = Shell::default();
+
+        let output = shell.execute_capture("\"echo\" hi; 'echo' a b").unwrap();
+        assert_eq!(output.stdout, b"hi\na b\n");
+        let output = shell.execute_capture("\"printf\" \"%s\\n\" a").unwrap();
+        assert_eq!(output.stdout, b"a\n");
+        let output = shell.execute_capture("f() { echo f $1; }; 'f' x").unwrap();
+        assert_eq!(output.stdout, b"f x\n");
+        let output = shell.execute_capture("E=echo; \"$E\" hi").unwrap();
+        assert_eq!(output.stdout, b"hi\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_expanded_command_names() {
+        let mut shell = Shell::default();
+
+        let output = shell.execute_capture("E=echo; $E hi; ${E} there").unwrap();
+        assert_eq!(output.stdout, b"hi\nthere\n");
+        // The name is split into words like any argument, and vanishes when empty
+        let output = shell.execute_capture("C='echo a'; $C b; N=; $N echo c").unwrap();
+        assert_eq!(output.stdout, b"a b\nc\n");
+        let output = shell.execute_capture("$(echo printf) '%s-' x y; `echo echo`").unwrap();
+        assert_eq!(output.stdout, b"x-y-\n");
+        assert_eq!(shell.execute("E=wpcsh-no-such-command; $E"), Ok(127));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exit_in_subshell_ends_only_the_subshell() {
+        let mut shell = Shell::default();
+
+        let output = shell.execute_capture("(exit 3); echo after $?").unwrap();
+        assert_eq!(output.stdout, b"after 3\n");
+        let output = shell.execute_capture("(echo in; exit 4; echo not) || echo $?").unwrap();
+        assert_eq!(output.stdout, b"in\n4\n");
+
+        // The shell's EXIT trap is not the subshell's to run
+        let output = shell.execute_capture("trap 'echo trap' EXIT; (exit 0); echo end").unwrap();
+        assert_eq!(output.stdout, b"end\n");
+        assert!(shell.traps.contains_key(&Signal::Exit));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_subshell_redirect_applies_to_body() {
+        let out = temp_path("subshell-redirect");
+        let path = out.display();
+        let mut shell = Shell::default();
+
+        assert_eq!(shell.execute(&format!("(echo a; echo b | cat) > {path}")), Ok(0));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "a\nb\n");
+
+        // An inner redirect still wins over the group's
+        shell.execute(&format!("(echo a > /dev/null; echo b) > {path}")).unwrap();
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "b\n");
+
+        let _ = std::fs::remove_file(&out);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_if_runs_matching_branch() {
+        let out = temp_path("if-branches");
+        let path = out.display();
+        let mut shell = Shell::default();
+
+        let script = format!(
+            "if false; then echo if >> {path}; elif true && false; then echo first >> {path}; \
+             elif true; then echo second >> {path}; else echo else >> {path}; fi"
+        );
+        assert_eq!(shell.execute(&script), Ok(0));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "second\n");
+
+        // The status is that of the branch, or 0 when none runs
+        assert_eq!(shell.execute("if true; then false; fi"), Ok(1));
+        assert_eq!(shell.execute("if false; then true; fi"), Ok(0));
+        assert_eq!(shell.execute("if false; then true; else false; fi"), Ok(1));
+
+        let _ = std::fs::remove_file(&out);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_for_loop_assigns_each_word() {
+        let out = temp_path("for-loop");
+        let path = out.display();
+        let mut shell = Shell::default();
+        shell.variables.insert("FIRST".to_string(), "a".to_string());
+
+        let script = format!("for x in $FIRST $(echo b c) \"d e\" f; do echo $x >> {path}; done");
+        assert_eq!(shell.execute(&script), Ok(0));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "a\nb\nc\nd e\nf\n");
+        assert_eq!(shell.variables["x"], "f");
+
+        // The status comes from the last iteration
+        assert_eq!(shell.execute("for x in 1 2; do false; done"), Ok(1));
+        assert_eq!(shell.execute("for x in; do false; done"), Ok(0));
+
+        let _ = std::fs::remove_file(&out);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_while_and_until_loops() {
+        let out = temp_path("while-loop");
+        let path = out.display();
+        let mut shell = Shell::default();
+
+        let script = format!("i=0; while [ $i -lt 3 ]; do echo $i >> {path}; i=$((i + 1)); done");
+        assert_eq!(shell.execute(&script), Ok(0));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "0\n1\n2\n");
+
+        let script = format!("until [ $i -eq 0 ]; do i=$((i - 1)); echo $i >> {path}; done");
+        assert_eq!(shell.execute(&script), Ok(0));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "0\n1\n2\n2\n1\n0\n");
+
+        // The status is that of the last body command, or 0 if the body never ran
+        assert_eq!(shell.execute("i=1; while [ $i -eq 1 ]; do i=2; false; done"), Ok(1));
+        assert_eq!(shell.execute("while false; do true; done"), Ok(0));
+
+        let _ = std::fs::remove_file(&out);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_brace_expansion_in_words() {
+        let mut shell = Shell::default();
+
+        assert_eq!(shell.execute("for i in {1..3} x{a,\"b c\"}; do L=\"$L<$i>\"; done"), Ok(0));
+        assert_eq!(shell.variables["L"], "<1><2><3><xa><xb c>");
+    }
+
+    #[test]
+    fn test_break_and_continue() {
+        let out = temp_path("break-continue");
+        let path = out.display();
+        let mut shell = Shell::default();
+
+        let script = format!("for x in 1 2 3; do [ $x = 2 ] && break; echo $x >> {path}; done");
+        assert_eq!(shell.execute(&script), Ok(0));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "1\n");
+
+        let script = format!("for x in 1 2 3; do [ $x = 2 ] && continue; echo $x >> {path}; done");
+        shell.execute(&script).unwrap();
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "1\n1\n3\n");
+
+        // A count leaves several loops at once
+        let script = format!(
+            "for x in a b; do for y in 1 2; do [ $y = 2 ] && continue 2; echo $x$y >> {path}; done; \
+             echo never >> {path}; done; for x in c d; do while true; do break 2; done; done"
+        );
+        shell.execute(&script).unwrap();
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "1\n1\n3\na1\nb1\n");
+        assert_eq!(shell.jump, None);
+        assert_eq!(shell.loop_depth, 0);
+
+        let _ = std::fs::remove_file(&out);
+    }
+
+    #[test]
+    fn test_break_outside_loop_is_ignored() {
+        let mut shell = Shell::default();
+
+        assert_eq!(shell.execute("break; true"), Ok(0));
+        assert_eq!(shell.jump, None);
+        assert_eq!(shell.execute("for x in a; do break 0; done"), Ok(1));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_case_runs_first_matching_branch() {
+        let out = temp_path("case");
+        let path = out.display();
+        let mut shell = Shell::default();
+
+        let script = |file: &str| {
+            format!(
+                "case {file} in *.md|*.txt) echo text >> {path};; \"*\") echo star >> {path};; \
+                 *) echo other >> {path}; false;; esac"
+            )
+        };
+
+        assert_eq!(shell.execute(&script("notes.txt")), Ok(0));
+        assert_eq!(shell.execute(&script("'*'")), Ok(0));
+        assert_eq!(shell.execute(&script("main.rs")), Ok(1));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "text\nstar\nother\n");
+
+        // A quoted pattern matches literally and nothing matching gives 0
+        shell.variables.insert("FILE".to_string(), "a.rs".to_string());
+        assert_eq!(shell.execute("false; case $FILE in \"*.rs\") false;; esac"), Ok(0));
+
+        let _ = std::fs::remove_file(&out);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_function_definition_and_call() {
+        let out = temp_path("functions");
+        let path = out.display();
+        let mut shell = Shell {
+            positional: vec!["outer".to_string()],
+            ..Shell::default()
+        };
+
+        let script = format!("greet() {{ echo hello $1 $# >> {path}; }}; greet world; greet a b");
+        assert_eq!(shell.execute(&script), Ok(0));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "hello world 1\nhello a 2\n");
+        assert_eq!(shell.positional, ["outer"]);
+
+        // A function shadows the command of the same name, which `command` still reaches
+        let script = format!("echo() {{ command echo wrapped $1 >> {path}; }}; echo hi");
+        shell.execute(&script).unwrap();
+        assert!(std::fs::read_to_string(&out).unwrap().ends_with("wrapped hi\n"));
+
+        // The call's status is that of the body
+        assert_eq!(shell.execute("fails() { true; false; }; fails"), Ok(1));
+
+        let _ = std::fs::remove_file(&out);
+    }
+
+    #[test]
+    fn test_arithmetic_command() {
+        let mut shell = Shell::default();
+        shell.set_var("n", "3");
+
+        assert_eq!(shell.execute("((1 + 2))"), Ok(0));
+        assert_eq!(shell.execute("(( n - 3 ))"), Ok(1));
+        assert_eq!(shell.execute("((1 / 0))"), Ok(1));
+        let output = shell.execute_capture("if ((n > 2)); then echo big; fi").unwrap();
+        assert_eq!(output.stdout, b"big\n");
+
+        // What parses but cannot run fails instead of stopping the shell
+        assert_eq!(shell.execute("!!"), Ok(2));
+        assert_eq!(shell.execute("select x in a; do echo $x; done"), Ok(2));
+    }
+
+    #[test]
+    fn test_function_definition_redirects() {
+        let out = temp_path("function-redirects");
+        let mut shell = Shell::default();
+
+        // Redirections after the body apply on every call
+        let script = format!("log() {{ echo \"$1\"; }} >> {}; log one; log two", out.display());
+        assert_eq!(shell.execute(&script), Ok(0));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "one\ntwo\n");
+
+        let output = shell.execute_capture("quiet() { echo out; } > /dev/null; quiet").unwrap();
+        assert_eq!(output.stdout, b"");
+        assert_eq!(output.status, 0);
+
+        let _ = std::fs::remove_file(&out);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_return_leaves_function() {
+        let out = temp_path("return");
+        let path = out.display();
+        let mut shell = Shell::default();
+
+        let script = format!(
+            "f() {{ for x in 1 2; do return 3; done; echo unreachable >> {path}; }}; f"
+        );
+        assert_eq!(shell.execute(&script), Ok(3));
+        assert!(!out.exists());
+        assert_eq!(shell.jump, None);
+
+        assert_eq!(shell.execute("f() { false; return; }; f"), Ok(0));
+        assert_eq!(shell.execute("f() { return abc; }; f"), Ok(255));
+
+        // Outside a function it only complains
+        assert_eq!(shell.execute("return 4; true"), Ok(0));
+        assert_eq!(shell.execute("return 4"), Ok(1));
+    }
+
+    #[test]
+    fn test_return_stops_sourcing() {
+        let script = temp_path("return-source");
+        std::fs::write(&script, "A=1\nreturn 5\nA=2\n").unwrap();
+        let mut shell = Shell::default();
+
+        assert_eq!(shell.execute(&format!("source {}", script.display())), Ok(5));
+        assert_eq!(shell.variables["A"], "1");
+        assert_eq!(shell.call_depth, 0);
+
+        let _ = std::fs::remove_file(&script);
+    }
+
+    #[test]
+    fn test_source_arguments_are_positional() {
+        let script = temp_path("source-args");
+        std::fs::write(&script, "A=$#:$1\nB=\"$*\"\n").unwrap();
+        let mut shell = Shell {
+            positional: vec!["outer".to_string()],
+            ..Shell::default()
+        };
+
+        assert_eq!(shell.execute(&format!("source {} x 'y z'", script.display())), Ok(0));
+        assert_eq!(shell.variables["A"], "2:x");
+        assert_eq!(shell.variables["B"], "x y z");
+        assert_eq!(shell.positional, ["outer"]);
+
+        // Without arguments the file sees the caller's
+        assert_eq!(shell.execute(&format!("source {}", script.display())), Ok(0));
+        assert_eq!(shell.variables["A"], "1:outer");
+
+        let _ = std::fs::remove_file(&script);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shift_through_arguments() {
+        let mut shell = Shell::default();
+
+        let script = "f() { while [ $# -gt 0 ]; do OUT=\"$OUT$1,\"; shift; done; }; f a 'b c' d";
+        assert_eq!(shell.execute(script), Ok(0));
+        assert_eq!(shell.variables["OUT"], "a,b c,d,");
+
+        shell.positional = vec!["1".to_string(), "2".to_string(), "3".to_string()];
+        assert_eq!(shell.execute("shift 2"), Ok(0));
+        assert_eq!(shell.positional, ["3"]);
+
+        // Shifting past the end fails and leaves the parameters alone
+        assert_eq!(shell.execute("shift 2"), Ok(1));
+        assert_eq!(shell.positional, ["3"]);
+        assert_eq!(shell.execute("shift x"), Ok(1));
+        assert_eq!(shell.execute("shift 0"), Ok(0));
+        assert_eq!(shell.positional, ["3"]);
+    }
+
+    #[test]
+    fn test_negation_inverts_status() {
+        let mut shell = Shell::default();
+
+        assert_eq!(shell.execute("! true"), Ok(1));
+        assert_eq!(shell.execute("! false"), Ok(0));
+        assert_eq!(shell.exit_status, 0);
+
+        // The whole pipeline is negated, using the status of its last command
+        assert_eq!(shell.execute("! false | true"), Ok(1));
+        assert_eq!(shell.execute("if ! false; then false; fi"), Ok(1));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_group_runs_in_current_shell() {
+        let out = temp_path("group");
+        let path = out.display();
+        let mut shell = Shell::default();
+
+        assert_eq!(shell.execute(&format!("{{ echo a; X=1; false; }} > {path}")), Ok(1));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "a\n");
+        assert_eq!(shell.variables["X"], "1");
+
+        let _ = std::fs::remove_file(&out);
+    }
 }
@@ -1,17 +1,14 @@
+use std::io::ErrorKind;
+use std::path::Path;
+
 use wpcsh::Shell;
 
+// Rust ignores SIGPIPE, so writing to a pipe nobody reads fails instead of ending the
+// process. A shell is expected to end quietly then, like the commands it runs.
 #[cfg(unix)]
-fn install_signal_handlers() {
-    use signal_hook::consts::{SIGHUP, SIGTERM};
-    use signal_hook::iterator::Signals;
-
-    let mut signals = Signals::new([SIGTERM, SIGHUP]).expect("signals");
-
-    std::thread::spawn(move || {
-        for _ in signals.forever() {
-            std::process::exit(0);
-        }
-    });
+fn restore_sigpipe() {
+    // SAFETY: sets the default action for a signal, before any other thread is started
+    unsafe { libc::signal(libc::SIGPIPE, libc::SIG_DFL) };
 }
 
 #[cfg(unix)]
@@ -27,11 +24,93 @@ fn is_login_shell() -> bool {
         .unwrap_or(false)
 }
 
+// --dump-ast prints the parsed AST of `-c <command>` or of stdin instead of executing it
+fn dump_ast(args: &[String]) {
+    let input = match args.iter().position(|a| a == "-c") {
+        Some(i) => match args.get(i + 1) {
+            Some(command) => command.clone(),
+            None => {
+                eprintln!("wpcsh: -c: option requires an argument");
+                std::process::exit(2);
+            }
+        },
+        None => std::io::read_to_string(std::io::stdin()).unwrap_or_default(),
+    };
+
+    println!("{}", wpcsh::dump_ast(&input));
+}
+
+// `-c command [name [args...]]` runs one command; `script [args...]` runs a file. Either way
+// the name becomes `$0` and the rest `$1`, `$2`, ... Returns the status to exit with.
+fn run_one_shot(args: &[String]) -> Option<i32> {
+    let (command, name, rest) = match args {
+        [flag, command, rest @ ..] if flag == "-c" => {
+            let (name, rest) = match rest.split_first() {
+                Some((name, rest)) => (Some(name.clone()), rest),
+                None => (None, rest),
+            };
+            (command.clone(), name, rest)
+        }
+        [flag] if flag == "-c" => {
+            eprintln!("wpcsh: -c: option requires an argument");
+            return Some(2);
+        }
+        [path, rest @ ..] if !path.starts_with('-') => {
+            let mut shell = start_shell();
+            let result = shell.execute_script(Path::new(path), rest.to_vec());
+            shell.run_exit_trap();
+            return Some(match result {
+                Ok(_) => shell.last_status(),
+                Err(ErrorKind::NotFound) => {
+                    eprintln!("wpcsh: {}: no such file or directory", path);
+                    127
+                }
+                Err(err) => {
+                    eprintln!("wpcsh: {}: {}", path, err);
+                    126
+                }
+            });
+        }
+        _ => return None,
+    };
+
+    let mut shell = start_shell();
+    shell.set_arguments(name, rest.to_vec());
+    let code = shell.execute(&command).unwrap_or_else(|_| shell.last_status());
+    shell.run_exit_trap();
+    Some(code)
+}
+
+// Shell::new starts in the home directory, but one-shot runs stay where they were started
+fn start_shell() -> Shell {
+    let start = std::env::current_dir().ok();
+    let mut shell = Shell::new().unwrap();
+    if let Some(start) = start {
+        let _ = shell.change_directory(&[start.to_string_lossy().to_string()]);
+    }
+    shell
+}
+
 fn main() {
     #[cfg(unix)]
-    {
-        install_signal_handlers();
+    restore_sigpipe();
+
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+
+    if args.iter().any(|a| a == "--dump-ast") {
+        dump_ast(&args);
+        return;
+    }
+
+    // Terminating signals go through the EXIT trap like any other way of exiting
+    wpcsh::catch_terminations();
+
+    if let Some(code) = run_one_shot(&args) {
+        std::process::exit(code);
+    }
 
+    #[cfg(unix)]
+    {
         let mut shell = Shell::new().unwrap();
 
         let login = is_login_shell();
@@ -46,11 +125,14 @@ fn main() {
         } else {
             shell.run_non_interactive();
         }
+        shell.run_exit_trap();
+        std::process::exit(shell.last_status());
     }
 
     #[cfg(windows)]
     {
         let mut shell = Shell::new().unwrap();
         shell.run_interactive();
+        shell.run_exit_trap();
     }
 }
@@ -0,0 +1,288 @@
+/// Matches `text` against a shell glob pattern. `*` matches any run of characters,
+/// `?` any single one, `[...]` one of a set, and `\` makes the next character literal.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // Where to resume after the last `*` if the rest fails to match
+    let mut star: Option<(usize, usize)> = None;
+
+    loop {
+        if pattern.get(p) == Some(&'*') {
+            p += 1;
+            star = Some((p, t));
+            continue;
+        }
+
+        let Some(&ch) = text.get(t) else {
+            break;
+        };
+
+        if let Some(next) = match_one(&pattern, p, ch) {
+            p = next;
+            t += 1;
+            continue;
+        }
+
+        // Let the last `*` swallow one more character and try again
+        match star {
+            Some((after_star, start)) => {
+                p = after_star;
+                t = start + 1;
+                star = Some((after_star, t));
+            }
+            None => return false,
+        }
+    }
+
+    p == pattern.len()
+}
+
+/// Whether `pattern` has an unescaped `*`, `?` or `[...]`, so it may match several names
+pub fn has_glob(pattern: &str) -> bool {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '*' | '?' => return true,
+            '[' if match_bracket(&chars, i, '\0').is_some() => return true,
+            _ => {}
+        }
+        i += 1;
+    }
+
+    false
+}
+
+/// Removes the backslashes that make pattern characters literal
+pub fn unescape(pattern: &str) -> String {
+    let mut out = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => out.extend(chars.next()),
+            _ => out.push(ch),
+        }
+    }
+
+    out
+}
+
+/// Expands a pattern into the sorted paths it matches, relative to the current directory
+/// unless it starts with `/`. Names starting with `.` only match a literal leading dot.
+pub fn glob(pattern: &str) -> Vec<String> {
+    let (mut paths, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec!["/".to_string()], rest),
+        None => (vec![String::new()], pattern),
+    };
+
+    for component in rest.split('/') {
+        paths = paths
+            .iter()
+            .flat_map(|path| glob_component(path, component))
+            .collect();
+        if paths.is_empty() {
+            break;
+        }
+    }
+
+    paths.sort();
+    paths
+}
+
+// Paths below `dir` matching one component of a pattern. An empty component, as after a
+// trailing slash, keeps `dir` if it is a directory.
+fn glob_component(dir: &str, component: &str) -> Vec<String> {
+    let join = |name: &str| match dir {
+        "" => name.to_string(),
+        _ if dir.ends_with('/') => format!("{}{}", dir, name),
+        _ => format!("{}/{}", dir, name),
+    };
+
+    if component.is_empty() {
+        let is_dir = std::fs::metadata(if dir.is_empty() { "." } else { dir })
+            .is_ok_and(|meta| meta.is_dir());
+        return if is_dir { vec![join("")] } else { Vec::new() };
+    }
+
+    if !has_glob(component) {
+        let path = join(&unescape(component));
+        return match std::fs::symlink_metadata(&path) {
+            Ok(_) => vec![path],
+            Err(_) => Vec::new(),
+        };
+    }
+
+    let Ok(entries) = std::fs::read_dir(if dir.is_empty() { "." } else { dir }) else {
+        return Vec::new();
+    };
+
+    let dot = component.starts_with('.') || component.starts_with("\\.");
+    entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| (dot || !name.starts_with('.')) && matches(component, name))
+        .map(|name| join(&name))
+        .collect()
+}
+
+// Matches the single-character pattern at `p` and returns the index after it
+fn match_one(pattern: &[char], p: usize, ch: char) -> Option<usize> {
+    match *pattern.get(p)? {
+        '?' => Some(p + 1),
+        '\\' if p + 1 < pattern.len() => (pattern[p + 1] == ch).then_some(p + 2),
+        '[' => match match_bracket(pattern, p, ch) {
+            Some((true, next)) => Some(next),
+            Some((false, _)) => None,
+            // An unclosed bracket is a literal '['
+            None => (ch == '[').then_some(p + 1),
+        },
+        literal => (literal == ch).then_some(p + 1),
+    }
+}
+
+// Returns whether `ch` is in the bracket expression at `start` and the index after it,
+// or None when the bracket is never closed
+fn match_bracket(pattern: &[char], start: usize, ch: char) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negate = matches!(pattern.get(i), Some('!' | '^'));
+    if negate {
+        i += 1;
+    }
+
+    let first = i;
+    let mut matched = false;
+
+    loop {
+        let current = *pattern.get(i)?;
+
+        // A ']' right after the opening bracket is part of the set
+        if current == ']' && i > first {
+            return Some((matched != negate, i + 1));
+        }
+
+        if current == '['
+            && pattern.get(i + 1) == Some(&':')
+            && let Some(end) = class_end(pattern, i + 2)
+        {
+            let name: String = pattern[i + 2..end].iter().collect();
+            matched |= in_class(&name, ch);
+            i = end + 2;
+            continue;
+        }
+
+        let (low, next) = literal_at(pattern, i)?;
+        if pattern.get(next) == Some(&'-') && pattern.get(next + 1).is_some_and(|&c| c != ']') {
+            let (high, after) = literal_at(pattern, next + 1)?;
+            matched |= low <= ch && ch <= high;
+            i = after;
+        } else {
+            matched |= low == ch;
+            i = next;
+        }
+    }
+}
+
+fn literal_at(pattern: &[char], i: usize) -> Option<(char, usize)> {
+    match *pattern.get(i)? {
+        '\\' => Some((*pattern.get(i + 1)?, i + 2)),
+        ch => Some((ch, i + 1)),
+    }
+}
+
+// Finds the ':' of the `:]` closing a `[:class:]` whose name starts at `start`
+fn class_end(pattern: &[char], start: usize) -> Option<usize> {
+    (start..pattern.len().saturating_sub(1))
+        .find(|&i| pattern[i] == ':' && pattern[i + 1] == ']')
+}
+
+fn in_class(name: &str, ch: char) -> bool {
+    match name {
+        "alpha" => ch.is_alphabetic(),
+        "digit" => ch.is_ascii_digit(),
+        "alnum" => ch.is_alphanumeric(),
+        "upper" => ch.is_uppercase(),
+        "lower" => ch.is_lowercase(),
+        "space" => ch.is_whitespace(),
+        "blank" => ch == ' ' || ch == '\t',
+        "punct" => ch.is_ascii_punctuation(),
+        "xdigit" => ch.is_ascii_hexdigit(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod matcher_tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcards() {
+        assert!(matches("*.txt", "notes.txt"));
+        assert!(matches("*.txt", ".txt"));
+        assert!(!matches("*.txt", "notes.txt.bak"));
+        assert!(matches("a*b*c", "aXbYbZc"));
+        assert!(matches("?at", "cat"));
+        assert!(!matches("?at", "at"));
+        assert!(matches("*", ""));
+        assert!(!matches("", "a"));
+    }
+
+    #[test]
+    fn test_brackets() {
+        assert!(matches("[abc]x", "bx"));
+        assert!(!matches("[!abc]x", "bx"));
+        assert!(matches("[^abc]x", "dx"));
+        assert!(matches("file[0-9]", "file7"));
+        assert!(matches("[]a]", "]"));
+        assert!(matches("[[:digit:]][[:alpha:]]", "1z"));
+        assert!(matches("[", "["));
+        assert!(!matches("[a-c", "b"));
+    }
+
+    #[test]
+    fn test_has_glob() {
+        assert!(has_glob("*.rs"));
+        assert!(has_glob("file?"));
+        assert!(has_glob("[ab]c"));
+        assert!(!has_glob("["));
+        assert!(!has_glob("a\\*b"));
+        assert!(!has_glob("plain"));
+        assert_eq!(unescape("a\\*\\\\b"), "a*\\b");
+    }
+
+    #[test]
+    fn test_glob_directory() {
+        let dir = std::env::temp_dir().join(format!("wpcsh-{}-glob", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        for name in ["b.rs", "a.rs", ".hidden.rs", "c.txt", "sub/d.rs"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        let base = dir.to_string_lossy();
+
+        let found = |pattern: &str| -> Vec<String> {
+            glob(&format!("{}/{}", base, pattern))
+                .iter()
+                .map(|path| path[base.len() + 1..].to_string())
+                .collect()
+        };
+
+        assert_eq!(found("*.rs"), ["a.rs", "b.rs"]);
+        assert_eq!(found(".*.rs"), [".hidden.rs"]);
+        assert_eq!(found("*/*.rs"), ["sub/d.rs"]);
+        assert_eq!(found("*/"), ["sub/"]);
+        assert_eq!(found("[ac].*"), ["a.rs", "c.txt"]);
+        assert!(found("*.none").is_empty());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_escapes() {
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "x"));
+        assert!(matches("a\\?", "a?"));
+    }
+}
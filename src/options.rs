@@ -0,0 +1,154 @@
+use linefeed::{Command, Interface, Terminal};
+
+/// Line-editing style used by the interactive prompt
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EditingMode {
+    #[default]
+    Emacs,
+    Vi,
+}
+
+impl EditingMode {
+    // linefeed only ships an emacs keymap, so vi mode rebinds Escape-prefixed
+    // sequences to the matching vi motions instead of switching keymaps
+    pub fn apply<T: Terminal>(self, interface: &Interface<T>) {
+        for (seq, vi, emacs) in VI_BINDINGS {
+            let command = match self {
+                EditingMode::Vi => Some(vi),
+                EditingMode::Emacs => emacs.as_ref(),
+            };
+
+            match command {
+                Some(command) => {
+                    interface.bind_sequence(*seq, command.clone());
+                }
+                None => {
+                    interface.unbind_sequence(seq);
+                }
+            }
+        }
+    }
+}
+
+// Sequence, vi command, and the emacs default it replaces
+const VI_BINDINGS: &[(&str, Command, Option<Command>)] = &[
+    ("\x1bh", Command::BackwardChar, None),
+    ("\x1bl", Command::ForwardChar, None),
+    ("\x1bk", Command::PreviousHistory, None),
+    ("\x1bj", Command::NextHistory, None),
+    ("\x1bw", Command::ForwardWord, None),
+    ("\x1bb", Command::BackwardWord, Some(Command::BackwardWord)),
+    ("\x1b0", Command::BeginningOfLine, Some(Command::DigitArgument)),
+    ("\x1b$", Command::EndOfLine, None),
+    ("\x1bx", Command::DeleteChar, None),
+];
+
+/// Options toggled with `set -o` / `set +o`
+#[derive(Debug, Default, Clone)]
+pub struct ShellOptions {
+    pub editing_mode: EditingMode,
+    pub noclobber: bool,
+    // Stop at the first command that fails outside a condition
+    pub errexit: bool,
+    // Print each command to stderr before running it
+    pub xtrace: bool,
+    // A pipeline fails with its rightmost failing command, not just its last one
+    pub pipefail: bool,
+}
+
+impl ShellOptions {
+    pub const NAMES: &'static [&'static str] =
+        &["emacs", "errexit", "noclobber", "pipefail", "vi", "xtrace"];
+
+    // The long name of a single-letter option, as in `set -x`
+    pub fn long_name(letter: char) -> Option<&'static str> {
+        match letter {
+            'C' => Some("noclobber"),
+            'e' => Some("errexit"),
+            'x' => Some("xtrace"),
+            _ => None,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<bool> {
+        match name {
+            "emacs" => Some(self.editing_mode == EditingMode::Emacs),
+            "vi" => Some(self.editing_mode == EditingMode::Vi),
+            "noclobber" => Some(self.noclobber),
+            "errexit" => Some(self.errexit),
+            "xtrace" => Some(self.xtrace),
+            "pipefail" => Some(self.pipefail),
+            _ => None,
+        }
+    }
+
+    // Returns false for unknown option names
+    pub fn set(&mut self, name: &str, enabled: bool) -> bool {
+        match (name, enabled) {
+            ("emacs", true) | ("vi", false) => self.editing_mode = EditingMode::Emacs,
+            ("vi", true) | ("emacs", false) => self.editing_mode = EditingMode::Vi,
+            ("noclobber", enabled) => self.noclobber = enabled,
+            ("errexit", enabled) => self.errexit = enabled,
+            ("xtrace", enabled) => self.xtrace = enabled,
+            ("pipefail", enabled) => self.pipefail = enabled,
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod options_tests {
+    use super::*;
+    use linefeed::memory::MemoryTerminal;
+
+    fn binding<T: Terminal>(interface: &Interface<T>, seq: &str) -> Option<Command> {
+        interface
+            .lock_reader()
+            .bindings()
+            .find(|(bound, _)| *bound == seq)
+            .map(|(_, command)| command.clone())
+    }
+
+    #[test]
+    fn test_set_o_toggles_editing_mode() {
+        let mut options = ShellOptions::default();
+        assert_eq!(options.get("emacs"), Some(true));
+
+        assert!(options.set("vi", true));
+        assert_eq!(options.editing_mode, EditingMode::Vi);
+        assert_eq!(options.get("emacs"), Some(false));
+
+        assert!(options.set("emacs", true));
+        assert_eq!(options.editing_mode, EditingMode::Emacs);
+
+        assert!(!options.set("nonsense", true));
+        assert_eq!(options.get("nonsense"), None);
+    }
+
+    #[test]
+    fn test_short_option_names() {
+        let mut options = ShellOptions::default();
+
+        for letter in ['x', 'C', 'e'] {
+            let name = ShellOptions::long_name(letter).unwrap();
+            assert!(options.set(name, true));
+            assert_eq!(options.get(name), Some(true));
+        }
+        assert!(options.xtrace && options.noclobber && options.errexit);
+        assert_eq!(ShellOptions::long_name('q'), None);
+    }
+
+    #[test]
+    fn test_apply_reconfigures_interface() {
+        let interface = Interface::with_term("wpcsh-test", MemoryTerminal::new()).unwrap();
+
+        EditingMode::Vi.apply(&interface);
+        assert_eq!(binding(&interface, "\x1bk"), Some(Command::PreviousHistory));
+        assert_eq!(binding(&interface, "\x1b0"), Some(Command::BeginningOfLine));
+
+        EditingMode::Emacs.apply(&interface);
+        assert_eq!(binding(&interface, "\x1bk"), None);
+        assert_eq!(binding(&interface, "\x1b0"), Some(Command::DigitArgument));
+    }
+}
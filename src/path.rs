@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Remembers where commands were found in `$PATH`, for as long as it stays the same
+#[derive(Debug, Default)]
+pub struct Cache {
+    path: String,
+    found: HashMap<String, PathBuf>,
+    // Every command name in `path`, listed the first time completion asks for it
+    commands: Option<Vec<String>>,
+}
+
+impl Cache {
+    /// The first executable called `name` in `path`. A remembered file that is gone is
+    /// looked up again.
+    pub fn find(&mut self, name: &str, path: &str) -> Option<PathBuf> {
+        self.use_path(path);
+
+        if let Some(found) = self.found.get(name)
+            && is_executable(found)
+        {
+            return Some(found.clone());
+        }
+
+        let found = find_all(name, path).next();
+        match &found {
+            Some(file) => self.found.insert(name.to_string(), file.clone()),
+            None => self.found.remove(name),
+        };
+        found
+    }
+
+    /// The names of the commands in `path`, sorted and without duplicates
+    pub fn commands(&mut self, path: &str) -> &[String] {
+        self.use_path(path);
+        self.commands.get_or_insert_with(|| list_commands(path))
+    }
+
+    pub fn clear(&mut self) {
+        self.found.clear();
+        self.commands = None;
+    }
+
+    // Forgets what was found in a different `$PATH`
+    fn use_path(&mut self, path: &str) {
+        if self.path != path {
+            self.clear();
+            self.path = path.to_string();
+        }
+    }
+
+    /// The remembered names and files, sorted by name
+    pub fn entries(&self) -> Vec<(&String, &PathBuf)> {
+        let mut entries: Vec<_> = self.found.iter().collect();
+        entries.sort();
+        entries
+    }
+}
+
+/// The executable files called `name` in the directories of `path`, a `$PATH` value, in
+/// the order they are searched
+pub fn find_all<'a>(name: &'a str, path: &'a str) -> impl Iterator<Item = PathBuf> + 'a {
+    candidates(name, path).filter(|candidate| is_executable(candidate))
+}
+
+/// Every file `name` could be in the directories of `path`, whether it exists or not.
+/// An empty entry stands for the current directory.
+pub fn candidates<'a>(name: &'a str, path: &'a str) -> impl Iterator<Item = PathBuf> + 'a {
+    directories(path).flat_map(move |dir| with_extensions(dir.join(name)))
+}
+
+/// The names of the commands in the directories of `path`, sorted and without duplicates
+pub fn list_commands(path: &str) -> Vec<String> {
+    let mut names: Vec<String> = directories(path)
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.flatten())
+        .filter_map(|entry| command_name(&entry.path()))
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+fn directories(path: &str) -> impl Iterator<Item = PathBuf> + '_ {
+    std::env::split_paths(path).map(|dir| match dir.as_os_str().is_empty() {
+        true => PathBuf::from("."),
+        false => dir,
+    })
+}
+
+/// The executable file a name with a directory part, like `./run`, refers to
+pub fn find_file(name: &str) -> Option<PathBuf> {
+    with_extensions(PathBuf::from(name))
+        .into_iter()
+        .find(|candidate| is_executable(candidate))
+}
+
+// On Windows a name without an extension is tried with each one in %PATHEXT%
+#[cfg(windows)]
+fn with_extensions(file: PathBuf) -> Vec<PathBuf> {
+    if file.extension().is_some() {
+        return vec![file];
+    }
+
+    extensions()
+        .into_iter()
+        .map(|ext| {
+            let mut name = file.clone().into_os_string();
+            name.push(ext);
+            PathBuf::from(name)
+        })
+        .collect()
+}
+
+#[cfg(not(windows))]
+fn with_extensions(file: PathBuf) -> Vec<PathBuf> {
+    vec![file]
+}
+
+#[cfg(windows)]
+fn extensions() -> Vec<String> {
+    let pathext = std::env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".to_string());
+    pathext.split(';').filter(|ext| !ext.is_empty()).map(str::to_string).collect()
+}
+
+// The name a file in `$PATH` is run by: on Windows, one with an extension from %PATHEXT%
+// without it
+#[cfg(windows)]
+fn command_name(file: &Path) -> Option<String> {
+    let extension = format!(".{}", file.extension()?.to_str()?);
+    let runnable = extensions().iter().any(|ext| ext.eq_ignore_ascii_case(&extension));
+    match runnable && file.is_file() {
+        true => Some(file.file_stem()?.to_str()?.to_string()),
+        false => None,
+    }
+}
+
+#[cfg(not(windows))]
+fn command_name(file: &Path) -> Option<String> {
+    match is_executable(file) {
+        true => Some(file.file_name()?.to_str()?.to_string()),
+        false => None,
+    }
+}
+
+/// Whether `path` is a file the shell could run
+#[cfg(unix)]
+pub fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+pub fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(test)]
+mod path_tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_all_in_order() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let base = std::env::temp_dir().join(format!("wpcsh-{}-path", std::process::id()));
+        let (first, second) = (base.join("first"), base.join("second"));
+        std::fs::create_dir_all(&first).unwrap();
+        std::fs::create_dir_all(&second).unwrap();
+
+        let executable = |path: &Path, mode: u32| {
+            std::fs::write(path, "").unwrap();
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).unwrap();
+        };
+        executable(&first.join("tool"), 0o644);
+        executable(&second.join("tool"), 0o755);
+        executable(&first.join("both"), 0o755);
+        executable(&second.join("both"), 0o755);
+
+        let search = format!("{}:{}", first.display(), second.display());
+        let found: Vec<PathBuf> = find_all("tool", &search).collect();
+        assert_eq!(found, [second.join("tool")]);
+        let found: Vec<PathBuf> = find_all("both", &search).collect();
+        assert_eq!(found, [first.join("both"), second.join("both")]);
+        assert_eq!(find_all("missing", &search).next(), None);
+        assert!(!is_executable(&first));
+
+        // The cache finds the file again once the remembered one is gone
+        let mut cache = Cache::default();
+        assert_eq!(cache.find("both", &search), Some(first.join("both")));
+        std::fs::remove_file(first.join("both")).unwrap();
+        assert_eq!(cache.find("both", &search), Some(second.join("both")));
+        assert_eq!(cache.entries(), [(&"both".to_string(), &second.join("both"))]);
+        assert_eq!(cache.find("both", &first.display().to_string()), None);
+        assert!(cache.entries().is_empty());
+
+        assert_eq!(list_commands(&search), ["both", "tool"]);
+        assert_eq!(cache.commands(&search), ["both", "tool"]);
+        executable(&first.join("new"), 0o755);
+        assert_eq!(cache.commands(&search), ["both", "tool"]);
+        cache.clear();
+        assert_eq!(cache.commands(&search), ["both", "new", "tool"]);
+
+        let _ = std::fs::remove_dir_all(&base);
+    }
+}
@@ -0,0 +1,362 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::{echo_escapes, escape_number};
+
+/// What `printf` prints, and the problems it warns about on the way
+pub struct Formatted {
+    pub text: String,
+    pub errors: Vec<String>,
+}
+
+/// Formats `args` the way `printf format args...` does. The format is used again while
+/// arguments are left, and missing arguments count as empty strings or zero. Arguments
+/// that are not numbers where one is expected are printed as 0 with a warning.
+pub fn format(format: &str, args: &[String]) -> Formatted {
+    let mut printer = Printer {
+        args,
+        next: 0,
+        text: String::new(),
+        errors: Vec::new(),
+    };
+
+    loop {
+        let start = printer.next;
+        if !printer.run(format) {
+            break;
+        }
+        // A format that takes no arguments is printed once
+        if printer.next >= args.len() || printer.next == start {
+            break;
+        }
+    }
+
+    Formatted {
+        text: printer.text,
+        errors: printer.errors,
+    }
+}
+
+struct Printer<'a> {
+    args: &'a [String],
+    // Index of the next argument a conversion takes
+    next: usize,
+    text: String,
+    errors: Vec<String>,
+}
+
+// Conversions are padded in memory, so larger widths and precisions are refused rather
+// than allocated
+const MAX_FIELD: u64 = 1 << 20;
+
+// The flags, width and precision written between `%` and the conversion character
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl<'a> Printer<'a> {
+    fn next_arg(&mut self) -> &'a str {
+        let arg = self.args.get(self.next).map_or("", String::as_str);
+        self.next += 1;
+        arg
+    }
+
+    // Prints the format once, returning false when printing has to stop, as after `\c`
+    // in a `%b` argument or an invalid conversion
+    fn run(&mut self, format: &str) -> bool {
+        let mut chars = format.chars().peekable();
+
+        while let Some(ch) = chars.next() {
+            match ch {
+                '\\' => self.escape(&mut chars),
+                '%' => {
+                    if !self.conversion(&mut chars) {
+                        return false;
+                    }
+                }
+                _ => self.text.push(ch),
+            }
+        }
+
+        true
+    }
+
+    // The escapes of the format, where octal values are written as `\NNN`
+    fn escape(&mut self, chars: &mut Peekable<Chars>) {
+        if chars.peek().is_some_and(|c| c.is_digit(8)) {
+            let (value, _) = escape_number(chars, 8, 3);
+            self.text.extend(char::from_u32(value));
+            return;
+        }
+
+        match chars.next() {
+            Some('a') => self.text.push('\x07'),
+            Some('b') => self.text.push('\x08'),
+            Some('e' | 'E') => self.text.push('\x1b'),
+            Some('f') => self.text.push('\x0c'),
+            Some('n') => self.text.push('\n'),
+            Some('r') => self.text.push('\r'),
+            Some('t') => self.text.push('\t'),
+            Some('v') => self.text.push('\x0b'),
+            Some(ch @ ('\\' | '"' | '\'')) => self.text.push(ch),
+            Some('x') => match escape_number(chars, 16, 2) {
+                (_, 0) => self.text.push_str("\\x"),
+                (value, _) => self.text.extend(char::from_u32(value)),
+            },
+            Some(other) => {
+                self.text.push('\\');
+                self.text.push(other);
+            }
+            None => self.text.push('\\'),
+        }
+    }
+
+    // Prints the conversion after a `%`, such as `%-5s` or `%.3d`
+    fn conversion(&mut self, chars: &mut Peekable<Chars>) -> bool {
+        let mut spec = Spec::default();
+
+        while let Some(&flag) = chars.peek() {
+            match flag {
+                '-' => spec.left = true,
+                '0' => spec.zero = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                '#' => spec.alternate = true,
+                _ => break,
+            }
+            chars.next();
+        }
+
+        // A `*` width comes from the arguments, and a negative one aligns to the left
+        if let Some(width) = self.number_field(chars) {
+            spec.left |= width < 0;
+            spec.width = width.unsigned_abs() as usize;
+            if width.unsigned_abs() > MAX_FIELD {
+                self.errors.push("field width too large".to_string());
+                return false;
+            }
+        }
+        if chars.next_if_eq(&'.').is_some() {
+            let precision = self.number_field(chars).unwrap_or(0);
+            spec.precision = usize::try_from(precision).ok();
+            if precision > MAX_FIELD as i64 {
+                self.errors.push("precision too large".to_string());
+                return false;
+            }
+        }
+
+        let Some(conversion) = chars.next() else {
+            self.errors.push("missing format character".to_string());
+            return false;
+        };
+
+        match conversion {
+            '%' => self.text.push('%'),
+            's' => {
+                let arg = self.next_arg();
+                let text = match spec.precision {
+                    Some(precision) => arg.chars().take(precision).collect(),
+                    None => arg.to_string(),
+                };
+                self.pad(&spec, &text);
+            }
+            'b' => {
+                let (text, stop) = echo_escapes(self.next_arg());
+                self.pad(&spec, &text);
+                if stop {
+                    return false;
+                }
+            }
+            'c' => {
+                let text: String = self.next_arg().chars().take(1).collect();
+                self.pad(&spec, &text);
+            }
+            'd' | 'i' => {
+                let value = self.integer();
+                let sign = match value {
+                    _ if value < 0 => "-",
+                    _ if spec.plus => "+",
+                    _ if spec.space => " ",
+                    _ => "",
+                };
+                self.pad_number(&spec, sign, "", value.unsigned_abs().to_string());
+            }
+            'u' | 'x' | 'X' | 'o' => {
+                // Negative values wrap around, as they do in C
+                let value = self.integer() as u64;
+                let (digits, prefix) = match conversion {
+                    'u' => (value.to_string(), ""),
+                    'x' => (format!("{:x}", value), "0x"),
+                    'X' => (format!("{:X}", value), "0X"),
+                    _ => (format!("{:o}", value), "0"),
+                };
+                let prefix = match spec.alternate && value != 0 {
+                    true => prefix,
+                    false => "",
+                };
+                self.pad_number(&spec, "", prefix, digits);
+            }
+            _ => {
+                self.errors.push(format!("`{}': invalid format character", conversion));
+                return false;
+            }
+        }
+
+        true
+    }
+
+    // A width or precision written in digits, or `*` to take it from the next argument
+    fn number_field(&mut self, chars: &mut Peekable<Chars>) -> Option<i64> {
+        if chars.next_if_eq(&'*').is_some() {
+            return Some(self.integer());
+        }
+
+        let mut digits = String::new();
+        while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+            digits.push(digit);
+        }
+        // Digits past the largest number are too large a field anyway
+        match digits.is_empty() {
+            true => None,
+            false => Some(digits.parse().unwrap_or(i64::MAX)),
+        }
+    }
+
+    // The next argument as a number. Like other shells, `'c` stands for the code of `c`.
+    fn integer(&mut self) -> i64 {
+        let arg = self.next_arg();
+        parse_integer(arg).unwrap_or_else(|| {
+            self.errors.push(format!("{}: invalid number", arg));
+            0
+        })
+    }
+
+    fn pad(&mut self, spec: &Spec, text: &str) {
+        let fill = " ".repeat(spec.width.saturating_sub(text.chars().count()));
+        if spec.left {
+            self.text.push_str(text);
+            self.text.push_str(&fill);
+        } else {
+            self.text.push_str(&fill);
+            self.text.push_str(text);
+        }
+    }
+
+    // The precision is the least number of digits; without one, `0` pads with zeros
+    // after the sign instead of spaces before it
+    fn pad_number(&mut self, spec: &Spec, sign: &str, prefix: &str, digits: String) {
+        let digits = match spec.precision {
+            Some(0) if digits == "0" => String::new(),
+            Some(precision) => format!("{:0>precision$}", digits),
+            None => digits,
+        };
+
+        let length = sign.len() + prefix.len() + digits.len();
+        if spec.zero && !spec.left && spec.precision.is_none() {
+            let zeros = "0".repeat(spec.width.saturating_sub(length));
+            self.text.push_str(&format!("{}{}{}{}", sign, prefix, zeros, digits));
+        } else {
+            self.pad(spec, &format!("{}{}{}", sign, prefix, digits));
+        }
+    }
+}
+
+// Reads a decimal, `0x` hexadecimal or `0` octal number with an optional sign. An empty
+// argument is 0.
+fn parse_integer(text: &str) -> Option<i64> {
+    let text = text.trim_start();
+    if text.is_empty() {
+        return Some(0);
+    }
+    if let Some(rest) = text.strip_prefix(['\'', '"']) {
+        return Some(rest.chars().next().map_or(0, |ch| ch as i64));
+    }
+
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    if digits.starts_with(['+', '-']) {
+        return None;
+    }
+
+    let value = if let Some(hex) = digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        u64::from_str_radix(&digits[1..], 8)
+    } else {
+        digits.parse()
+    };
+
+    let value = value.ok()? as i64;
+    Some(if negative { value.wrapping_neg() } else { value })
+}
+
+#[cfg(test)]
+mod printf_tests {
+    use super::*;
+
+    fn printf(format_text: &str, args: &[&str]) -> String {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let formatted = format(format_text, &args);
+        assert!(formatted.errors.is_empty(), "{:?}", formatted.errors);
+        formatted.text
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(printf("%s\\t%d\\n", &["a", "42"]), "a\t42\n");
+        assert_eq!(printf("%i %x %X %o %u", &["-7", "255", "255", "8", "3"]), "-7 ff FF 10 3");
+        assert_eq!(printf("%c%c", &["hello", ""]), "h");
+        assert_eq!(printf("100%%", &[]), "100%");
+        assert_eq!(printf("%d %d %d", &["0x1f", "010", "'A"]), "31 8 65");
+        assert_eq!(printf("%b|%s", &["a\\tb", "a\\tb"]), "a\tb|a\\tb");
+        assert_eq!(printf("\\101\\x42\\\\", &[]), "AB\\");
+    }
+
+    #[test]
+    fn test_width_and_precision() {
+        assert_eq!(printf("[%5s][%-5s]", &["ab", "ab"]), "[   ab][ab   ]");
+        assert_eq!(printf("[%.2s][%*s]", &["abcd", "3", "x"]), "[ab][  x]");
+        let args = ["-42", "7", "3", "3"];
+        assert_eq!(printf("[%05d][%-4d][%+d][% d]", &args), "[-0042][7   ][+3][ 3]");
+        let args = ["5", "-5", "255", "8"];
+        assert_eq!(printf("[%.3d][%6.3d][%#x][%#o]", &args), "[005][  -005][0xff][010]");
+    }
+
+    #[test]
+    fn test_format_reuse() {
+        assert_eq!(printf("%s=%s\\n", &["a", "1", "b", "2", "c"]), "a=1\nb=2\nc=\n");
+        assert_eq!(printf("x\\n", &["unused"]), "x\n");
+        assert_eq!(printf("%s %d|", &[]), " 0|");
+        assert_eq!(printf("%b.", &["a", "b\\cc", "d"]), "a.b");
+    }
+
+    #[test]
+    fn test_errors() {
+        let args = vec!["abc".to_string(), "2".to_string()];
+        let formatted = format("%d-%d", &args);
+        assert_eq!(formatted.text, "0-2");
+        assert_eq!(formatted.errors, ["abc: invalid number"]);
+
+        let formatted = format("a%qb", &[]);
+        assert_eq!(formatted.text, "a");
+        assert_eq!(formatted.errors, ["`q': invalid format character"]);
+
+        // Huge fields stop printing instead of being allocated
+        let formatted = format("a%999999999999d", &["1".to_string()]);
+        assert_eq!(formatted.text, "a");
+        assert_eq!(formatted.errors, ["field width too large"]);
+        let formatted = format("%*d|%.99999999999999999999s", &["-9999999".to_string()]);
+        assert_eq!(formatted.errors, ["field width too large"]);
+        let formatted = format("%.99999999999999999999s", &["x".to_string()]);
+        assert_eq!(formatted.errors, ["precision too large"]);
+    }
+}
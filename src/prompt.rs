@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Values the prompt escapes can refer to
+pub struct PromptContext<'a> {
+    pub current_dir: &'a Path,
+    pub home_dir: &'a Path,
+    pub variables: &'a HashMap<String, String>,
+}
+
+/// Expands bash-style prompt escapes such as `\u`, `\h`, `\w`, `\t` and `\$`
+pub fn expand_escapes(template: &str, ctx: &PromptContext) -> String {
+    let mut result = String::new();
+    let mut chars = template.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('u') => result.push_str(&user_name(ctx)),
+            Some('h') => {
+                let host = host_name(ctx);
+                result.push_str(host.split('.').next().unwrap_or(&host));
+            }
+            Some('H') => result.push_str(&host_name(ctx)),
+            Some('w') => result.push_str(&tilde_dir(ctx)),
+            Some('W') => match ctx.current_dir.file_name() {
+                Some(name) if ctx.current_dir != ctx.home_dir => {
+                    result.push_str(&name.to_string_lossy())
+                }
+                _ => result.push_str(&tilde_dir(ctx)),
+            },
+            Some('$') => result.push(if user_name(ctx) == "root" { '#' } else { '$' }),
+            Some('n') => result.push('\n'),
+            Some('t') => result.push_str(&clock()),
+            Some('e') => result.push('\x1b'),
+            Some('a') => result.push('\x07'),
+            Some('\\') => result.push('\\'),
+            // Non-printing markers only matter to readline's width calculation
+            Some('[') | Some(']') => {}
+            Some(other) => {
+                result.push('\\');
+                result.push(other);
+            }
+            None => result.push('\\'),
+        }
+    }
+
+    result
+}
+
+fn user_name(ctx: &PromptContext) -> String {
+    ctx.variables
+        .get("USER")
+        .or_else(|| ctx.variables.get("USERNAME"))
+        .cloned()
+        .unwrap_or_default()
+}
+
+fn host_name(ctx: &PromptContext) -> String {
+    if let Some(host) = ctx.variables.get("HOSTNAME") {
+        return host.clone();
+    }
+
+    std::fs::read_to_string("/etc/hostname")
+        .map(|host| host.trim().to_string())
+        .unwrap_or_else(|_| "localhost".to_string())
+}
+
+// The local time as HH:MM:SS
+#[cfg(unix)]
+fn clock() -> String {
+    // SAFETY: localtime_r only writes into the zeroed `tm` we own
+    let tm = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&now, &mut tm);
+        tm
+    };
+    format!("{:02}:{:02}:{:02}", tm.tm_hour, tm.tm_min, tm.tm_sec)
+}
+
+// Without a time zone database at hand this is UTC
+#[cfg(not(unix))]
+fn clock() -> String {
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() % 86400)
+        .unwrap_or(0);
+    format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+fn tilde_dir(ctx: &PromptContext) -> String {
+    match ctx.current_dir.strip_prefix(ctx.home_dir) {
+        Ok(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+        Ok(rest) => format!("~/{}", rest.display()),
+        Err(_) => ctx.current_dir.display().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod prompt_tests {
+    use super::*;
+
+    fn expand(template: &str, cwd: &str) -> String {
+        let variables = HashMap::from([
+            ("USER".to_string(), "alice".to_string()),
+            ("HOSTNAME".to_string(), "box.example.com".to_string()),
+        ]);
+        let ctx = PromptContext {
+            current_dir: Path::new(cwd),
+            home_dir: Path::new("/home/alice"),
+            variables: &variables,
+        };
+        expand_escapes(template, &ctx)
+    }
+
+    #[test]
+    fn test_user_host_and_dir() {
+        assert_eq!(
+            expand("\\u@\\h:\\w\\$ ", "/home/alice/src"),
+            "alice@box:~/src$ "
+        );
+        assert_eq!(expand("\\H", "/"), "box.example.com");
+    }
+
+    #[test]
+    fn test_basename_and_home() {
+        assert_eq!(expand("\\W", "/home/alice/src/wpcsh"), "wpcsh");
+        assert_eq!(expand("\\W \\w", "/home/alice"), "~ ~");
+        assert_eq!(expand("\\w", "/tmp"), "/tmp");
+    }
+
+    #[test]
+    fn test_clock() {
+        let time = expand("[\\t]", "/");
+        let digits: Vec<&str> = time.trim_matches(['[', ']']).split(':').collect();
+
+        assert_eq!(digits.len(), 3, "{time}");
+        assert!(digits.iter().all(|part| part.len() == 2 && part.parse::<u8>().is_ok()));
+    }
+
+    #[test]
+    fn test_literal_and_unknown_escapes() {
+        assert_eq!(expand("\\[\\e[1m\\]>\\\\", "/"), "\x1b[1m>\\");
+        assert_eq!(expand("\\q", "/"), "\\q");
+        assert_eq!(expand("end\\", "/"), "end\\");
+    }
+}
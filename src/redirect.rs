@@ -0,0 +1,419 @@
+use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
+use std::fs::{File, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::process::{Command, Stdio};
+
+use crate::flash::parser::{Redirect, RedirectKind};
+
+/// Files a command's standard streams are connected to; `None` inherits the shell's own
+#[derive(Debug, Default)]
+pub struct StdStreams {
+    pub stdin: Option<File>,
+    pub stdout: Option<File>,
+    pub stderr: Option<File>,
+    // Descriptors past stderr, as `3>file` opens, which started commands get at the same
+    // numbers
+    pub extra: BTreeMap<u32, File>,
+    // set -o noclobber: `>` refuses to truncate existing regular files
+    pub noclobber: bool,
+}
+
+/// The null device, which background commands read instead of the terminal
+pub fn null_input() -> io::Result<File> {
+    File::open(if cfg!(windows) { "NUL" } else { "/dev/null" })
+}
+
+impl StdStreams {
+    // Redirections are applied left to right, so `2>&1` sees any earlier stdout change
+    pub fn redirect(&mut self, redirect: &Redirect, target: &str) -> io::Result<()> {
+        self.open(redirect, target).map_err(|err| with_target(err, target))
+    }
+
+    fn open(&mut self, redirect: &Redirect, target: &str) -> io::Result<()> {
+        let default_fd = match redirect.kind {
+            RedirectKind::Input
+            | RedirectKind::InputDup
+            | RedirectKind::HereDoc
+            | RedirectKind::HereDocDash
+            | RedirectKind::HereDocLiteral
+            | RedirectKind::HereString => 0,
+            RedirectKind::ErrorOutput | RedirectKind::ErrorAppend => 2,
+            _ => 1,
+        };
+        let fd = redirect.fd.unwrap_or(default_fd);
+        // Checked first so that `3>file` creates nothing where it cannot be used
+        check_descriptor(fd)?;
+
+        let dup = matches!(redirect.kind, RedirectKind::OutputDup | RedirectKind::InputDup);
+        if dup && target == "-" {
+            return self.close(fd);
+        }
+
+        let file = match redirect.kind {
+            RedirectKind::Input => File::open(target)?,
+            RedirectKind::Output => self.create(target)?,
+            RedirectKind::OutputForce | RedirectKind::ErrorOutput => File::create(target)?,
+            RedirectKind::Append | RedirectKind::ErrorAppend => open_append(target)?,
+            RedirectKind::OutputBoth => {
+                let file = self.create(target)?;
+                self.stderr = Some(file.try_clone()?);
+                file
+            }
+            RedirectKind::AppendBoth => {
+                let file = open_append(target)?;
+                self.stderr = Some(file.try_clone()?);
+                file
+            }
+            RedirectKind::HereDoc | RedirectKind::HereDocDash | RedirectKind::HereDocLiteral => {
+                feed_pipe(target.to_string())?
+            }
+            RedirectKind::HereString => feed_pipe(format!("{}\n", target))?,
+            RedirectKind::OutputDup | RedirectKind::InputDup => {
+                let source = target.parse::<u32>().map_err(|_| bad_descriptor(target))?;
+                self.duplicate(source)?
+            }
+        };
+        match fd {
+            0..=2 => *self.slot(fd)? = Some(file),
+            _ => _ = self.extra.insert(fd, file),
+        }
+        Ok(())
+    }
+
+    // Under noclobber only a new file is created; an existing one is opened untruncated and
+    // kept only if it is not a regular file, like /dev/null
+    fn create(&self, target: &str) -> io::Result<File> {
+        if !self.noclobber {
+            return File::create(target);
+        }
+        match OpenOptions::new().write(true).create_new(true).open(target) {
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                let file = OpenOptions::new().write(true).open(target)?;
+                if file.metadata()?.is_file() {
+                    return Err(io::Error::new(
+                        ErrorKind::AlreadyExists,
+                        format!("{}: cannot overwrite existing file", target),
+                    ));
+                }
+                Ok(file)
+            }
+            result => result,
+        }
+    }
+
+    // `n>&-` closes a descriptor past stderr; the standard streams always stay open
+    fn close(&mut self, fd: u32) -> io::Result<()> {
+        match fd {
+            0..=2 => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("{}: cannot close a standard stream", fd),
+            )),
+            _ => {
+                self.extra.remove(&fd);
+                Ok(())
+            }
+        }
+    }
+
+    // Streams left unset fall back to copies of `defaults`, such as a redirected group's output
+    pub fn inherit(&mut self, defaults: &StdStreams) -> io::Result<()> {
+        let slots = [
+            (&mut self.stdin, &defaults.stdin),
+            (&mut self.stdout, &defaults.stdout),
+            (&mut self.stderr, &defaults.stderr),
+        ];
+
+        for (slot, default) in slots {
+            if slot.is_none()
+                && let Some(file) = default
+            {
+                *slot = Some(file.try_clone()?);
+            }
+        }
+        for (&fd, file) in &defaults.extra {
+            if let Entry::Vacant(slot) = self.extra.entry(fd) {
+                slot.insert(file.try_clone()?);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn apply_to(self, command: &mut Command) {
+        if let Some(file) = self.stdin {
+            command.stdin(Stdio::from(file));
+        }
+        if let Some(file) = self.stdout {
+            command.stdout(Stdio::from(file));
+        }
+        if let Some(file) = self.stderr {
+            command.stderr(Stdio::from(file));
+        }
+        #[cfg(unix)]
+        if !self.extra.is_empty() {
+            use std::os::unix::process::CommandExt;
+
+            let extra = self.extra;
+            // Filled in the child, which must not allocate
+            let mut moved = vec![0; extra.len()];
+            // SAFETY: the child only calls fcntl and dup2 before exec, which are
+            // async-signal-safe
+            unsafe { command.pre_exec(move || place_descriptors(&extra, &mut moved)) };
+        }
+    }
+
+    fn slot(&mut self, fd: u32) -> io::Result<&mut Option<File>> {
+        match fd {
+            0 => Ok(&mut self.stdin),
+            1 => Ok(&mut self.stdout),
+            2 => Ok(&mut self.stderr),
+            _ => Err(bad_descriptor(&fd.to_string())),
+        }
+    }
+
+    fn duplicate(&mut self, fd: u32) -> io::Result<File> {
+        if fd > 2 {
+            let file = self.extra.get(&fd).ok_or_else(|| bad_descriptor(&fd.to_string()))?;
+            return file.try_clone();
+        }
+        match self.slot(fd)? {
+            Some(file) => file.try_clone(),
+            None => inherited(fd),
+        }
+    }
+}
+
+/// Creates a pipe and returns its read and write ends as files
+pub fn pipe() -> io::Result<(File, File)> {
+    let (reader, writer) = os_pipe::pipe()?;
+    Ok((into_file(reader), into_file(writer)))
+}
+
+fn open_append(path: &str) -> io::Result<File> {
+    OpenOptions::new().append(true).create(true).open(path)
+}
+
+// Write from a thread so bodies larger than the pipe buffer cannot block us
+fn feed_pipe(body: String) -> io::Result<File> {
+    let (reader, mut writer) = pipe()?;
+
+    std::thread::spawn(move || {
+        let _ = writer.write_all(body.as_bytes());
+    });
+
+    Ok(reader)
+}
+
+// OS errors do not mention the path, so prefix it like `cat: file: no such file or directory`
+fn with_target(err: io::Error, target: &str) -> io::Error {
+    if err.raw_os_error().is_none() {
+        return err;
+    }
+
+    let reason = match err.kind() {
+        ErrorKind::NotFound => "no such file or directory".to_string(),
+        ErrorKind::PermissionDenied => "permission denied".to_string(),
+        ErrorKind::IsADirectory => "is a directory".to_string(),
+        _ => err.to_string(),
+    };
+
+    io::Error::new(err.kind(), format!("{}: {}", target, reason))
+}
+
+// Descriptors past stderr can only be handed to started commands where there is dup2
+fn check_descriptor(fd: u32) -> io::Result<()> {
+    match fd <= 2 || cfg!(unix) {
+        true => Ok(()),
+        false => Err(bad_descriptor(&fd.to_string())),
+    }
+}
+
+// Gives a starting command each file at its descriptor number. All files are first copied
+// past every target, so that placing one cannot close another before it is placed.
+#[cfg(unix)]
+fn place_descriptors(extra: &BTreeMap<u32, File>, moved: &mut [i32]) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let floor = extra.keys().last().map_or(0, |&fd| fd as i32 + 1);
+    for (file, copy) in extra.values().zip(moved.iter_mut()) {
+        // SAFETY: fcntl only works on descriptors; the copy closes on exec
+        *copy = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_DUPFD_CLOEXEC, floor) };
+        if *copy < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    for (&fd, &copy) in extra.keys().zip(moved.iter()) {
+        // SAFETY: as above; the target is left open across exec
+        if unsafe { libc::dup2(copy, fd as i32) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+fn bad_descriptor(fd: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, format!("{}: bad file descriptor", fd))
+}
+
+#[cfg(unix)]
+fn into_file<T: Into<std::os::fd::OwnedFd>>(end: T) -> File {
+    File::from(end.into())
+}
+
+#[cfg(windows)]
+fn into_file<T: Into<std::os::windows::io::OwnedHandle>>(end: T) -> File {
+    File::from(end.into())
+}
+
+// Duplicates one of the shell's own standard streams
+#[cfg(unix)]
+fn inherited(fd: u32) -> io::Result<File> {
+    use std::os::fd::AsFd;
+
+    let owned = match fd {
+        0 => io::stdin().as_fd().try_clone_to_owned()?,
+        1 => io::stdout().as_fd().try_clone_to_owned()?,
+        _ => io::stderr().as_fd().try_clone_to_owned()?,
+    };
+    Ok(File::from(owned))
+}
+
+#[cfg(windows)]
+fn inherited(fd: u32) -> io::Result<File> {
+    use std::os::windows::io::AsHandle;
+
+    let owned = match fd {
+        0 => io::stdin().as_handle().try_clone_to_owned()?,
+        1 => io::stdout().as_handle().try_clone_to_owned()?,
+        _ => io::stderr().as_handle().try_clone_to_owned()?,
+    };
+    Ok(File::from(owned))
+}
+
+#[cfg(test)]
+mod redirect_tests {
+    use super::*;
+    use std::io::Read;
+
+    fn dup(fd: Option<u32>, target: &str) -> Redirect {
+        Redirect {
+            kind: RedirectKind::OutputDup,
+            fd,
+            file: target.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_dup_follows_earlier_redirects() {
+        let (mut reader, writer) = pipe().unwrap();
+        let mut streams = StdStreams {
+            stdout: Some(writer),
+            ..StdStreams::default()
+        };
+
+        streams.redirect(&dup(Some(2), "1"), "1").unwrap();
+        streams.stdout = None;
+
+        let mut stderr = streams.stderr.take().unwrap();
+        stderr.write_all(b"merged").unwrap();
+        drop(stderr);
+
+        let mut out = String::new();
+        reader.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "merged");
+    }
+
+    #[test]
+    fn test_noclobber_refuses_existing_files() {
+        let path = std::env::temp_dir().join(format!("wpcsh-{}-noclobber", std::process::id()));
+        std::fs::write(&path, "keep").unwrap();
+        let target = path.to_string_lossy();
+
+        let mut streams = StdStreams {
+            noclobber: true,
+            ..StdStreams::default()
+        };
+        let output = |kind| Redirect {
+            kind,
+            fd: None,
+            file: target.to_string(),
+        };
+
+        let err = streams.redirect(&output(RedirectKind::Output), &target).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "keep");
+        let err = streams.redirect(&output(RedirectKind::OutputBoth), &target).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        assert!(streams.stdout.is_none() && streams.stderr.is_none());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "keep");
+
+        // Devices are still written, and new files created
+        #[cfg(unix)]
+        {
+            let null = Redirect {
+                file: "/dev/null".to_string(),
+                ..output(RedirectKind::Output)
+            };
+            streams.redirect(&null, "/dev/null").unwrap();
+        }
+        let _ = std::fs::remove_file(&path);
+        streams.redirect(&output(RedirectKind::OutputBoth), &target).unwrap();
+        assert!(streams.stdout.is_some() && streams.stderr.is_some());
+        std::fs::write(&path, "keep").unwrap();
+
+        streams.redirect(&output(RedirectKind::OutputForce), &target).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_missing_file_error_names_target() {
+        let mut streams = StdStreams::default();
+        let input = Redirect {
+            kind: RedirectKind::Input,
+            fd: None,
+            file: "/no/such/file".to_string(),
+        };
+
+        let err = streams.redirect(&input, "/no/such/file").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert_eq!(err.to_string(), "/no/such/file: no such file or directory");
+    }
+
+    #[test]
+    fn test_dup_rejects_unknown_descriptors() {
+        let mut streams = StdStreams::default();
+
+        assert!(streams.redirect(&dup(Some(2), "x"), "x").is_err());
+        assert!(streams.redirect(&dup(Some(1), "7"), "7").is_err());
+        assert!(streams.redirect(&dup(Some(2), "-"), "-").is_err());
+    }
+
+    #[test]
+    fn test_redirects_replace_the_numbered_stream() {
+        let path = std::env::temp_dir().join(format!("wpcsh-{}-numbered", std::process::id()));
+        let target = path.to_string_lossy();
+        let output = |fd| Redirect {
+            kind: RedirectKind::Output,
+            fd,
+            file: target.to_string(),
+        };
+
+        let mut streams = StdStreams::default();
+        streams.redirect(&output(Some(1)), &target).unwrap();
+        assert!(streams.stdout.is_some() && streams.stderr.is_none());
+        let _ = std::fs::remove_file(&path);
+
+        // A descriptor past stderr gets its own slot, which `3>&-` closes again
+        let mut streams = StdStreams::default();
+        streams.redirect(&output(Some(3)), &target).unwrap();
+        assert!(streams.stdout.is_none() && streams.extra.contains_key(&3));
+        streams.redirect(&dup(Some(1), "3"), "3").unwrap();
+        assert!(streams.stdout.is_some());
+        streams.redirect(&dup(Some(3), "-"), "-").unwrap();
+        assert!(streams.extra.is_empty());
+        assert!(streams.redirect(&dup(Some(1), "3"), "3").is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,156 @@
+/// The signals known by name, in the order `kill -l` lists them
+#[cfg(unix)]
+pub const SIGNALS: &[(&str, i32)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG),
+    ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ),
+    ("VTALRM", libc::SIGVTALRM),
+    ("PROF", libc::SIGPROF),
+    ("WINCH", libc::SIGWINCH),
+    ("IO", libc::SIGIO),
+    ("SYS", libc::SIGSYS),
+];
+
+// Without signals, the usual numbers still name the ways a job can be ended
+#[cfg(not(unix))]
+pub const SIGNALS: &[(&str, i32)] =
+    &[("HUP", 1), ("INT", 2), ("QUIT", 3), ("KILL", 9), ("TERM", 15)];
+
+/// What `kill` sends unless told otherwise
+#[cfg(unix)]
+pub const TERM: i32 = libc::SIGTERM;
+#[cfg(not(unix))]
+pub const TERM: i32 = 15;
+
+/// What Ctrl-C sends
+#[cfg(unix)]
+pub const INT: i32 = libc::SIGINT;
+#[cfg(not(unix))]
+pub const INT: i32 = 2;
+
+/// What a trap is set on: the shell exiting, or a signal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Signal {
+    Exit,
+    Number(i32),
+}
+
+impl Signal {
+    /// `EXIT`, or a signal as [`number`] reads it. Signal 0 also stands for `EXIT`.
+    pub fn parse(spec: &str) -> Option<Signal> {
+        let upper = spec.to_ascii_uppercase();
+        if upper == "EXIT" || upper == "SIGEXIT" {
+            return Some(Signal::Exit);
+        }
+        match number(spec)? {
+            0 => Some(Signal::Exit),
+            number => Some(Signal::Number(number)),
+        }
+    }
+
+    /// The name `trap -p` shows, like `EXIT` or `SIGINT`
+    pub fn name(self) -> String {
+        match self {
+            Signal::Exit => "EXIT".to_string(),
+            Signal::Number(number) => match name(number) {
+                Some(name) => format!("SIG{}", name),
+                None => number.to_string(),
+            },
+        }
+    }
+}
+
+/// The number of a signal given as `TERM`, `SIGTERM` or `15`, in any case
+pub fn number(spec: &str) -> Option<i32> {
+    if let Ok(number) = spec.parse::<i32>() {
+        return (number == 0 || name(number).is_some()).then_some(number);
+    }
+
+    let upper = spec.to_ascii_uppercase();
+    let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+    SIGNALS.iter().find(|(known, _)| *known == name).map(|&(_, number)| number)
+}
+
+/// The name of signal `number` without its `SIG` prefix
+pub fn name(number: i32) -> Option<&'static str> {
+    SIGNALS.iter().find(|&&(_, known)| known == number).map(|&(name, _)| name)
+}
+
+/// How `jobs` says a job was ended by signal `number`, like `Terminated`
+pub fn description(number: i32) -> String {
+    let text = match name(number) {
+        Some("HUP") => "Hangup",
+        Some("INT") => "Interrupt",
+        Some("QUIT") => "Quit",
+        Some("ILL") => "Illegal instruction",
+        Some("TRAP") => "Trace/breakpoint trap",
+        Some("ABRT") => "Aborted",
+        Some("BUS") => "Bus error",
+        Some("FPE") => "Floating point exception",
+        Some("KILL") => "Killed",
+        Some("USR1") => "User defined signal 1",
+        Some("SEGV") => "Segmentation fault",
+        Some("USR2") => "User defined signal 2",
+        Some("PIPE") => "Broken pipe",
+        Some("ALRM") => "Alarm clock",
+        Some("TERM") => "Terminated",
+        _ => return format!("Signal {}", number),
+    };
+    text.to_string()
+}
+
+/// Sends `signal` to process `pid`, or to the process group `-pid`
+#[cfg(unix)]
+pub fn send(pid: i32, signal: i32) -> std::io::Result<()> {
+    // SAFETY: kill has no memory effects
+    match unsafe { libc::kill(pid, signal) } {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+#[cfg(test)]
+mod signal_tests {
+    use super::*;
+
+    #[test]
+    fn test_signal_names() {
+        assert_eq!(number("TERM"), Some(15));
+        assert_eq!(number("sigkill"), Some(9));
+        assert_eq!(number("2"), Some(2));
+        assert_eq!(number("0"), Some(0));
+        assert_eq!(number("NOPE"), None);
+        assert_eq!(number("-1"), None);
+        assert_eq!(name(1), Some("HUP"));
+        assert_eq!(name(0), None);
+        assert_eq!(description(15), "Terminated");
+        assert_eq!(description(9), "Killed");
+
+        assert_eq!(Signal::parse("exit"), Some(Signal::Exit));
+        assert_eq!(Signal::parse("0"), Some(Signal::Exit));
+        assert_eq!(Signal::parse("SIGINT"), Some(Signal::Number(2)));
+        assert_eq!(Signal::parse("NOPE"), None);
+        assert_eq!(Signal::Number(2).name(), "SIGINT");
+    }
+}
@@ -0,0 +1,279 @@
+use std::process::Command;
+
+#[test]
+fn test_dump_ast_with_command() {
+    let output = Command::new(env!("CARGO_BIN_EXE_wpcsh"))
+        .args(["--dump-ast", "-c", "a | b && c"])
+        .output()
+        .expect("failed to run wpcsh");
+
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("List"), "{stdout}");
+    assert!(stdout.contains("Pipeline"), "{stdout}");
+    assert!(stdout.contains("\"&&\""), "{stdout}");
+}
+
+#[test]
+fn test_command_flag_runs_once() {
+    let output = Command::new(env!("CARGO_BIN_EXE_wpcsh"))
+        .args(["-c", "echo $0 $1 && exit 4", "name", "one"])
+        .output()
+        .expect("failed to run wpcsh");
+
+    assert_eq!(output.status.code(), Some(4));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "name one\n");
+}
+
+#[test]
+fn test_script_argument_runs_file() {
+    let path = std::env::temp_dir().join(format!("wpcsh-{}-script.sh", std::process::id()));
+    std::fs::write(&path, "if true; then\n  echo \"ran $1\"\nfi\nfalse\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_wpcsh"))
+        .args([path.to_str().unwrap(), "arg"])
+        .output()
+        .expect("failed to run wpcsh");
+
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ran arg\n");
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_script_runs_here_documents() {
+    let path = std::env::temp_dir().join(format!("wpcsh-{}-heredoc.sh", std::process::id()));
+    let script = "cat <<EOF\nline1 $1\nEOF\ncat <<-'END'\n\tkeep $1\n\tEND\necho done\n";
+    std::fs::write(&path, script).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_wpcsh"))
+        .args([path.to_str().unwrap(), "arg"])
+        .output()
+        .expect("failed to run wpcsh");
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "line1 arg\nkeep $1\ndone\n");
+    assert!(output.stderr.is_empty());
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_stdin_joins_multiline_commands() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_wpcsh"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to run wpcsh");
+
+    let script = "for x in a b\ndo\n  echo $x\ndone\necho one \\\ntwo\necho \"c\nd\"\n";
+    child.stdin.take().unwrap().write_all(script.as_bytes()).unwrap();
+
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "a\nb\none two\nc\nd\n");
+}
+
+#[test]
+fn test_source_errors_name_file_and_line() {
+    let path = std::env::temp_dir().join(format!("wpcsh-{}-rc.sh", std::process::id()));
+    std::fs::write(&path, "echo one\n\nwpcsh-no-such-command\necho two\n").unwrap();
+    let rc = path.to_str().unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_wpcsh"))
+        .args(["-c", &format!("source {rc}; set -e; source {rc}; echo three")])
+        .output()
+        .expect("failed to run wpcsh");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let message = format!("wpcsh: {rc}: line 3: wpcsh-no-such-command: command not found\n");
+    assert_eq!(stderr, message.repeat(2));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "one\ntwo\none\n");
+    assert_eq!(output.status.code(), Some(127));
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_exit_trap_runs_when_the_shell_exits() {
+    let run = |command: &str| {
+        Command::new(env!("CARGO_BIN_EXE_wpcsh"))
+            .args(["-c", command])
+            .output()
+            .expect("failed to run wpcsh")
+    };
+
+    let output = run("trap 'echo bye $?' EXIT; echo hi; exit 3");
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hi\nbye 3\n");
+
+    let output = run("trap 'echo bye' EXIT; false");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "bye\n");
+
+    let output = run("trap 'echo bye' EXIT; trap - EXIT");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_int_trap_runs_on_sigint() {
+    let output = Command::new(env!("CARGO_BIN_EXE_wpcsh"))
+        .args(["-c", "trap 'echo caught' INT; kill -INT $$; sleep 0.2; echo after $?"])
+        .output()
+        .expect("failed to run wpcsh");
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "caught\nafter 0\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_exec_replaces_the_shell() {
+    let output = Command::new(env!("CARGO_BIN_EXE_wpcsh"))
+        .args(["-c", "echo $$; exec sh -c 'echo $$; exit 5'; echo not reached"])
+        .output()
+        .expect("failed to run wpcsh");
+
+    assert_eq!(output.status.code(), Some(5));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let pids: Vec<&str> = stdout.lines().collect();
+    assert_eq!(pids.len(), 2, "{stdout}");
+    assert_eq!(pids[0], pids[1]);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_closed_stdout_ends_the_shell_quietly() {
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::Stdio;
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_wpcsh"))
+        .args(["-c", "while true; do echo y; done"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run wpcsh");
+
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().unwrap()).read_line(&mut line).unwrap();
+    assert_eq!(line, "y\n");
+
+    // The reader is gone, so the next write ends the shell as it would end any command
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.signal(), Some(libc::SIGPIPE));
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+}
+
+#[test]
+fn test_unset_parameter_error_exits() {
+    let output = Command::new(env!("CARGO_BIN_EXE_wpcsh"))
+        .args(["-c", "echo ${Z:?boom}; echo still"])
+        .env_remove("Z")
+        .output()
+        .expect("failed to run wpcsh");
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Z: boom"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_signals_run_traps() {
+    let script = "trap 'echo bye' EXIT; trap 'echo int' INT; kill -INT $$; echo x";
+    let output = Command::new(env!("CARGO_BIN_EXE_wpcsh"))
+        .args(["-c", &format!("{script}; kill $$; echo no")])
+        .output()
+        .expect("failed to run wpcsh");
+
+    assert_eq!(output.status.code(), Some(143));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "int\nx\nbye\n");
+
+    // A signal from outside ends the shell once the running command is done
+    let child = Command::new(env!("CARGO_BIN_EXE_wpcsh"))
+        .args(["-c", "trap 'echo bye' EXIT; sleep 0.5; echo no"])
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .expect("failed to run wpcsh");
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    // SAFETY: kill has no memory effects
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGHUP) };
+
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(129));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "bye\n");
+}
+
+// Directory changes are tested through the binary, as they change the whole process's
+// working directory
+#[cfg(unix)]
+#[test]
+fn test_cd_dash_returns_to_oldpwd() {
+    let temp = std::env::temp_dir().join(format!("wpcsh-{}-cd-dash", std::process::id()));
+    std::fs::create_dir_all(temp.join("a")).unwrap();
+    let root = std::fs::canonicalize(&temp).unwrap();
+    let (base, a) = (root.display().to_string(), root.join("a").display().to_string());
+
+    // A second `cd -` toggles back
+    let script = format!(
+        "unset OLDPWD; cd - 2>/dev/null || echo unset; cd {a}; cd -; echo $PWD $OLDPWD; cd -"
+    );
+    let output = Command::new(env!("CARGO_BIN_EXE_wpcsh"))
+        .args(["-c", &script])
+        .current_dir(&root)
+        .env("HOME", &root)
+        .output()
+        .expect("failed to run wpcsh");
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let expected = format!("unset\n{base}\n{base} {a}\n{a}\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+
+    let _ = std::fs::remove_dir_all(&root);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_directory_stack() {
+    let temp = std::env::temp_dir().join(format!("wpcsh-{}-dir-stack", std::process::id()));
+    std::fs::create_dir_all(temp.join("a")).unwrap();
+    let root = std::fs::canonicalize(&temp).unwrap();
+    let (base, a) = (root.display().to_string(), root.join("a").display().to_string());
+
+    let script = format!(
+        "popd 2>/dev/null || echo empty; pushd 2>/dev/null || echo none; \
+         pushd {a}; pushd; dirs; popd; echo $PWD $OLDPWD; dirs"
+    );
+    let output = Command::new(env!("CARGO_BIN_EXE_wpcsh"))
+        .args(["-c", &script])
+        .current_dir(&root)
+        .env("HOME", &a)
+        .output()
+        .expect("failed to run wpcsh");
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let expected = format!("empty\nnone\n~ {base}\n{base} ~\n{base} ~\n~\n{a} {base}\n~\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+
+    let _ = std::fs::remove_dir_all(&root);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_subshell_keeps_the_working_directory() {
+    let root = std::fs::canonicalize(std::env::temp_dir()).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_wpcsh"))
+        .args(["-c", "(cd /; pwd); pwd; echo $PWD"])
+        .current_dir(&root)
+        .output()
+        .expect("failed to run wpcsh");
+
+    let dir = root.display();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), format!("/\n{dir}\n{dir}\n"));
+}